@@ -0,0 +1,116 @@
+//! An allocation-free way to stop the kernel when it cannot go on.
+//!
+//! `panic!` formats its message, and formatting may allocate, so a failing
+//! allocator cannot report itself by panicking without risking recursion.
+//! The functions here write fixed messages and plain decimal numbers a byte
+//! at a time, and `abort` sends them straight to the UART without taking the
+//! console lock.
+
+use core::alloc::Layout;
+
+use pi::uart::MiniUart;
+
+/// Writes `bytes` to `sink`.
+pub fn write_bytes<F: FnMut(u8) + ?Sized>(sink: &mut F, bytes: &[u8]) {
+    for &byte in bytes {
+        sink(byte);
+    }
+}
+
+/// Writes `n` to `sink` in decimal.
+pub fn write_decimal<F: FnMut(u8) + ?Sized>(sink: &mut F, mut n: usize) {
+    // `usize::MAX` has 20 decimal digits.
+    let mut digits = [0u8; 20];
+    let mut start = digits.len();
+    loop {
+        start -= 1;
+        digits[start] = b'0' + (n % 10) as u8;
+        n /= 10;
+        if n == 0 {
+            break;
+        }
+    }
+    write_bytes(sink, &digits[start..]);
+}
+
+/// Writes the message reported when an allocation of `layout` fails.
+pub fn write_oom_report<F: FnMut(u8) + ?Sized>(sink: &mut F, layout: Layout) {
+    write_bytes(sink, b"\r\nout of memory: failed to allocate ");
+    write_decimal(sink, layout.size());
+    write_bytes(sink, b" bytes aligned to ");
+    write_decimal(sink, layout.align());
+    write_bytes(sink, b"\r\nhalting\r\n");
+}
+
+/// Reports that an allocation of `layout` failed and halts the calling core.
+pub fn oom(layout: Layout) -> ! {
+    abort(|sink| write_oom_report(sink, layout))
+}
+
+/// Writes a report with `report` directly to the UART and halts the calling
+/// core. Neither allocates nor takes a lock, so it is safe to call from the
+/// allocator's error path or while the console is held.
+pub fn abort<R: FnOnce(&mut dyn FnMut(u8))>(report: R) -> ! {
+    let mut uart = MiniUart::new();
+    // A fresh `MiniUart` has no write timeout, so writes cannot fail.
+    report(&mut |byte| {
+        let _ = uart.write_byte(byte);
+    });
+    loop {
+        aarch64::wfe();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{write_decimal, write_oom_report};
+    use core::alloc::Layout;
+
+    /// Collects written bytes in a fixed buffer, so that writing can be
+    /// checked without allocating.
+    struct Capture {
+        bytes: [u8; 128],
+        len: usize,
+    }
+
+    impl Capture {
+        fn new() -> Capture {
+            Capture { bytes: [0; 128], len: 0 }
+        }
+
+        fn sink(&mut self) -> impl FnMut(u8) + '_ {
+            move |byte| {
+                self.bytes[self.len] = byte;
+                self.len += 1;
+            }
+        }
+
+        fn written(&self) -> &[u8] {
+            &self.bytes[..self.len]
+        }
+    }
+
+    #[test]
+    fn decimal() {
+        for &(n, expected) in [(0, "0"), (7, "7"), (10, "10"), (65536, "65536")].iter() {
+            let mut capture = Capture::new();
+            write_decimal(&mut capture.sink(), n);
+            assert_eq!(capture.written(), expected.as_bytes());
+        }
+
+        let mut capture = Capture::new();
+        write_decimal(&mut capture.sink(), core::usize::MAX);
+        assert_eq!(capture.written(), b"18446744073709551615");
+    }
+
+    #[test]
+    fn oom_report() {
+        let mut capture = Capture::new();
+        let layout = Layout::from_size_align(4096, 16).unwrap();
+        write_oom_report(&mut capture.sink(), layout);
+        assert_eq!(
+            capture.written(),
+            &b"\r\nout of memory: failed to allocate 4096 bytes aligned to 16\r\nhalting\r\n"[..]
+        );
+    }
+}
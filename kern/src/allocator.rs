@@ -6,12 +6,17 @@ mod bump;
 
 type AllocatorImpl = bin::Allocator;
 
+pub use self::bin::{bin_index_size, AllocStats};
+
 #[cfg(test)]
 mod tests;
 
 use core::alloc::{GlobalAlloc, Layout};
-use core::fmt;
+use core::{cmp, fmt, ptr};
+
+use pi::atags::{Atag, Atags};
 
+use crate::init_flag::InitFlag;
 use crate::mutex::Mutex;
 
 /// `LocalAlloc` is an analogous trait to the standard library's `GlobalAlloc`,
@@ -19,10 +24,35 @@ use crate::mutex::Mutex;
 pub trait LocalAlloc {
     unsafe fn alloc(&mut self, layout: Layout) -> *mut u8;
     unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout);
+
+    /// Resizes the block at `ptr`, allocated with `layout`, to `new_size`
+    /// bytes, keeping its contents up to the smaller of the two sizes.
+    /// Returns the block's new address, or a null pointer if it could not be
+    /// resized, in which case the old block is left untouched.
+    unsafe fn realloc(&mut self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        realloc_by_copy(self, ptr, layout, new_size)
+    }
+}
+
+/// Resizes a block by allocating a new one, copying the contents over and
+/// freeing the old one. This is `LocalAlloc::realloc`'s default.
+unsafe fn realloc_by_copy<A: LocalAlloc + ?Sized>(
+    allocator: &mut A,
+    ptr: *mut u8,
+    layout: Layout,
+    new_size: usize,
+) -> *mut u8 {
+    let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+    let new_ptr = allocator.alloc(new_layout);
+    if !new_ptr.is_null() {
+        ptr::copy_nonoverlapping(ptr, new_ptr, cmp::min(layout.size(), new_size));
+        allocator.dealloc(ptr, layout);
+    }
+    new_ptr
 }
 
 /// Thread-safe (locking) wrapper around a particular memory allocator.
-pub struct Allocator(Mutex<Option<AllocatorImpl>>);
+pub struct Allocator(Mutex<Option<AllocatorImpl>>, InitFlag);
 
 impl Allocator {
     /// Returns an uninitialized `Allocator`.
@@ -30,7 +60,12 @@ impl Allocator {
     /// The allocator must be initialized by calling `initialize()` before the
     /// first memory allocation. Failure to do will result in panics.
     pub const fn uninitialized() -> Self {
-        Allocator(Mutex::new(None))
+        Allocator(Mutex::new(None), InitFlag::new())
+    }
+
+    /// Returns `true` if `initialize()` has been called.
+    pub fn is_initialized(&self) -> bool {
+        self.1.is_set()
     }
 
     /// Initializes the memory allocator.
@@ -39,12 +74,19 @@ impl Allocator {
     ///
     /// # Panics
     ///
-    /// Panics if the system's memory map could not be retrieved.
+    /// Panics if the allocator was already initialized or if the system's
+    /// memory map could not be retrieved.
     pub unsafe fn initialize(&self) {
+        self.1.set("allocator");
         let (start, end) = memory_map().expect("failed to find memory map");
         info!("heap beg: {:x}, end: {:x}", start, end);
         *self.0.lock() = Some(AllocatorImpl::new(start, end));
     }
+
+    /// Returns the heap usage, or `None` if the allocator is uninitialized.
+    pub fn stats(&self) -> Option<AllocStats> {
+        self.0.lock().as_ref().map(AllocatorImpl::stats)
+    }
 }
 
 unsafe impl GlobalAlloc for Allocator {
@@ -63,20 +105,42 @@ unsafe impl GlobalAlloc for Allocator {
             .expect("allocator uninitialized")
             .dealloc(ptr, layout);
     }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        self.0
+            .lock()
+            .as_mut()
+            .expect("allocator uninitialized")
+            .realloc(ptr, layout, new_size)
+    }
 }
 
 extern "C" {
     static __text_end: u8;
 }
 
+/// The end of RAM assumed when the bootloader reports no memory region that
+/// holds the kernel.
+const DEFAULT_MEMORY_END: usize = 0x1_0000_0000;
+
 /// Returns the (start address, end address) of the available memory on this
 /// system if it can be determined. If it cannot, `None` is returned.
 ///
+/// Memory starts after the kernel binary and ends with the `Mem` ATAG that
+/// contains it, or at `DEFAULT_MEMORY_END` if there is no such ATAG.
+///
 /// This function is expected to return `Some` under all normal cirumstances.
 pub fn memory_map() -> Option<(usize, usize)> {
     let binary_end = unsafe { (&__text_end as *const u8) as usize };
 
-    Some((binary_end, 0x1_0000_0000))
+    let end = Atags::get()
+        .filter_map(Atag::mem)
+        .map(|mem| (mem.start as usize, mem.start as usize + mem.size as usize))
+        .find(|&(start, end)| start <= binary_end && binary_end < end)
+        .map(|(_, end)| end)
+        .unwrap_or(DEFAULT_MEMORY_END);
+
+    Some((binary_end, end))
 }
 
 impl fmt::Debug for Allocator {
@@ -1,9 +1,10 @@
 use core::alloc::Layout;
+use core::cmp;
 use core::fmt;
 
 use crate::allocator::linked_list::LinkedList;
 use crate::allocator::util::*;
-use crate::allocator::LocalAlloc;
+use crate::allocator::{realloc_by_copy, LocalAlloc};
 
 /// A simple allocator that allocates based on size classes.
 ///   bin 0 (2^3 bytes)    : handles allocations in (0, 2^3]
@@ -13,29 +14,107 @@ use crate::allocator::LocalAlloc;
 ///
 ///   map_to_bin(size) -> k
 ///
+/// Freed blocks are merged with their buddy, the neighbouring block of the
+/// same size whose offset from the start of the heap differs only in the bit
+/// for that size, whenever the buddy is free as well.
+///
 
 const BINS_START_K: usize = 3;
 pub const BINS_LEN: usize = 30;
 
 pub struct Allocator {
+    base: usize,
     start: usize,
     end: usize,
     bins: [LinkedList; BINS_LEN],
 }
 
+/// A snapshot of how the heap is used, returned by `Allocator::stats`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AllocStats {
+    /// Bytes handed out from the end of the heap so far.
+    pub bump_used: usize,
+    /// Bytes at the end of the heap that were never handed out.
+    pub bump_free: usize,
+    /// Bytes currently parked in each bin's free list.
+    pub bins: [usize; BINS_LEN],
+    /// The largest size that can currently be allocated, ignoring alignment.
+    pub largest_alloc: usize,
+}
+
 impl Allocator {
     /// Creates a new bin allocator that will allocate memory from the region
     /// starting at address `start` and ending at address `end`.
     pub fn new(start: usize, end: usize) -> Allocator {
         Self {
             bins: [LinkedList::new(); BINS_LEN],
+            base: start,
             start,
             end,
         }
     }
+
+    /// Returns how much of the heap is handed out and how much sits in the
+    /// free lists.
+    pub fn stats(&self) -> AllocStats {
+        let mut bins = [0; BINS_LEN];
+        let mut largest_alloc = 0;
+        for (i, bin) in self.bins.iter().enumerate() {
+            bins[i] = bin.iter().count() * bin_index_size(i);
+            if bins[i] > 0 {
+                largest_alloc = bin_index_size(i);
+            }
+        }
+
+        let bump_free = self.end - self.start;
+        for i in 0..BINS_LEN {
+            if bin_index_size(i) <= bump_free {
+                largest_alloc = cmp::max(largest_alloc, bin_index_size(i));
+            }
+        }
+
+        AllocStats {
+            bump_used: self.start - self.base,
+            bump_free,
+            bins,
+            largest_alloc,
+        }
+    }
+
+    /// Puts the free block of bin `n` at `addr` on the free lists, merging
+    /// it with its buddy for as long as the buddy is free, moving up a bin
+    /// each time.
+    unsafe fn free(&mut self, mut addr: usize, mut n: usize) {
+        while n + 1 < BINS_LEN {
+            let buddy = self.base + ((addr - self.base) ^ bin_index_size(n));
+            if !take(&mut self.bins[n], buddy) {
+                break;
+            }
+            addr = cmp::min(addr, buddy);
+            n += 1;
+        }
+        self.bins[n].push(addr as *mut usize);
+    }
+
+    /// Frees the unused bytes in `[start, end)`, split into the largest
+    /// blocks that are aligned to their size relative to the start of the
+    /// heap. Bytes that don't make up a block of the smallest bin are lost.
+    unsafe fn reclaim(&mut self, start: usize, end: usize) {
+        let mut addr = align_up(start, bin_index_size(0));
+        while addr + bin_index_size(0) <= end {
+            let offset = addr - self.base;
+            let mut n = BINS_LEN - 1;
+            while n > 0 && (bin_index_size(n) > end - addr || offset % bin_index_size(n) != 0) {
+                n -= 1;
+            }
+            self.free(addr, n);
+            addr += bin_index_size(n);
+        }
+    }
 }
 
-const fn bin_index_size(index: usize) -> usize {
+/// Returns the size of the blocks in bin `index`.
+pub const fn bin_index_size(index: usize) -> usize {
     1 << (BINS_START_K + index)
 }
 
@@ -48,6 +127,17 @@ pub fn get_bin_for_size(size: usize) -> Result<usize, ()> {
     return Err(());
 }
 
+/// Removes `addr` from `bin`. Returns `true` if it was in the list.
+fn take(bin: &mut LinkedList, addr: usize) -> bool {
+    for node in bin.iter_mut() {
+        if node.value() as usize == addr {
+            node.pop();
+            return true;
+        }
+    }
+    false
+}
+
 impl LocalAlloc for Allocator {
     /// Allocates memory. Returns a pointer meeting the size and alignment
     /// properties of `layout.size()` and `layout.align()`.
@@ -85,11 +175,11 @@ impl LocalAlloc for Allocator {
                 let addr_align = align_up(addr, layout.align());
                 if addr == addr_align {
                     node.pop();
-                    // If we found a bigger bin than needed, move second unused half of the memory
-                    // into the previous bin
-                    if i > n {
-                        let this_bin_size = bin_index_size(i);
-                        self.bins[i - 1].push((addr + this_bin_size / 2) as *mut usize);
+                    // If we found a bigger bin than needed, split the block in halves down to
+                    // the size we need, keeping the first half and moving each second half
+                    // into the bin below
+                    for j in (n..i).rev() {
+                        self.bins[j].push((addr + bin_index_size(j)) as *mut usize);
                     }
                     return addr as *mut u8;
                 }
@@ -102,7 +192,10 @@ impl LocalAlloc for Allocator {
         if overflow || end > self.end {
             return core::ptr::null_mut();
         } else {
+            // Keep the bytes skipped to align the block for later allocations
+            let gap_start = self.start;
             self.start = end;
+            self.reclaim(gap_start, start);
             start as *mut u8
         }
     }
@@ -123,7 +216,17 @@ impl LocalAlloc for Allocator {
     unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout) {
         let bin_number = get_bin_for_size(layout.size())
             .expect("should never be deallocating something that could not have been allocated");
-        self.bins[bin_number].push(ptr as *mut usize);
+        self.free(ptr as usize, bin_number);
+    }
+
+    /// Resizes the block at `ptr` in place if `new_size` falls in the same
+    /// bin as `layout.size()`, since the block already has room for it.
+    /// Otherwise the contents are moved to a block from the right bin.
+    unsafe fn realloc(&mut self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        match (get_bin_for_size(layout.size()), get_bin_for_size(new_size)) {
+            (Ok(old), Ok(new)) if old == new => ptr,
+            _ => realloc_by_copy(self, ptr, layout, new_size),
+        }
     }
 }
 
@@ -307,6 +307,110 @@ mod allocator {
             }
         }
     });
+
+    test_allocators!(@bin, bin_coalesce, 4096, |(_, _, mut a)| {
+        let small = layout!(8, 8);
+
+        // use up all of the memory in the smallest bin
+        let mut ptrs = vec![];
+        for _ in 0..(4096 / 8) {
+            let ptr = a.alloc(small.clone());
+            assert!(!ptr.is_null());
+            ptrs.push(ptr);
+        }
+        assert!(a.alloc(small.clone()).is_null());
+
+        // freeing every block merges them back into a single one
+        for ptr in ptrs {
+            a.dealloc(ptr, small.clone());
+        }
+
+        let large = layout!(4096, 8);
+        let ptr = a.alloc(large.clone());
+        assert!(!ptr.is_null());
+        scribble(ptr, large.size());
+        a.dealloc(ptr, large.clone());
+
+        // and the merged block can be split up again
+        for _ in 0..(4096 / 8) {
+            assert!(!a.alloc(small.clone()).is_null());
+        }
+    });
+
+    test_allocators!(bin_realloc, bump_realloc, 4096, |(_, _, mut a)| {
+        let layout = layout!(24, 8);
+        let ptr = a.alloc(layout.clone());
+        assert!(!ptr.is_null());
+        for i in 0..24 {
+            *ptr.add(i) = i as u8;
+        }
+
+        let bigger = a.realloc(ptr, layout.clone(), 100);
+        assert!(!bigger.is_null());
+        for i in 0..24 {
+            assert_eq!(*bigger.add(i), i as u8);
+        }
+
+        let smaller = a.realloc(bigger, layout!(100, 8), 10);
+        assert!(!smaller.is_null());
+        for i in 0..10 {
+            assert_eq!(*smaller.add(i), i as u8);
+        }
+    });
+
+    test_allocators!(@bin, bin_realloc_same_bin, 4096, |(_, _, mut a)| {
+        // 17 and 32 bytes both come from the 32 byte bin
+        let ptr = a.alloc(layout!(17, 8));
+        assert!(!ptr.is_null());
+        scribble(ptr, 17);
+        assert_eq!(a.realloc(ptr, layout!(17, 8), 32), ptr);
+        assert_eq!(a.realloc(ptr, layout!(32, 8), 20), ptr);
+
+        // 33 bytes needs the next bin
+        let moved = a.realloc(ptr, layout!(20, 8), 33);
+        assert!(!moved.is_null());
+        assert_ne!(moved, ptr);
+        assert_eq!(*moved, 0xAF);
+    });
+
+    test_allocators!(@bin, bin_stats, 4096, |(_, _, mut a)| {
+        let stats = a.stats();
+        assert_eq!(stats.bump_used, 0);
+        assert_eq!(stats.bump_free, 4096);
+        assert_eq!(stats.bins, [0; bin::BINS_LEN]);
+        assert_eq!(stats.largest_alloc, 4096);
+
+        let small = a.alloc(layout!(8, 8));
+        let large = a.alloc(layout!(2048, 8));
+        assert!(!small.is_null() && !large.is_null());
+        let stats = a.stats();
+        assert_eq!(stats.bump_used, 8 + 2048);
+        assert_eq!(stats.bump_free, 4096 - 8 - 2048);
+        assert_eq!(stats.largest_alloc, 1024);
+
+        a.dealloc(large, layout!(2048, 8));
+        let stats = a.stats();
+        assert_eq!(stats.bins[8], 2048);
+        assert_eq!(stats.bins.iter().sum::<usize>(), 2048);
+        assert_eq!(stats.largest_alloc, 2048);
+    });
+
+    test_allocators!(@bin, bin_alignment_gap, 3 * 4096, |(start, _, mut a)| {
+        let small = layout!(8, 8);
+        assert!(!a.alloc(small.clone()).is_null());
+
+        // skips the bytes up to the next page boundary
+        let page = a.alloc(layout!(4096, 4096)) as usize;
+        assert!(page != 0 && page % 4096 == 0);
+        let gap = page - (start + 8);
+
+        // which small allocations can use before anything past the page
+        for _ in 0..(gap / 8) {
+            let ptr = a.alloc(small.clone()) as usize;
+            assert!(ptr >= start + 8 && ptr < page, "{:x} is not in the gap", ptr);
+        }
+        assert!(a.alloc(small.clone()) as usize >= page + 4096);
+    });
 }
 
 mod linked_list {
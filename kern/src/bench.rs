@@ -0,0 +1,200 @@
+//! On-device benchmarks for the allocator and file system.
+
+use alloc::alloc::{alloc, dealloc};
+use alloc::vec::Vec;
+use core::alloc::Layout;
+use core::fmt;
+use core::time::Duration;
+
+use fat32::traits::{Dir, FileSystem};
+use pi::timer::current_time;
+use shim::io::{self, Read};
+use shim::ioerr;
+
+use crate::FILESYSTEM;
+
+/// The number of blocks allocated per size by default.
+pub const DEFAULT_ALLOC_COUNT: usize = 1000;
+
+/// The block sizes allocated by the allocator benchmark.
+pub const ALLOC_SIZES: [usize; 4] = [16, 256, 4096, 65536];
+
+/// The file read by default.
+pub const DEFAULT_FILE: &str = "/kernel8.img";
+
+/// The directory listed by default.
+pub const DEFAULT_DIR: &str = "/";
+
+/// The result of timing `count` operations over `bytes` bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Measurement {
+    pub count: u64,
+    pub bytes: u64,
+    pub elapsed: Duration,
+}
+
+impl Measurement {
+    /// Returns the number of operations per second, or `None` if no time
+    /// elapsed.
+    pub fn ops_per_sec(&self) -> Option<u64> {
+        per_sec(self.count, self.elapsed)
+    }
+
+    /// Returns the throughput in bytes per second, or `None` if no time
+    /// elapsed.
+    pub fn bytes_per_sec(&self) -> Option<u64> {
+        per_sec(self.bytes, self.elapsed)
+    }
+}
+
+/// Returns `n / elapsed` in units per second, or `None` if `elapsed` is zero.
+fn per_sec(n: u64, elapsed: Duration) -> Option<u64> {
+    let nanos = elapsed.as_nanos();
+    if nanos == 0 {
+        return None;
+    }
+    Some((n as u128 * 1_000_000_000 / nanos) as u64)
+}
+
+/// Formats a byte rate as megabytes (10^6 bytes) per second with two decimal
+/// places.
+pub struct MegabytesPerSec(pub u64);
+
+impl fmt::Display for MegabytesPerSec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let hundredths = self.0 / 10_000;
+        write!(f, "{}.{:02} MB/s", hundredths / 100, hundredths % 100)
+    }
+}
+
+impl fmt::Display for Measurement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ops in {} us", self.count, self.elapsed.as_micros())?;
+        match self.ops_per_sec() {
+            Some(ops) => write!(f, ", {} ops/s", ops)?,
+            None => write!(f, ", too fast to measure")?,
+        }
+        if self.bytes > 0 {
+            if let Some(rate) = self.bytes_per_sec() {
+                write!(f, ", {}", MegabytesPerSec(rate))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Allocates `count` blocks of `size` bytes, then frees all of them. Each
+/// allocation and each free counts as one operation.
+///
+/// # Errors
+///
+/// Returns an error of kind `Other` if an allocation fails; blocks allocated
+/// up to that point are freed.
+pub fn alloc_free(count: usize, size: usize) -> io::Result<Measurement> {
+    let layout = match Layout::from_size_align(size, 8) {
+        Ok(layout) => layout,
+        Err(_) => return ioerr!(InvalidInput, "bad block size"),
+    };
+    let mut blocks = Vec::with_capacity(count);
+
+    let start = current_time();
+    for _ in 0..count {
+        let ptr = unsafe { alloc(layout) };
+        if ptr.is_null() {
+            break;
+        }
+        blocks.push(ptr);
+    }
+    let allocated = blocks.len();
+    for ptr in blocks {
+        unsafe { dealloc(ptr, layout) };
+    }
+    let elapsed = current_time() - start;
+
+    if allocated < count {
+        return ioerr!(Other, "out of memory");
+    }
+    Ok(Measurement {
+        count: 2 * count as u64,
+        bytes: (count * size) as u64,
+        elapsed,
+    })
+}
+
+/// Reads the file at `path` to the end in `chunk`-sized reads. Each read
+/// counts as one operation.
+pub fn read_file(path: &str, chunk: usize) -> io::Result<Measurement> {
+    let mut buf = Vec::new();
+    buf.resize(chunk, 0u8);
+
+    let start = current_time();
+    let mut file = (&FILESYSTEM).open_file(path)?;
+    let mut count = 0;
+    let mut bytes = 0;
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        count += 1;
+        bytes += n as u64;
+    }
+    let elapsed = current_time() - start;
+
+    Ok(Measurement { count, bytes, elapsed })
+}
+
+/// Lists the directory at `path`. Each entry counts as one operation.
+pub fn list_dir(path: &str) -> io::Result<Measurement> {
+    let start = current_time();
+    let count = (&FILESYSTEM).open_dir(path)?.entries()?.count();
+    let elapsed = current_time() - start;
+
+    Ok(Measurement { count: count as u64, bytes: 0, elapsed })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Measurement, MegabytesPerSec};
+    use alloc::string::ToString;
+    use core::time::Duration;
+
+    #[test]
+    fn rates() {
+        let m = Measurement {
+            count: 500,
+            bytes: 4 * 1_000_000,
+            elapsed: Duration::from_millis(250),
+        };
+        assert_eq!(m.ops_per_sec(), Some(2000));
+        assert_eq!(m.bytes_per_sec(), Some(16_000_000));
+
+        let m = Measurement { count: 3, bytes: 3, elapsed: Duration::from_nanos(1) };
+        assert_eq!(m.ops_per_sec(), Some(3_000_000_000));
+    }
+
+    #[test]
+    fn zero_elapsed() {
+        let m = Measurement { count: 1, bytes: 1, elapsed: Duration::from_secs(0) };
+        assert_eq!(m.ops_per_sec(), None);
+        assert_eq!(m.bytes_per_sec(), None);
+    }
+
+    #[test]
+    fn megabytes_formatting() {
+        assert_eq!(MegabytesPerSec(16_000_000).to_string(), "16.00 MB/s");
+        assert_eq!(MegabytesPerSec(1_234_567).to_string(), "1.23 MB/s");
+        assert_eq!(MegabytesPerSec(50_000).to_string(), "0.05 MB/s");
+        assert_eq!(MegabytesPerSec(0).to_string(), "0.00 MB/s");
+    }
+
+    #[test]
+    fn measurement_display() {
+        let m = Measurement {
+            count: 10,
+            bytes: 2_500_000,
+            elapsed: Duration::from_secs(2),
+        };
+        assert_eq!(m.to_string(), "10 ops in 2000000 us, 5 ops/s, 1.25 MB/s");
+    }
+}
@@ -0,0 +1,177 @@
+//! Timing of the boot sequence.
+//!
+//! `kmain` wraps each slow initialization step in `time`, and calls `finish`
+//! once the kernel is about to hand over to the scheduler, which prints how
+//! long each step took. Steps timed after that, such as remounting the file
+//! system from the shell, are not recorded.
+
+use core::fmt;
+use core::time::Duration;
+
+use pi::timer::current_time;
+
+use crate::console::kprintln;
+use crate::mutex::Mutex;
+
+/// The most phases recorded. Phases past this are dropped.
+pub const MAX_PHASES: usize = 8;
+
+/// A timed step of the boot sequence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Phase {
+    pub name: &'static str,
+    pub duration: Duration,
+}
+
+/// The durations of the boot phases, in the order they ran.
+#[derive(Debug)]
+pub struct PhaseTimes {
+    phases: [Phase; MAX_PHASES],
+    len: usize,
+    finished: bool,
+}
+
+impl PhaseTimes {
+    pub const fn new() -> PhaseTimes {
+        PhaseTimes {
+            phases: [Phase { name: "", duration: Duration::from_secs(0) }; MAX_PHASES],
+            len: 0,
+            finished: false,
+        }
+    }
+
+    /// Records that phase `name` took `duration`. Does nothing once
+    /// `MAX_PHASES` phases were recorded or `finish` was called.
+    pub fn record(&mut self, name: &'static str, duration: Duration) {
+        if self.finished || self.len == MAX_PHASES {
+            return;
+        }
+        self.phases[self.len] = Phase { name, duration };
+        self.len += 1;
+    }
+
+    /// Stops recording phases.
+    pub fn finish(&mut self) {
+        self.finished = true;
+    }
+
+    /// Returns the recorded phases in the order they ran.
+    pub fn phases(&self) -> &[Phase] {
+        &self.phases[..self.len]
+    }
+
+    /// Returns the sum of the recorded phases' durations.
+    pub fn total(&self) -> Duration {
+        self.phases().iter().fold(Duration::from_secs(0), |total, phase| total + phase.duration)
+    }
+}
+
+/// Writes `duration` in milliseconds with three decimals, right-aligned in
+/// ten columns.
+fn write_ms(f: &mut fmt::Formatter, duration: Duration) -> fmt::Result {
+    let us = duration.as_micros();
+    write!(f, "{:>6}.{:03} ms", us / 1000, us % 1000)
+}
+
+impl fmt::Display for PhaseTimes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let total = self.total().as_micros();
+        writeln!(f, "{:<12} {:>13} {:>5}", "phase", "time", "share")?;
+        for phase in self.phases() {
+            write!(f, "{:<12} ", phase.name)?;
+            write_ms(f, phase.duration)?;
+            let share = if total == 0 { 0 } else { phase.duration.as_micros() * 100 / total };
+            writeln!(f, " {:>4}%", share)?;
+        }
+        write!(f, "{:<12} ", "total")?;
+        write_ms(f, self.total())?;
+        writeln!(f)
+    }
+}
+
+static BOOT_TIMES: Mutex<PhaseTimes> = Mutex::new(PhaseTimes::new());
+
+/// Runs `f` and records how long it took as boot phase `name`.
+pub fn time<R, F: FnOnce() -> R>(name: &'static str, f: F) -> R {
+    let start = current_time();
+    let result = f();
+    BOOT_TIMES.lock().record(name, current_time() - start);
+    result
+}
+
+/// Stops recording boot phases and prints how long each one took.
+pub fn finish() {
+    let mut times = BOOT_TIMES.lock();
+    times.finish();
+    kprintln!("boot timing:\n{}", *times);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Phase, PhaseTimes, MAX_PHASES};
+    use alloc::string::ToString;
+    use core::time::Duration;
+
+    fn us(us: u64) -> Duration {
+        Duration::from_micros(us)
+    }
+
+    #[test]
+    fn records_in_order() {
+        let mut times = PhaseTimes::new();
+        assert!(times.phases().is_empty());
+        assert_eq!(times.total(), us(0));
+
+        times.record("allocator", us(120));
+        times.record("emmc", us(250_000));
+        assert_eq!(
+            times.phases(),
+            &[Phase { name: "allocator", duration: us(120) }, Phase { name: "emmc", duration: us(250_000) }]
+        );
+        assert_eq!(times.total(), us(250_120));
+    }
+
+    #[test]
+    fn full_table_drops_phases() {
+        let mut times = PhaseTimes::new();
+        for _ in 0..MAX_PHASES + 2 {
+            times.record("phase", us(1));
+        }
+        assert_eq!(times.phases().len(), MAX_PHASES);
+        assert_eq!(times.total(), us(MAX_PHASES as u64));
+    }
+
+    #[test]
+    fn finish_stops_recording() {
+        let mut times = PhaseTimes::new();
+        times.record("allocator", us(5));
+        times.finish();
+        times.record("remount", us(7));
+        assert_eq!(times.phases(), &[Phase { name: "allocator", duration: us(5) }]);
+    }
+
+    #[test]
+    fn summary() {
+        let mut times = PhaseTimes::new();
+        times.record("allocator", us(1_500));
+        times.record("emmc", us(75_000));
+        times.record("filesystem", us(23_500));
+        assert_eq!(
+            times.to_string(),
+            "phase                 time share\n\
+             allocator         1.500 ms    1%\n\
+             emmc             75.000 ms   75%\n\
+             filesystem       23.500 ms   23%\n\
+             total           100.000 ms\n"
+        );
+    }
+
+    #[test]
+    fn empty_summary() {
+        assert_eq!(
+            PhaseTimes::new().to_string(),
+            "phase                 time share\n\
+             total             0.000 ms\n"
+        );
+    }
+}
@@ -0,0 +1,177 @@
+//! Runtime kernel settings that can be toggled from the shell.
+
+use core::fmt;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use core::time::Duration;
+
+use crate::param::TICK_MS;
+
+static COLORS_ENABLED: AtomicBool = AtomicBool::new(true);
+
+/// Returns `true` if console output may contain ANSI escape sequences.
+pub fn colors_enabled() -> bool {
+    COLORS_ENABLED.load(Ordering::Relaxed)
+}
+
+/// Enables or disables ANSI escape sequences in console output.
+pub fn set_colors_enabled(enabled: bool) {
+    COLORS_ENABLED.store(enabled, Ordering::Relaxed)
+}
+
+/// The shortest scheduling quantum accepted by `set_quantum`, in milliseconds.
+pub const MIN_QUANTUM_MS: u64 = 1;
+
+/// The longest scheduling quantum accepted by `set_quantum`, in milliseconds.
+pub const MAX_QUANTUM_MS: u64 = 1000;
+
+static QUANTUM_MS: AtomicU64 = AtomicU64::new(TICK_MS);
+
+/// Error returned by `set_quantum` for a quantum outside
+/// `MIN_QUANTUM_MS..=MAX_QUANTUM_MS`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuantumOutOfRange(pub u64);
+
+impl fmt::Display for QuantumOutOfRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "quantum must be between {} and {} ms, got {}",
+            MIN_QUANTUM_MS, MAX_QUANTUM_MS, self.0
+        )
+    }
+}
+
+/// Returns the scheduling quantum the timer interrupt is re-armed with.
+pub fn quantum() -> Duration {
+    Duration::from_millis(QUANTUM_MS.load(Ordering::Relaxed))
+}
+
+/// Sets the scheduling quantum to `ms` milliseconds. Takes effect when each
+/// core's timer is next re-armed.
+pub fn set_quantum(ms: u64) -> Result<(), QuantumOutOfRange> {
+    if ms < MIN_QUANTUM_MS || ms > MAX_QUANTUM_MS {
+        return Err(QuantumOutOfRange(ms));
+    }
+    QUANTUM_MS.store(ms, Ordering::Relaxed);
+    Ok(())
+}
+
+/// The highest GPIO pin number accepted by `set_heartbeat`.
+pub const MAX_HEARTBEAT_PIN: u8 = 53;
+
+/// The shortest heartbeat interval accepted by `set_heartbeat`, in
+/// milliseconds.
+pub const MIN_HEARTBEAT_MS: u64 = 10;
+
+/// The longest heartbeat interval accepted by `set_heartbeat`, in
+/// milliseconds.
+pub const MAX_HEARTBEAT_MS: u64 = 10_000;
+
+/// Stored in `HEARTBEAT_PIN` while the heartbeat is disabled.
+const HEARTBEAT_OFF: u64 = core::u64::MAX;
+
+static HEARTBEAT_PIN: AtomicU64 = AtomicU64::new(HEARTBEAT_OFF);
+static HEARTBEAT_MS: AtomicU64 = AtomicU64::new(500);
+
+/// Error returned by `set_heartbeat` for an invalid pin or interval.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BadHeartbeat {
+    Pin(u8),
+    Interval(u64),
+}
+
+impl fmt::Display for BadHeartbeat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BadHeartbeat::Pin(pin) => write!(f, "pin must be at most {}, got {}", MAX_HEARTBEAT_PIN, pin),
+            BadHeartbeat::Interval(ms) => write!(
+                f,
+                "interval must be between {} and {} ms, got {}",
+                MIN_HEARTBEAT_MS, MAX_HEARTBEAT_MS, ms
+            ),
+        }
+    }
+}
+
+/// Returns the GPIO pin toggled by the heartbeat and the time between
+/// toggles, or `None` if the heartbeat is disabled, which is the default.
+pub fn heartbeat() -> Option<(u8, Duration)> {
+    let pin = HEARTBEAT_PIN.load(Ordering::Relaxed);
+    if pin == HEARTBEAT_OFF {
+        return None;
+    }
+    Some((pin as u8, Duration::from_millis(HEARTBEAT_MS.load(Ordering::Relaxed))))
+}
+
+/// Makes the heartbeat toggle GPIO `pin` every `ms` milliseconds.
+pub fn set_heartbeat(pin: u8, ms: u64) -> Result<(), BadHeartbeat> {
+    if pin > MAX_HEARTBEAT_PIN {
+        return Err(BadHeartbeat::Pin(pin));
+    }
+    if ms < MIN_HEARTBEAT_MS || ms > MAX_HEARTBEAT_MS {
+        return Err(BadHeartbeat::Interval(ms));
+    }
+    HEARTBEAT_MS.store(ms, Ordering::Relaxed);
+    HEARTBEAT_PIN.store(pin as u64, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Disables the heartbeat.
+pub fn disable_heartbeat() {
+    HEARTBEAT_PIN.store(HEARTBEAT_OFF, Ordering::Relaxed);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{quantum, set_quantum, QuantumOutOfRange, MAX_QUANTUM_MS, MIN_QUANTUM_MS};
+    use super::{disable_heartbeat, heartbeat, set_heartbeat, BadHeartbeat};
+    use crate::param::TICK;
+    use alloc::string::ToString;
+    use core::time::Duration;
+
+    #[test]
+    fn quantum_range() {
+        assert_eq!(set_quantum(0), Err(QuantumOutOfRange(0)));
+        assert_eq!(set_quantum(MAX_QUANTUM_MS + 1), Err(QuantumOutOfRange(1001)));
+        assert_eq!(
+            QuantumOutOfRange(0).to_string(),
+            "quantum must be between 1 and 1000 ms, got 0"
+        );
+    }
+
+    #[test]
+    fn quantum_is_read_at_rearm() {
+        // The timer handler calls `quantum()` on every tick, so a new value
+        // must be visible there instead of the compile-time `TICK`.
+        assert_eq!(set_quantum(MIN_QUANTUM_MS), Ok(()));
+        assert_eq!(quantum(), Duration::from_millis(1));
+        assert_eq!(set_quantum(MAX_QUANTUM_MS), Ok(()));
+        assert_eq!(quantum(), Duration::from_millis(1000));
+
+        assert_eq!(set_quantum(0), Err(QuantumOutOfRange(0)));
+        assert_eq!(quantum(), Duration::from_millis(1000));
+
+        assert_eq!(set_quantum(TICK.as_millis() as u64), Ok(()));
+        assert_eq!(quantum(), TICK);
+    }
+
+    #[test]
+    fn heartbeat_settings() {
+        assert_eq!(heartbeat(), None);
+
+        assert_eq!(set_heartbeat(54, 500), Err(BadHeartbeat::Pin(54)));
+        assert_eq!(set_heartbeat(42, 9), Err(BadHeartbeat::Interval(9)));
+        assert_eq!(set_heartbeat(42, 10_001), Err(BadHeartbeat::Interval(10_001)));
+        assert_eq!(heartbeat(), None);
+
+        assert_eq!(set_heartbeat(42, 250), Ok(()));
+        assert_eq!(heartbeat(), Some((42, Duration::from_millis(250))));
+        disable_heartbeat();
+        assert_eq!(heartbeat(), None);
+
+        assert_eq!(
+            BadHeartbeat::Interval(9).to_string(),
+            "interval must be between 10 and 10000 ms, got 9"
+        );
+    }
+}
@@ -1,10 +1,16 @@
+use alloc::boxed::Box;
 use core::fmt;
-use pi::uart::MiniUart;
+use core::sync::atomic::{AtomicBool, Ordering};
+use pi::interrupt::{Controller, Interrupt};
+use pi::uart::{self, MiniUart};
 use shim::io;
 use shim::io::Write;
 use shim::io::Read;
 
+use crate::config::colors_enabled;
 use crate::mutex::Mutex;
+use crate::traps::irq::IrqHandlerRegistry;
+use crate::GLOBAL_IRQ;
 
 /// A global singleton allowing read/write access to the console.
 pub struct Console {
@@ -24,15 +30,21 @@ impl Console {
     }
 
     /// Returns a mutable borrow to the inner `MiniUart`, initializing it as
-    /// needed.
+    /// needed. If the receive interrupt handler masked the interrupt because
+    /// the console was locked, drains the FIFO and unmasks it again.
     fn inner(&mut self) -> &mut MiniUart {
         if let None = self.inner {
             self.initialize()
         }
-        return match &mut self.inner {
+        let inner = match &mut self.inner {
             Some(x) => x,
             None => panic!(),
+        };
+        if RX_MASKED.swap(false, Ordering::SeqCst) {
+            inner.drain_rx();
+            inner.enable_rx_interrupt();
         }
+        inner
     }
 
     /// Reads a byte from the UART device, blocking until a byte is available.
@@ -43,10 +55,39 @@ impl Console {
         buf[0]
     }
 
+    /// Reads a byte from the UART device if one is available, without
+    /// blocking.
+    pub fn try_read_byte(&mut self) -> Option<u8> {
+        let inner = self.inner();
+        if inner.has_byte() {
+            Some(inner.read_byte())
+        } else {
+            None
+        }
+    }
+
     /// Writes the byte `byte` to the UART device.
     pub fn write_byte(&mut self, byte: u8) {
         self.inner().write(&[byte]);
     }
+
+    /// Enables the UART's receive interrupt. See `initialize_rx_interrupt`.
+    pub fn enable_rx_interrupt(&mut self) {
+        self.inner().enable_rx_interrupt()
+    }
+
+    /// Moves received bytes from the UART's FIFO into its buffer.
+    pub fn drain_rx(&mut self) {
+        self.inner().drain_rx()
+    }
+
+    /// Runs `pi::uart::loopback_test` on the UART device after letting
+    /// pending output go out, so it is not looped back instead.
+    pub fn uart_loopback_test(&mut self) -> bool {
+        let inner = self.inner();
+        inner.drain();
+        uart::loopback_test(inner, 0xA5)
+    }
 }
 
 impl io::Read for Console {
@@ -61,7 +102,7 @@ impl io::Write for Console {
     }
 
     fn flush(&mut self) -> io::Result<()> {
-        Ok(())
+        self.inner().flush()
     }
 }
 
@@ -71,9 +112,125 @@ impl fmt::Write for Console {
     }
 }
 
+/// Foreground colors understood by ANSI terminals.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Color {
+    Black = 30,
+    Red = 31,
+    Green = 32,
+    Yellow = 33,
+    Blue = 34,
+    Magenta = 35,
+    Cyan = 36,
+    White = 37,
+}
+
+/// An ANSI SGR escape sequence. Formats to nothing when colors were disabled
+/// at the time it was created.
+#[derive(Debug, Clone, Copy)]
+pub struct Style {
+    code: u8,
+    enabled: bool,
+}
+
+impl Style {
+    const fn new(code: u8, enabled: bool) -> Style {
+        Style { code, enabled }
+    }
+}
+
+impl fmt::Display for Style {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.enabled {
+            write!(f, "\x1b[{}m", self.code)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Returns the escape sequence switching the foreground color to `fg`.
+pub fn color(fg: Color) -> Style {
+    Style::new(fg as u8, colors_enabled())
+}
+
+/// Returns the escape sequence restoring the default colors.
+pub fn reset() -> Style {
+    Style::new(0, colors_enabled())
+}
+
+/// An ANSI cursor or screen control sequence. Like `Style`, formats to
+/// nothing when colors were disabled at the time it was created, so dumb
+/// terminals are not sent escape codes.
+#[derive(Debug, Clone, Copy)]
+pub struct Control {
+    kind: ControlKind,
+    enabled: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum ControlKind {
+    ClearScreen,
+    MoveCursor(u16, u16),
+}
+
+impl fmt::Display for Control {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if !self.enabled {
+            return Ok(());
+        }
+        match self.kind {
+            ControlKind::ClearScreen => write!(f, "\x1b[2J\x1b[H"),
+            ControlKind::MoveCursor(row, col) => write!(f, "\x1b[{};{}H", row, col),
+        }
+    }
+}
+
+/// Returns the escape sequence clearing the screen and moving the cursor to
+/// the top-left corner.
+pub fn clear_screen() -> Control {
+    Control { kind: ControlKind::ClearScreen, enabled: colors_enabled() }
+}
+
+/// Returns the escape sequence moving the cursor to `row` and `col`, both
+/// counted from 1.
+#[allow(dead_code)]
+pub fn move_cursor(row: u16, col: u16) -> Control {
+    Control { kind: ControlKind::MoveCursor(row, col), enabled: colors_enabled() }
+}
+
 /// Global `Console` singleton.
 pub static CONSOLE: Mutex<Console> = Mutex::new(Console::new());
 
+/// Set when the receive interrupt was masked because `CONSOLE` was locked,
+/// so that the next user of the console unmasks it.
+static RX_MASKED: AtomicBool = AtomicBool::new(false);
+
+/// Buffers console input from the mini UART's receive interrupt, so bytes
+/// arriving while nobody is reading are kept instead of overflowing the
+/// UART's 8-byte FIFO. Must be called on core 0, which handles global
+/// interrupts.
+pub fn initialize_rx_interrupt() {
+    GLOBAL_IRQ.register(
+        Interrupt::Aux,
+        Box::new(|_| {
+            // The interrupt stays asserted until the FIFO is drained, so if
+            // the console is locked, mask it rather than being re-entered
+            // until the holder is done. The holder unmasks it; see
+            // `Console::inner`.
+            match CONSOLE.try_lock() {
+                Some(mut console) => console.drain_rx(),
+                None => {
+                    uart::mask_rx_interrupt();
+                    RX_MASKED.store(true, Ordering::SeqCst);
+                }
+            }
+        }),
+    );
+    CONSOLE.lock().enable_rx_interrupt();
+    Controller::new().enable(Interrupt::Aux);
+}
+
 /// Internal function called by the `kprint[ln]!` macros.
 #[doc(hidden)]
 pub fn _print(args: fmt::Arguments) {
@@ -97,7 +254,48 @@ pub macro kprintln {
 ($fmt:expr, $($arg:tt)*) => (kprint!(concat!($fmt, "\n"), $($arg)*))
 }
 
+/// Like `kprintln!`, but prints the message in red.
+pub macro kerrorln($($arg:tt)*) {
+_print(format_args!("{}{}{}\n", color(Color::Red), format_args!($($arg)*), reset()))
+}
+
 /// Like `print!`, but for kernel-space.
 pub macro kprint($($arg:tt)*) {
 _print(format_args!($($arg)*))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Color, Control, ControlKind, Style};
+    use alloc::string::ToString;
+
+    #[test]
+    fn escape_sequences() {
+        assert_eq!(Style::new(Color::Red as u8, true).to_string(), "\x1b[31m");
+        assert_eq!(Style::new(Color::Blue as u8, true).to_string(), "\x1b[34m");
+        assert_eq!(Style::new(0, true).to_string(), "\x1b[0m");
+    }
+
+    #[test]
+    fn disabled_has_no_escapes() {
+        let s = format!("{}ls{}", Style::new(Color::Blue as u8, false), Style::new(0, false));
+        assert_eq!(s, "ls");
+        assert!(!s.bytes().any(|b| b == 0x1b));
+    }
+
+    #[test]
+    fn control_sequences() {
+        let clear = Control { kind: ControlKind::ClearScreen, enabled: true };
+        assert_eq!(clear.to_string().as_bytes(), b"\x1b[2J\x1b[H");
+        let cursor = Control { kind: ControlKind::MoveCursor(3, 14), enabled: true };
+        assert_eq!(cursor.to_string().as_bytes(), b"\x1b[3;14H");
+    }
+
+    #[test]
+    fn disabled_control_is_empty() {
+        let clear = Control { kind: ControlKind::ClearScreen, enabled: false };
+        let cursor = Control { kind: ControlKind::MoveCursor(1, 1), enabled: false };
+        assert_eq!(clear.to_string(), "");
+        assert_eq!(cursor.to_string(), "");
+    }
+}
@@ -0,0 +1,224 @@
+//! Per-core scheduling statistics reported by the `coreinfo` command.
+//!
+//! Each core only writes its own entry, from the scheduler and its local
+//! timer handler, so the counters are plain atomics and never take a lock.
+
+use core::fmt;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+
+use crate::param::NCORES;
+use crate::process::Id;
+
+/// Stored in `CoreStat::running` while no process runs on the core.
+const NO_PROCESS: u64 = 0;
+
+/// Stored in `CoreStat::next_tick_us` until the core's timer is first armed.
+const NOT_ARMED: u64 = core::u64::MAX;
+
+/// The statistics of one core.
+pub struct CoreStat {
+    /// The ID of the process running on the core, or `NO_PROCESS`.
+    running: AtomicU64,
+    /// Timer ticks that interrupted a running process.
+    busy_ticks: AtomicU64,
+    /// Timer ticks that found the core waiting for a ready process.
+    idle_ticks: AtomicU64,
+    /// When the local timer fires next, in microseconds of `current_time`.
+    next_tick_us: AtomicU64,
+}
+
+impl CoreStat {
+    pub const fn new() -> CoreStat {
+        CoreStat {
+            running: AtomicU64::new(NO_PROCESS),
+            busy_ticks: AtomicU64::new(0),
+            idle_ticks: AtomicU64::new(0),
+            next_tick_us: AtomicU64::new(NOT_ARMED),
+        }
+    }
+
+    /// Records that the core now runs process `id`, or is idle if `None`.
+    pub fn set_running(&self, id: Option<Id>) {
+        self.running.store(id.unwrap_or(NO_PROCESS), Ordering::Relaxed);
+    }
+
+    /// Records a timer tick at `now` and that the timer was re-armed to fire
+    /// again after `quantum`. The tick counts as busy if a process was
+    /// running when it fired.
+    pub fn record_tick(&self, now: Duration, quantum: Duration) {
+        if self.running.load(Ordering::Relaxed) == NO_PROCESS {
+            self.idle_ticks.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.busy_ticks.fetch_add(1, Ordering::Relaxed);
+        }
+        let next = now + quantum;
+        self.next_tick_us.store(next.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    /// Returns a copy of the current values.
+    pub fn snapshot(&self) -> CoreSnapshot {
+        let running = self.running.load(Ordering::Relaxed);
+        let next_tick = self.next_tick_us.load(Ordering::Relaxed);
+        CoreSnapshot {
+            running: if running == NO_PROCESS { None } else { Some(running) },
+            busy_ticks: self.busy_ticks.load(Ordering::Relaxed),
+            idle_ticks: self.idle_ticks.load(Ordering::Relaxed),
+            next_tick: if next_tick == NOT_ARMED { None } else { Some(Duration::from_micros(next_tick)) },
+        }
+    }
+}
+
+/// The values of a `CoreStat` at one point in time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CoreSnapshot {
+    pub running: Option<Id>,
+    pub busy_ticks: u64,
+    pub idle_ticks: u64,
+    /// When the local timer fires next, or `None` if it was never armed.
+    pub next_tick: Option<Duration>,
+}
+
+impl CoreSnapshot {
+    /// Returns the percentage of ticks that found the core busy, or `None`
+    /// if no tick has been recorded.
+    pub fn busy_percent(&self) -> Option<u64> {
+        let total = self.busy_ticks + self.idle_ticks;
+        if total == 0 {
+            None
+        } else {
+            Some(self.busy_ticks * 100 / total)
+        }
+    }
+
+    /// Returns a `Display` of this snapshot with the next tick relative to
+    /// `now`.
+    pub fn at(&self, now: Duration) -> CoreReport {
+        CoreReport { snapshot: *self, now }
+    }
+}
+
+/// A `CoreSnapshot` formatted relative to a point in time, as printed by
+/// `coreinfo`.
+pub struct CoreReport {
+    snapshot: CoreSnapshot,
+    now: Duration,
+}
+
+impl fmt::Display for CoreReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = &self.snapshot;
+        match s.running {
+            Some(id) => write!(f, "running pid {}", id)?,
+            None => write!(f, "idle")?,
+        }
+        match s.busy_percent() {
+            Some(percent) => write!(f, ", busy {}% ({}/{} ticks)", percent, s.busy_ticks, s.busy_ticks + s.idle_ticks)?,
+            None => write!(f, ", no ticks yet")?,
+        }
+        match s.next_tick {
+            Some(next) => match next.checked_sub(self.now) {
+                Some(wait) => write!(f, ", next tick in {} us", wait.as_micros()),
+                None => write!(f, ", next tick overdue"),
+            },
+            None => write!(f, ", timer not armed"),
+        }
+    }
+}
+
+static CORE_STATS: [CoreStat; NCORES] = [
+    CoreStat::new(),
+    CoreStat::new(),
+    CoreStat::new(),
+    CoreStat::new(),
+];
+
+/// Returns the statistics of the calling core.
+pub fn current() -> &'static CoreStat {
+    &CORE_STATS[aarch64::affinity()]
+}
+
+/// Returns a snapshot of every core's statistics.
+pub fn all() -> [CoreSnapshot; NCORES] {
+    let mut snapshots = [CORE_STATS[0].snapshot(); NCORES];
+    for (snapshot, stat) in snapshots.iter_mut().zip(CORE_STATS.iter()) {
+        *snapshot = stat.snapshot();
+    }
+    snapshots
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CoreSnapshot, CoreStat};
+    use alloc::string::ToString;
+    use core::time::Duration;
+
+    const QUANTUM: Duration = Duration::from_millis(10);
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn fresh_core() {
+        let stat = CoreStat::new();
+        let snapshot = stat.snapshot();
+        assert_eq!(
+            snapshot,
+            CoreSnapshot { running: None, busy_ticks: 0, idle_ticks: 0, next_tick: None }
+        );
+        assert_eq!(snapshot.busy_percent(), None);
+        assert_eq!(snapshot.at(ms(0)).to_string(), "idle, no ticks yet, timer not armed");
+    }
+
+    #[test]
+    fn ticks_follow_running_process() {
+        let stat = CoreStat::new();
+        // Idle for two ticks, then process 3 runs for three.
+        stat.record_tick(ms(10), QUANTUM);
+        stat.record_tick(ms(20), QUANTUM);
+        stat.set_running(Some(3));
+        stat.record_tick(ms(30), QUANTUM);
+        stat.record_tick(ms(40), QUANTUM);
+        stat.record_tick(ms(50), QUANTUM);
+
+        let snapshot = stat.snapshot();
+        assert_eq!(snapshot.running, Some(3));
+        assert_eq!((snapshot.busy_ticks, snapshot.idle_ticks), (3, 2));
+        assert_eq!(snapshot.next_tick, Some(ms(60)));
+
+        stat.set_running(None);
+        stat.record_tick(ms(60), QUANTUM);
+        assert_eq!(stat.snapshot().idle_ticks, 3);
+        assert_eq!(stat.snapshot().running, None);
+    }
+
+    #[test]
+    fn busy_ratio() {
+        let snapshot = |busy, idle| CoreSnapshot { running: None, busy_ticks: busy, idle_ticks: idle, next_tick: None };
+        assert_eq!(snapshot(0, 4).busy_percent(), Some(0));
+        assert_eq!(snapshot(4, 0).busy_percent(), Some(100));
+        assert_eq!(snapshot(1, 3).busy_percent(), Some(25));
+        assert_eq!(snapshot(2, 1).busy_percent(), Some(66));
+    }
+
+    #[test]
+    fn report() {
+        let stat = CoreStat::new();
+        stat.set_running(Some(2));
+        stat.record_tick(ms(100), QUANTUM);
+        stat.set_running(None);
+        stat.record_tick(ms(110), QUANTUM);
+        stat.set_running(Some(2));
+
+        let snapshot = stat.snapshot();
+        assert_eq!(
+            snapshot.at(ms(115)).to_string(),
+            "running pid 2, busy 50% (1/2 ticks), next tick in 5000 us"
+        );
+        assert_eq!(
+            snapshot.at(ms(125)).to_string(),
+            "running pid 2, busy 50% (1/2 ticks), next tick overdue"
+        );
+    }
+}
@@ -0,0 +1,140 @@
+use core::fmt;
+
+use aarch64::{MIDR_EL1, CNTFRQ_EL0};
+
+use crate::mutex::Mutex;
+use crate::param::NCORES;
+
+/// The fields of the Main ID Register (`MIDR_EL1`) identifying a core.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Midr {
+    pub implementer: u8,
+    pub variant: u8,
+    pub architecture: u8,
+    pub part: u16,
+    pub revision: u8,
+}
+
+impl Midr {
+    /// Splits a raw `MIDR_EL1` value into its fields.
+    pub fn decode(raw: u64) -> Midr {
+        Midr {
+            implementer: MIDR_EL1::get_value(raw, MIDR_EL1::Implementer) as u8,
+            variant: MIDR_EL1::get_value(raw, MIDR_EL1::Variant) as u8,
+            architecture: MIDR_EL1::get_value(raw, MIDR_EL1::Architecture) as u8,
+            part: MIDR_EL1::get_value(raw, MIDR_EL1::PartNum) as u16,
+            revision: MIDR_EL1::get_value(raw, MIDR_EL1::Revision) as u8,
+        }
+    }
+
+    /// Returns the name of the implementer, if known.
+    pub fn implementer_name(&self) -> Option<&'static str> {
+        match self.implementer {
+            0x41 => Some("ARM"),
+            0x42 => Some("Broadcom"),
+            _ => None,
+        }
+    }
+
+    /// Returns the name of the part, if known.
+    pub fn part_name(&self) -> Option<&'static str> {
+        match (self.implementer, self.part) {
+            (0x41, 0xD03) => Some("Cortex-A53"),
+            (0x41, 0xD08) => Some("Cortex-A72"),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Midr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {} r{}p{}",
+            self.implementer_name().unwrap_or("unknown implementer"),
+            self.part_name().unwrap_or("unknown part"),
+            self.variant,
+            self.revision
+        )
+    }
+}
+
+/// Identification and configuration of a single core.
+#[derive(Debug, Clone, Copy)]
+pub struct CpuInfo {
+    pub core: usize,
+    pub midr: Midr,
+    pub exception_level: u8,
+    pub timer_frequency: u64,
+}
+
+impl CpuInfo {
+    /// Reads the identification registers of the core executing this
+    /// function. Must be called at EL1 or above.
+    pub fn current() -> CpuInfo {
+        unsafe {
+            CpuInfo {
+                core: aarch64::affinity(),
+                midr: Midr::decode(MIDR_EL1.get()),
+                exception_level: aarch64::current_el(),
+                timer_frequency: CNTFRQ_EL0.get(),
+            }
+        }
+    }
+}
+
+impl fmt::Display for CpuInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "core {}: {}, EL{}, timer {} Hz",
+            self.core, self.midr, self.exception_level, self.timer_frequency
+        )
+    }
+}
+
+static CPU_INFO: Mutex<[Option<CpuInfo>; NCORES]> = Mutex::new([None; NCORES]);
+
+/// Records the `CpuInfo` of the calling core so that it can be reported from
+/// any core later. Each core calls this once during start up.
+pub fn record() {
+    let info = CpuInfo::current();
+    CPU_INFO.lock()[info.core] = Some(info);
+}
+
+/// Returns the `CpuInfo` recorded by each core, or `None` for cores that have
+/// not started yet.
+pub fn all() -> [Option<CpuInfo>; NCORES] {
+    *CPU_INFO.lock()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Midr;
+
+    #[test]
+    fn decode_cortex_a72() {
+        // The value reported by the Raspberry Pi 4's cores.
+        let midr = Midr::decode(0x410F_D083);
+        assert_eq!(midr, Midr {
+            implementer: 0x41,
+            variant: 0,
+            architecture: 0xF,
+            part: 0xD08,
+            revision: 3,
+        });
+        assert_eq!(midr.implementer_name(), Some("ARM"));
+        assert_eq!(midr.part_name(), Some("Cortex-A72"));
+    }
+
+    #[test]
+    fn decode_fields() {
+        let midr = Midr::decode(0xFFFF_FFFF_42A5_C3D7);
+        assert_eq!(midr.implementer, 0x42);
+        assert_eq!(midr.variant, 0xA);
+        assert_eq!(midr.architecture, 0x5);
+        assert_eq!(midr.part, 0xC3D);
+        assert_eq!(midr.revision, 0x7);
+        assert_eq!(midr.part_name(), None);
+    }
+}
@@ -0,0 +1,231 @@
+//! Raw sector access for the shell's `dd` command, which reads and writes the
+//! SD card below the file system.
+
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+
+use fat32::traits::BlockDevice;
+use shim::io;
+
+/// The size of a sector of the SD card.
+pub const SECTOR_SIZE: usize = 512;
+
+/// The most sectors one `dd read` dumps, so that a typo does not flood the
+/// console for minutes.
+pub const MAX_COUNT: u64 = 16;
+
+/// Why a `dd` command line was refused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DdError {
+    /// The arguments were not `read <sector> <count>` or
+    /// `write <sector> <hex>`.
+    Usage,
+    /// `read` asked for more than `MAX_COUNT` sectors.
+    TooMany(u64),
+    /// `write` was given more bytes than fit in a sector.
+    TooLong(usize),
+}
+
+impl fmt::Display for DdError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DdError::Usage => write!(f, "usage: dd read <sector> <count> | dd write <sector> <hex>"),
+            DdError::TooMany(count) => write!(f, "count must be at most {}, got {}", MAX_COUNT, count),
+            DdError::TooLong(len) => write!(f, "at most {} bytes fit in a sector, got {}", SECTOR_SIZE, len),
+        }
+    }
+}
+
+/// A parsed `dd` command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Dd {
+    /// Dump `count` sectors starting at `sector`.
+    Read { sector: u64, count: u64 },
+    /// Overwrite the start of `sector` with `data`.
+    Write { sector: u64, data: Vec<u8> },
+}
+
+/// Parses a number in decimal or with a `0x` prefix in hexadecimal.
+fn parse_number(s: &str) -> Option<u64> {
+    if s.starts_with("0x") {
+        u64::from_str_radix(&s[2..], 16).ok()
+    } else {
+        u64::from_str_radix(s, 10).ok()
+    }
+}
+
+/// Parses a string of hex digit pairs, such as `deadbeef`.
+fn parse_bytes(s: &str) -> Option<Vec<u8>> {
+    let s = s.trim_start_matches("0x");
+    if s.is_empty() || s.len() % 2 != 0 || !s.is_ascii() {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).ok())
+        .collect()
+}
+
+/// Parses the arguments of `dd read <sector> <count>` and
+/// `dd write <sector> <hex>`.
+pub fn parse_args(args: &[&str]) -> Result<Dd, DdError> {
+    if args.len() != 3 {
+        return Err(DdError::Usage);
+    }
+    let sector = parse_number(args[1]).ok_or(DdError::Usage)?;
+
+    match args[0] {
+        "read" => {
+            let count = parse_number(args[2]).ok_or(DdError::Usage)?;
+            if count == 0 {
+                return Err(DdError::Usage);
+            }
+            if count > MAX_COUNT {
+                return Err(DdError::TooMany(count));
+            }
+            Ok(Dd::Read { sector, count })
+        }
+        "write" => {
+            let data = parse_bytes(args[2]).ok_or(DdError::Usage)?;
+            if data.len() > SECTOR_SIZE {
+                return Err(DdError::TooLong(data.len()));
+            }
+            Ok(Dd::Write { sector, data })
+        }
+        _ => Err(DdError::Usage),
+    }
+}
+
+/// Reads `count` sectors starting at `sector` from `device`.
+pub fn read<D: BlockDevice>(mut device: D, sector: u64, count: u64) -> io::Result<Vec<u8>> {
+    let mut bytes = vec![0u8; count as usize * SECTOR_SIZE];
+    for (i, chunk) in bytes.chunks_mut(SECTOR_SIZE).enumerate() {
+        device.read_sector(sector + i as u64, chunk)?;
+    }
+    Ok(bytes)
+}
+
+/// Overwrites the first `data.len()` bytes of `sector` on `device`, keeping
+/// the rest of the sector.
+///
+/// # Panics
+///
+/// Panics if `data` is longer than a sector.
+pub fn write<D: BlockDevice>(mut device: D, sector: u64, data: &[u8]) -> io::Result<()> {
+    let mut buf = [0u8; SECTOR_SIZE];
+    if data.len() < SECTOR_SIZE {
+        device.read_sector(sector, &mut buf)?;
+    }
+    buf[..data.len()].copy_from_slice(data);
+    device.write_sector(sector, &buf)?;
+    Ok(())
+}
+
+/// A hex dump of bytes read from `sector` onwards, 16 bytes per line, with
+/// each line prefixed by its byte offset on the device.
+pub struct Hexdump<'a> {
+    pub sector: u64,
+    pub bytes: &'a [u8],
+}
+
+impl fmt::Display for Hexdump<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let start = self.sector * SECTOR_SIZE as u64;
+        for (i, line) in self.bytes.chunks(16).enumerate() {
+            write!(f, "{:08x}:", start + 16 * i as u64)?;
+            for byte in line {
+                write!(f, " {:02x}", byte)?;
+            }
+            write!(f, "{:width$}  |", "", width = 3 * (16 - line.len()))?;
+            for &byte in line {
+                let c = if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '.' };
+                write!(f, "{}", c)?;
+            }
+            writeln!(f, "|")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_args, read, write, Dd, DdError, Hexdump, MAX_COUNT, SECTOR_SIZE};
+    use alloc::string::ToString;
+    use alloc::vec::Vec;
+    use shim::io::Cursor;
+
+    /// An in-memory device of four sectors, each filled with its number.
+    fn device() -> Cursor<Vec<u8>> {
+        let mut bytes = Vec::new();
+        for sector in 0..4u8 {
+            bytes.extend_from_slice(&[sector; SECTOR_SIZE]);
+        }
+        Cursor::new(bytes)
+    }
+
+    #[test]
+    fn parse_read() {
+        assert_eq!(parse_args(&["read", "2", "1"]), Ok(Dd::Read { sector: 2, count: 1 }));
+        assert_eq!(parse_args(&["read", "0x10", "0x4"]), Ok(Dd::Read { sector: 16, count: 4 }));
+        assert_eq!(parse_args(&["read", "0", "17"]), Err(DdError::TooMany(17)));
+        assert_eq!(parse_args(&["read", "0", "0"]), Err(DdError::Usage));
+        assert_eq!(parse_args(&["read", "x", "1"]), Err(DdError::Usage));
+        assert_eq!(parse_args(&["read", "1"]), Err(DdError::Usage));
+        assert_eq!(parse_args(&["erase", "1", "1"]), Err(DdError::Usage));
+        assert!(MAX_COUNT < 17);
+    }
+
+    #[test]
+    fn parse_write() {
+        assert_eq!(
+            parse_args(&["write", "3", "deadBEEF"]),
+            Ok(Dd::Write { sector: 3, data: vec![0xde, 0xad, 0xbe, 0xef] })
+        );
+        assert_eq!(parse_args(&["write", "3", "0x55aa"]), Ok(Dd::Write { sector: 3, data: vec![0x55, 0xaa] }));
+        assert_eq!(parse_args(&["write", "3", "abc"]), Err(DdError::Usage));
+        assert_eq!(parse_args(&["write", "3", "zz"]), Err(DdError::Usage));
+        assert_eq!(parse_args(&["write", "3", ""]), Err(DdError::Usage));
+
+        let long = "00".repeat(SECTOR_SIZE + 1);
+        assert_eq!(parse_args(&["write", "0", &long]), Err(DdError::TooLong(SECTOR_SIZE + 1)));
+    }
+
+    #[test]
+    fn raw_read() {
+        let mut device = device();
+        let bytes = read(&mut device, 1, 2).expect("read sectors 1 and 2");
+        assert_eq!(bytes.len(), 2 * SECTOR_SIZE);
+        assert!(bytes[..SECTOR_SIZE].iter().all(|&b| b == 1));
+        assert!(bytes[SECTOR_SIZE..].iter().all(|&b| b == 2));
+
+        assert!(read(&mut device, 3, 2).is_err());
+    }
+
+    #[test]
+    fn raw_write_round_trips() {
+        let mut device = device();
+        write(&mut device, 2, &[0xde, 0xad]).expect("write sector 2");
+
+        let bytes = read(&mut device, 1, 3).expect("read back");
+        assert!(bytes[..SECTOR_SIZE].iter().all(|&b| b == 1));
+        assert_eq!(&bytes[SECTOR_SIZE..SECTOR_SIZE + 3], &[0xde, 0xad, 2]);
+        assert!(bytes[SECTOR_SIZE + 2..2 * SECTOR_SIZE].iter().all(|&b| b == 2));
+        assert!(bytes[2 * SECTOR_SIZE..].iter().all(|&b| b == 3));
+
+        let full = [0x7fu8; SECTOR_SIZE];
+        write(&mut device, 0, &full).expect("write sector 0");
+        assert_eq!(read(&mut device, 0, 1).unwrap(), &full[..]);
+    }
+
+    #[test]
+    fn hexdump() {
+        let bytes = b"Hello, sector!\x00\xff\x01";
+        let dump = Hexdump { sector: 1, bytes: &bytes[..] }.to_string();
+        assert_eq!(
+            dump,
+            "00000200: 48 65 6c 6c 6f 2c 20 73 65 63 74 6f 72 21 00 ff  |Hello, sector!..|\n\
+             00000210: 01                                               |.|\n"
+        );
+    }
+}
@@ -0,0 +1,236 @@
+//! Lock-order debugging for `Mutex`, enabled with the `deadlock-detection`
+//! feature.
+//!
+//! Every core records the locks it holds and the lock it is spinning on. A
+//! lock is identified by its address. When a core fails to take a lock it
+//! follows the wait-for chain, lock to owner to the lock that owner waits
+//! for, and panics if the chain leads back to itself.
+
+use core::fmt;
+
+use crate::param::NCORES;
+
+/// A wait-for chain: `links[i].0` is a core spinning on lock `links[i].1`,
+/// which is held by the core in `links[i + 1]`, and the last lock is held by
+/// the first core.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Chain {
+    links: [(usize, usize); NCORES],
+    len: usize,
+}
+
+impl Chain {
+    /// Returns the `(core, lock)` pairs of the chain.
+    pub fn links(&self) -> &[(usize, usize)] {
+        &self.links[..self.len]
+    }
+}
+
+impl fmt::Display for Chain {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let links = self.links();
+        for (i, &(core, lock)) in links.iter().enumerate() {
+            let owner = links[(i + 1) % links.len()].0;
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "core {} waits for lock {:#x} held by core {}", core, lock, owner)?;
+        }
+        Ok(())
+    }
+}
+
+/// Follows the wait-for graph from core `start` and returns the chain if it
+/// leads back to `start`. `waiting_on` returns the lock a core is spinning
+/// on and `owner_of` the core holding a lock.
+///
+/// Each core waits for at most one lock and each lock has at most one owner,
+/// so the chain is a path and visits at most `NCORES` cores.
+pub fn find_cycle<W, O>(start: usize, waiting_on: W, owner_of: O) -> Option<Chain>
+    where
+        W: Fn(usize) -> Option<usize>,
+        O: Fn(usize) -> Option<usize>,
+{
+    let mut chain = Chain { links: [(0, 0); NCORES], len: 0 };
+    let mut core = start;
+    while chain.len < NCORES {
+        let lock = waiting_on(core)?;
+        chain.links[chain.len] = (core, lock);
+        chain.len += 1;
+
+        core = owner_of(lock)?;
+        if core == start {
+            return Some(chain);
+        }
+    }
+    None
+}
+
+#[cfg(feature = "deadlock-detection")]
+pub use self::registry::{acquired, released, stop_waiting, wait_for};
+
+#[cfg(feature = "deadlock-detection")]
+mod registry {
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::find_cycle;
+    use crate::param::NCORES;
+
+    /// The most locks tracked per core. Locks taken beyond this are not
+    /// recorded, so cycles through them go undetected.
+    const MAX_HELD: usize = 8;
+
+    /// Marks an empty slot. No `Mutex` lives at address 0.
+    const NONE: usize = 0;
+
+    struct LockState {
+        waiting: AtomicUsize,
+        held: [AtomicUsize; MAX_HELD],
+    }
+
+    impl LockState {
+        const fn new() -> LockState {
+            LockState {
+                waiting: AtomicUsize::new(NONE),
+                held: [
+                    AtomicUsize::new(NONE),
+                    AtomicUsize::new(NONE),
+                    AtomicUsize::new(NONE),
+                    AtomicUsize::new(NONE),
+                    AtomicUsize::new(NONE),
+                    AtomicUsize::new(NONE),
+                    AtomicUsize::new(NONE),
+                    AtomicUsize::new(NONE),
+                ],
+            }
+        }
+    }
+
+    static LOCK_STATE: [LockState; NCORES] = [
+        LockState::new(),
+        LockState::new(),
+        LockState::new(),
+        LockState::new(),
+    ];
+
+    /// Records that `core` holds `lock`.
+    pub fn acquired(lock: usize, core: usize) {
+        for slot in LOCK_STATE[core].held.iter() {
+            if slot.compare_and_swap(NONE, lock, Ordering::AcqRel) == NONE {
+                return;
+            }
+        }
+    }
+
+    /// Records that `core` released `lock`.
+    pub fn released(lock: usize, core: usize) {
+        for slot in LOCK_STATE[core].held.iter() {
+            if slot.compare_and_swap(lock, NONE, Ordering::AcqRel) == lock {
+                return;
+            }
+        }
+    }
+
+    /// Records that `core` is spinning on `lock` and panics with the wait-for
+    /// chain if that closes a cycle.
+    pub fn wait_for(lock: usize, core: usize) {
+        LOCK_STATE[core].waiting.store(lock, Ordering::Release);
+
+        if let Some(chain) = find_cycle(core, waiting_on, owner_of) {
+            stop_waiting(core);
+            panic!("deadlock: {}", chain);
+        }
+    }
+
+    /// Records that `core` is no longer spinning on a lock.
+    pub fn stop_waiting(core: usize) {
+        LOCK_STATE[core].waiting.store(NONE, Ordering::Release);
+    }
+
+    fn waiting_on(core: usize) -> Option<usize> {
+        match LOCK_STATE[core].waiting.load(Ordering::Acquire) {
+            NONE => None,
+            lock => Some(lock),
+        }
+    }
+
+    fn owner_of(lock: usize) -> Option<usize> {
+        LOCK_STATE.iter().position(|state| {
+            state.held.iter().any(|slot| slot.load(Ordering::Acquire) == lock)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::find_cycle;
+    use alloc::string::ToString;
+
+    /// A synthetic wait-for graph. `waits[core]` is the lock the core spins
+    /// on and `owners[lock]` the core holding the lock.
+    fn cycle(waits: &[Option<usize>], owners: &[Option<usize>], start: usize) -> Option<super::Chain> {
+        find_cycle(start, |core| waits[core], |lock| owners[lock])
+    }
+
+    #[test]
+    fn no_waiting_no_cycle() {
+        let waits = [None, None, None, None];
+        let owners = [Some(0), Some(1)];
+        assert_eq!(cycle(&waits, &owners, 0), None);
+    }
+
+    #[test]
+    fn chain_without_cycle() {
+        // Core 0 waits for lock 0 held by core 1, which waits for lock 1
+        // held by core 2, which is running.
+        let waits = [Some(0), Some(1), None, None];
+        let owners = [Some(1), Some(2)];
+        assert_eq!(cycle(&waits, &owners, 0), None);
+
+        // Core 2 waits for a free lock.
+        let waits = [Some(0), Some(1), Some(2), None];
+        let owners = [Some(1), Some(2), None];
+        assert_eq!(cycle(&waits, &owners, 0), None);
+    }
+
+    #[test]
+    fn two_core_cycle() {
+        let waits = [Some(1), Some(0), None, None];
+        let owners = [Some(0), Some(1)];
+        let chain = cycle(&waits, &owners, 0).unwrap();
+        assert_eq!(chain.links(), &[(0, 1), (1, 0)]);
+        assert_eq!(
+            chain.to_string(),
+            "core 0 waits for lock 0x1 held by core 1, core 1 waits for lock 0x0 held by core 0"
+        );
+    }
+
+    #[test]
+    fn self_deadlock() {
+        // Core 3 takes lock 2 again while holding it.
+        let waits = [None, None, None, Some(2)];
+        let owners = [None, None, Some(3)];
+        assert_eq!(cycle(&waits, &owners, 3).unwrap().links(), &[(3, 2)]);
+    }
+
+    #[test]
+    fn four_core_cycle() {
+        let waits = [Some(0), Some(1), Some(2), Some(3)];
+        let owners = [Some(1), Some(2), Some(3), Some(0)];
+        for start in 0..4 {
+            let chain = cycle(&waits, &owners, start).unwrap();
+            assert_eq!(chain.links().len(), 4);
+            assert_eq!(chain.links()[0], (start, start));
+        }
+    }
+
+    #[test]
+    fn cycle_elsewhere_is_not_reported() {
+        // Cores 1 and 2 are deadlocked; core 0 waits on them but is not
+        // part of the cycle, so only they report it.
+        let waits = [Some(0), Some(1), Some(2), None];
+        let owners = [Some(1), Some(2), Some(1)];
+        assert_eq!(cycle(&waits, &owners, 0), None);
+        assert!(cycle(&waits, &owners, 1).is_some());
+    }
+}
@@ -1,26 +1,30 @@
 pub use fat32::traits;
-use fat32::vfat::{Dir, Entry, File, VFat, VFatHandle};
+use fat32::vfat::{BackupBootCheck, CacheStats, Dir, Entry, File, VFat, VFatHandle};
 use shim::io;
-use shim::newioerr;
+use shim::{ioerr, newioerr};
 use shim::path::Path;
 
-use alloc::rc::Rc;
 use core::fmt::{self, Debug};
 
+use crate::boottime;
+use crate::console::kerrorln;
 use crate::mutex::Mutex;
+use crate::shared::Shared;
+use crate::ALLOCATOR;
 
 use self::sd::Sd;
 
 pub mod sd;
 
+/// A handle to the mounted file system. The file system is mounted before the
+/// MMU is enabled, so the handle is a `Shared` rather than an `Arc`.
 #[derive(Clone)]
-pub struct PiVFatHandle(Rc<Mutex<VFat<Self>>>);
+pub struct PiVFatHandle(Shared<Mutex<VFat<Self>>>);
 
-// These impls are *unsound*. We should use `Arc` instead of `Rc` to implement
-// `Sync` and `Send` trait for `PiVFatHandle`. However, `Arc` uses atomic memory
-// access, which requires MMU to be initialized on ARM architecture. Since we
-// have enabled only one core of the board, these unsound impls will not cause
-// any immediate harm for now. We will fix this in the future.
+// `VFat` keeps its device as a `Box<dyn BlockDevice>`, which is not `Send`, so
+// these cannot be derived. The device is always the SD card, whose driver
+// serializes access through `EMMC_CONT`, and the `VFat` is only reached
+// through its mutex.
 unsafe impl Send for PiVFatHandle {}
 unsafe impl Sync for PiVFatHandle {}
 
@@ -32,7 +36,7 @@ impl Debug for PiVFatHandle {
 
 impl VFatHandle for PiVFatHandle {
     fn new(val: VFat<PiVFatHandle>) -> Self {
-        PiVFatHandle(Rc::new(Mutex::new(val)))
+        PiVFatHandle(Shared::new(Mutex::new(val)))
     }
 
     fn lock<R>(&self, f: impl FnOnce(&mut VFat<PiVFatHandle>) -> R) -> R {
@@ -43,26 +47,137 @@ impl VFatHandle for PiVFatHandle {
 pub struct FileSystem(Mutex<Option<PiVFatHandle>>);
 
 impl FileSystem {
-    /// Returns an uninitialized `FileSystem`.
+    /// Returns an unmounted `FileSystem`.
     ///
-    /// The file system must be initialized by calling `initialize()` before the
-    /// first memory allocation. Failure to do will result in panics.
+    /// The file system must be mounted by calling `try_initialize()`. Until
+    /// then, every file system operation fails with an error of kind
+    /// `NotConnected`.
     pub const fn uninitialized() -> Self {
         FileSystem(Mutex::new(None))
     }
 
-    /// Initializes the file system.
-    /// The caller should assure that the method is invoked only once during the
-    /// kernel initialization.
+    /// Mounts the first FAT32 partition of the SD card, read-only. Call
+    /// `set_writable(true)` to allow writes.
+    ///
+    /// On failure the file system is left unmounted, so the call can be
+    /// retried later (e.g. after inserting a card). A backup boot sector that
+    /// cannot be read or differs from the boot sector is reported as a
+    /// warning, but does not stop the mount.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `AlreadyExists` if the file system is already
+    /// mounted. Errors from the SD card controller are returned as is, and an
+    /// error of kind `InvalidData` is returned if the card does not hold a
+    /// valid FAT32 file system.
     ///
     /// # Panics
     ///
-    /// Panics if the underlying disk or file system failed to initialize.
-    pub unsafe fn initialize(&self) {
-        let sd_device = Sd::new().expect("No SD card found");
+    /// Panics if the allocator has not been initialized yet, since building
+    /// the file system allocates.
+    pub unsafe fn try_initialize(&self) -> io::Result<()> {
+        if !ALLOCATOR.is_initialized() {
+            panic!("allocator must be initialized before filesystem");
+        }
+
+        let mut guard = self.0.lock();
+        if guard.is_some() {
+            return ioerr!(AlreadyExists, "filesystem already mounted");
+        }
+
+        let sd_device = boottime::time("emmc", || Sd::new())?;
+        let handle = boottime::time("filesystem", || VFat::<PiVFatHandle>::from_readonly(sd_device)).map_err(|e| match e {
+            fat32::vfat::Error::Io(e) => e,
+            _ => newioerr!(InvalidData, "no FAT32 filesystem found on SD card"),
+        })?;
+        match handle.lock(|vfat| vfat.backup_boot_check()) {
+            BackupBootCheck::Unreadable => kerrorln!("warning: backup boot sector unreadable; run fsck"),
+            BackupBootCheck::Mismatch => kerrorln!("warning: backup boot sector differs from the boot sector; run fsck"),
+            BackupBootCheck::Match | BackupBootCheck::NoBackup => {}
+        }
+        *guard = Some(handle);
+        Ok(())
+    }
+
+    /// Returns `true` if a file system is mounted.
+    pub fn is_mounted(&self) -> bool {
+        self.0.lock().is_some()
+    }
+
+    /// Returns `true` if a file system is mounted and accepts writes.
+    pub fn is_writable(&self) -> bool {
+        self.handle().map(|handle| handle.lock(|vfat| vfat.is_writable())).unwrap_or(false)
+    }
+
+    /// Allows or forbids writes to the mounted file system.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `NotConnected` if no file system is mounted.
+    pub fn set_writable(&self, writable: bool) -> io::Result<()> {
+        self.handle()?.lock(|vfat| vfat.set_writable(writable));
+        Ok(())
+    }
+
+    /// Returns the result of comparing the boot sector with its backup when
+    /// the file system was mounted.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `NotConnected` if no file system is mounted.
+    pub fn backup_boot_check(&self) -> io::Result<BackupBootCheck> {
+        Ok(self.handle()?.lock(|vfat| vfat.backup_boot_check()))
+    }
+
+    /// Returns `true` if every copy of the FAT matches the first. See
+    /// `VFat::verify_fats`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `NotConnected` if no file system is mounted,
+    /// and any error from reading the SD card.
+    pub fn verify_fats(&self) -> io::Result<bool> {
+        self.handle()?.lock(|vfat| vfat.verify_fats())
+    }
+
+    /// Returns the sector cache's hit and miss counts. See
+    /// `VFat::cache_stats`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `NotConnected` if no file system is mounted.
+    pub fn cache_stats(&self) -> io::Result<CacheStats> {
+        Ok(self.handle()?.lock(|vfat| vfat.cache_stats()))
+    }
 
-        let handle = VFat::<PiVFatHandle>::from(sd_device).expect("Could not initialize filesystem from SD device");
-        *self.0.lock() = Some(handle);
+    /// Drops the mounted file system's cached sectors, so that it sees raw
+    /// writes to the SD card such as `dd write`. Does nothing if no file
+    /// system is mounted.
+    pub fn invalidate_cache(&self) {
+        if let Ok(handle) = self.handle() {
+            handle.lock(|vfat| vfat.invalidate_cache());
+        }
+    }
+
+    /// Writes any data buffered for the file system to the SD card.
+    ///
+    /// `VFat` currently writes every sector straight to the card, so there is
+    /// nothing to write out yet; callers that must not lose data, such as
+    /// `poweroff`, call this anyway. Succeeds if no file system is mounted.
+    pub fn sync(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Returns the mounted file system.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `NotConnected` if no file system is mounted.
+    fn handle(&self) -> io::Result<PiVFatHandle> {
+        self.0
+            .lock()
+            .clone()
+            .ok_or(newioerr!(NotConnected, "no filesystem mounted"))
     }
 }
 
@@ -73,7 +188,7 @@ impl fat32::traits::FileSystem for &FileSystem {
 
 
     fn open<P: AsRef<Path>>(self, path: P) -> io::Result<Self::Entry> {
-        self.0.lock().as_ref().unwrap().open(path)
+        self.handle()?.open(path)
     }
 
     fn open_file<P: AsRef<Path>>(self, path: P) -> io::Result<Self::File> {
@@ -88,3 +203,27 @@ impl fat32::traits::FileSystem for &FileSystem {
         thing.into_dir().ok_or(newioerr!(NotFound, "Is a file, not a directory"))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::FileSystem;
+    use fat32::traits::FileSystem as VFatFileSystem;
+    use shim::io;
+
+    #[test]
+    #[should_panic(expected = "allocator must be initialized before filesystem")]
+    fn requires_allocator() {
+        unsafe { FileSystem::uninitialized().try_initialize().ok(); }
+    }
+
+    #[test]
+    fn unmounted_operations_fail() {
+        let fs = FileSystem::uninitialized();
+        assert!(!fs.is_mounted());
+
+        let kind = |r: io::Result<()>| r.unwrap_err().kind();
+        assert_eq!(kind((&fs).open("/").map(|_| ())), io::ErrorKind::NotConnected);
+        assert_eq!(kind((&fs).open_file("/a.txt").map(|_| ())), io::ErrorKind::NotConnected);
+        assert_eq!(kind((&fs).open_dir("/").map(|_| ())), io::ErrorKind::NotConnected);
+    }
+}
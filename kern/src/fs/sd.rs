@@ -1,5 +1,5 @@
 use fat32::traits::BlockDevice;
-use pi::emmc::{EMMCController, SdResult};
+use pi::emmc::EMMCController;
 use shim::io;
 use shim::ioerr;
 
@@ -15,20 +15,15 @@ pub static EMMC_CONT: Mutex<EMMCController> = unsafe { Mutex::new(EMMCController
 
 impl Sd {
     /// Initializes the SD card controller and returns a handle to it.
-    /// The caller should assure that the method is invoked only once during the
-    /// kernel initialization. We can enforce the requirement in safe Rust code
-    /// with atomic memory access, but we can't use it yet since we haven't
-    /// written the memory management unit (MMU).
+    /// The caller should assure that the method succeeds only once; a failed
+    /// call (e.g. because no card is inserted) may be retried. We can enforce
+    /// the requirement in safe Rust code with atomic memory access, but we
+    /// can't use it yet since we haven't written the memory management unit
+    /// (MMU).
     pub unsafe fn new() -> Result<Sd, io::Error> {
-        match EMMC_CONT.lock().emmc_init_card() {
-            SdResult::EMMC_OK => {
-                kprintln!("EMMC2 driver initialized...\n");
-                Ok(Sd {})
-            }
-            _ => {
-                ioerr!(BrokenPipe, "sending command")
-            }
-        }
+        EMMC_CONT.lock().emmc_init_card().into_result()?;
+        kprintln!("EMMC2 driver initialized...\n");
+        Ok(Sd {})
     }
 }
 
@@ -41,10 +36,9 @@ impl BlockDevice for Sd {
     /// An I/O error of kind `InvalidInput` is returned if `buf.len() < 512` or
     /// `n > 2^31 - 1` (the maximum value for an `i32`).
     ///
-    /// An error of kind `TimedOut` is returned if a timeout occurs while
-    /// reading from the SD card.
-    ///
-    /// An error of kind `Other` is returned for all other errors.
+    /// Controller failures are translated by `SdResult::into_result`; in
+    /// particular, an error of kind `TimedOut` is returned if a timeout occurs
+    /// while reading from the SD card.
     fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
         if buf.len() < 512 {
             return ioerr!(InvalidInput, "buf.len() < 512");
@@ -52,19 +46,28 @@ impl BlockDevice for Sd {
         if n > 0x7fffffff {
             return ioerr!(InvalidInput, "n > 0x7fffffff");
         }
-        let res = EMMC_CONT.lock().emmc_transfer_blocks(n as u32, 1, buf, false);
-
-        return match res {
-            SdResult::EMMC_OK => {
-                Ok(512)
-            },
-            SdResult::EMMC_TIMEOUT => ioerr!(BrokenPipe, "timeout"),
-            SdResult::EMMC_ERROR_APP_CMD => ioerr!(TimedOut, "error sending commsnt"),
-            _ => ioerr!(Other, "unknown error"),
-        }
+        EMMC_CONT.lock().emmc_transfer_blocks(n as u32, 1, buf, false).into_result()?;
+        Ok(512)
     }
 
-    fn write_sector(&mut self, _n: u64, _buf: &[u8]) -> io::Result<usize> {
-        unimplemented!("SD card and file system are read only")
+    /// Writes the first 512 bytes of `buf` to sector `n`. On success, `512`
+    /// is returned.
+    ///
+    /// # Errors
+    ///
+    /// An I/O error of kind `UnexpectedEof` is returned if `buf.len() < 512`,
+    /// and one of kind `InvalidInput` if `n > 2^31 - 1`.
+    fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+        if buf.len() < 512 {
+            return ioerr!(UnexpectedEof, "buf.len() < 512");
+        }
+        if n > 0x7fffffff {
+            return ioerr!(InvalidInput, "n > 0x7fffffff");
+        }
+        // The controller takes a mutable buffer for both directions.
+        let mut sector = [0u8; 512];
+        sector.copy_from_slice(&buf[..512]);
+        EMMC_CONT.lock().emmc_transfer_blocks(n as u32, 1, &mut sector, true).into_result()?;
+        Ok(512)
     }
 }
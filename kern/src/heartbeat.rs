@@ -0,0 +1,144 @@
+//! A liveness indicator: a GPIO pin, usually wired to an LED, that is toggled
+//! at a fixed interval while the kernel runs and freezes if it hangs.
+//!
+//! The pin and interval come from `config::heartbeat`. `poll` is called from
+//! core 0's timer interrupt next to the kernel timers, so the pin is toggled
+//! at the granularity of the scheduling quantum. When the heartbeat is
+//! disabled, `poll` only reads the setting.
+
+use core::time::Duration;
+
+use pi::gpio::Gpio;
+
+use crate::config;
+use crate::mutex::Mutex;
+
+/// Decides when the heartbeat pin is toggled.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Blinker {
+    /// When the pin is toggled next, or `None` before the first poll.
+    next_toggle: Option<Duration>,
+    lit: bool,
+}
+
+impl Blinker {
+    pub const fn new() -> Blinker {
+        Blinker { next_toggle: None, lit: false }
+    }
+
+    /// Returns the level the pin should be driven to at `now`, or `None` if
+    /// it should be left as is. The first poll lights the pin.
+    ///
+    /// Toggles are spaced `interval` apart. If polls stop for longer than
+    /// that, the pin is toggled once and the schedule restarts from `now`
+    /// instead of catching up with a burst of toggles.
+    pub fn poll(&mut self, now: Duration, interval: Duration) -> Option<bool> {
+        match self.next_toggle {
+            Some(next) if now < next => return None,
+            Some(next) if now < next + interval => self.next_toggle = Some(next + interval),
+            _ => self.next_toggle = Some(now + interval),
+        }
+        self.lit = !self.lit;
+        Some(self.lit)
+    }
+}
+
+/// The pin currently driven by the heartbeat and its blinker.
+static HEARTBEAT: Mutex<Option<(u8, Blinker)>> = Mutex::new(None);
+
+fn drive(pin: u8, level: bool) {
+    let mut gpio = Gpio::new(pin).into_output();
+    if level {
+        gpio.set();
+    } else {
+        gpio.clear();
+    }
+}
+
+/// Toggles the heartbeat pin if it is due at `now`. A pin that is no longer
+/// configured, because the heartbeat was disabled or moved, is cleared.
+pub fn poll(now: Duration) {
+    let setting = config::heartbeat();
+    let mut state = HEARTBEAT.lock();
+
+    if let Some((pin, _)) = *state {
+        if setting.map(|(p, _)| p) != Some(pin) {
+            drive(pin, false);
+            *state = None;
+        }
+    }
+
+    if let Some((pin, interval)) = setting {
+        let (_, blinker) = state.get_or_insert((pin, Blinker::new()));
+        if let Some(level) = blinker.poll(now, interval) {
+            drive(pin, level);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Blinker;
+    use alloc::vec::Vec;
+    use core::time::Duration;
+
+    const INTERVAL: Duration = Duration::from_millis(500);
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn first_poll_lights() {
+        let mut blinker = Blinker::new();
+        assert_eq!(blinker.poll(ms(1234), INTERVAL), Some(true));
+        assert_eq!(blinker.poll(ms(1234), INTERVAL), None);
+    }
+
+    #[test]
+    fn toggles_every_interval() {
+        let mut blinker = Blinker::new();
+        let mut toggles = Vec::new();
+        // Polled every 100 ms, like a timer tick.
+        for t in (0..=2000).step_by(100) {
+            if let Some(level) = blinker.poll(ms(t), INTERVAL) {
+                toggles.push((t, level));
+            }
+        }
+        assert_eq!(
+            toggles,
+            [(0, true), (500, false), (1000, true), (1500, false), (2000, true)]
+        );
+    }
+
+    #[test]
+    fn late_polls_keep_the_schedule() {
+        let mut blinker = Blinker::new();
+        blinker.poll(ms(0), INTERVAL);
+        // A tick that arrives late toggles, and the next toggle stays on the
+        // original 500 ms grid.
+        assert_eq!(blinker.poll(ms(530), INTERVAL), Some(false));
+        assert_eq!(blinker.poll(ms(990), INTERVAL), None);
+        assert_eq!(blinker.poll(ms(1000), INTERVAL), Some(true));
+    }
+
+    #[test]
+    fn stall_does_not_burst() {
+        let mut blinker = Blinker::new();
+        blinker.poll(ms(0), INTERVAL);
+        // After a 5 s stall the pin toggles once, then every interval from
+        // the stall's end.
+        assert_eq!(blinker.poll(ms(5000), INTERVAL), Some(false));
+        assert_eq!(blinker.poll(ms(5100), INTERVAL), None);
+        assert_eq!(blinker.poll(ms(5499), INTERVAL), None);
+        assert_eq!(blinker.poll(ms(5500), INTERVAL), Some(true));
+    }
+
+    #[test]
+    fn interval_change_applies_from_next_toggle() {
+        let mut blinker = Blinker::new();
+        blinker.poll(ms(0), INTERVAL);
+        assert_eq!(blinker.poll(ms(500), ms(100)), Some(false));
+        assert_eq!(blinker.poll(ms(600), ms(100)), Some(true));
+    }
+}
@@ -131,6 +131,7 @@ unsafe fn kmain2() -> ! {
     let addr = (SPINNING_BASE as u64 + 8 * core_idx) as *mut usize;
     *addr = 0;
     VMM.wait();
+    crate::cpuinfo::record();
     SCHEDULER.start()
 }
 
@@ -1,6 +1,10 @@
 use core::alloc::Layout;
 
+use crate::abort;
+
+/// Reports the failed allocation and halts. Panicking here could allocate
+/// while formatting the message, so the report is written by `abort::oom`.
 #[alloc_error_handler]
-pub fn oom(_layout: Layout) -> ! {
-    panic!("OOM");
+pub fn oom(layout: Layout) -> ! {
+    abort::oom(layout)
 }
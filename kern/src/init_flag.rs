@@ -0,0 +1,53 @@
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// Records whether a global singleton has been initialized.
+///
+/// Only plain atomic loads and stores are used: exclusive accesses such as
+/// `swap` require the MMU, which is not yet enabled when the allocator and the
+/// file system are initialized.
+pub struct InitFlag(AtomicBool);
+
+impl InitFlag {
+    /// Returns a flag in the uninitialized state.
+    pub const fn new() -> InitFlag {
+        InitFlag(AtomicBool::new(false))
+    }
+
+    /// Returns `true` if `set()` has been called.
+    pub fn is_set(&self) -> bool {
+        self.0.load(Ordering::Acquire)
+    }
+
+    /// Marks `name` as initialized.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `name` was already initialized.
+    pub fn set(&self, name: &str) {
+        if self.is_set() {
+            panic!("{} already initialized", name);
+        }
+        self.0.store(true, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::InitFlag;
+
+    #[test]
+    fn set_once() {
+        let flag = InitFlag::new();
+        assert!(!flag.is_set());
+        flag.set("thing");
+        assert!(flag.is_set());
+    }
+
+    #[test]
+    #[should_panic(expected = "thing already initialized")]
+    fn double_init_panics() {
+        let flag = InitFlag::new();
+        flag.set("thing");
+        flag.set("thing");
+    }
+}
@@ -0,0 +1,81 @@
+//! A report of where the kernel was loaded, printed at boot.
+
+use core::fmt;
+use core::ops::Range;
+
+use aarch64::SP;
+
+use crate::allocator::memory_map;
+
+extern "C" {
+    static __text_beg: u8;
+    static __text_end: u8;
+    static __bss_beg: u8;
+    static __bss_end: u8;
+}
+
+/// The addresses of the kernel image, heap and stack.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BootLayout {
+    pub text: Range<usize>,
+    pub bss: Range<usize>,
+    /// The memory handed to the allocator, if it could be determined.
+    pub heap: Option<Range<usize>>,
+    pub sp: usize,
+}
+
+impl BootLayout {
+    /// Returns the layout of the running kernel, using the current stack
+    /// pointer.
+    pub fn current() -> BootLayout {
+        unsafe {
+            BootLayout {
+                text: (&__text_beg as *const u8 as usize)..(&__text_end as *const u8 as usize),
+                bss: (&__bss_beg as *const u8 as usize)..(&__bss_end as *const u8 as usize),
+                heap: memory_map().map(|(start, end)| start..end),
+                sp: SP.get(),
+            }
+        }
+    }
+}
+
+impl fmt::Display for BootLayout {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "text  {:016x}-{:016x}", self.text.start, self.text.end)?;
+        writeln!(f, "bss   {:016x}-{:016x}", self.bss.start, self.bss.end)?;
+        match &self.heap {
+            Some(heap) => writeln!(f, "heap  {:016x}-{:016x}", heap.start, heap.end)?,
+            None => writeln!(f, "heap  unknown")?,
+        }
+        write!(f, "stack {:016x}", self.sp)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BootLayout;
+    use alloc::string::ToString;
+
+    #[test]
+    fn report() {
+        let layout = BootLayout {
+            text: 0x8_0000..0x12_3000,
+            bss: 0x11_0000..0x12_3000,
+            heap: Some(0x12_3000..0x1_0000_0000),
+            sp: 0x8_0000,
+        };
+        assert_eq!(
+            layout.to_string(),
+            "text  0000000000080000-0000000000123000\n\
+             bss   0000000000110000-0000000000123000\n\
+             heap  0000000000123000-0000000100000000\n\
+             stack 0000000000080000"
+        );
+    }
+
+    #[test]
+    fn report_without_heap() {
+        let layout = BootLayout { text: 0..0x10, bss: 0x10..0x20, heap: None, sp: 0x80 };
+        assert!(layout.to_string().contains("\nheap  unknown\n"));
+    }
+}
@@ -17,16 +17,33 @@ extern crate alloc;
 #[macro_use]
 extern crate log;
 
+pub mod abort;
 pub mod allocator;
+pub mod bench;
+pub mod boottime;
+pub mod config;
 pub mod console;
+pub mod corestats;
+pub mod cpuinfo;
+pub mod dd;
+#[cfg(any(test, feature = "deadlock-detection"))]
+pub mod deadlock;
 pub mod fs;
+pub mod heartbeat;
+pub mod init_flag;
+pub mod layout;
 pub mod logger;
 pub mod mutex;
 pub mod net;
 pub mod param;
 pub mod percore;
+pub mod poke;
+pub mod power;
 pub mod process;
+pub mod selftest;
+pub mod shared;
 pub mod shell;
+pub mod timers;
 pub mod traps;
 pub mod vm;
 
@@ -35,7 +52,9 @@ use fs::FileSystem;
 use net::uspi::Usb;
 use net::GlobalEthernetDriver;
 use process::GlobalScheduler;
+use mutex::Mutex;
 use traps::irq::{Fiq, GlobalIrq};
+use traps::stats::IrqStats;
 use vm::VMManager;
 
 #[cfg_attr(not(test), global_allocator)]
@@ -46,35 +65,30 @@ pub static VMM: VMManager = VMManager::uninitialized();
 pub static USB: Usb = Usb::uninitialized();
 pub static GLOBAL_IRQ: GlobalIrq = GlobalIrq::new();
 pub static FIQ: Fiq = Fiq::new();
+pub static IRQ_STATS: Mutex<IrqStats> = Mutex::new(IrqStats::new());
 pub static ETHERNET: GlobalEthernetDriver = GlobalEthernetDriver::uninitialized();
 
-extern "C" {
-    static __text_beg: u64;
-    static __text_end: u64;
-    static __bss_beg: u64;
-    static __bss_end: u64;
-}
-
 unsafe fn kmain() -> ! {
     crate::logger::init_logger();
 
-    info!(
-        "text beg: {:016x}, end: {:016x}",
-        &__text_beg as *const _ as u64, &__text_end as *const _ as u64
-    );
-    info!(
-        "bss  beg: {:016x}, end: {:016x}",
-        &__bss_beg as *const _ as u64, &__bss_end as *const _ as u64
-    );
-
-    ALLOCATOR.initialize();
-    FILESYSTEM.initialize();
-    VMM.initialize();
-    SCHEDULER.initialize();
+    // Printed before the allocator is initialized, so it must not allocate.
+    info!("kernel layout:\n{}", layout::BootLayout::current());
 
-    init::initialize_app_cores();
+    boottime::time("allocator", || ALLOCATOR.initialize());
+    // Times the "emmc" and "filesystem" phases itself.
+    if let Err(e) = FILESYSTEM.try_initialize() {
+        warn!("failed to mount filesystem: {:?}", e);
+    }
+    boottime::time("page tables", || VMM.initialize());
+    boottime::time("scheduler", || SCHEDULER.initialize());
 
-    VMM.wait();
+    boottime::time("app cores", || {
+        init::initialize_app_cores();
+        VMM.wait();
+    });
+    cpuinfo::record();
+    console::initialize_rx_interrupt();
+    boottime::finish();
     SCHEDULER.start();
 
     loop {}
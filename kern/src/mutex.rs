@@ -3,6 +3,8 @@ use core::fmt;
 use core::ops::{Deref, DerefMut, Drop};
 use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
+#[cfg(feature = "deadlock-detection")]
+use crate::deadlock;
 use crate::percore::{getcpu, is_mmu_ready, putcpu};
 
 #[repr(align(32))]
@@ -40,6 +42,8 @@ impl<T> Mutex<T> {
         if is_mmu_ready() {
             if !self.lock.swap(true, Ordering::AcqRel) {
                 self.owner.store(cpu, Ordering::Release);
+                #[cfg(feature = "deadlock-detection")]
+                deadlock::acquired(self.id(), cpu);
                 //getcpu();
                 Some(MutexGuard { lock: &self })
             } else {
@@ -62,18 +66,37 @@ impl<T> Mutex<T> {
         // Wait until we can "aquire" the lock, then "acquire" it.
         loop {
             match self.try_lock() {
-                Some(guard) => return guard,
-                None => continue,
+                Some(guard) => {
+                    #[cfg(feature = "deadlock-detection")]
+                    deadlock::stop_waiting(aarch64::affinity());
+                    return guard;
+                }
+                None => {
+                    #[cfg(feature = "deadlock-detection")]
+                    {
+                        if is_mmu_ready() {
+                            deadlock::wait_for(self.id(), aarch64::affinity());
+                        }
+                    }
+                }
             }
         }
     }
 
+    /// Identifies this lock to the deadlock detector.
+    #[cfg(feature = "deadlock-detection")]
+    fn id(&self) -> usize {
+        self as *const Self as usize
+    }
+
     fn unlock(&self) {
         let ordering = if is_mmu_ready() {
             Ordering::Release
         } else {
             Ordering::Relaxed
         };
+        #[cfg(feature = "deadlock-detection")]
+        deadlock::released(self.id(), aarch64::affinity());
         self.lock.store(false, ordering);
         //putcpu(aarch64::affinity());
     }
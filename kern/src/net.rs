@@ -1,6 +1,10 @@
 ///! Network device that wraps USPi in smoltcp abstraction
+pub mod dns;
+pub mod http;
 pub mod uspi;
 
+pub use self::dns::resolve;
+
 use alloc::boxed::Box;
 use alloc::collections::BTreeMap;
 use alloc::vec;
@@ -11,17 +15,18 @@ use core::time::Duration;
 
 use smoltcp::iface::{EthernetInterfaceBuilder, NeighborCache};
 use smoltcp::phy::{self, Device, DeviceCapabilities};
-use smoltcp::socket::{SocketHandle, SocketRef, TcpSocketBuffer};
+use smoltcp::socket::{Socket, SocketHandle, SocketRef, TcpSocketBuffer, TcpState, UdpPacketMetadata, UdpSocketBuffer};
 use smoltcp::time::Instant;
-use smoltcp::wire::{IpAddress, IpCidr};
+use smoltcp::wire::{IpAddress, IpCidr, IpEndpoint};
 
 use crate::mutex::Mutex;
 use crate::param::MTU;
-use crate::USB;
+use crate::{ETHERNET, USB};
 
 // We always use owned buffer as internal storage
 pub type SocketSet = smoltcp::socket::SocketSet<'static, 'static, 'static>;
 pub type TcpSocket = smoltcp::socket::TcpSocket<'static>;
+pub type UdpSocket = smoltcp::socket::UdpSocket<'static, 'static>;
 pub type EthernetInterface<T> = smoltcp::iface::EthernetInterface<'static, 'static, 'static, T>;
 
 /// 8-byte aligned `u8` slice.
@@ -141,6 +146,38 @@ pub fn create_interface() -> EthernetInterface<UsbEthernet> {
     unimplemented!("create_interface")
 }
 
+/// The endpoints and state of one TCP socket, as listed by `netstat`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SocketInfo {
+    pub handle: SocketHandle,
+    pub local: IpEndpoint,
+    pub remote: IpEndpoint,
+    pub state: TcpState,
+}
+
+/// Returns the endpoints and state of every TCP socket in `set`. Sockets in
+/// transitional states, e.g. `SynReceived` or `TimeWait`, are included with
+/// whatever endpoints they currently have.
+pub fn summarize(set: &smoltcp::socket::SocketSet<'_, '_, '_>) -> Vec<SocketInfo> {
+    set.iter()
+        .filter_map(|socket| match socket {
+            Socket::Tcp(tcp) => Some(SocketInfo {
+                handle: socket.handle(),
+                local: tcp.local_endpoint(),
+                remote: tcp.remote_endpoint(),
+                state: tcp.state(),
+            }),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Returns the endpoints and state of every socket of the global ethernet
+/// driver, or nothing if the driver is not initialized.
+pub fn socket_summary() -> Vec<SocketInfo> {
+    ETHERNET.socket_summary()
+}
+
 const PORT_MAP_SIZE: usize = 65536 / 64;
 
 pub struct EthernetDriver {
@@ -206,6 +243,20 @@ impl EthernetDriver {
         self.socket_set.add(tcp_socket)
     }
 
+    /// Finds a UDP socket with a `SocketHandle`.
+    pub fn get_udp_socket(&mut self, handle: SocketHandle) -> SocketRef<'_, UdpSocket> {
+        self.socket_set.get::<UdpSocket>(handle)
+    }
+
+    /// This function creates a new UDP socket, adds it to the internal socket
+    /// set, and returns the `SocketHandle` of the new socket.
+    pub fn add_udp_socket(&mut self) -> SocketHandle {
+        let rx_buffer = UdpSocketBuffer::new(vec![UdpPacketMetadata::EMPTY; 4], vec![0; 2048]);
+        let tx_buffer = UdpSocketBuffer::new(vec![UdpPacketMetadata::EMPTY; 4], vec![0; 2048]);
+        let udp_socket = UdpSocket::new(rx_buffer, tx_buffer);
+        self.socket_set.add(udp_socket)
+    }
+
     /// Releases a socket from the internal socket set.
     pub fn release(&mut self, handle: SocketHandle) {
         self.socket_set.release(handle);
@@ -215,6 +266,11 @@ impl EthernetDriver {
     pub fn prune(&mut self) {
         self.socket_set.prune();
     }
+
+    /// Returns the endpoints and state of every socket. See `summarize`.
+    pub fn socket_summary(&self) -> Vec<SocketInfo> {
+        summarize(&self.socket_set)
+    }
 }
 
 /// A thread-safe wrapper for `EthernetDriver`.
@@ -267,6 +323,15 @@ impl GlobalEthernetDriver {
             .add_socket()
     }
 
+    /// Returns the endpoints and state of every socket, or nothing if the
+    /// driver is not initialized.
+    pub fn socket_summary(&self) -> Vec<SocketInfo> {
+        match self.0.lock().as_ref() {
+            Some(driver) => driver.socket_summary(),
+            None => Vec::new(),
+        }
+    }
+
     /// Enters a critical region and execute the provided closure with a mutable
     /// reference to the socket.
     pub fn with_socket<F, R>(&self, handle: SocketHandle, f: F) -> R
@@ -294,3 +359,66 @@ impl GlobalEthernetDriver {
         f(&mut ethernet)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{summarize, SocketInfo, TcpSocket};
+    use alloc::collections::BTreeMap;
+    use alloc::vec;
+    use alloc::vec::Vec;
+    use smoltcp::iface::{EthernetInterfaceBuilder, NeighborCache};
+    use smoltcp::phy::Loopback;
+    use smoltcp::socket::{SocketSet, TcpSocketBuffer, TcpState};
+    use smoltcp::time::Instant;
+    use smoltcp::wire::{EthernetAddress, IpAddress, IpCidr, IpEndpoint};
+
+    fn tcp_socket() -> TcpSocket {
+        TcpSocket::new(TcpSocketBuffer::new(vec![0; 1024]), TcpSocketBuffer::new(vec![0; 1024]))
+    }
+
+    fn find(summary: &[SocketInfo], port: u16) -> SocketInfo {
+        *summary.iter().find(|info| info.local.port == port).expect("socket listed")
+    }
+
+    #[test]
+    fn listening_and_established() {
+        let mut iface = EthernetInterfaceBuilder::new(Loopback::new())
+            .ethernet_addr(EthernetAddress::default())
+            .neighbor_cache(NeighborCache::new(BTreeMap::new()))
+            .ip_addrs(vec![IpCidr::new(IpAddress::v4(127, 0, 0, 1), 8)])
+            .finalize();
+
+        let mut sockets = SocketSet::new(Vec::new());
+        let idle = sockets.add(tcp_socket());
+        let server = sockets.add(tcp_socket());
+        let client = sockets.add(tcp_socket());
+        sockets.get::<TcpSocket>(idle).listen(8080).unwrap();
+        sockets.get::<TcpSocket>(server).listen(80).unwrap();
+        sockets
+            .get::<TcpSocket>(client)
+            .connect((IpAddress::v4(127, 0, 0, 1), 80), 49152)
+            .unwrap();
+
+        for ms in 0..20 {
+            let _ = iface.poll(&mut sockets, Instant::from_millis(ms));
+        }
+
+        let summary = summarize(&sockets);
+        assert_eq!(summary.len(), 3);
+
+        let listener = find(&summary, 8080);
+        assert_eq!(listener.handle, idle);
+        assert_eq!(listener.state, TcpState::Listen);
+
+        let loopback = IpAddress::v4(127, 0, 0, 1);
+        let accepted = find(&summary, 80);
+        assert_eq!(accepted.handle, server);
+        assert_eq!(accepted.state, TcpState::Established);
+        assert_eq!(accepted.remote, IpEndpoint::new(loopback, 49152));
+
+        let connected = find(&summary, 49152);
+        assert_eq!(connected.handle, client);
+        assert_eq!(connected.state, TcpState::Established);
+        assert_eq!(connected.remote, IpEndpoint::new(loopback, 80));
+    }
+}
@@ -0,0 +1,478 @@
+//! A minimal DNS resolver for A records over UDP.
+//!
+//! Queries and responses are built and parsed by plain functions over byte
+//! slices, so they can be tested without a network. `lookup` sends a query to
+//! the resolver set with `set_server`, e.g. by the shell's `nameserver`
+//! command since the interface has a static address and no DHCP client,
+//! and polls the ethernet driver until the answer arrives or `TIMEOUT`
+//! passes. Answers are cached for at most `MAX_CACHE_TTL`.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use core::time::Duration;
+
+use pi::timer::{current_time, spin_sleep};
+use smoltcp::socket::SocketHandle;
+use smoltcp::time::Instant;
+use smoltcp::wire::{IpAddress, IpEndpoint, Ipv4Address};
+
+use crate::mutex::Mutex;
+use crate::ETHERNET;
+
+/// The UDP port DNS servers listen on.
+pub const DNS_PORT: u16 = 53;
+
+/// How long `lookup` waits for a response.
+pub const TIMEOUT: Duration = Duration::from_secs(3);
+
+/// How often `lookup` polls the network while waiting for a response.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// The longest an answer is cached, whatever TTL the server gave it.
+pub const MAX_CACHE_TTL: Duration = Duration::from_secs(60);
+
+/// The most names cached at once.
+pub const CACHE_SIZE: usize = 8;
+
+/// The longest encoded name a query may carry, in bytes.
+const MAX_NAME_LEN: usize = 255;
+
+/// The longest label of a name.
+const MAX_LABEL_LEN: usize = 63;
+
+const HEADER_LEN: usize = 12;
+const TYPE_A: u16 = 1;
+const CLASS_IN: u16 = 1;
+
+/// Header flags: the response bit, the truncation bit, recursion desired.
+const FLAG_QR: u16 = 0x8000;
+const FLAG_TC: u16 = 0x0200;
+const FLAG_RD: u16 = 0x0100;
+
+/// The response code for a name that does not exist.
+const RCODE_NXDOMAIN: u8 = 3;
+
+/// Why a name could not be resolved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DnsError {
+    /// The hostname is not a valid DNS name.
+    BadName,
+    /// The response is cut short or otherwise not a valid DNS message.
+    Malformed,
+    /// The response does not answer our query: a different ID, or not a
+    /// response at all.
+    NotOurs,
+    /// The server truncated the response to fit in a UDP datagram.
+    Truncated,
+    /// The name does not exist (NXDOMAIN).
+    NoSuchName,
+    /// The server failed with the given response code.
+    Server(u8),
+    /// The response holds no A record for the name.
+    NoAddress,
+    /// No resolver is configured, or the query could not be sent.
+    Unreachable,
+    /// No response arrived within `TIMEOUT`.
+    TimedOut,
+}
+
+impl fmt::Display for DnsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DnsError::BadName => write!(f, "invalid hostname"),
+            DnsError::Malformed => write!(f, "malformed response"),
+            DnsError::NotOurs => write!(f, "response does not match the query"),
+            DnsError::Truncated => write!(f, "truncated response"),
+            DnsError::NoSuchName => write!(f, "no such name"),
+            DnsError::Server(rcode) => write!(f, "server failure (rcode {})", rcode),
+            DnsError::NoAddress => write!(f, "no address for name"),
+            DnsError::Unreachable => write!(f, "no resolver reachable"),
+            DnsError::TimedOut => write!(f, "timed out"),
+        }
+    }
+}
+
+/// An address from a response and how long it may be cached.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Answer {
+    pub addr: Ipv4Address,
+    pub ttl: Duration,
+}
+
+/// Parses a dotted-quad IPv4 address such as `8.8.8.8`.
+pub fn parse_address(s: &str) -> Option<Ipv4Address> {
+    let mut octets = [0u8; 4];
+    let mut parts = s.split('.');
+    for octet in octets.iter_mut() {
+        let part = parts.next()?;
+        if part.is_empty() || !part.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        *octet = u8::from_str_radix(part, 10).ok()?;
+    }
+    match parts.next() {
+        Some(_) => None,
+        None => Some(Ipv4Address::from_bytes(&octets)),
+    }
+}
+
+/// Builds a query with ID `id` asking for the A records of `name`, with
+/// recursion desired. A trailing dot on `name` is ignored.
+pub fn build_query(id: u16, name: &str) -> Result<Vec<u8>, DnsError> {
+    let name = if name.ends_with('.') { &name[..name.len() - 1] } else { name };
+    if name.is_empty() || !name.is_ascii() || name.len() + 2 > MAX_NAME_LEN {
+        return Err(DnsError::BadName);
+    }
+
+    let mut query = Vec::with_capacity(HEADER_LEN + name.len() + 6);
+    query.extend_from_slice(&id.to_be_bytes());
+    query.extend_from_slice(&FLAG_RD.to_be_bytes());
+    // One question, no answer, authority or additional records.
+    query.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0]);
+    for label in name.split('.') {
+        if label.is_empty() || label.len() > MAX_LABEL_LEN {
+            return Err(DnsError::BadName);
+        }
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+    query.push(0);
+    query.extend_from_slice(&TYPE_A.to_be_bytes());
+    query.extend_from_slice(&CLASS_IN.to_be_bytes());
+    Ok(query)
+}
+
+fn read_u16(packet: &[u8], pos: usize) -> Result<u16, DnsError> {
+    match packet.get(pos..pos + 2) {
+        Some(bytes) => Ok(u16::from_be_bytes([bytes[0], bytes[1]])),
+        None => Err(DnsError::Malformed),
+    }
+}
+
+fn read_u32(packet: &[u8], pos: usize) -> Result<u32, DnsError> {
+    Ok((read_u16(packet, pos)? as u32) << 16 | read_u16(packet, pos + 2)? as u32)
+}
+
+/// Returns the position just past the name starting at `pos`. Compression
+/// pointers are not followed, since only the name's length is needed.
+fn skip_name(packet: &[u8], mut pos: usize) -> Result<usize, DnsError> {
+    loop {
+        let len = *packet.get(pos).ok_or(DnsError::Malformed)? as usize;
+        match len {
+            0 => return Ok(pos + 1),
+            // A pointer ends the name.
+            0xC0..=0xFF if pos + 1 < packet.len() => return Ok(pos + 2),
+            1..=MAX_LABEL_LEN => pos += 1 + len,
+            _ => return Err(DnsError::Malformed),
+        }
+    }
+}
+
+/// Extracts the first A record from `packet`, a response to the query with ID
+/// `id`. CNAME records leading to the address are skipped.
+pub fn parse_response(id: u16, packet: &[u8]) -> Result<Answer, DnsError> {
+    if packet.len() < HEADER_LEN {
+        return Err(DnsError::Malformed);
+    }
+    let flags = read_u16(packet, 2)?;
+    if read_u16(packet, 0)? != id || flags & FLAG_QR == 0 {
+        return Err(DnsError::NotOurs);
+    }
+    if flags & FLAG_TC != 0 {
+        return Err(DnsError::Truncated);
+    }
+    match (flags & 0xF) as u8 {
+        0 => {}
+        RCODE_NXDOMAIN => return Err(DnsError::NoSuchName),
+        rcode => return Err(DnsError::Server(rcode)),
+    }
+
+    let questions = read_u16(packet, 4)?;
+    let answers = read_u16(packet, 6)?;
+    let mut pos = HEADER_LEN;
+    for _ in 0..questions {
+        // The name, then its type and class.
+        pos = skip_name(packet, pos)? + 4;
+    }
+    if pos > packet.len() {
+        return Err(DnsError::Malformed);
+    }
+    for _ in 0..answers {
+        pos = skip_name(packet, pos)?;
+        let rtype = read_u16(packet, pos)?;
+        let class = read_u16(packet, pos + 2)?;
+        let ttl = read_u32(packet, pos + 4)?;
+        let len = read_u16(packet, pos + 8)? as usize;
+        let data = packet.get(pos + 10..pos + 10 + len).ok_or(DnsError::Malformed)?;
+        if rtype == TYPE_A && class == CLASS_IN {
+            if len != 4 {
+                return Err(DnsError::Malformed);
+            }
+            return Ok(Answer { addr: Ipv4Address::from_bytes(data), ttl: Duration::from_secs(ttl as u64) });
+        }
+        pos += 10 + len;
+    }
+    Err(DnsError::NoAddress)
+}
+
+/// Recently resolved names and when they expire.
+#[derive(Debug, Default)]
+pub struct Cache {
+    entries: Vec<(String, Ipv4Address, Duration)>,
+}
+
+impl Cache {
+    pub fn new() -> Cache {
+        Cache { entries: Vec::new() }
+    }
+
+    /// Returns the cached address of `name` if it has not expired at `now`.
+    /// Names are compared case-insensitively.
+    pub fn get(&self, name: &str, now: Duration) -> Option<Ipv4Address> {
+        self.entries
+            .iter()
+            .find(|(cached, _, expires)| cached.eq_ignore_ascii_case(name) && now < *expires)
+            .map(|&(_, addr, _)| addr)
+    }
+
+    /// Caches `answer` for `name` from `now` on, for its TTL but at most
+    /// `MAX_CACHE_TTL`. Expired entries are dropped, and when the cache is
+    /// full the entry that expires first makes room.
+    pub fn insert(&mut self, name: &str, answer: Answer, now: Duration) {
+        self.entries.retain(|(cached, _, expires)| now < *expires && !cached.eq_ignore_ascii_case(name));
+        if self.entries.len() == CACHE_SIZE {
+            let soonest = (0..CACHE_SIZE).min_by_key(|&i| self.entries[i].2).unwrap();
+            self.entries.remove(soonest);
+        }
+        let ttl = core::cmp::min(answer.ttl, MAX_CACHE_TTL);
+        self.entries.push((String::from(name), answer.addr, now + ttl));
+    }
+}
+
+static CACHE: Mutex<Option<Cache>> = Mutex::new(None);
+
+static SERVER: Mutex<Option<Ipv4Address>> = Mutex::new(None);
+
+/// Sets the resolver queried by `lookup`.
+pub fn set_server(addr: Ipv4Address) {
+    *SERVER.lock() = Some(addr);
+}
+
+/// Returns the resolver queried by `lookup`, if one is configured.
+pub fn server() -> Option<Ipv4Address> {
+    *SERVER.lock()
+}
+
+fn timestamp() -> Instant {
+    Instant::from_millis(current_time().as_millis() as i64)
+}
+
+/// Sends `query` from a fresh UDP socket bound to `port` and waits for the
+/// response to it.
+fn exchange(handle: SocketHandle, port: u16, server: Ipv4Address, query: &[u8], id: u16) -> Result<Answer, DnsError> {
+    let endpoint = IpEndpoint::new(IpAddress::Ipv4(server), DNS_PORT);
+    ETHERNET
+        .critical(|driver| {
+            let mut socket = driver.get_udp_socket(handle);
+            socket.bind(port)?;
+            socket.send_slice(query, endpoint)
+        })
+        .map_err(|_| DnsError::Unreachable)?;
+
+    let deadline = current_time() + TIMEOUT;
+    while current_time() < deadline {
+        ETHERNET.poll(timestamp());
+        let reply = ETHERNET.critical(|driver| {
+            let mut socket = driver.get_udp_socket(handle);
+            match socket.recv() {
+                Ok((packet, from)) if from.addr == endpoint.addr => Some(parse_response(id, packet)),
+                _ => None,
+            }
+        });
+        match reply {
+            // A late answer to an earlier query on the same port.
+            Some(Err(DnsError::NotOurs)) | None => spin_sleep(POLL_INTERVAL),
+            Some(result) => return result,
+        }
+    }
+    Err(DnsError::TimedOut)
+}
+
+/// Resolves `hostname` to an IPv4 address, from the cache or by asking the
+/// configured resolver.
+pub fn lookup(hostname: &str) -> Result<Ipv4Address, DnsError> {
+    let now = current_time();
+    if let Some(addr) = CACHE.lock().get_or_insert_with(Cache::new).get(hostname, now) {
+        return Ok(addr);
+    }
+
+    let server = server().ok_or(DnsError::Unreachable)?;
+    let id = now.as_micros() as u16;
+    let query = build_query(id, hostname)?;
+
+    let port = ETHERNET.get_ephemeral_port().ok_or(DnsError::Unreachable)?;
+    ETHERNET.mark_port(port).ok_or(DnsError::Unreachable)?;
+    let handle = ETHERNET.critical(|driver| driver.add_udp_socket());
+    let result = exchange(handle, port, server, &query, id);
+    ETHERNET.critical(|driver| {
+        driver.release(handle);
+        driver.erase_port(port);
+    });
+
+    let answer = result?;
+    CACHE.lock().get_or_insert_with(Cache::new).insert(hostname, answer, current_time());
+    Ok(answer.addr)
+}
+
+/// Resolves `hostname` like `lookup`, returning `None` on any failure.
+pub fn resolve(hostname: &str) -> Option<IpAddress> {
+    lookup(hostname).ok().map(IpAddress::Ipv4)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The start of a response to query 0x1234 for `example.com`, with
+    /// `flags` and `answers` answer records.
+    fn response(flags: u16, answers: u16) -> Vec<u8> {
+        let mut packet = vec![0x12, 0x34];
+        packet.extend_from_slice(&flags.to_be_bytes());
+        packet.extend_from_slice(&[0, 1]);
+        packet.extend_from_slice(&answers.to_be_bytes());
+        packet.extend_from_slice(&[0, 0, 0, 0]);
+        packet.extend_from_slice(b"\x07example\x03com\x00\x00\x01\x00\x01");
+        packet
+    }
+
+    /// An A record for the name at offset 12, the question's, with a TTL of
+    /// an hour.
+    const A_RECORD: &[u8] = b"\xc0\x0c\x00\x01\x00\x01\x00\x00\x0e\x10\x00\x04\x5d\xb8\xd8\x22";
+
+    fn secs(secs: u64) -> Duration {
+        Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn query() {
+        let query = build_query(0x1234, "example.com").unwrap();
+        let mut expected = vec![0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0];
+        expected.extend_from_slice(b"\x07example\x03com\x00\x00\x01\x00\x01");
+        assert_eq!(query, expected);
+
+        assert_eq!(build_query(0x1234, "example.com.").unwrap(), expected);
+    }
+
+    #[test]
+    fn bad_names() {
+        let long_label = "a".repeat(64);
+        let long_name = ["abcdefghi"; 26].join(".");
+        for &name in ["", ".", "a..b", ".a", "ex\u{e4}mple.com", &long_label, &long_name].iter() {
+            assert_eq!(build_query(1, name), Err(DnsError::BadName), "{:?}", name);
+        }
+        assert!(build_query(1, &"a".repeat(63)).is_ok());
+    }
+
+    #[test]
+    fn a_record() {
+        let mut packet = response(0x8180, 1);
+        packet.extend_from_slice(A_RECORD);
+        assert_eq!(
+            parse_response(0x1234, &packet),
+            Ok(Answer { addr: Ipv4Address::new(93, 184, 216, 34), ttl: secs(3600) })
+        );
+    }
+
+    #[test]
+    fn cname_then_a_record() {
+        let mut packet = response(0x8180, 2);
+        // www.example.com is a CNAME for example.com, at offset 12.
+        packet.extend_from_slice(b"\x03www\xc0\x0c\x00\x05\x00\x01\x00\x00\x00\x3c\x00\x02\xc0\x0c");
+        packet.extend_from_slice(A_RECORD);
+        assert_eq!(parse_response(0x1234, &packet).unwrap().addr, Ipv4Address::new(93, 184, 216, 34));
+    }
+
+    #[test]
+    fn error_responses() {
+        assert_eq!(parse_response(0x1234, &response(0x8183, 0)), Err(DnsError::NoSuchName));
+        assert_eq!(parse_response(0x1234, &response(0x8182, 0)), Err(DnsError::Server(2)));
+        assert_eq!(parse_response(0x1234, &response(0x8380, 0)), Err(DnsError::Truncated));
+        assert_eq!(parse_response(0x1234, &response(0x8180, 0)), Err(DnsError::NoAddress));
+    }
+
+    #[test]
+    fn not_our_response() {
+        let mut packet = response(0x8180, 1);
+        packet.extend_from_slice(A_RECORD);
+        assert_eq!(parse_response(0x4321, &packet), Err(DnsError::NotOurs));
+
+        // A query rather than a response.
+        let query = build_query(0x1234, "example.com").unwrap();
+        assert_eq!(parse_response(0x1234, &query), Err(DnsError::NotOurs));
+    }
+
+    #[test]
+    fn malformed_responses() {
+        let mut packet = response(0x8180, 1);
+        packet.extend_from_slice(A_RECORD);
+        // Cut off anywhere, the response is rejected rather than misread.
+        for len in 0..packet.len() {
+            assert_eq!(parse_response(0x1234, &packet[..len]), Err(DnsError::Malformed), "length {}", len);
+        }
+
+        // An A record that is not four bytes long.
+        let mut packet = response(0x8180, 1);
+        packet.extend_from_slice(b"\xc0\x0c\x00\x01\x00\x01\x00\x00\x0e\x10\x00\x03\x5d\xb8\xd8");
+        assert_eq!(parse_response(0x1234, &packet), Err(DnsError::Malformed));
+
+        // A label length with the reserved top bits 01.
+        let mut packet = response(0x8180, 1);
+        packet[12] = 0x47;
+        assert_eq!(parse_response(0x1234, &packet), Err(DnsError::Malformed));
+    }
+
+    #[test]
+    fn cache_expires() {
+        let mut cache = Cache::new();
+        let addr = Ipv4Address::new(10, 0, 0, 1);
+        cache.insert("example.com", Answer { addr, ttl: secs(5) }, secs(100));
+        assert_eq!(cache.get("example.com", secs(104)), Some(addr));
+        assert_eq!(cache.get("EXAMPLE.com", secs(104)), Some(addr));
+        assert_eq!(cache.get("example.com", secs(105)), None);
+        assert_eq!(cache.get("example.org", secs(100)), None);
+
+        // Long TTLs are capped.
+        cache.insert("example.org", Answer { addr, ttl: secs(86400) }, secs(0));
+        assert_eq!(cache.get("example.org", MAX_CACHE_TTL - secs(1)), Some(addr));
+        assert_eq!(cache.get("example.org", MAX_CACHE_TTL), None);
+    }
+
+    #[test]
+    fn parse_dotted_quad() {
+        assert_eq!(parse_address("8.8.4.4"), Some(Ipv4Address::new(8, 8, 4, 4)));
+        assert_eq!(parse_address("192.168.0.255"), Some(Ipv4Address::new(192, 168, 0, 255)));
+        assert_eq!(parse_address("1.2.3"), None);
+        assert_eq!(parse_address("1.2.3.4.5"), None);
+        assert_eq!(parse_address("1.2.3.256"), None);
+        assert_eq!(parse_address("1..3.4"), None);
+        assert_eq!(parse_address("1.2.3.+4"), None);
+        assert_eq!(parse_address("example.com"), None);
+    }
+
+    #[test]
+    fn cache_evicts_soonest_expiry() {
+        let mut cache = Cache::new();
+        let names: Vec<String> = (0..CACHE_SIZE + 1).map(|i| format!("host{}", i)).collect();
+        for (i, name) in names.iter().enumerate().take(CACHE_SIZE) {
+            // host3 expires first.
+            let ttl = if i == 3 { secs(1) } else { secs(30) };
+            cache.insert(name, Answer { addr: Ipv4Address::new(10, 0, 0, i as u8), ttl }, secs(0));
+        }
+        cache.insert(&names[CACHE_SIZE], Answer { addr: Ipv4Address::new(10, 0, 1, 0), ttl: secs(30) }, secs(0));
+
+        assert_eq!(cache.get("host3", secs(0)), None);
+        assert_eq!(cache.get(&names[CACHE_SIZE], secs(0)), Some(Ipv4Address::new(10, 0, 1, 0)));
+        assert_eq!(cache.get("host0", secs(0)), Some(Ipv4Address::new(10, 0, 0, 0)));
+    }
+}
@@ -0,0 +1,541 @@
+//! HTTP/1.1 request parsing and response framing.
+//!
+//! The code here only needs an `io::Read + io::Write` stream, so it can sit on
+//! top of a TCP socket as well as an in-memory buffer in tests.
+
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+
+use shim::io::{self, Read, Write};
+use shim::ioerr;
+
+/// The most headers a request may carry.
+pub const MAX_HEADERS: usize = 16;
+
+/// The longest request line plus headers accepted, in bytes.
+pub const MAX_HEAD_LEN: usize = 2048;
+
+/// Why a request could not be parsed. Both are answered with `400`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseError {
+    /// The request line or a header is not well formed.
+    Malformed,
+    /// The request has more than `MAX_HEADERS` headers or a head longer than
+    /// `MAX_HEAD_LEN`.
+    TooLarge,
+}
+
+/// An HTTP version.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Version {
+    Http10,
+    Http11,
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Version::Http10 => write!(f, "HTTP/1.0"),
+            Version::Http11 => write!(f, "HTTP/1.1"),
+        }
+    }
+}
+
+/// A bounded, case-insensitive map of header names to values.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Headers {
+    entries: Vec<(String, String)>,
+}
+
+impl Headers {
+    /// Returns an empty map.
+    pub fn new() -> Headers {
+        Headers { entries: Vec::new() }
+    }
+
+    /// Adds a header. Fails with `ParseError::TooLarge` if the map already
+    /// holds `MAX_HEADERS` entries.
+    pub fn insert(&mut self, name: &str, value: &str) -> Result<(), ParseError> {
+        if self.entries.len() == MAX_HEADERS {
+            return Err(ParseError::TooLarge);
+        }
+        self.entries.push((String::from(name), String::from(value)));
+        Ok(())
+    }
+
+    /// Returns the value of the first header called `name`, ignoring case.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Returns the number of headers.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+}
+
+/// A parsed request head.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub version: Version,
+    pub headers: Headers,
+}
+
+impl Request {
+    /// Parses a request head: the request line and headers, without the
+    /// blank line that ends them.
+    pub fn parse(head: &[u8]) -> Result<Request, ParseError> {
+        if head.len() > MAX_HEAD_LEN {
+            return Err(ParseError::TooLarge);
+        }
+        let head = core::str::from_utf8(head).map_err(|_| ParseError::Malformed)?;
+        let mut lines = head.split("\r\n");
+
+        let mut parts = lines.next().ok_or(ParseError::Malformed)?.split(' ');
+        let method = parts.next().filter(|m| !m.is_empty()).ok_or(ParseError::Malformed)?;
+        let path = parts.next().filter(|p| p.starts_with('/')).ok_or(ParseError::Malformed)?;
+        let version = match parts.next() {
+            Some("HTTP/1.0") => Version::Http10,
+            Some("HTTP/1.1") => Version::Http11,
+            _ => return Err(ParseError::Malformed),
+        };
+        if parts.next().is_some() {
+            return Err(ParseError::Malformed);
+        }
+
+        let mut headers = Headers::new();
+        for line in lines {
+            let colon = line.find(':').ok_or(ParseError::Malformed)?;
+            let name = &line[..colon];
+            if name.is_empty() || name.contains(' ') {
+                return Err(ParseError::Malformed);
+            }
+            headers.insert(name, line[colon + 1..].trim())?;
+        }
+
+        Ok(Request {
+            method: String::from(method),
+            path: String::from(path),
+            version,
+            headers,
+        })
+    }
+
+    /// Returns `true` if the client asked to reuse the connection: the
+    /// default for HTTP/1.1 unless `Connection: close` is sent, and opt-in
+    /// with `Connection: keep-alive` for HTTP/1.0.
+    pub fn keep_alive(&self) -> bool {
+        let connection = self.headers.get("Connection");
+        match self.version {
+            Version::Http11 => !connection.map_or(false, |c| c.eq_ignore_ascii_case("close")),
+            Version::Http10 => connection.map_or(false, |c| c.eq_ignore_ascii_case("keep-alive")),
+        }
+    }
+
+    /// Returns the length of the request body from `Content-Length`, or 0.
+    fn content_length(&self) -> Result<usize, ParseError> {
+        match self.headers.get("Content-Length") {
+            None => Ok(0),
+            Some(len) => len.parse().map_err(|_| ParseError::Malformed),
+        }
+    }
+}
+
+/// A response body.
+pub enum Body {
+    /// A body whose length is known up front; sent with `Content-Length`.
+    Bytes(Vec<u8>),
+    /// A body streamed from a reader until it is exhausted; sent with chunked
+    /// transfer encoding, or by closing the connection for HTTP/1.0 clients.
+    Stream(Box<dyn Read>),
+}
+
+/// A response to a request.
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: Body,
+}
+
+impl Response {
+    /// Returns a `text/plain` response with a fixed body.
+    pub fn text(status: u16, body: &str) -> Response {
+        Response { status, content_type: "text/plain", body: Body::Bytes(body.as_bytes().to_vec()) }
+    }
+}
+
+/// Returns the reason phrase for the status codes the server sends.
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        500 => "Internal Server Error",
+        _ => "",
+    }
+}
+
+/// Writes each `write` to the inner writer as one chunk of a chunked body.
+/// `finish` writes the terminating zero-length chunk.
+pub struct ChunkedWriter<W: Write> {
+    inner: W,
+}
+
+impl<W: Write> ChunkedWriter<W> {
+    pub fn new(inner: W) -> ChunkedWriter<W> {
+        ChunkedWriter { inner }
+    }
+
+    /// Writes the last chunk and returns the inner writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.inner.write_all(b"0\r\n\r\n")?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for ChunkedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // An empty chunk would end the body early.
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        write!(self.inner, "{:x}\r\n", buf.len())?;
+        self.inner.write_all(buf)?;
+        self.inner.write_all(b"\r\n")?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Copies `reader` to `writer` until `reader` is exhausted.
+fn copy<R: Read + ?Sized, W: Write>(reader: &mut R, writer: &mut W) -> io::Result<()> {
+    let mut buf = [0u8; 512];
+    loop {
+        match reader.read(&mut buf)? {
+            0 => return Ok(()),
+            n => writer.write_all(&buf[..n])?,
+        }
+    }
+}
+
+/// Writes `response` to `stream` for a request made with `version`. Returns
+/// `false` if the connection must be closed afterwards to end the body.
+pub fn write_response<W: Write>(
+    stream: &mut W,
+    version: Version,
+    keep_alive: bool,
+    response: Response,
+) -> io::Result<bool> {
+    write!(stream, "{} {} {}\r\n", version, response.status, reason(response.status))?;
+    write!(stream, "Content-Type: {}\r\n", response.content_type)?;
+
+    let keep_alive = match &response.body {
+        Body::Bytes(_) => keep_alive,
+        // Without chunked encoding only closing the connection ends the body.
+        Body::Stream(_) => keep_alive && version == Version::Http11,
+    };
+    let connection = if keep_alive { "keep-alive" } else { "close" };
+    write!(stream, "Connection: {}\r\n", connection)?;
+
+    match response.body {
+        Body::Bytes(bytes) => {
+            write!(stream, "Content-Length: {}\r\n\r\n", bytes.len())?;
+            stream.write_all(&bytes)?;
+        }
+        Body::Stream(mut reader) if version == Version::Http11 => {
+            stream.write_all(b"Transfer-Encoding: chunked\r\n\r\n")?;
+            let mut chunked = ChunkedWriter::new(&mut *stream);
+            copy(&mut *reader, &mut chunked)?;
+            chunked.finish()?;
+        }
+        Body::Stream(mut reader) => {
+            stream.write_all(b"\r\n")?;
+            copy(&mut *reader, stream)?;
+        }
+    }
+    stream.flush()?;
+    Ok(keep_alive)
+}
+
+/// Reads requests from one connection and answers each with `handler` until
+/// the client closes the connection or a response requires closing it.
+/// Malformed requests are answered with `400` and end the connection.
+pub fn serve<S, F>(stream: &mut S, mut handler: F) -> io::Result<()>
+    where
+        S: Read + Write,
+        F: FnMut(&Request) -> Response,
+{
+    let mut buf = Vec::new();
+    loop {
+        let head = match read_head(stream, &mut buf)? {
+            Some(head) => head,
+            None => return Ok(()),
+        };
+
+        let parsed = Request::parse(&head).and_then(|request| {
+            let len = request.content_length()?;
+            Ok((request, len))
+        });
+        let request = match parsed {
+            Ok((request, len)) => {
+                // The server ignores request bodies, but must skip them to
+                // find the next request.
+                discard(stream, &mut buf, len)?;
+                request
+            }
+            Err(_) => {
+                write_response(stream, Version::Http11, false, Response::text(400, "bad request\n"))?;
+                return Ok(());
+            }
+        };
+
+        let keep_alive = request.keep_alive();
+        let response = handler(&request);
+        if !write_response(stream, request.version, keep_alive, response)? {
+            return Ok(());
+        }
+    }
+}
+
+/// Reads from `stream` into `buf` until it holds a full request head, then
+/// removes the head and its blank line from `buf` and returns the head. Bytes
+/// after the head stay in `buf` for the next call. Returns `None` if the
+/// stream ends before any byte of a new request arrives.
+fn read_head<R: Read>(stream: &mut R, buf: &mut Vec<u8>) -> io::Result<Option<Vec<u8>>> {
+    let mut chunk = [0u8; 256];
+    loop {
+        if let Some(end) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            let head = buf[..end].to_vec();
+            buf.drain(..end + 4);
+            return Ok(Some(head));
+        }
+        if buf.len() > MAX_HEAD_LEN {
+            // Let the parser reject it.
+            return Ok(Some(buf.split_off(0)));
+        }
+
+        let n = stream.read(&mut chunk)?;
+        if n == 0 {
+            if buf.is_empty() {
+                return Ok(None);
+            }
+            return ioerr!(UnexpectedEof, "connection closed mid-request");
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    }
+}
+
+/// Drops the next `len` bytes of the stream, taking them from `buf` first.
+fn discard<R: Read>(stream: &mut R, buf: &mut Vec<u8>, len: usize) -> io::Result<()> {
+    let buffered = core::cmp::min(len, buf.len());
+    buf.drain(..buffered);
+    let mut remaining = len - buffered;
+    let mut chunk = [0u8; 256];
+    while remaining > 0 {
+        let want = core::cmp::min(remaining, chunk.len());
+        let n = stream.read(&mut chunk[..want])?;
+        if n == 0 {
+            return ioerr!(UnexpectedEof, "connection closed mid-body");
+        }
+        remaining -= n;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+    use alloc::vec;
+    use shim::io::Cursor;
+
+    /// A connection whose input is fixed and whose output is recorded.
+    struct MockStream {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl MockStream {
+        fn new(input: &[u8]) -> MockStream {
+            MockStream { input: Cursor::new(input.to_vec()), output: Vec::new() }
+        }
+
+        fn output(&self) -> &str {
+            core::str::from_utf8(&self.output).unwrap()
+        }
+    }
+
+    impl Read for MockStream {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for MockStream {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn echo_path(request: &Request) -> Response {
+        match request.path.as_str() {
+            "/a" => Response::text(200, "first"),
+            "/b" => Response::text(200, "second"),
+            _ => Response::text(404, "missing"),
+        }
+    }
+
+    #[test]
+    fn parses_request_head() {
+        let head = b"GET /index.html HTTP/1.1\r\nHost: pi\r\ncontent-length:  3 \r\nX-Empty:";
+        let request = Request::parse(head).unwrap();
+        assert_eq!(request.method, "GET");
+        assert_eq!(request.path, "/index.html");
+        assert_eq!(request.version, Version::Http11);
+        assert_eq!(request.headers.len(), 3);
+        assert_eq!(request.headers.get("host"), Some("pi"));
+        assert_eq!(request.headers.get("Content-Length"), Some("3"));
+        assert_eq!(request.headers.get("x-empty"), Some(""));
+        assert_eq!(request.headers.get("Accept"), None);
+    }
+
+    #[test]
+    fn rejects_malformed_heads() {
+        let heads: [&[u8]; 10] = [
+            b"",
+            b"GET",
+            b"GET /",
+            b"GET / HTTP/2.0",
+            b"GET index.html HTTP/1.1",
+            b"GET / HTTP/1.1 extra",
+            b"GET / HTTP/1.1\r\nNo colon",
+            b"GET / HTTP/1.1\r\nBad Name: x",
+            b"GET / HTTP/1.1\r\n: empty name",
+            b"GET /\xff HTTP/1.1",
+        ];
+        for head in heads.iter() {
+            assert_eq!(Request::parse(head), Err(ParseError::Malformed), "{:?}", head);
+        }
+    }
+
+    #[test]
+    fn header_map_is_bounded() {
+        let mut head = String::from("GET / HTTP/1.1");
+        for i in 0..MAX_HEADERS {
+            head.push_str(&alloc::format!("\r\nX-{}: {}", i, i));
+        }
+        assert_eq!(Request::parse(head.as_bytes()).unwrap().headers.len(), MAX_HEADERS);
+
+        head.push_str("\r\nX-Extra: 1");
+        assert_eq!(Request::parse(head.as_bytes()), Err(ParseError::TooLarge));
+    }
+
+    #[test]
+    fn keep_alive_defaults() {
+        let parse = |head: &str| Request::parse(head.as_bytes()).unwrap().keep_alive();
+        assert!(parse("GET / HTTP/1.1"));
+        assert!(!parse("GET / HTTP/1.1\r\nConnection: Close"));
+        assert!(!parse("GET / HTTP/1.0"));
+        assert!(parse("GET / HTTP/1.0\r\nConnection: keep-alive"));
+    }
+
+    #[test]
+    fn keep_alive_serves_sequential_requests() {
+        let mut stream = MockStream::new(
+            b"GET /a HTTP/1.1\r\nHost: pi\r\n\r\n\
+              POST /b HTTP/1.1\r\nContent-Length: 4\r\n\r\nbody\
+              GET /c HTTP/1.1\r\nConnection: close\r\n\r\n\
+              GET /a HTTP/1.1\r\n\r\n",
+        );
+        serve(&mut stream, echo_path).unwrap();
+        assert_eq!(
+            stream.output(),
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nConnection: keep-alive\r\n\
+             Content-Length: 5\r\n\r\nfirst\
+             HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nConnection: keep-alive\r\n\
+             Content-Length: 6\r\n\r\nsecond\
+             HTTP/1.1 404 Not Found\r\nContent-Type: text/plain\r\nConnection: close\r\n\
+             Content-Length: 7\r\n\r\nmissing"
+        );
+    }
+
+    #[test]
+    fn malformed_request_gets_400() {
+        let mut stream = MockStream::new(b"GARBAGE\r\n\r\nGET /a HTTP/1.1\r\n\r\n");
+        serve(&mut stream, echo_path).unwrap();
+        assert!(stream.output().starts_with("HTTP/1.1 400 Bad Request\r\n"));
+        assert!(stream.output().contains("Connection: close\r\n"));
+        assert!(!stream.output().contains("first"));
+    }
+
+    #[test]
+    fn chunked_framing() {
+        let mut chunked = ChunkedWriter::new(Vec::new());
+        chunked.write_all(b"hello").unwrap();
+        chunked.write_all(b"").unwrap();
+        chunked.write_all(&[b'x'; 26]).unwrap();
+        let out = chunked.finish().unwrap();
+
+        let mut expected = b"5\r\nhello\r\n1a\r\n".to_vec();
+        expected.extend_from_slice(&[b'x'; 26]);
+        expected.extend_from_slice(b"\r\n0\r\n\r\n");
+        assert_eq!(out, expected);
+    }
+
+    #[test]
+    fn streamed_body_is_chunked() {
+        let mut stream = MockStream::new(b"GET /file HTTP/1.1\r\n\r\nGET /a HTTP/1.1\r\n\r\n");
+        serve(&mut stream, |request| {
+            if request.path == "/file" {
+                let file = Cursor::new(vec![b'z'; 700]);
+                Response { status: 200, content_type: "text/plain", body: Body::Stream(Box::new(file)) }
+            } else {
+                echo_path(request)
+            }
+        })
+        .unwrap();
+
+        // The body is copied in 512-byte reads, so it arrives in two chunks.
+        let mut expected = b"HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nConnection: keep-alive\r\n\
+                             Transfer-Encoding: chunked\r\n\r\n200\r\n"
+            .to_vec();
+        expected.extend_from_slice(&[b'z'; 512]);
+        expected.extend_from_slice(b"\r\nbc\r\n");
+        expected.extend_from_slice(&[b'z'; 188]);
+        expected.extend_from_slice(b"\r\n0\r\n\r\nHTTP/1.1 200 OK");
+        assert!(stream.output.starts_with(&expected));
+        assert!(stream.output().ends_with("first"));
+    }
+
+    #[test]
+    fn streamed_body_closes_http10_connection() {
+        let mut stream = MockStream::new(b"GET /file HTTP/1.0\r\nConnection: keep-alive\r\n\r\n");
+        serve(&mut stream, |_| Response {
+            status: 200,
+            content_type: "text/plain",
+            body: Body::Stream(Box::new(Cursor::new(b"abc".to_vec()))),
+        })
+        .unwrap();
+        assert_eq!(
+            stream.output(),
+            "HTTP/1.0 200 OK\r\nContent-Type: text/plain\r\nConnection: close\r\n\r\nabc"
+        );
+        assert_eq!(Version::Http10.to_string(), "HTTP/1.0");
+    }
+}
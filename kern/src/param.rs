@@ -28,8 +28,12 @@ pub const KERN_STACK_SIZE: usize = PAGE_SIZE;
 
 pub const USER_MAX_VA: usize = 0xffff_ffff_ffff_ffff;
 
-/// The `tick` time.
-pub const TICK: Duration = Duration::from_millis(100);
+/// The default `tick` time in milliseconds.
+pub const TICK_MS: u64 = 100;
+
+/// The default `tick` time. The scheduler re-arms the timer with
+/// `config::quantum()`, which starts out at this value.
+pub const TICK: Duration = Duration::from_millis(TICK_MS);
 
 // Match this value with `HZ` in `timer.h`
 pub const USPI_TIMER_HZ: usize = 10;
@@ -0,0 +1,183 @@
+//! Validation for the shell's `poke` command, which writes a word to memory.
+
+use core::fmt;
+use core::ops::Range;
+
+use pi::common::{IO_BASE, IO_BASE_END};
+
+use crate::allocator::memory_map;
+
+/// Why a `poke` was refused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PokeError {
+    /// The arguments were not `[--force] 0xADDR 0xVALUE`.
+    Usage,
+    /// The address is not 4-byte aligned.
+    Unaligned,
+    /// The address is inside the kernel image.
+    KernelImage,
+    /// The address is outside of the memory managed by the allocator.
+    OutOfBounds,
+    /// The address is a device register and `--force` was not given.
+    DeviceNeedsForce,
+}
+
+impl fmt::Display for PokeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let msg = match self {
+            PokeError::Usage => "usage: poke [--force] 0xADDR 0xVALUE",
+            PokeError::Unaligned => "address is not 4-byte aligned",
+            PokeError::KernelImage => "address is inside the kernel image",
+            PokeError::OutOfBounds => "address is outside of allocator memory",
+            PokeError::DeviceNeedsForce => "address is a device register; use --force",
+        };
+        f.write_str(msg)
+    }
+}
+
+/// The memory regions that decide whether an address may be written.
+#[derive(Debug, Clone)]
+pub struct MemoryLayout {
+    /// The kernel image: code, read-only data and kernel statics.
+    pub kernel: Range<usize>,
+    /// The memory handed to the allocator.
+    pub heap: Range<usize>,
+    /// The memory-mapped peripherals.
+    pub io: Range<usize>,
+}
+
+/// A parsed `poke` request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Poke {
+    pub addr: usize,
+    pub value: u32,
+    pub force: bool,
+}
+
+/// Parses a hexadecimal number with an optional `0x` prefix.
+fn parse_hex(s: &str) -> Option<u64> {
+    u64::from_str_radix(s.trim_start_matches("0x"), 16).ok()
+}
+
+/// Parses the arguments of `poke [--force] 0xADDR 0xVALUE`.
+pub fn parse_args(args: &[&str]) -> Result<Poke, PokeError> {
+    let (force, args) = match args.split_first() {
+        Some((&"--force", rest)) => (true, rest),
+        _ => (false, args),
+    };
+    if args.len() != 2 {
+        return Err(PokeError::Usage);
+    }
+
+    let addr = parse_hex(args[0]).ok_or(PokeError::Usage)?;
+    let value = parse_hex(args[1]).ok_or(PokeError::Usage)?;
+    if value > core::u32::MAX as u64 {
+        return Err(PokeError::Usage);
+    }
+    Ok(Poke { addr: addr as usize, value: value as u32, force })
+}
+
+extern "C" {
+    static __text_beg: u8;
+    static __text_end: u8;
+}
+
+impl MemoryLayout {
+    /// Returns the layout of the running kernel.
+    pub fn current() -> Option<MemoryLayout> {
+        let (start, end) = memory_map()?;
+        let kernel = unsafe {
+            (&__text_beg as *const u8 as usize)..(&__text_end as *const u8 as usize)
+        };
+        Some(MemoryLayout { kernel, heap: start..end, io: IO_BASE..IO_BASE_END })
+    }
+
+    /// Checks that `poke` may write to its address.
+    pub fn check(&self, poke: &Poke) -> Result<(), PokeError> {
+        let addr = poke.addr;
+        if addr % 4 != 0 {
+            return Err(PokeError::Unaligned);
+        }
+        if self.kernel.start <= addr && addr < self.kernel.end {
+            return Err(PokeError::KernelImage);
+        }
+        // The peripherals are above the RAM reported to the allocator.
+        if self.io.start <= addr && addr < self.io.end {
+            return match poke.force {
+                true => Ok(()),
+                false => Err(PokeError::DeviceNeedsForce),
+            };
+        }
+        // The whole word must lie inside the heap.
+        if addr < self.heap.start || addr > self.heap.end - 4 {
+            return Err(PokeError::OutOfBounds);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_args, MemoryLayout, Poke, PokeError};
+
+    fn layout() -> MemoryLayout {
+        MemoryLayout {
+            kernel: 0x8_0000..0x20_0000,
+            heap: 0x20_0000..0x3C00_0000,
+            io: 0xFE00_0000..0x1_0000_0000,
+        }
+    }
+
+    fn poke(addr: usize, force: bool) -> Poke {
+        Poke { addr, value: 0, force }
+    }
+
+    #[test]
+    fn parses_two_hex_values() {
+        assert_eq!(
+            parse_args(&["0x400000", "0xDEADBEEF"]),
+            Ok(Poke { addr: 0x40_0000, value: 0xDEAD_BEEF, force: false })
+        );
+        assert_eq!(
+            parse_args(&["--force", "fe200000", "1"]),
+            Ok(Poke { addr: 0xFE20_0000, value: 1, force: true })
+        );
+    }
+
+    #[test]
+    fn rejects_bad_arguments() {
+        assert_eq!(parse_args(&[]), Err(PokeError::Usage));
+        assert_eq!(parse_args(&["0x400000"]), Err(PokeError::Usage));
+        assert_eq!(parse_args(&["0x400000", "0x1", "0x2"]), Err(PokeError::Usage));
+        assert_eq!(parse_args(&["0xZZ", "0x1"]), Err(PokeError::Usage));
+        assert_eq!(parse_args(&["0x400000", "0x100000000"]), Err(PokeError::Usage));
+        assert_eq!(parse_args(&["--force"]), Err(PokeError::Usage));
+    }
+
+    #[test]
+    fn allows_heap_words() {
+        let layout = layout();
+        assert_eq!(layout.check(&poke(0x20_0000, false)), Ok(()));
+        assert_eq!(layout.check(&poke(0x3BFF_FFFC, false)), Ok(()));
+    }
+
+    #[test]
+    fn rejects_unsafe_addresses() {
+        let layout = layout();
+        assert_eq!(layout.check(&poke(0x40_0002, false)), Err(PokeError::Unaligned));
+        assert_eq!(layout.check(&poke(0x8_0000, false)), Err(PokeError::KernelImage));
+        assert_eq!(layout.check(&poke(0x1F_FFFC, true)), Err(PokeError::KernelImage));
+        assert_eq!(layout.check(&poke(0x1000, true)), Err(PokeError::OutOfBounds));
+        assert_eq!(layout.check(&poke(0x3C00_0000, true)), Err(PokeError::OutOfBounds));
+        assert_eq!(layout.check(&poke(0xFDFF_FFFC, true)), Err(PokeError::OutOfBounds));
+        assert_eq!(layout.check(&poke(0x1_0000_0000, true)), Err(PokeError::OutOfBounds));
+    }
+
+    #[test]
+    fn device_range_requires_force() {
+        let layout = layout();
+        assert_eq!(layout.check(&poke(0xFE20_0000, false)), Err(PokeError::DeviceNeedsForce));
+        assert_eq!(layout.check(&poke(0xFE20_0000, true)), Ok(()));
+        assert_eq!(layout.check(&poke(0xFFFF_FFFC, true)), Ok(()));
+    }
+}
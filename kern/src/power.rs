@@ -0,0 +1,157 @@
+//! Stopping and restarting the machine safely.
+//!
+//! The Pi cannot cut its own power without external hardware, so `poweroff`
+//! halts the core instead. Once it reports that it has halted, it is safe to
+//! pull the plug. `reboot` resets the board through the watchdog.
+
+use shim::io::{self, Write};
+
+use crate::console::{kerrorln, kprintln, CONSOLE};
+use crate::FILESYSTEM;
+
+/// The steps of a shutdown. Split out of `poweroff` so that their order can
+/// be tested.
+pub trait Shutdown {
+    /// Writes buffered file system data to the card.
+    fn sync_filesystem(&mut self) -> io::Result<()>;
+    /// Waits until all console output has been sent.
+    fn drain_console(&mut self);
+    /// Masks IRQs and FIQs on this core.
+    fn mask_interrupts(&mut self);
+    /// Stops this core. Only returns in tests.
+    fn halt(&mut self);
+    /// Resets the board, booting `partition` next. Only returns in tests.
+    fn reset(&mut self, partition: u8);
+}
+
+/// Runs the shutdown sequence: sync the file system, drain the console, mask
+/// interrupts and halt. A failed sync is reported but does not stop the
+/// shutdown.
+pub fn shut_down<S: Shutdown>(board: &mut S) {
+    if let Err(e) = board.sync_filesystem() {
+        kerrorln!("poweroff: failed to sync filesystem: {:?}", e.kind());
+    }
+    kprintln!("halted; it is now safe to power off");
+    board.drain_console();
+    board.mask_interrupts();
+    board.halt();
+}
+
+/// Runs the reboot sequence: the same steps as `shut_down`, but resetting
+/// the board into `partition` instead of halting.
+pub fn restart<S: Shutdown>(board: &mut S, partition: u8) {
+    if let Err(e) = board.sync_filesystem() {
+        kerrorln!("reboot: failed to sync filesystem: {:?}", e.kind());
+    }
+    kprintln!("rebooting");
+    board.drain_console();
+    board.mask_interrupts();
+    board.reset(partition);
+}
+
+/// The running board.
+struct Board;
+
+impl Shutdown for Board {
+    fn sync_filesystem(&mut self) -> io::Result<()> {
+        FILESYSTEM.sync()
+    }
+
+    fn drain_console(&mut self) {
+        let _ = CONSOLE.lock().flush();
+    }
+
+    fn mask_interrupts(&mut self) {
+        aarch64::disable_irq_interrupt();
+        aarch64::disable_fiq_interrupt();
+    }
+
+    fn halt(&mut self) {
+        loop {
+            aarch64::wfi();
+        }
+    }
+
+    fn reset(&mut self, partition: u8) {
+        pi::power::reset(partition)
+    }
+}
+
+/// Syncs the file system and halts this core in a low-power loop. This does
+/// not cut the power; see the module documentation.
+pub fn poweroff() -> ! {
+    shut_down(&mut Board);
+    unreachable!("Board::halt() returned")
+}
+
+/// Syncs the file system and resets the board. The firmware boots
+/// `partition` next; see `pi::power` for what the partition numbers mean.
+pub fn reboot(partition: u8) -> ! {
+    restart(&mut Board, partition);
+    unreachable!("Board::reset() returned")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{restart, shut_down, Shutdown};
+    use alloc::vec::Vec;
+    use shim::io;
+
+    #[derive(Default)]
+    struct MockBoard {
+        steps: Vec<&'static str>,
+        sync_fails: bool,
+    }
+
+    impl Shutdown for MockBoard {
+        fn sync_filesystem(&mut self) -> io::Result<()> {
+            self.steps.push("sync");
+            if self.sync_fails {
+                Err(io::Error::new(io::ErrorKind::TimedOut, "card timed out"))
+            } else {
+                Ok(())
+            }
+        }
+
+        fn drain_console(&mut self) {
+            self.steps.push("drain");
+        }
+
+        fn mask_interrupts(&mut self) {
+            self.steps.push("mask");
+        }
+
+        fn halt(&mut self) {
+            self.steps.push("halt");
+        }
+
+        fn reset(&mut self, partition: u8) {
+            self.steps.push(if partition == 0 { "reset" } else { "reset partition" });
+        }
+    }
+
+    #[test]
+    fn sync_before_halt() {
+        let mut board = MockBoard::default();
+        shut_down(&mut board);
+        assert_eq!(board.steps, ["sync", "drain", "mask", "halt"]);
+    }
+
+    #[test]
+    fn failed_sync_still_halts() {
+        let mut board = MockBoard { sync_fails: true, ..MockBoard::default() };
+        shut_down(&mut board);
+        assert_eq!(board.steps, ["sync", "drain", "mask", "halt"]);
+    }
+
+    #[test]
+    fn sync_before_reset() {
+        let mut board = MockBoard::default();
+        restart(&mut board, 0);
+        assert_eq!(board.steps, ["sync", "drain", "mask", "reset"]);
+
+        let mut board = MockBoard { sync_fails: true, ..MockBoard::default() };
+        restart(&mut board, 3);
+        assert_eq!(board.steps, ["sync", "drain", "mask", "reset partition"]);
+    }
+}
@@ -1,10 +1,15 @@
+mod cwd;
+mod fd;
+mod image;
 mod process;
 mod scheduler;
 mod stack;
 mod state;
 
+pub use self::cwd::{chdir, copy_cwd, resolve};
+pub use self::fd::{Fd, FdTable, OpenFile};
 pub use self::process::{Id, Process};
 pub use self::scheduler::GlobalScheduler;
 pub use self::stack::Stack;
-pub use self::state::State;
+pub use self::state::{EventPollFn, State, WAIT_ANY_INDEX_REG};
 pub use crate::param::TICK;
@@ -0,0 +1,98 @@
+use shim::path::{Component, Path, PathBuf};
+
+use kernel_api::{OsError, OsResult};
+
+/// Resolves `path` against the working directory `cwd`. Absolute paths are
+/// returned as is; `.` and `..` components are folded away.
+pub fn resolve<P: AsRef<Path>>(cwd: &Path, path: P) -> PathBuf {
+    let mut resolved = cwd.to_path_buf();
+    for component in path.as_ref().components() {
+        match component {
+            Component::RootDir => resolved = PathBuf::from("/"),
+            Component::ParentDir => {
+                resolved.pop();
+            }
+            Component::Normal(name) => resolved.push(name),
+            _ => (), // Nothing to do for `Prefix` or `CurDir`
+        }
+    }
+    resolved
+}
+
+/// Returns the new working directory after changing from `cwd` to `path`.
+/// `is_dir` reports whether the resolved path names a directory.
+///
+/// # Errors
+///
+/// Returns `OsError::InvalidArgument` if the path names a file, and any error
+/// returned by `is_dir`, e.g. `OsError::NoEntry` if nothing exists there.
+pub fn chdir<F>(cwd: &Path, path: &str, is_dir: F) -> OsResult<PathBuf>
+    where
+        F: FnOnce(&Path) -> OsResult<bool>,
+{
+    let target = resolve(cwd, path);
+    if is_dir(&target)? {
+        Ok(target)
+    } else {
+        Err(OsError::InvalidArgument)
+    }
+}
+
+/// Copies `cwd` into `buf` as UTF-8 and returns its length in bytes.
+///
+/// # Errors
+///
+/// Returns `OsError::InvalidArgument` if `buf` is too small.
+pub fn copy_cwd(cwd: &Path, buf: &mut [u8]) -> OsResult<usize> {
+    let bytes = cwd.to_str().ok_or(OsError::InvalidArgument)?.as_bytes();
+    if bytes.len() > buf.len() {
+        return Err(OsError::InvalidArgument);
+    }
+    buf[..bytes.len()].copy_from_slice(bytes);
+    Ok(bytes.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{chdir, copy_cwd, resolve};
+    use kernel_api::OsError;
+    use shim::path::{Path, PathBuf};
+
+    fn only_dirs(path: &Path) -> Result<bool, OsError> {
+        match path.to_str().unwrap() {
+            "/" | "/programs" | "/programs/old" => Ok(true),
+            "/programs/sleep.bin" => Ok(false),
+            _ => Err(OsError::NoEntry),
+        }
+    }
+
+    #[test]
+    fn resolves_relative_paths() {
+        let cwd = Path::new("/programs");
+        assert_eq!(resolve(cwd, "sleep.bin"), PathBuf::from("/programs/sleep.bin"));
+        assert_eq!(resolve(cwd, "./old/../sleep.bin"), PathBuf::from("/programs/sleep.bin"));
+        assert_eq!(resolve(cwd, ".."), PathBuf::from("/"));
+        assert_eq!(resolve(cwd, "/etc/motd"), PathBuf::from("/etc/motd"));
+    }
+
+    #[test]
+    fn chdir_validates_target() {
+        let cwd = Path::new("/");
+        assert_eq!(chdir(cwd, "programs", only_dirs), Ok(PathBuf::from("/programs")));
+        assert_eq!(chdir(Path::new("/programs"), "old", only_dirs), Ok(PathBuf::from("/programs/old")));
+        assert_eq!(chdir(cwd, "/programs/sleep.bin", only_dirs), Err(OsError::InvalidArgument));
+        assert_eq!(chdir(cwd, "missing", only_dirs), Err(OsError::NoEntry));
+    }
+
+    #[test]
+    fn getcwd_round_trip() {
+        let cwd = chdir(Path::new("/"), "programs/old", only_dirs).unwrap();
+
+        let mut buf = [0u8; 64];
+        let len = copy_cwd(&cwd, &mut buf).unwrap();
+        assert_eq!(&buf[..len], b"/programs/old");
+
+        let mut small = [0u8; 4];
+        assert_eq!(copy_cwd(&cwd, &mut small), Err(OsError::InvalidArgument));
+    }
+}
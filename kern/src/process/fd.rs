@@ -0,0 +1,204 @@
+use alloc::vec::Vec;
+use core::mem;
+
+use kernel_api::{OsError, OsResult};
+
+/// Type alias for the type of a file descriptor.
+pub type Fd = usize;
+
+/// The number of descriptors reserved for the console: stdin, stdout and
+/// stderr.
+pub const NUM_STD_FDS: Fd = 3;
+
+/// An entry in a process's descriptor table.
+#[derive(Debug)]
+pub enum OpenFile<F> {
+    /// The kernel console.
+    Console,
+    /// A file on the file system. The file keeps track of its own offset.
+    File(F),
+}
+
+/// A slot of an `FdTable`.
+#[derive(Debug)]
+enum Slot<F> {
+    Free,
+    Open(OpenFile<F>),
+    /// Taken out with `FdTable::lend` and not yet returned.
+    Lent,
+}
+
+/// A per-process table mapping file descriptors to open files.
+///
+/// Descriptors 0, 1 and 2 are opened on the console when the table is
+/// created. New files are given the lowest free descriptor, so closed
+/// descriptors are reused.
+#[derive(Debug)]
+pub struct FdTable<F> {
+    entries: Vec<Slot<F>>,
+}
+
+impl<F> FdTable<F> {
+    /// Returns a table with the standard descriptors opened on the console.
+    pub fn new() -> FdTable<F> {
+        let mut entries = Vec::new();
+        for _ in 0..NUM_STD_FDS {
+            entries.push(Slot::Open(OpenFile::Console));
+        }
+        FdTable { entries }
+    }
+
+    /// Stores `file` in the lowest free slot and returns its descriptor.
+    pub fn insert(&mut self, file: F) -> Fd {
+        let entry = Slot::Open(OpenFile::File(file));
+        match self.entries.iter().position(|e| match e { Slot::Free => true, _ => false }) {
+            Some(fd) => {
+                self.entries[fd] = entry;
+                fd
+            }
+            None => {
+                self.entries.push(entry);
+                self.entries.len() - 1
+            }
+        }
+    }
+
+    /// Returns the open file for `fd`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `OsError::BadDescriptor` if `fd` is not open or is lent out.
+    pub fn get_mut(&mut self, fd: Fd) -> OsResult<&mut OpenFile<F>> {
+        match self.entries.get_mut(fd) {
+            Some(Slot::Open(file)) => Ok(file),
+            _ => Err(OsError::BadDescriptor),
+        }
+    }
+
+    /// Takes the open file for `fd` out of the table, so that slow I/O on it
+    /// can run without whatever lock guards the table. The descriptor stays
+    /// allocated but unusable until the file is returned with `give_back`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `OsError::BadDescriptor` if `fd` is not open or is lent out.
+    pub fn lend(&mut self, fd: Fd) -> OsResult<OpenFile<F>> {
+        let slot = self.entries.get_mut(fd).ok_or(OsError::BadDescriptor)?;
+        match mem::replace(slot, Slot::Lent) {
+            Slot::Open(file) => Ok(file),
+            other => {
+                *slot = other;
+                Err(OsError::BadDescriptor)
+            }
+        }
+    }
+
+    /// Returns a file taken out with `lend` to its descriptor.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `fd` is not lent out.
+    pub fn give_back(&mut self, fd: Fd, file: OpenFile<F>) {
+        match self.entries.get_mut(fd) {
+            Some(slot) => match slot {
+                Slot::Lent => *slot = Slot::Open(file),
+                _ => panic!("fd {} was not lent out", fd),
+            },
+            None => panic!("fd {} was not lent out", fd),
+        }
+    }
+
+    /// Closes `fd`, dropping the file it refers to.
+    ///
+    /// # Errors
+    ///
+    /// Returns `OsError::BadDescriptor` if `fd` is not open or is lent out.
+    pub fn close(&mut self, fd: Fd) -> OsResult<()> {
+        let slot = self.entries.get_mut(fd).ok_or(OsError::BadDescriptor)?;
+        match slot {
+            Slot::Open(_) => {
+                *slot = Slot::Free;
+                Ok(())
+            }
+            _ => Err(OsError::BadDescriptor),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FdTable, OpenFile, NUM_STD_FDS};
+    use kernel_api::OsError;
+    use shim::io::{Cursor, Read};
+
+    #[test]
+    fn std_fds_are_console() {
+        let mut table: FdTable<()> = FdTable::new();
+        for fd in 0..NUM_STD_FDS {
+            match table.get_mut(fd) {
+                Ok(OpenFile::Console) => {}
+                other => panic!("fd {} is {:?}", fd, other),
+            }
+        }
+    }
+
+    #[test]
+    fn allocation_and_reuse() {
+        let mut table = FdTable::new();
+        assert_eq!(table.insert(10), 3);
+        assert_eq!(table.insert(11), 4);
+        assert_eq!(table.insert(12), 5);
+
+        table.close(4).unwrap();
+        assert_eq!(table.insert(13), 4);
+        match table.get_mut(4) {
+            Ok(OpenFile::File(13)) => {}
+            other => panic!("fd 4 is {:?}", other),
+        }
+        assert_eq!(table.insert(14), 6);
+    }
+
+    #[test]
+    fn read_through_fd() {
+        let mut table = FdTable::new();
+        let fd = table.insert(Cursor::new(b"hello, world".to_vec()));
+
+        let mut buf = [0u8; 5];
+        for expected in [&b"hello"[..], &b", wor"[..], &b"ld"[..]].iter() {
+            let n = match table.get_mut(fd).unwrap() {
+                OpenFile::File(file) => file.read(&mut buf).unwrap(),
+                OpenFile::Console => panic!("expected a file"),
+            };
+            assert_eq!(&buf[..n], *expected);
+        }
+    }
+
+    #[test]
+    fn lent_descriptor_stays_allocated() {
+        let mut table = FdTable::new();
+        let fd = table.insert(10);
+        let file = table.lend(fd).unwrap();
+
+        assert_eq!(table.get_mut(fd).unwrap_err(), OsError::BadDescriptor);
+        assert_eq!(table.lend(fd).unwrap_err(), OsError::BadDescriptor);
+        assert_eq!(table.close(fd).unwrap_err(), OsError::BadDescriptor);
+        assert_eq!(table.insert(11), fd + 1);
+
+        table.give_back(fd, file);
+        match table.get_mut(fd) {
+            Ok(OpenFile::File(10)) => {}
+            other => panic!("fd {} is {:?}", fd, other),
+        }
+    }
+
+    #[test]
+    fn bad_descriptors() {
+        let mut table = FdTable::new();
+        let fd = table.insert(1);
+        table.close(fd).unwrap();
+
+        assert_eq!(table.get_mut(fd).unwrap_err(), OsError::BadDescriptor);
+        assert_eq!(table.close(fd).unwrap_err(), OsError::BadDescriptor);
+        assert_eq!(table.get_mut(100).unwrap_err(), OsError::BadDescriptor);
+    }
+}
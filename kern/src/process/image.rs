@@ -0,0 +1,114 @@
+//! Demand paging of a process's executable image.
+//!
+//! A loaded program is not read into memory up front. Its pages are left
+//! unmapped, and the first access to each one raises a translation fault
+//! that `Process::page_in` answers by reading the page from the file the
+//! program was loaded from.
+
+use core::cmp::min;
+
+use shim::io;
+use shim::io::{Read, Seek, SeekFrom};
+
+use crate::fs::PiVFatHandle;
+use crate::param::{PAGE_MASK, PAGE_SIZE, USER_IMG_BASE};
+
+/// What to do about a translation fault at a user virtual address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaultAction {
+    /// Map the page at virtual address `page` and fill its first `len` bytes
+    /// with the image's bytes starting at `offset`.
+    PageIn { page: usize, offset: usize, len: usize },
+    /// The address is not backed by the image; the process is killed.
+    Kill,
+}
+
+/// Decides how to answer a translation fault at `va` in a process whose image
+/// is `image_size` bytes long and loaded at `USER_IMG_BASE`. `mapped` tells
+/// whether the page holding `va` is already mapped.
+///
+/// Only unmapped pages overlapping the image are paged in. The last page of
+/// the image is read up to the end of the file.
+pub fn resolve_fault(va: usize, image_size: usize, mapped: bool) -> FaultAction {
+    if mapped || va < USER_IMG_BASE {
+        return FaultAction::Kill;
+    }
+    let offset = (va - USER_IMG_BASE) & PAGE_MASK;
+    if offset >= image_size {
+        return FaultAction::Kill;
+    }
+    FaultAction::PageIn {
+        page: USER_IMG_BASE + offset,
+        offset,
+        len: min(PAGE_SIZE, image_size - offset),
+    }
+}
+
+/// The file a process's image is paged in from.
+#[derive(Debug)]
+pub struct Image {
+    file: fat32::vfat::File<PiVFatHandle>,
+    size: usize,
+}
+
+impl Image {
+    pub fn new(file: fat32::vfat::File<PiVFatHandle>) -> Image {
+        let size = file.metadata.size as usize;
+        Image { file, size }
+    }
+
+    /// Returns the size of the image in bytes.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Fills `buf` with the image's bytes starting at `offset`.
+    pub fn read_at(&mut self, offset: usize, buf: &mut [u8]) -> io::Result<()> {
+        self.file.seek(SeekFrom::Start(offset as u64))?;
+        self.file.read_exact(buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{resolve_fault, FaultAction};
+    use crate::param::{PAGE_SIZE, USER_IMG_BASE};
+
+    const SIZE: usize = 2 * PAGE_SIZE + 100;
+
+    #[test]
+    fn offset_of_faulting_address() {
+        assert_eq!(
+            resolve_fault(USER_IMG_BASE, SIZE, false),
+            FaultAction::PageIn { page: USER_IMG_BASE, offset: 0, len: PAGE_SIZE }
+        );
+        // A fault anywhere in a page loads the whole page.
+        assert_eq!(
+            resolve_fault(USER_IMG_BASE + PAGE_SIZE + 0x123, SIZE, false),
+            FaultAction::PageIn { page: USER_IMG_BASE + PAGE_SIZE, offset: PAGE_SIZE, len: PAGE_SIZE }
+        );
+    }
+
+    #[test]
+    fn last_page_stops_at_end_of_file() {
+        let expected = FaultAction::PageIn { page: USER_IMG_BASE + 2 * PAGE_SIZE, offset: 2 * PAGE_SIZE, len: 100 };
+        assert_eq!(resolve_fault(USER_IMG_BASE + 2 * PAGE_SIZE, SIZE, false), expected);
+        // Past the end of the file but inside its last page, e.g. the .bss.
+        assert_eq!(resolve_fault(USER_IMG_BASE + 3 * PAGE_SIZE - 1, SIZE, false), expected);
+    }
+
+    #[test]
+    fn kills_outside_image() {
+        assert_eq!(resolve_fault(USER_IMG_BASE + 3 * PAGE_SIZE, SIZE, false), FaultAction::Kill);
+        assert_eq!(resolve_fault(USER_IMG_BASE - 1, SIZE, false), FaultAction::Kill);
+        assert_eq!(resolve_fault(0, SIZE, false), FaultAction::Kill);
+        assert_eq!(resolve_fault(USER_IMG_BASE, 0, false), FaultAction::Kill);
+    }
+
+    #[test]
+    fn kills_on_mapped_page() {
+        // A translation fault on a page that is already mapped cannot be
+        // fixed by reading the image again.
+        assert_eq!(resolve_fault(USER_IMG_BASE, SIZE, true), FaultAction::Kill);
+    }
+}
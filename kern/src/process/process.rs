@@ -1,19 +1,21 @@
 use aarch64;
 use aarch64::SPSR_EL1;
-use fat32::traits::{Entry, File, FileSystem};
+use fat32::traits::{Entry, FileSystem};
 use kernel_api::{OsError, OsResult};
 use shim::io;
-use shim::io::Read;
-use shim::path::Path;
+use shim::path::{Path, PathBuf};
 use smoltcp::socket::SocketHandle;
 
 use alloc::boxed::Box;
 use core::mem;
 
 use crate::allocator::util::{align_down};
+use crate::fs::PiVFatHandle;
 use crate::FILESYSTEM;
 use crate::param::*;
-use crate::process::{Stack, State};
+use crate::process::{FdTable, Stack, State, WAIT_ANY_INDEX_REG};
+use crate::process::image::{resolve_fault, FaultAction, Image};
+use crate::process::state::poll_any;
 use crate::traps::TrapFrame;
 use crate::vm::*;
 
@@ -31,6 +33,13 @@ pub struct Process {
     pub vmap: Box<UserPageTable>,
     /// The scheduling state of the process.
     pub state: State,
+    /// The files opened by the process, indexed by file descriptor.
+    pub files: FdTable<fat32::vfat::File<PiVFatHandle>>,
+    /// The working directory against which relative paths are resolved.
+    pub cwd: PathBuf,
+    /// The file the process's image is paged in from, if it was loaded from
+    /// one.
+    pub image: Option<Image>,
     // Lab 5 2.C
     // Socket handles held by the current process
     // pub sockets: Vec<SocketHandle>,
@@ -50,6 +59,9 @@ impl Process {
             stack: stack,
             state: State::Ready,
             vmap: Box::new(UserPageTable::new()),
+            files: FdTable::new(),
+            cwd: PathBuf::from("/"),
+            image: None,
         })
     }
 
@@ -78,27 +90,62 @@ impl Process {
     }
 
     /// Creates a process and open a file with given path.
-    /// Allocates one page for stack with read/write permission, and N pages with read/write/execute
-    /// permission to load file's contents.
+    /// Allocates one page for stack with read/write permission. The file's
+    /// contents are not read here: each page of the image is mapped with
+    /// read/write/execute permission by `page_in` when it is first touched.
     fn do_load<P: AsRef<Path>>(pn: P) -> OsResult<Process> {
         let mut p = Process::new()?;
 
         let entry = (&FILESYSTEM).open(pn)?;
-        let mut file = entry.into_file().ok_or(OsError::NoEntry)?;
+        let file = entry.into_file().ok_or(OsError::NoEntry)?;
 
         p.vmap.alloc(VirtualAddr::from(Process::get_stack_base()), PagePerm::RW);
+        p.image = Some(Image::new(file));
 
-        let size = file.size() as usize;
-        let mut addr = USER_IMG_BASE;
-        let end_addr = addr + size;
+        Ok(p)
+    }
 
-        while addr < end_addr {
-            let bytes = p.vmap.alloc(VirtualAddr::from(addr), PagePerm::RWX);
-            file.read(bytes)?;
-            addr += PAGE_SIZE;
+    /// Handles a translation fault at user virtual address `va` by reading the
+    /// page of the image holding `va` from the file and mapping it.
+    ///
+    /// Returns `false` if the fault cannot be fixed this way, because `va` is
+    /// outside the image or the file could not be read, in which case the
+    /// process should be killed.
+    pub fn page_in(&mut self, va: usize) -> bool {
+        let image = match self.image.as_mut() {
+            Some(image) => image,
+            None => return false,
+        };
+        match resolve_fault(va, image.size(), self.vmap.is_mapped(va, 1)) {
+            FaultAction::PageIn { page, offset, len } => {
+                let bytes = self.vmap.alloc(VirtualAddr::from(page), PagePerm::RWX);
+                image.read_at(offset, &mut bytes[..len]).is_ok()
+            }
+            FaultAction::Kill => false,
         }
+    }
 
-        Ok(p)
+    /// Pages in every unmapped page of the image overlapping the `len` bytes
+    /// at `va`, so that the kernel can access them on the process's behalf.
+    /// Returns `true` if all of them are mapped afterwards.
+    pub fn fault_in(&mut self, va: usize, len: usize) -> bool {
+        if va < USER_IMG_BASE || len == 0 {
+            return self.vmap.is_mapped(va, len);
+        }
+        let last = match va.checked_add(len - 1) {
+            Some(last) => last & PAGE_MASK,
+            None => return false,
+        };
+        let mut page = va & PAGE_MASK;
+        loop {
+            if !self.vmap.is_mapped(page, 1) && !self.page_in(page) {
+                return false;
+            }
+            if page == last {
+                return true;
+            }
+            page += PAGE_SIZE;
+        }
     }
 
     /// Returns the highest `VirtualAddr` that is supported by this system.
@@ -138,25 +185,114 @@ impl Process {
     ///     If the process is currently waiting, the corresponding event
     ///     function is polled to determine if the event being waiting for has
     ///     occurred. If it has, the state is switched to `Ready` and this
-    ///     function returns `true`.
+    ///     function returns `true`. A process waiting on several events is
+    ///     woken by the first one that has occurred, and the index of that
+    ///     event is written to `x[WAIT_ANY_INDEX_REG]` of its trap frame.
     ///
     /// Returns `false` in all other cases.
     pub fn is_ready(&mut self) -> bool {
-        let mut state = mem::replace(&mut self.state, State::Ready);
-        match state {
-            State::Ready => true,
-            State::Waiting(ref mut event_poll_fn) => {
-                if event_poll_fn(self) {
+        wake(self, |p| &mut p.state, |p| &mut *p.context)
+    }
+}
+
+/// `Process::is_ready` for anything with a scheduling state and a trap frame,
+/// reached through `state` and `context`, so that it can be tested without
+/// allocating a process.
+fn wake<T>(waiter: &mut T, state: fn(&mut T) -> &mut State<T>, context: fn(&mut T) -> &mut TrapFrame) -> bool {
+    let mut current = mem::replace(state(waiter), State::Ready);
+    match current {
+        State::Ready => true,
+        State::Waiting(ref mut event_poll_fn) => {
+            if event_poll_fn(waiter) {
+                true
+            } else {
+                *state(waiter) = current;
+                false
+            }
+        }
+        State::WaitingAny(ref mut event_poll_fns) => {
+            match poll_any(event_poll_fns, waiter) {
+                Some(index) => {
+                    context(waiter).x[WAIT_ANY_INDEX_REG] = index as u64;
                     true
-                } else {
-                    self.state = state;
+                }
+                None => {
+                    *state(waiter) = current;
                     false
                 }
             }
-            _ => {
-                self.state = state;
-                false
-            }
         }
+        _ => {
+            *state(waiter) = current;
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::wake;
+    use crate::process::{State, WAIT_ANY_INDEX_REG};
+    use crate::traps::TrapFrame;
+    use alloc::boxed::Box;
+
+    /// Stands in for a `Process`, which cannot be allocated in tests.
+    struct Waiter {
+        state: State<Waiter>,
+        context: TrapFrame,
+        polls: usize,
+    }
+
+    fn is_ready(waiter: &mut Waiter) -> bool {
+        wake(waiter, |w| &mut w.state, |w| &mut w.context)
+    }
+
+    #[test]
+    fn waiting_any_writes_woken_index_to_x0() {
+        let mut waiter = Waiter {
+            state: State::WaitingAny(vec![
+                Box::new(|w: &mut Waiter| {
+                    w.polls += 1;
+                    false
+                }),
+                Box::new(|w: &mut Waiter| w.polls >= 2),
+            ]),
+            context: TrapFrame::default(),
+            polls: 0,
+        };
+        waiter.context.x[WAIT_ANY_INDEX_REG] = 99;
+
+        assert!(!is_ready(&mut waiter));
+        assert_eq!(waiter.context.x[WAIT_ANY_INDEX_REG], 99);
+        match waiter.state {
+            State::WaitingAny(ref fns) => assert_eq!(fns.len(), 2),
+            ref other => panic!("still waiting, not {:?}", other),
+        }
+
+        assert!(is_ready(&mut waiter));
+        assert_eq!(waiter.context.x[WAIT_ANY_INDEX_REG], 1);
+        match waiter.state {
+            State::Ready => {}
+            ref other => panic!("woken, not {:?}", other),
+        }
+    }
+
+    #[test]
+    fn waiting_leaves_x0_alone() {
+        let mut waiter = Waiter {
+            state: State::Waiting(Box::new(|w: &mut Waiter| {
+                w.polls += 1;
+                w.polls == 2
+            })),
+            context: TrapFrame::default(),
+            polls: 0,
+        };
+        waiter.context.x[WAIT_ANY_INDEX_REG] = 7;
+
+        assert!(!is_ready(&mut waiter));
+        assert!(is_ready(&mut waiter));
+        assert!(is_ready(&mut waiter));
+        assert_eq!(waiter.polls, 2);
+        assert_eq!(waiter.context.x[WAIT_ANY_INDEX_REG], 7);
     }
 }
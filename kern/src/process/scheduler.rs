@@ -2,13 +2,15 @@ use aarch64::*;
 use pi::armlocal::ArmLocalController;
 use pi::interrupt::{Controller, Interrupt};
 use pi::local_interrupt::{LocalController, LocalInterrupt};
+use pi::timer::current_time;
 
 use alloc::boxed::Box;
 use alloc::collections::vec_deque::VecDeque;
+use alloc::vec::Vec;
 use core::fmt;
 use core::ffi::c_void;
 
-use crate::{GLOBAL_IRQ, process, shell, VMM};
+use crate::{config, corestats, heartbeat, GLOBAL_IRQ, process, shell, VMM};
 use crate::{ETHERNET, USB};
 use crate::mutex::Mutex;
 use crate::net::uspi::TKernelTimerHandle;
@@ -16,9 +18,12 @@ use crate::param::*;
 use crate::percore::{get_preemptive_counter, is_mmu_ready, local_irq};
 use crate::process::{Id, Process, State};
 use crate::SCHEDULER;
+use crate::timers;
 use crate::traps::irq::IrqHandlerRegistry;
 use crate::traps::TrapFrame;
 
+use kernel_api::{OsError, OsResult};
+
 /// Process scheduler for the entire machine.
 #[derive(Debug)]
 pub struct GlobalScheduler(Mutex<Option<Box<Scheduler>>>);
@@ -62,6 +67,7 @@ impl GlobalScheduler {
     pub fn switch_to(&self, tf: &mut TrapFrame) -> Id {
         loop {
             let rtn = self.critical(|scheduler| scheduler.switch_to(tf));
+            corestats::current().set_running(rtn);
             if let Some(id) = rtn {
                 trace!(
                     "[core-{}] switch_to {:?}, pc: {:x}, lr: {:x}, x29: {:x}, x28: {:x}, x27: {:x}",
@@ -87,13 +93,21 @@ impl GlobalScheduler {
         self.critical(|scheduler| scheduler.kill(tf))
     }
 
+    /// Pages in the image page holding `va` for the process that faulted with
+    /// `tf`. Returns `false` if the fault cannot be fixed this way. For more
+    /// details, see the documentation on `Process::page_in()`.
+    pub fn page_in(&self, va: usize, tf: &TrapFrame) -> bool {
+        self.critical(|scheduler| scheduler.find_process(tf).page_in(va))
+    }
+
     /// Starts executing processes in user space using timer interrupt based
     /// preemptive scheduling. This method should not return under normal
     /// conditions.
     pub fn start(&self) -> ! {
 
         let mut tf = TrapFrame::default();
-        self.critical(|scheduler| scheduler.switch_to(&mut tf));
+        let id = self.critical(|scheduler| scheduler.switch_to(&mut tf));
+        corestats::current().set_running(id);
         let core = aarch64::affinity();
         if core == 0 {
             //self.initialize_global_timer_interrupt();
@@ -124,24 +138,32 @@ impl GlobalScheduler {
     }
 
     /// Initializes the per-core local timer interrupt with `pi::local_interrupt`.
-    /// The timer should be configured in a way that `CntpnsIrq` interrupt fires
-    /// every `TICK` duration, which is defined in `param.rs`.
+    /// The timer is re-armed after every interrupt with the current scheduling
+    /// quantum, `config::quantum()`, so changes take effect from the next tick.
+    /// Core 0 also drives the kernel timers in `crate::timers` and the
+    /// heartbeat pin from this interrupt.
     pub fn initialize_local_timer_interrupt(&self) {
         // Setup timer interrupt
         let registry = local_irq();
         registry.register(
             LocalInterrupt::TIMER_IRQ,
             Box::new(|tf| {
-                SCHEDULER.switch(State::Ready, tf);
                 let core = aarch64::affinity();
+                if core == 0 {
+                    timers::poll();
+                    heartbeat::poll(current_time());
+                }
+                let quantum = config::quantum();
+                corestats::current().record_tick(current_time(), quantum);
+                SCHEDULER.switch(State::Ready, tf);
                 let mut controller = LocalController::new(core);
-                controller.tick_in(TICK);
+                controller.tick_in(quantum);
             }),
         );
         let core = aarch64::affinity();
         let mut controller = LocalController::new(core);
         controller.enable_local_timer();
-        controller.tick_in(TICK);
+        controller.tick_in(config::quantum());
     }
 
     /// Initializes the scheduler and add userspace processes to the Scheduler.
@@ -179,6 +201,8 @@ extern "C" fn poll_ethernet(_: TKernelTimerHandle, _: *mut c_void, _: *mut c_voi
 pub struct Scheduler {
     processes: VecDeque<Process>,
     last_id: Option<Id>,
+    /// Processes killed by `kill_id` while they were running on a core.
+    doomed: Vec<Id>,
 }
 
 impl Scheduler {
@@ -187,6 +211,7 @@ impl Scheduler {
         Scheduler {
             processes: VecDeque::new(),
             last_id: None,
+            doomed: Vec::new(),
         }
     }
 
@@ -214,6 +239,9 @@ impl Scheduler {
     /// into the current process, and push the current process back to the
     /// end of `processes` queue.
     ///
+    /// If the current process was killed with `kill_id` while it was
+    /// running, its state is set to `Dead` instead of `new_state`.
+    ///
     /// If the `processes` queue is empty or there is no current process,
     /// returns `false`. Otherwise, returns `true`.
     fn schedule_out(&mut self, new_state: State, tf: &mut TrapFrame) -> bool {
@@ -222,7 +250,13 @@ impl Scheduler {
             let process = &mut self.processes[i];
             if process.context.TPIDR == tf.TPIDR {
                 *process.context = *tf;
-                process.state = new_state;
+                process.state = match self.doomed.iter().position(|&id| id == tf.TPIDR) {
+                    Some(doomed) => {
+                        self.doomed.swap_remove(doomed);
+                        State::Dead
+                    }
+                    None => new_state,
+                };
                 let process = self.processes.remove(i).unwrap();
                 self.processes.push_back(process);
                 return true;
@@ -236,9 +270,16 @@ impl Scheduler {
     /// `Running`, and performs context switch by restoring the next process`s
     /// trap frame into `tf`.
     ///
+    /// Dead processes left in the queue by `schedule_out` are dropped first.
+    ///
     /// If there is no process to switch to, returns `None`. Otherwise, returns
     /// `Some` of the next process`s process ID.
     fn switch_to(&mut self, tf: &mut TrapFrame) -> Option<Id> {
+        self.processes.retain(|process| match process.state {
+            State::Dead => false,
+            _ => true,
+        });
+
         let mut i = 0;
         while let Some(mut process) = self.processes.swap_remove_front(i) {
             if process.is_ready() {
@@ -258,18 +299,56 @@ impl Scheduler {
     /// as `Dead` state. Releases all process resources held by the process,
     /// removes the dead process from the queue, drops the dead process's
     /// instance, and returns the dead process's process ID.
+    ///
+    /// Returns `None` and kills nothing if `tf` is not a process's.
     fn kill(&mut self, tf: &mut TrapFrame) -> Option<Id> {
-        self.schedule_out(State::Dead, tf);
+        if !self.schedule_out(State::Dead, tf) {
+            return None;
+        }
         let process = self.processes.pop_back()?;
         Some((*process.context).TPIDR)
     }
 
+    /// Kills the process with ID `id`, which need not be the current one. A
+    /// process that is not running is removed from the queue and dropped
+    /// right away. One that is running on a core can't be dropped under it,
+    /// so it is killed when that core schedules it out instead.
+    ///
+    /// Returns `OsError::NoEntry` if there is no process with ID `id`.
+    pub fn kill_id(&mut self, id: Id) -> OsResult<()> {
+        let i = self.processes
+            .iter()
+            .position(|p| p.context.TPIDR == id)
+            .ok_or(OsError::NoEntry)?;
+        match self.processes[i].state {
+            State::Running => {
+                if !self.doomed.contains(&id) {
+                    self.doomed.push(id);
+                }
+            }
+            _ => {
+                self.processes.remove(i);
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the processes in the queue, in scheduling order.
+    pub fn processes(&self) -> impl Iterator<Item = &Process> {
+        self.processes.iter()
+    }
+
     /// Releases all process resources held by the current process such as sockets.
     fn release_process_resources(&mut self, tf: &mut TrapFrame) {
         // Lab 5 2.C
         unimplemented!("release_process_resources")
     }
 
+    /// Returns the process with ID `id`, if it exists.
+    pub fn process_by_id(&mut self, id: Id) -> Option<&mut Process> {
+        self.processes.iter_mut().find(|p| p.context.TPIDR == id)
+    }
+
     /// Finds a process corresponding with tpidr saved in a trap frame.
     /// Panics if the search fails.
     pub fn find_process(&mut self, tf: &TrapFrame) -> &mut Process {
@@ -1,6 +1,7 @@
 use core::fmt;
 
 use alloc::boxed::Box;
+use alloc::vec::Vec;
 
 use crate::process::Process;
 
@@ -9,27 +10,95 @@ use crate::process::Process;
 /// execute. If the function returns `true`, the process is scheduled. If it
 /// returns `false`, the process is not scheduled, and this function will be
 /// called on the next time slice.
-pub type EventPollFn = Box<dyn FnMut(&mut Process) -> bool + Send>;
+pub type EventPollFn<T = Process> = Box<dyn FnMut(&mut T) -> bool + Send>;
 
-/// The scheduling state of a process.
-pub enum State {
+/// The trap frame register that receives the index of the event that woke a
+/// process in the `WaitingAny` state.
+pub const WAIT_ANY_INDEX_REG: usize = 0;
+
+/// Polls each of `fns` in order with `arg` and returns the index of the first
+/// one that reports its event has occurred. Functions after that one are not
+/// polled.
+pub fn poll_any<T>(fns: &mut [Box<dyn FnMut(&mut T) -> bool + Send>], arg: &mut T) -> Option<usize> {
+    fns.iter_mut().position(|poll_fn| poll_fn(arg))
+}
+
+/// The scheduling state of a process. `T` is what the poll functions are
+/// given, the process itself outside of tests.
+pub enum State<T = Process> {
     /// The process is ready to be scheduled.
     Ready,
     /// The process is waiting on an event to occur before it can be scheduled.
-    Waiting(EventPollFn),
+    Waiting(EventPollFn<T>),
+    /// The process is waiting on any one of several events. When one occurs,
+    /// its index is written to register `x[WAIT_ANY_INDEX_REG]`.
+    WaitingAny(Vec<EventPollFn<T>>),
     /// The process is currently running.
     Running,
     /// The process is currently dead (ready to be reclaimed).
     Dead,
 }
 
-impl fmt::Debug for State {
+impl<T> fmt::Debug for State<T> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             State::Ready => write!(f, "State::Ready"),
             State::Running => write!(f, "State::Running"),
             State::Waiting(_) => write!(f, "State::Waiting"),
+            State::WaitingAny(ref fns) => write!(f, "State::WaitingAny({})", fns.len()),
             State::Dead => write!(f, "State::Dead"),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::poll_any;
+    use alloc::boxed::Box;
+    use alloc::vec::Vec;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    type PollFn = Box<dyn FnMut(&mut u32) -> bool + Send>;
+
+    fn event(flag: &Arc<AtomicBool>) -> PollFn {
+        let flag = flag.clone();
+        Box::new(move |polls: &mut u32| {
+            *polls += 1;
+            flag.load(Ordering::SeqCst)
+        })
+    }
+
+    fn two_events() -> (Arc<AtomicBool>, Arc<AtomicBool>, Vec<PollFn>) {
+        let a = Arc::new(AtomicBool::new(false));
+        let b = Arc::new(AtomicBool::new(false));
+        let fns = vec![event(&a), event(&b)];
+        (a, b, fns)
+    }
+
+    #[test]
+    fn waits_until_an_event_fires() {
+        let (_a, _b, mut fns) = two_events();
+        let mut polls = 0;
+        assert_eq!(poll_any(&mut fns, &mut polls), None);
+        assert_eq!(poll_any(&mut fns, &mut polls), None);
+        assert_eq!(polls, 4);
+    }
+
+    #[test]
+    fn reports_index_of_fired_event() {
+        let (a, b, mut fns) = two_events();
+        b.store(true, Ordering::SeqCst);
+        assert_eq!(poll_any(&mut fns, &mut 0), Some(1));
+
+        let (a2, _b2, mut fns2) = two_events();
+        a2.store(true, Ordering::SeqCst);
+        assert_eq!(poll_any(&mut fns2, &mut 0), Some(0));
+
+        // When both have fired, the first one wins and the rest are not polled.
+        a.store(true, Ordering::SeqCst);
+        let mut polls = 0;
+        assert_eq!(poll_any(&mut fns, &mut polls), Some(0));
+        assert_eq!(polls, 1);
+    }
+}
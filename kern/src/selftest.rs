@@ -0,0 +1,215 @@
+//! On-device smoke tests runnable from the shell with `selftest`.
+
+use alloc::alloc::{alloc, dealloc};
+use alloc::vec::Vec;
+use core::alloc::Layout;
+
+use fat32::traits::FileSystem;
+use pi::timer::current_time;
+use shim::io::Read;
+
+use crate::console::CONSOLE;
+use crate::mutex::Mutex;
+use crate::param::{PAGE_SIZE, USER_IMG_BASE};
+use crate::vm::{PagePerm, UserPageTable, VirtualAddr};
+use crate::FILESYSTEM;
+
+/// A smoke test. Returns a description of the failure if it fails.
+pub type SelftestFn = fn() -> Result<(), &'static str>;
+
+/// The outcome of a run of the registered tests.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Summary {
+    pub passed: usize,
+    pub failed: usize,
+    /// Tests not run because an earlier test failed and the run stopped.
+    pub skipped: usize,
+}
+
+/// An ordered set of named smoke tests.
+pub struct Registry {
+    tests: Vec<(&'static str, SelftestFn)>,
+}
+
+impl Registry {
+    /// Returns an empty registry.
+    pub fn new() -> Registry {
+        Registry { tests: Vec::new() }
+    }
+
+    /// Returns a registry holding the built-in tests.
+    pub fn with_builtins() -> Registry {
+        let mut registry = Registry::new();
+        registry.register("allocator round-trip", alloc_round_trip);
+        registry.register("timer monotonic", timer_monotonic);
+        registry.register("filesystem read", filesystem_read);
+        registry.register("page table alloc/free", page_table_alloc);
+        registry.register("uart loopback", uart_loopback);
+        registry
+    }
+
+    /// Adds `test` to the end of the registry.
+    pub fn register(&mut self, name: &'static str, test: SelftestFn) {
+        self.tests.push((name, test));
+    }
+
+    /// Returns the number of registered tests.
+    pub fn len(&self) -> usize {
+        self.tests.len()
+    }
+
+    /// Runs the tests in registration order, calling `report` with the name
+    /// and result of each one. If `stop_on_failure` is set, the tests after
+    /// the first failing one are skipped.
+    pub fn run<F>(&self, stop_on_failure: bool, mut report: F) -> Summary
+        where
+            F: FnMut(&'static str, Result<(), &'static str>),
+    {
+        let mut summary = Summary::default();
+        for (i, &(name, test)) in self.tests.iter().enumerate() {
+            let result = test();
+            match result {
+                Ok(()) => summary.passed += 1,
+                Err(_) => summary.failed += 1,
+            }
+            report(name, result);
+
+            if result.is_err() && stop_on_failure {
+                summary.skipped = self.tests.len() - i - 1;
+                break;
+            }
+        }
+        summary
+    }
+}
+
+static SELFTESTS: Mutex<Option<Registry>> = Mutex::new(None);
+
+/// Adds `test` to the tests run by the `selftest` shell command.
+pub fn register_selftest(name: &'static str, test: SelftestFn) {
+    SELFTESTS.lock().get_or_insert_with(Registry::with_builtins).register(name, test);
+}
+
+/// Runs the registered tests. See `Registry::run`.
+pub fn run<F>(stop_on_failure: bool, report: F) -> Summary
+    where
+        F: FnMut(&'static str, Result<(), &'static str>),
+{
+    SELFTESTS.lock().get_or_insert_with(Registry::with_builtins).run(stop_on_failure, report)
+}
+
+fn alloc_round_trip() -> Result<(), &'static str> {
+    for &size in [8usize, 100, 4096, 1 << 20].iter() {
+        let layout = Layout::from_size_align(size, 16).map_err(|_| "bad layout")?;
+        let ptr = unsafe { alloc(layout) };
+        if ptr.is_null() {
+            return Err("allocation failed");
+        }
+        if ptr as usize % 16 != 0 {
+            return Err("allocation misaligned");
+        }
+        unsafe {
+            ptr.write_bytes(0xA5, size);
+            let last = *ptr.add(size - 1);
+            dealloc(ptr, layout);
+            if last != 0xA5 {
+                return Err("allocation not writable");
+            }
+        }
+    }
+    Ok(())
+}
+
+fn timer_monotonic() -> Result<(), &'static str> {
+    let mut last = current_time();
+    for _ in 0..1000 {
+        let now = current_time();
+        if now < last {
+            return Err("timer went backwards");
+        }
+        last = now;
+    }
+    Ok(())
+}
+
+fn filesystem_read() -> Result<(), &'static str> {
+    let mut file = (&FILESYSTEM)
+        .open_file("/programs/sleep.bin")
+        .map_err(|_| "cannot open /programs/sleep.bin")?;
+    let mut buf = [0u8; 512];
+    match file.read(&mut buf) {
+        Ok(0) => Err("/programs/sleep.bin is empty"),
+        Ok(_) => Ok(()),
+        Err(_) => Err("cannot read /programs/sleep.bin"),
+    }
+}
+
+fn page_table_alloc() -> Result<(), &'static str> {
+    let mut table = UserPageTable::new();
+    let page = table.alloc(VirtualAddr::from(USER_IMG_BASE), PagePerm::RW);
+    page[PAGE_SIZE - 1] = 0x5A;
+    if !table.is_mapped(USER_IMG_BASE, PAGE_SIZE) {
+        return Err("allocated page is not mapped");
+    }
+    if table.is_mapped(USER_IMG_BASE + PAGE_SIZE, 1) {
+        return Err("unallocated page is mapped");
+    }
+    // Dropping the table frees the page.
+    Ok(())
+}
+
+fn uart_loopback() -> Result<(), &'static str> {
+    if CONSOLE.lock().uart_loopback_test() {
+        Ok(())
+    } else {
+        Err("byte written in loopback mode was not read back")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Registry, Summary};
+    use alloc::vec::Vec;
+
+    fn pass() -> Result<(), &'static str> {
+        Ok(())
+    }
+
+    fn fail() -> Result<(), &'static str> {
+        Err("failed")
+    }
+
+    fn registry(tests: &[fn() -> Result<(), &'static str>]) -> Registry {
+        let mut registry = Registry::new();
+        for (i, &test) in tests.iter().enumerate() {
+            registry.register(["a", "b", "c", "d"][i], test);
+        }
+        registry
+    }
+
+    #[test]
+    fn registration_order() {
+        let registry = registry(&[pass, fail, pass]);
+        assert_eq!(registry.len(), 3);
+
+        let mut seen = Vec::new();
+        registry.run(false, |name, result| seen.push((name, result)));
+        assert_eq!(seen, [("a", Ok(())), ("b", Err("failed")), ("c", Ok(()))]);
+    }
+
+    #[test]
+    fn tallies() {
+        let registry = registry(&[pass, fail, pass, fail]);
+        let summary = registry.run(false, |_, _| {});
+        assert_eq!(summary, Summary { passed: 2, failed: 2, skipped: 0 });
+    }
+
+    #[test]
+    fn stops_on_first_failure() {
+        let registry = registry(&[pass, fail, pass, fail]);
+        let mut seen = Vec::new();
+        let summary = registry.run(true, |name, _| seen.push(name));
+        assert_eq!(summary, Summary { passed: 1, failed: 1, skipped: 2 });
+        assert_eq!(seen, ["a", "b"]);
+    }
+}
@@ -0,0 +1,117 @@
+use alloc::boxed::Box;
+use core::ops::Deref;
+use core::ptr::NonNull;
+use core::sync::atomic::{self, AtomicUsize, Ordering};
+
+use crate::percore::is_mmu_ready;
+
+/// A thread-safe reference-counted pointer, like `Arc`, that can be created
+/// and cloned before the MMU is enabled.
+///
+/// `Arc` updates its count with exclusive accesses, which require the MMU on
+/// ARM. Like `Mutex`, `Shared` falls back to plain loads and stores while the
+/// MMU of the calling core is off. Only core 0 runs at that point, and the
+/// other cores enable their MMU before touching anything shared, so the count
+/// is never updated concurrently without exclusive accesses.
+pub struct Shared<T> {
+    inner: NonNull<Inner<T>>,
+}
+
+struct Inner<T> {
+    count: AtomicUsize,
+    value: T,
+}
+
+unsafe impl<T: Send + Sync> Send for Shared<T> {}
+unsafe impl<T: Send + Sync> Sync for Shared<T> {}
+
+impl<T> Shared<T> {
+    /// Moves `value` to the heap and returns the first reference to it.
+    pub fn new(value: T) -> Shared<T> {
+        let inner = Box::new(Inner { count: AtomicUsize::new(1), value });
+        Shared { inner: unsafe { NonNull::new_unchecked(Box::into_raw(inner)) } }
+    }
+
+    fn inner(&self) -> &Inner<T> {
+        unsafe { self.inner.as_ref() }
+    }
+}
+
+/// Adds one to `count`, with exclusive accesses if `exclusive` is set.
+fn increment(count: &AtomicUsize, exclusive: bool) {
+    if exclusive {
+        count.fetch_add(1, Ordering::Relaxed);
+    } else {
+        count.store(count.load(Ordering::Relaxed) + 1, Ordering::Relaxed);
+    }
+}
+
+/// Subtracts one from `count`, with exclusive accesses if `exclusive` is set,
+/// and returns `true` if that removed the last reference.
+fn decrement(count: &AtomicUsize, exclusive: bool) -> bool {
+    if exclusive {
+        if count.fetch_sub(1, Ordering::Release) != 1 {
+            return false;
+        }
+        // Orders the drop after every other reference's last use.
+        atomic::fence(Ordering::Acquire);
+        true
+    } else {
+        let n = count.load(Ordering::Relaxed) - 1;
+        count.store(n, Ordering::Relaxed);
+        n == 0
+    }
+}
+
+impl<T> Clone for Shared<T> {
+    fn clone(&self) -> Shared<T> {
+        increment(&self.inner().count, is_mmu_ready());
+        Shared { inner: self.inner }
+    }
+}
+
+impl<T> Drop for Shared<T> {
+    fn drop(&mut self) {
+        if decrement(&self.inner().count, is_mmu_ready()) {
+            unsafe { drop(Box::from_raw(self.inner.as_ptr())) }
+        }
+    }
+}
+
+impl<T> Deref for Shared<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.inner().value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decrement, increment};
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn count_updates() {
+        for &exclusive in [false, true].iter() {
+            let count = AtomicUsize::new(1);
+            increment(&count, exclusive);
+            increment(&count, exclusive);
+            assert_eq!(count.load(Ordering::Relaxed), 3);
+            assert!(!decrement(&count, exclusive));
+            assert!(!decrement(&count, exclusive));
+            assert!(decrement(&count, exclusive));
+        }
+    }
+
+    #[test]
+    fn mixed_updates() {
+        // References taken before the MMU is up may be dropped after.
+        let count = AtomicUsize::new(1);
+        increment(&count, false);
+        assert!(!decrement(&count, true));
+        increment(&count, true);
+        assert!(!decrement(&count, false));
+        assert!(decrement(&count, true));
+    }
+}
@@ -1,16 +1,41 @@
-use shim::path::{PathBuf, Component};
+use shim::path::PathBuf;
 
 use fat32::traits::FileSystem;
 use fat32::traits::{Dir, Entry, File};
+use fat32::vfat::{BackupBootCheck, VFatHandle};
 
-use crate::console::{kprint, kprintln, CONSOLE};
-use crate::{FILESYSTEM};
+use crate::allocator;
+use crate::bench;
+use crate::config;
+use crate::corestats;
+use crate::cpuinfo;
+use crate::dd;
+use crate::net;
+use crate::poke;
+use crate::power;
+use crate::process;
+use crate::selftest;
+use crate::traps::stats::IrqStat;
+use crate::vm;
+use crate::fs::sd::Sd;
+use crate::console::{clear_screen, color, kerrorln, kprint, kprintln, reset, Color, CONSOLE};
+use crate::{ALLOCATOR, FILESYSTEM, IRQ_STATS, SCHEDULER};
 
+use shim::io;
 use shim::io::Write;
 use shim::io::Read;
 
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::format;
+use alloc::string::String;
 use alloc::vec::Vec;
 
+use kernel_api::OsError;
+
+use pi::interrupt::Interrupt;
+use pi::local_interrupt::LocalInterrupt;
+use pi::timer::current_time;
+
 use core::str;
 use core::fmt;
 use core::time::Duration;
@@ -52,65 +77,417 @@ impl<'a> Command<'a> {
 }
 
 
+/// The maximum length of a command line in bytes.
+const CMD_LEN: usize = 512;
+
+/// The commands handled by `Shell::process_command`.
+const BUILTINS: &[&str] = &[
+    "echo", "time", "uptime", "clear", "pwd", "ls", "cd", "cat", "mkdir", "rm", "bench", "maps", "filefrag", "tree", "dd", "fsck",
+    "selftest", "mount", "remount", "cpuinfo", "coreinfo", "exit", "poweroff", "reboot", "colors", "sched_quantum", "heartbeat", "watch",
+    "sleep", "ps", "kill", "mem", "mem-stats", "poke", "irqstats", "netstat", "nameserver", "host", "alias", "which",
+];
+
+/// How often `watch` re-runs its command unless `-n` is given.
+const DEFAULT_WATCH_INTERVAL: Duration = Duration::from_secs(2);
+
+/// How often `watch` checks for a key press while it waits.
+const WATCH_POLL: Duration = Duration::from_millis(100);
+
+/// The number of command lines the shell remembers for recalling with the
+/// arrow keys.
+const HISTORY_LEN: usize = 16;
+
+/// The most aliases expanded for one command line. Deeper expansion means
+/// the aliases refer to each other in a loop.
+const MAX_ALIAS_DEPTH: usize = 8;
+
+/// Error returned by `Aliases::expand` when expansion does not terminate
+/// within `MAX_ALIAS_DEPTH` steps.
+#[derive(Debug, PartialEq)]
+struct AliasLoop<'a>(&'a str);
+
+/// Shorthand names for command lines, expanded before a line is parsed.
+struct Aliases {
+    map: BTreeMap<String, String>,
+}
+
+impl Aliases {
+    fn new() -> Aliases {
+        Aliases { map: BTreeMap::new() }
+    }
+
+    /// Defines `name` as shorthand for `expansion`, replacing any previous
+    /// definition.
+    fn define(&mut self, name: &str, expansion: &str) {
+        self.map.insert(String::from(name), String::from(expansion));
+    }
+
+    /// Returns the expansion of `name`, if it is an alias.
+    fn get(&self, name: &str) -> Option<&str> {
+        self.map.get(name).map(|e| e.as_str())
+    }
+
+    /// Replaces the first word of `line` while it names an alias. An alias
+    /// whose expansion starts with its own name, like `ls='ls -a'`, is
+    /// expanded once.
+    ///
+    /// # Errors
+    ///
+    /// Returns the name of the first alias if expansion takes more than
+    /// `MAX_ALIAS_DEPTH` steps.
+    fn expand<'a>(&self, line: &'a str) -> Result<String, AliasLoop<'a>> {
+        let mut expanded = String::from(line);
+        for depth in 0..=MAX_ALIAS_DEPTH {
+            let trimmed = expanded.trim_start();
+            let word = trimmed.split(' ').next().unwrap_or("");
+            let expansion = match self.get(word) {
+                Some(expansion) => expansion,
+                None => return Ok(expanded),
+            };
+            if depth == MAX_ALIAS_DEPTH {
+                break;
+            }
+
+            let next = format!("{}{}", expansion, &trimmed[word.len()..]);
+            if expansion.split(' ').next() == Some(word) {
+                return Ok(next);
+            }
+            expanded = next;
+        }
+        Err(AliasLoop(line.trim_start().split(' ').next().unwrap_or("")))
+    }
+
+    /// Parses `name=expansion` as written after `alias`, removing one pair of
+    /// matching quotes around the expansion.
+    fn parse_definition(definition: &str) -> Option<(&str, &str)> {
+        let eq = definition.find('=')?;
+        let (name, expansion) = (&definition[..eq], &definition[eq + 1..]);
+        if name.is_empty() || name.contains(' ') {
+            return None;
+        }
+        let unquoted = ["'", "\""].iter().find_map(|q| {
+            if expansion.len() >= 2 && expansion.starts_with(q) && expansion.ends_with(q) {
+                Some(&expansion[1..expansion.len() - 1])
+            } else {
+                None
+            }
+        });
+        Some((name, unquoted.unwrap_or(expansion)))
+    }
+}
+
+/// What `which` reports a command name to be.
+#[derive(Debug, PartialEq)]
+enum Which<'a> {
+    Alias(&'a str),
+    Builtin,
+    NotFound,
+}
+
+/// Classifies `name`. Aliases take precedence over builtins because they are
+/// expanded first.
+fn which<'a>(aliases: &'a Aliases, name: &str) -> Which<'a> {
+    match aliases.get(name) {
+        Some(expansion) => Which::Alias(expansion),
+        None if BUILTINS.contains(&name) => Which::Builtin,
+        None => Which::NotFound,
+    }
+}
+
+/// The most recently entered command lines, oldest first.
+struct History {
+    lines: VecDeque<String>,
+}
+
+impl History {
+    fn new() -> History {
+        History { lines: VecDeque::with_capacity(HISTORY_LEN) }
+    }
+
+    /// Remembers `line`, forgetting the oldest line if `HISTORY_LEN` lines
+    /// are remembered already. Blank lines are not remembered.
+    fn push(&mut self, line: &str) {
+        if line.trim().is_empty() {
+            return;
+        }
+        if self.lines.len() == HISTORY_LEN {
+            self.lines.pop_front();
+        }
+        self.lines.push_back(String::from(line));
+    }
+
+    /// Returns the line entered `back` lines ago, where 1 is the most recent.
+    fn get(&self, back: usize) -> Option<&str> {
+        let index = self.lines.len().checked_sub(back)?;
+        self.lines.get(index).map(|l| l.as_str())
+    }
+}
+
+/// The byte stream the shell reads commands from and echoes them to.
+trait Terminal {
+    /// Blocks until a byte is available and returns it.
+    fn read_byte(&mut self) -> u8;
+
+    /// Returns a byte if one is available, without blocking.
+    fn try_read_byte(&mut self) -> Option<u8>;
+
+    /// Writes `bytes` to the terminal.
+    fn write_bytes(&mut self, bytes: &[u8]);
+}
+
+/// The kernel console.
+struct ConsoleTerminal;
+
+impl Terminal for ConsoleTerminal {
+    fn read_byte(&mut self) -> u8 {
+        CONSOLE.lock().read_byte()
+    }
+
+    fn try_read_byte(&mut self) -> Option<u8> {
+        CONSOLE.lock().try_read_byte()
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        let mut console = CONSOLE.lock();
+        for &byte in bytes {
+            console.write_byte(byte);
+        }
+    }
+}
+
+/// Error returned by `read_line` when a line does not fit in the buffer.
+#[derive(Debug, PartialEq)]
+struct LineTooLong;
+
+/// Erases the `len` characters of the line shown on `terminal` and shows
+/// `line` instead, copying as much of it as fits into `buf`. Returns the new
+/// length of the line.
+fn replace_line<T: Terminal>(terminal: &mut T, buf: &mut [u8], len: usize, line: &[u8]) -> usize {
+    for _ in 0..len {
+        terminal.write_bytes(b"\x08 \x08");
+    }
+    let new_len = core::cmp::min(line.len(), buf.len());
+    buf[..new_len].copy_from_slice(&line[..new_len]);
+    terminal.write_bytes(&buf[..new_len]);
+    new_len
+}
+
+/// What pressing tab does to a partially typed path.
+#[derive(Debug, PartialEq)]
+enum Completion {
+    /// The path is completed by appending these characters.
+    Extend(String),
+    /// The names of several entries that match, with no longer common prefix.
+    Ambiguous(Vec<String>),
+    /// No entry matches.
+    NoMatch,
+}
+
+/// Completes `word`, a path typed up to the cursor, against the names that
+/// `list_dir` returns for the directory part of `word`, e.g. `/` for `/pro`
+/// or the empty string for a path in the working directory. Directory names
+/// are expected to end in `/` so that completing one leads into it.
+fn complete<L: Fn(&str) -> Vec<String>>(word: &str, list_dir: L) -> Completion {
+    let split = word.rfind('/').map_or(0, |i| i + 1);
+    let (dir, prefix) = word.split_at(split);
+    let matches: Vec<String> = list_dir(dir)
+        .into_iter()
+        .filter(|name| name.starts_with(prefix))
+        .collect();
+
+    let first = match matches.first() {
+        Some(first) => first,
+        None => return Completion::NoMatch,
+    };
+    let mut common = first.len();
+    for name in &matches[1..] {
+        common = first.bytes().zip(name.bytes()).take(common).take_while(|(a, b)| a == b).count();
+    }
+    while !first.is_char_boundary(common) {
+        common -= 1;
+    }
+
+    if matches.len() == 1 || common > prefix.len() {
+        Completion::Extend(String::from(&first[prefix.len()..common]))
+    } else {
+        Completion::Ambiguous(matches)
+    }
+}
+
+/// Reads one line from `terminal` into `buf`, echoing it and handling
+/// backspace. Returns the length of the line, excluding the line terminator.
+///
+/// The up and down arrow keys (`ESC [ A` and `ESC [ B`) replace the line with
+/// an older or newer one from `history`. Going down past the most recent line
+/// restores what was typed before; going past either end does nothing. Other
+/// escape sequences, including ones with parameters such as Delete
+/// (`ESC [ 3 ~`) or Ctrl-Up (`ESC [ 1 ; 5 A`), are read whole and ignored.
+///
+/// Tab completes the path being typed as an argument using `list_dir`, see
+/// `complete`. If several entries match, they are printed and the line is
+/// redrawn after `prompt`.
+///
+/// # Errors
+///
+/// If the line is longer than `buf`, the rest of the line is read and
+/// discarded so that it is not run as a separate command, and `LineTooLong`
+/// is returned.
+fn read_line<T, L>(terminal: &mut T, buf: &mut [u8], prompt: &str, history: &History, list_dir: L) -> Result<usize, LineTooLong>
+    where
+        T: Terminal,
+        L: Fn(&str) -> Vec<String>,
+{
+    let mut i = 0;
+    // How many lines back in `history` the line being edited is, and the
+    // line that was typed before going back, if any.
+    let mut back = 0;
+    let mut draft = Vec::new();
+    loop {
+        let byte = terminal.read_byte();
+        match byte {
+            0x1b => {
+                if terminal.read_byte() != b'[' {
+                    continue;
+                }
+                // Parameter and intermediate bytes (0x20-0x3F) come before
+                // the byte that ends the sequence.
+                let mut plain = true;
+                let mut last = terminal.read_byte();
+                while (0x20..=0x3f).contains(&last) {
+                    plain = false;
+                    last = terminal.read_byte();
+                }
+                let next = match last {
+                    b'A' if plain => back + 1,
+                    b'B' if plain && back > 0 => back - 1,
+                    _ => continue,
+                };
+                if back == 0 {
+                    draft = buf[..i].to_vec();
+                }
+                let line = match next {
+                    0 => &draft[..],
+                    _ => match history.get(next) {
+                        Some(line) => line.as_bytes(),
+                        None => continue,
+                    },
+                };
+                i = replace_line(terminal, buf, i, line);
+                back = next;
+            }
+            b'\t' => {
+                // Only arguments are completed, not the command name.
+                let start = match buf[..i].iter().rposition(|&b| b == b' ') {
+                    Some(space) => space + 1,
+                    None => continue,
+                };
+                let word = match str::from_utf8(&buf[start..i]) {
+                    Ok(word) => word,
+                    Err(_) => continue,
+                };
+                match complete(word, &list_dir) {
+                    Completion::Extend(rest) => {
+                        let rest = &rest.as_bytes()[..core::cmp::min(rest.len(), buf.len() - i)];
+                        buf[i..i + rest.len()].copy_from_slice(rest);
+                        terminal.write_bytes(rest);
+                        i += rest.len();
+                    }
+                    Completion::Ambiguous(names) => {
+                        terminal.write_bytes(b"\n");
+                        for name in names {
+                            terminal.write_bytes(name.as_bytes());
+                            terminal.write_bytes(b"  ");
+                        }
+                        terminal.write_bytes(b"\n");
+                        terminal.write_bytes(prompt.as_bytes());
+                        terminal.write_bytes(&buf[..i]);
+                    }
+                    Completion::NoMatch => terminal.write_bytes(b"\x07"),
+                }
+            }
+            b'\n' | b'\r' => {
+                terminal.write_bytes(b"\n");
+                return Ok(i);
+            }
+            8 | 127 => { // backspace
+                if i > 0 {
+                    terminal.write_bytes(b"\x08 \x08");
+                    i -= 1;
+                }
+            }
+            _ if i == buf.len() => {
+                loop {
+                    match terminal.read_byte() {
+                        b'\n' | b'\r' => break,
+                        _ => {}
+                    }
+                }
+                terminal.write_bytes(b"\n");
+                return Err(LineTooLong);
+            }
+            _ => {
+                buf[i] = byte;
+                terminal.write_bytes(&[byte]);
+                i += 1;
+            }
+        }
+    }
+}
+
 struct Shell {
     cwd: PathBuf,
+    aliases: Aliases,
+    history: History,
 }
 
 impl Shell {
     pub fn new() -> Shell {
-        Shell { cwd: PathBuf::from("/") }
+        Shell { cwd: PathBuf::from("/"), aliases: Aliases::new(), history: History::new() }
     }
 
     fn _shell(&mut self, prefix: &str) -> () {
-        const CMD_LEN: usize = 512;
         kprintln!();
         kprintln!("======================================================================");
         kprintln!("                           Welcome to my OS                           ");
         kprintln!("======================================================================");
         kprintln!();
-        'outer: loop {
+        let mut terminal = ConsoleTerminal;
+        loop {
             let mut cmd_buf = [0u8; CMD_LEN];
 
-            kprint!("{} {}", self.cwd.to_str().unwrap(), prefix);
-
-            let mut i = 0;
-            'cmd: loop {
-                if i == CMD_LEN {
-                    kprintln!();
-                    kprintln!("command length exceeds {}", CMD_LEN);
-                    break 'cmd;
-                }
-
-                let byte = CONSOLE.lock().read_byte();
-                if byte == b'\n' || byte == b'\r' {
-                    kprint!("\n");
-                    let cmd_result = str::from_utf8(&cmd_buf[0..i]);
-                    if let Ok(cmd) = cmd_result {
-                        match Command::parse(cmd) { // enter
-                            Err(Error::Empty) => {}
-                            Ok(cmd) => {
-                                let result = self.process_command(cmd);
-                                if result.is_none() {
-                                    break 'outer
-                                }
+            let prompt = format!("{} {}", self.cwd.to_str().unwrap(), prefix);
+            kprint!("{}", prompt);
+
+            let len = match read_line(&mut terminal, &mut cmd_buf, &prompt, &self.history, |dir| self.list_dir(dir)) {
+                Ok(len) => len,
+                Err(LineTooLong) => {
+                    kerrorln!("command length exceeds {}", CMD_LEN);
+                    continue;
+                }
+            };
+
+            match str::from_utf8(&cmd_buf[..len]) {
+                Ok(cmd) => {
+                    self.history.push(cmd);
+                    let line = match self.aliases.expand(cmd) {
+                        Ok(line) => line,
+                        Err(AliasLoop(name)) => {
+                            kerrorln!("{}: alias expansion too deep", name);
+                            continue;
+                        }
+                    };
+                    match Command::parse(&line) {
+                        Err(Error::Empty) => {}
+                        Ok(cmd) => {
+                            if self.process_command(cmd).is_none() {
+                                break;
                             }
                         }
-                        break 'cmd;
-                    } else {
-                        kprintln!("Could not parse input bytes into string");
-                        kprint!("\u{7}");
-                        kprintln!("");
-                        cmd_buf = [0u8; CMD_LEN];
-                    }
-                } else if byte == 8 || byte == 127 { // backspace
-                    if i > 0 {
-                        kprint!("\u{8} \u{8}");
-                        i -= 1
                     }
-                } else {
-                    cmd_buf[i] = byte;
-                    CONSOLE.lock().write_byte(byte);
-                    i += 1;
+                }
+                Err(_) => {
+                    kerrorln!("Could not parse input bytes into string");
+                    kprint!("\u{7}");
                 }
             }
         }
@@ -120,39 +497,172 @@ impl Shell {
     fn process_command(&mut self, mut cmd: Command) -> Option<()> {
         let arg1 = cmd.args.remove(0);
         match arg1 {
-            "echo" => {
-                for arg in cmd.args {
-                    kprint!("{} ", arg);
+            "echo" => { echo(&mut ConsoleTerminal, &cmd.args) }
+            "time" => {
+                if cmd.args.is_empty() {
+                    kerrorln!("usage: time <command>");
+                    return Some(());
                 }
-                kprint!("\n");
+                return timed(&mut ConsoleTerminal, current_time, |_| self.process_command(cmd));
+            }
+            "uptime" => {
+                // Goes through the `time` system call rather than reading
+                // the timer directly, so that it exercises the syscall path.
+                kprintln!("up {}", Uptime(kernel_api::syscall::time()));
+            }
+            "watch" => {
+                let watch = match parse_watch(&cmd.args) {
+                    Some(watch) => watch,
+                    None => {
+                        kerrorln!("usage: watch [-n seconds] <command>");
+                        return Some(());
+                    }
+                };
+                let header = watch.args.join(" ");
+                watch_loop(
+                    &mut ConsoleTerminal,
+                    watch.interval,
+                    &header,
+                    |_| {
+                        self.process_command(Command { args: watch.args.clone() });
+                    },
+                    |duration| {
+                        let _ = kernel_api::syscall::sleep(duration);
+                    },
+                );
+            }
+            "clear" => {
+                kprint!("{}", clear_screen());
             }
             "pwd" => {
                 kprintln!("{}", self.cwd.to_str().unwrap());
             }
+            "ls" | "cd" | "cat" | "mkdir" | "rm" | "bench" | "filefrag" | "remount" | "tree" | "fsck" if !FILESYSTEM.is_mounted() => {
+                kerrorln!("no filesystem mounted");
+            }
             "ls" => { self.ls(cmd.args) }
             "cd" => { self.cd(cmd.args) }
             "cat" => { self.cat(cmd.args) }
+            "mkdir" => { self.mkdir(cmd.args) }
+            "rm" => { self.rm(cmd.args) }
+            "bench" => { self.bench(cmd.args) }
+            "maps" => { self.maps(cmd.args) }
+            "filefrag" => { self.filefrag(cmd.args) }
+            "tree" => { self.tree(cmd.args) }
+            "dd" => { self.dd(cmd.args) }
+            "fsck" => { self.fsck() }
+            "selftest" => {
+                let stop_on_failure = cmd.args.get(0) == Some(&"-x");
+                let summary = selftest::run(stop_on_failure, |name, result| match result {
+                    Ok(()) => kprintln!("{}ok{}    {}", color(Color::Green), reset(), name),
+                    Err(e) => kprintln!("{}FAIL{}  {}: {}", color(Color::Red), reset(), name, e),
+                });
+                kprintln!("{} passed, {} failed, {} skipped", summary.passed, summary.failed, summary.skipped);
+            }
+            "mount" => {
+                match cmd.args.get(0) {
+                    Some(&"sd") => match unsafe { FILESYSTEM.try_initialize() } {
+                        Ok(()) => kprintln!("mounted sd"),
+                        Err(e) => kerrorln!("mount failed: {:?}", e),
+                    },
+                    _ => kerrorln!("usage: mount sd"),
+                }
+            }
+            "remount" => {
+                let writable = match cmd.args.get(0) {
+                    Some(&"rw") => true,
+                    Some(&"ro") => false,
+                    _ => {
+                        kerrorln!("usage: remount <rw|ro>");
+                        return Some(());
+                    }
+                };
+                if let Err(e) = FILESYSTEM.set_writable(writable) {
+                    kerrorln!("remount failed: {:?}", e.kind());
+                }
+            }
+            "cpuinfo" => {
+                for (core, info) in cpuinfo::all().iter().enumerate() {
+                    match info {
+                        Some(info) => kprintln!("{}", info),
+                        None => kprintln!("core {}: not started", core),
+                    }
+                }
+            }
+            "coreinfo" => {
+                let now = current_time();
+                for (core, stat) in corestats::all().iter().enumerate() {
+                    kprintln!("core {}: {}", core, stat.at(now));
+                }
+            }
             "exit" => { return None; }
+            "poweroff" => { power::poweroff() }
+            "reboot" => {
+                let partition = match cmd.args.get(0) {
+                    None => Some(0),
+                    Some(arg) => parse_partition(arg),
+                };
+                match partition {
+                    Some(partition) => power::reboot(partition),
+                    None => kerrorln!("usage: reboot [partition 0-{}]", pi::power::MAX_PARTITION),
+                }
+            }
+            "colors" => {
+                match cmd.args.get(0) {
+                    Some(&"on") => config::set_colors_enabled(true),
+                    Some(&"off") => config::set_colors_enabled(false),
+                    _ => kerrorln!("usage: colors <on|off>"),
+                }
+            }
+            "alias" => { self.alias(cmd.args) }
+            "which" => {
+                if cmd.args.is_empty() {
+                    kerrorln!("usage: which <command>...");
+                }
+                for name in cmd.args {
+                    match which(&self.aliases, name) {
+                        Which::Alias(expansion) => kprintln!("{}: aliased to '{}'", name, expansion),
+                        Which::Builtin => kprintln!("{}: shell builtin", name),
+                        Which::NotFound => kerrorln!("{}: not found", name),
+                    }
+                }
+            }
+            "sched_quantum" => {
+                match cmd.args.get(0) {
+                    None => kprintln!("{} ms", config::quantum().as_millis()),
+                    Some(arg) => match u64::from_str_radix(arg, 10) {
+                        Ok(ms) => {
+                            if let Err(e) = config::set_quantum(ms) {
+                                kerrorln!("{}", e);
+                            }
+                        }
+                        Err(_) => kerrorln!("usage: sched_quantum [ms]"),
+                    },
+                }
+            }
+            "heartbeat" => { self.heartbeat(cmd.args) }
             "sleep" => {
                 if cmd.args.len() != 1 {
-                    kprintln!("Accepts exactly one argument");
+                    kerrorln!("Accepts exactly one argument");
                     return Some(());
                 }
                 let millis = u64::from_str_radix(cmd.args[0], 10);
                 match millis {
                     Err(e) => {
-                        kprintln!("{}", e)
+                        kerrorln!("{}", e)
                     }
                     Ok(ms) => {
                         let duration = Duration::from_millis(ms);
                         let result = kernel_api::syscall::sleep(duration);
                         match result {
-                            Err(e) => kprintln!("{:?}", e),
+                            Err(e) => kerrorln!("{:?}", e),
                             Ok(d) => kprintln!("{}", d.as_millis()),
                         };
                     }
                 }
             }
+            "ps" => { self.ps() }
+            "kill" => { self.kill(cmd.args) }
             "mem" => {
                 let mem = cmd.args[0];
                 let my_int = u64::from_str_radix(mem.trim_start_matches("0x"), 16);
@@ -162,15 +672,50 @@ impl Shell {
                         kprintln!("{:X?}", value)
                     }
                     Err(e) => {
-                        kprintln!("{}", e)
+                        kerrorln!("{}", e)
                     }
                 }
             }
+            "mem-stats" => { self.mem_stats() }
+            "poke" => { self.poke(cmd.args) }
+            "irqstats" => { self.irqstats(cmd.args) }
+            "netstat" => {
+                let sockets = net::socket_summary();
+                kprintln!("{:<8} {:<22} {:<22} {}", "handle", "local", "remote", "state");
+                for info in sockets {
+                    kprintln!(
+                        "{:<8} {:<22} {:<22} {}",
+                        format!("{}", info.handle), format!("{}", info.local), format!("{}", info.remote), info.state
+                    );
+                }
+            }
+            "nameserver" => {
+                match cmd.args.as_slice() {
+                    [] => match net::dns::server() {
+                        Some(addr) => kprintln!("{}", addr),
+                        None => kprintln!("no nameserver set"),
+                    },
+                    [addr] => match net::dns::parse_address(addr) {
+                        Some(addr) => net::dns::set_server(addr),
+                        None => kerrorln!("nameserver: not an IPv4 address: {}", addr),
+                    },
+                    _ => kerrorln!("usage: nameserver [a.b.c.d]"),
+                }
+            }
+            "host" => {
+                match cmd.args.as_slice() {
+                    [name] => match net::dns::lookup(name) {
+                        Ok(addr) => kprintln!("{} has address {}", name, addr),
+                        Err(e) => kerrorln!("host: {}: {}", name, e),
+                    },
+                    _ => kerrorln!("usage: host <name>"),
+                }
+            }
             "" => {
                 kprintln!();
             }
             _ => {
-                kprintln!("Unknown command: {}", arg1);
+                kerrorln!("Unknown command: {}", arg1);
             }
         };
         return Some(());
@@ -179,27 +724,23 @@ impl Shell {
 
     fn cd(&mut self, mut args: Vec<&str>) {
         if args.len() != 1 {
-            kprintln!("cd takes only 1 argument, but received {}", args.len());
+            kerrorln!("cd takes only 1 argument, but received {}", args.len());
             return;
         }
 
         let arg = args.remove(0);
-        match FILESYSTEM.open(self.get_entry(arg)) {
-            Err(_) => kprintln!("Error opening {}", arg),
-            Ok(entry) => {
-                if entry.is_dir() {
-                    self.cwd = self.get_entry(arg);
-                } else {
-                    kprintln!("{} is not a directory", arg);
-                }
-            }
+        let path = self.get_entry(arg);
+        match kernel_api::syscall::chdir(path.to_str().unwrap()) {
+            Ok(()) => self.cwd = path,
+            Err(OsError::InvalidArgument) => kerrorln!("cd: {}: not a directory", arg),
+            Err(e) => kerrorln!("cd: {}: {:?}", arg, e),
         }
     }
 
 
     fn cat(&self, args: Vec<&str>) {
         if args.len() == 0 {
-            kprintln!("expected at least one argument");
+            kerrorln!("expected at least one argument");
         }
 
         for arg in args {
@@ -213,21 +754,435 @@ impl Shell {
                         match file.read(file_contents.as_mut_slice()) {
                             Ok(bytes_read) => {
                                 if bytes_read < file.size() as usize {
-                                    kprintln!("Could only read {} of {} bytes in {}",
+                                    kerrorln!("Could only read {} of {} bytes in {}",
                                             bytes_read, file.size(), arg);
                                 } else {
                                     match core::str::from_utf8(file_contents.as_slice()) {
                                         Ok(contents) => kprintln!("{}", contents),
-                                        Err(_) => kprintln!("{} contains non-UTF8 characters", arg),
+                                        Err(_) => kerrorln!("{} contains non-UTF8 characters", arg),
                                     }
                                 }
                             }
-                            Err(_) => kprintln!("Error reading the contents of {}", arg),
+                            Err(e) => kerrorln!("{}", FsError::new("cat", arg, &e)),
                         }
                     }
-                    None => kprintln!("{} is a directory", arg),
+                    None => kerrorln!("{} is a directory", arg),
+                }
+                Err(e) => kerrorln!("{}", FsError::new("cat", arg, &e)),
+            }
+        }
+    }
+
+    fn mkdir(&self, args: Vec<&str>) {
+        if args.len() == 0 {
+            kerrorln!("expected at least one argument");
+        }
+
+        for arg in args {
+            let path = self.get_entry(arg);
+            let name = match path.file_name().and_then(|n| n.to_str()) {
+                Some(name) => name,
+                None => {
+                    kerrorln!("Invalid directory name {}", arg);
+                    continue;
+                }
+            };
+            let parent = path.parent().unwrap_or(&path);
+            match FILESYSTEM.open_dir(parent) {
+                Ok(dir) => {
+                    if let Err(e) = dir.create_dir(name) {
+                        kerrorln!("{}", FsError::new("mkdir", arg, &e));
+                    }
+                }
+                Err(e) => kerrorln!("{}", FsError::new("mkdir", parent.to_str().unwrap(), &e)),
+            }
+        }
+    }
+
+    /// `rm path...`: removes files and empty directories.
+    fn rm(&self, args: Vec<&str>) {
+        if args.len() == 0 {
+            kerrorln!("expected at least one argument");
+        }
+
+        for arg in args {
+            let path = self.get_entry(arg);
+            let name = match path.file_name().and_then(|n| n.to_str()) {
+                Some(name) => name,
+                None => {
+                    kerrorln!("Invalid file name {}", arg);
+                    continue;
+                }
+            };
+            let parent = path.parent().unwrap_or(&path);
+            match FILESYSTEM.open_dir(parent) {
+                Ok(dir) => {
+                    if let Err(e) = dir.remove(name) {
+                        kerrorln!("{}", FsError::new("rm", arg, &e));
+                    }
+                }
+                Err(e) => kerrorln!("{}", FsError::new("rm", parent.to_str().unwrap(), &e)),
+            }
+        }
+    }
+
+    /// `bench [alloc [count] | read [path] | ls [path]]`: runs one benchmark,
+    /// or all of them with default parameters if none is named.
+    fn bench(&self, args: Vec<&str>) {
+        let which = args.get(0).cloned();
+        let param = args.get(1).cloned();
+
+        if which.is_none() || which == Some("alloc") {
+            let count = match param.map(|p| usize::from_str_radix(p, 10)) {
+                None => bench::DEFAULT_ALLOC_COUNT,
+                Some(Ok(count)) => count,
+                Some(Err(e)) => {
+                    kerrorln!("{}", e);
+                    return;
+                }
+            };
+            for &size in bench::ALLOC_SIZES.iter() {
+                match bench::alloc_free(count, size) {
+                    Ok(m) => kprintln!("alloc/free {} x {} B: {}", count, size, m),
+                    Err(e) => kerrorln!("alloc/free {} x {} B: {:?}", count, size, e),
                 }
-                Err(_) => kprintln!("Error opening {}", arg),
+            }
+        }
+        if which.is_none() || which == Some("read") {
+            let path = self.get_entry(param.unwrap_or(bench::DEFAULT_FILE));
+            let path = path.to_str().unwrap();
+            match bench::read_file(path, 4096) {
+                Ok(m) => kprintln!("read {}: {}", path, m),
+                Err(e) => kerrorln!("read {}: {:?}", path, e),
+            }
+        }
+        if which.is_none() || which == Some("ls") {
+            let path = self.get_entry(param.unwrap_or(bench::DEFAULT_DIR));
+            let path = path.to_str().unwrap();
+            match bench::list_dir(path) {
+                Ok(m) => kprintln!("ls {}: {}", path, m),
+                Err(e) => kerrorln!("ls {}: {:?}", path, e),
+            }
+        }
+        match which {
+            None | Some("alloc") | Some("read") | Some("ls") => {}
+            Some(other) => kerrorln!("unknown benchmark: {}", other),
+        }
+        if which.is_none() || which == Some("read") || which == Some("ls") {
+            if let Ok(stats) = FILESYSTEM.cache_stats() {
+                kprintln!("sector cache: {} hits, {} misses", stats.hits, stats.misses);
+            }
+        }
+    }
+
+    /// `maps [pid]`: lists the pages mapped by process `pid`, or by the shell
+    /// itself if no `pid` is given.
+    fn maps(&self, args: Vec<&str>) {
+        let pid = match args.get(0) {
+            None => kernel_api::syscall::getpid(),
+            Some(arg) => match u64::from_str_radix(arg, 10) {
+                Ok(pid) => pid,
+                Err(e) => {
+                    kerrorln!("{}", e);
+                    return;
+                }
+            },
+        };
+
+        let maps = SCHEDULER.critical(|scheduler| {
+            scheduler.process_by_id(pid).map(|process| {
+                let mut out = String::new();
+                vm::write_maps(&mut out, process.vmap.mappings()).map(|_| out)
+            })
+        });
+        match maps {
+            Some(Ok(out)) => kprint!("{}", out),
+            Some(Err(_)) => kerrorln!("failed to format mappings"),
+            None => kerrorln!("no process with pid {}", pid),
+        }
+    }
+
+    /// `irqstats [reset]`: shows how often each interrupt fired and how long
+    /// its handler took, or clears the counters.
+    fn irqstats(&self, args: Vec<&str>) {
+        match args.get(0) {
+            None => {}
+            Some(&"reset") => {
+                IRQ_STATS.lock().reset();
+                return;
+            }
+            Some(_) => {
+                kerrorln!("usage: irqstats [reset]");
+                return;
+            }
+        }
+
+        // Copy the counters out so interrupts are not held up while printing.
+        let stats = *IRQ_STATS.lock();
+        kprintln!("{:<14} {:>10} {:>10} {:>10} {:>10}", "source", "count", "min us", "avg us", "max us");
+        for int in Interrupt::iter() {
+            print_irq_stat(&format!("{:?}", int), stats.global(int));
+        }
+        for int in LocalInterrupt::iter() {
+            print_irq_stat(&format!("{:?}", int), stats.local(int));
+        }
+    }
+
+    /// `ps`: lists the processes in the scheduler's queue with their state.
+    /// The process the shell runs in is marked with `*`.
+    fn ps(&self) {
+        // Copy the list out so the scheduler is not held up while printing.
+        let processes: Vec<(u64, String)> = SCHEDULER.critical(|scheduler| {
+            scheduler
+                .processes()
+                .map(|p| (p.context.TPIDR, format!("{:?}", p.state)))
+                .collect()
+        });
+        let me = kernel_api::syscall::getpid();
+        kprintln!("  {:>5} {}", "pid", "state");
+        for (pid, state) in processes {
+            let marker = if pid == me { '*' } else { ' ' };
+            kprintln!("{} {:>5} {}", marker, pid, state);
+        }
+    }
+
+    /// `kill <pid>`: kills a process. The shell refuses to kill the process
+    /// it runs in, since nothing could run the shell afterwards.
+    fn kill(&self, args: Vec<&str>) {
+        let pid = match args.as_slice() {
+            [pid] => match u64::from_str_radix(pid, 10) {
+                Ok(pid) => pid,
+                Err(_) => {
+                    kerrorln!("kill: {}: not a process id", pid);
+                    return;
+                }
+            },
+            _ => {
+                kerrorln!("usage: kill <pid>");
+                return;
+            }
+        };
+        if pid == kernel_api::syscall::getpid() {
+            kerrorln!("kill: {}: the shell runs in this process; use exit instead", pid);
+            return;
+        }
+        match SCHEDULER.critical(|scheduler| scheduler.kill_id(pid)) {
+            Ok(()) => {}
+            Err(OsError::NoEntry) => kerrorln!("kill: {}: no such process", pid),
+            Err(e) => kerrorln!("kill: {}: {:?}", pid, e),
+        }
+    }
+
+    /// `mem-stats`: prints how much of the heap is in use and how much is
+    /// parked in each bin's free list.
+    fn mem_stats(&self) {
+        let stats = match ALLOCATOR.stats() {
+            Some(stats) => stats,
+            None => {
+                kerrorln!("mem-stats: allocator not initialized");
+                return;
+            }
+        };
+
+        kprintln!("heap used: {} bytes, never used: {} bytes", stats.bump_used, stats.bump_free);
+        kprintln!("largest allocation: {} bytes", stats.largest_alloc);
+        kprintln!("{:<6} {:>12} {:>12}", "bin", "block size", "free bytes");
+        for (i, &free) in stats.bins.iter().enumerate().filter(|&(_, &free)| free > 0) {
+            kprintln!("{:<6} {:>12} {:>12}", i, allocator::bin_index_size(i), free);
+        }
+    }
+
+    /// `alias [name[=expansion]]`: defines an alias, or prints one or all of
+    /// them.
+    fn alias(&mut self, args: Vec<&str>) {
+        if args.is_empty() {
+            for (name, expansion) in self.aliases.map.iter() {
+                kprintln!("alias {}='{}'", name, expansion);
+            }
+            return;
+        }
+
+        // The expansion may contain spaces, which split it into arguments.
+        let definition = args.join(" ");
+        if !definition.contains('=') {
+            match self.aliases.get(&definition) {
+                Some(expansion) => kprintln!("alias {}='{}'", definition, expansion),
+                None => kerrorln!("alias: {}: not found", definition),
+            }
+            return;
+        }
+        match Aliases::parse_definition(&definition) {
+            Some((name, expansion)) => self.aliases.define(name, expansion),
+            None => kerrorln!("usage: alias name=expansion"),
+        }
+    }
+
+    /// `heartbeat [off | <pin> [ms]]`: shows the heartbeat setting, disables
+    /// it, or toggles GPIO `pin` every `ms` milliseconds (default 500).
+    fn heartbeat(&self, args: Vec<&str>) {
+        let usage = "usage: heartbeat [off | <pin> [ms]]";
+        match args.as_slice() {
+            [] => match config::heartbeat() {
+                Some((pin, interval)) => kprintln!("gpio {} every {} ms", pin, interval.as_millis()),
+                None => kprintln!("off"),
+            },
+            ["off"] => config::disable_heartbeat(),
+            [pin] | [pin, _] => {
+                let pin = u8::from_str_radix(pin, 10);
+                let ms = args.get(1).map_or(Ok(500), |ms| u64::from_str_radix(ms, 10));
+                match (pin, ms) {
+                    (Ok(pin), Ok(ms)) => {
+                        if let Err(e) = config::set_heartbeat(pin, ms) {
+                            kerrorln!("{}", e);
+                        }
+                    }
+                    _ => kerrorln!("{}", usage),
+                }
+            }
+            _ => kerrorln!("{}", usage),
+        }
+    }
+
+    /// `poke [--force] 0xADDR 0xVALUE`: writes a 32-bit word to a writable
+    /// address. Device registers are only written with `--force`.
+    fn poke(&self, args: Vec<&str>) {
+        let request = match poke::parse_args(&args) {
+            Ok(request) => request,
+            Err(e) => {
+                kerrorln!("{}", e);
+                return;
+            }
+        };
+        let layout = match poke::MemoryLayout::current() {
+            Some(layout) => layout,
+            None => {
+                kerrorln!("memory map unavailable");
+                return;
+            }
+        };
+
+        match layout.check(&request) {
+            Ok(()) => {
+                unsafe { (request.addr as *mut u32).write_volatile(request.value) };
+                kprintln!("{:#010x} <- {:#010x}", request.addr, request.value);
+            }
+            Err(e) => kerrorln!("{:#x}: {}", request.addr, e),
+        }
+    }
+
+    /// `tree [dir]`: prints every entry below `dir`, or the working
+    /// directory, indented by depth.
+    fn tree(&self, args: Vec<&str>) {
+        if args.len() > 1 {
+            kerrorln!("usage: tree [dir]");
+            return;
+        }
+        let arg = args.get(0).cloned().unwrap_or(".");
+        let dir = match FILESYSTEM.open_dir(self.get_entry(arg)) {
+            Ok(dir) => dir,
+            Err(e) => {
+                kerrorln!("{}", FsError::new("tree", arg, &e));
+                return;
+            }
+        };
+
+        kprintln!("{}", arg);
+        let result = dir.walk(|path, entry| {
+            // Paths start at `/` for `dir`, so its children have 2 components.
+            let indent = 2 * (path.components().count() - 1);
+            if entry.is_dir() {
+                kprintln!("{:indent$}{}{}{}", "", color(Color::Blue), entry.name(), reset(), indent = indent);
+            } else {
+                kprintln!("{:indent$}{}", "", entry.name(), indent = indent);
+            }
+        });
+        if let Err(e) = result {
+            kerrorln!("{}", FsError::new("tree", arg, &e));
+        }
+    }
+
+    /// `dd read <sector> <count>` hex dumps raw sectors of the SD card and
+    /// `dd write <sector> <hex>` overwrites the start of a sector, both
+    /// bypassing the file system, so they work even if mounting failed. While
+    /// a file system is mounted, writes need `remount rw`, since they can
+    /// corrupt it, and drop its sector cache so that it sees them.
+    fn dd(&self, args: Vec<&str>) {
+        let request = match dd::parse_args(&args) {
+            Ok(request) => request,
+            Err(e) => {
+                kerrorln!("{}", e);
+                return;
+            }
+        };
+
+        match request {
+            dd::Dd::Read { sector, count } => match dd::read(Sd, sector, count) {
+                Ok(bytes) => kprint!("{}", dd::Hexdump { sector, bytes: &bytes }),
+                Err(e) => kerrorln!("dd: sector {}: {:?}", sector, e.kind()),
+            },
+            dd::Dd::Write { .. } if FILESYSTEM.is_mounted() && !FILESYSTEM.is_writable() => {
+                kerrorln!("dd: writes bypass the file system; remount rw first");
+            }
+            dd::Dd::Write { sector, data } => {
+                let result = dd::write(Sd, sector, &data);
+                FILESYSTEM.invalidate_cache();
+                match result {
+                    Ok(()) => kprintln!("wrote {} bytes to sector {}", data.len(), sector),
+                    Err(e) => kerrorln!("dd: sector {}: {:?}", sector, e.kind()),
+                }
+            }
+        }
+    }
+
+    /// `fsck`: checks the redundant copies of the file system's metadata, the
+    /// backup boot sector and the FAT copies, against the primary ones.
+    fn fsck(&self) {
+        match FILESYSTEM.backup_boot_check() {
+            Ok(BackupBootCheck::Match) => kprintln!("boot sector: backup matches"),
+            Ok(BackupBootCheck::NoBackup) => kprintln!("boot sector: no backup"),
+            Ok(BackupBootCheck::Unreadable) => kerrorln!("boot sector: backup unreadable"),
+            Ok(BackupBootCheck::Mismatch) => kerrorln!("boot sector: backup differs"),
+            Err(e) => kerrorln!("fsck: {:?}", e.kind()),
+        }
+        match FILESYSTEM.verify_fats() {
+            Ok(true) => kprintln!("FAT: copies match"),
+            Ok(false) => kerrorln!("FAT: copies differ"),
+            Err(e) => kerrorln!("fsck: FAT: {:?}", e.kind()),
+        }
+    }
+
+    /// `filefrag <path>...`: lists the clusters of each file and the sectors
+    /// they start at.
+    fn filefrag(&self, args: Vec<&str>) {
+        if args.len() == 0 {
+            kerrorln!("expected at least one argument");
+        }
+
+        for arg in args {
+            let file = match FILESYSTEM.open_file(self.get_entry(arg)) {
+                Ok(file) => file,
+                Err(e) => {
+                    kerrorln!("{}", FsError::new("filefrag", arg, &e));
+                    continue;
+                }
+            };
+            if file.size() == 0 {
+                kprintln!("{}: empty", arg);
+                continue;
+            }
+
+            match file.vfat.lock(|vfat| vfat.file_extents(file.first_cluster)) {
+                Ok(extents) => {
+                    let fragments = 1 + extents
+                        .windows(2)
+                        .filter(|pair| pair[1].0.raw() != pair[0].0.raw() + 1)
+                        .count();
+                    kprintln!("{}: {} clusters, {} fragments", arg, extents.len(), fragments);
+                    for (cluster, sector) in extents {
+                        kprintln!("  cluster {:>8} sector {:>10}", cluster.raw(), sector);
+                    }
+                }
+                Err(e) => kerrorln!("{}: {:?}", arg, e.kind()),
             }
         }
     }
@@ -251,18 +1206,20 @@ impl Shell {
                             Ok(entries) => {
                                 for entry in entries {
                                     if display_hidden || !entry.metadata().attributes.hidden() {
-                                        if entry.metadata().attributes.directory() || entry.metadata().attributes.archive() {
+                                        if entry.metadata().attributes.directory() {
+                                            kprintln!("{}{}{}", color(Color::Blue), entry.name(), reset());
+                                        } else if entry.metadata().attributes.archive() {
                                             kprintln!("{}", entry.name());
                                         }
                                     }
                                 }
                             }
-                            Err(_) => kprintln!("Cannot open directory {}", path.to_str().unwrap()),
+                            Err(e) => kerrorln!("{}", FsError::new("ls", path.to_str().unwrap(), &e)),
                         }
                     }
                     None => kprintln!("{}", entry.name()),
                 }
-                Err(_) => kprintln!("Cannot open directory {}", path.to_str().unwrap()),
+                Err(e) => kerrorln!("{}", FsError::new("ls", path.to_str().unwrap(), &e)),
             };
         };
 
@@ -277,20 +1234,30 @@ impl Shell {
         }
     }
 
+    /// Returns the names in the directory `dir`, relative to the working
+    /// directory, with a `/` after the names of directories. Returns nothing
+    /// if `dir` can't be listed, e.g. because nothing is mounted.
+    fn list_dir(&self, dir: &str) -> Vec<String> {
+        let dir = match FILESYSTEM.open_dir(self.get_entry(dir)) {
+            Ok(dir) => dir,
+            Err(_) => return Vec::new(),
+        };
+        let entries = match dir.entries() {
+            Ok(entries) => entries,
+            Err(_) => return Vec::new(),
+        };
+        entries
+            .filter(|entry| entry.name() != "." && entry.name() != "..")
+            .map(|entry| match entry.is_dir() {
+                true => format!("{}/", entry.name()),
+                false => String::from(entry.name()),
+            })
+            .collect()
+    }
+
     // Gets the entries identified by the given path.
     fn get_entry(&self, path: &str) -> PathBuf {
-        let mut curr = self.cwd.clone();
-        let path = PathBuf::from(path);
-
-        for component in path.components() {
-            match component {
-                Component::RootDir => curr = PathBuf::from("/"),
-                Component::ParentDir => { curr.pop(); }
-                Component::Normal(entry) => curr.push(entry),
-                _ => (), // Nothing to do for `Prefix` or `CurDir`
-            }
-        }
-        curr
+        process::resolve(&self.cwd, path)
     }
 }
 
@@ -301,3 +1268,567 @@ pub fn shell(prefix: &str) -> () {
     let mut the_shell = Shell::new();
     the_shell._shell(prefix)
 }
+
+/// The wall time taken by a command, printed by `time` as `real 1.234567s`.
+struct Elapsed(Duration);
+
+impl fmt::Display for Elapsed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "real {}.{:06}s", self.0.as_secs(), self.0.subsec_micros())
+    }
+}
+
+/// The time since boot, printed by `uptime` as `1h 02m 03.456s`.
+struct Uptime(Duration);
+
+impl fmt::Display for Uptime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let secs = self.0.as_secs();
+        write!(f, "{}h {:02}m {:02}.{:03}s", secs / 3600, secs / 60 % 60, secs % 60, self.0.subsec_millis())
+    }
+}
+
+/// A file system error reported by a shell command, formatted as
+/// `command: path: kind` so that e.g. a missing file and a failing SD card
+/// can be told apart.
+struct FsError<'a> {
+    command: &'a str,
+    path: &'a str,
+    kind: io::ErrorKind,
+}
+
+impl<'a> FsError<'a> {
+    fn new(command: &'a str, path: &'a str, error: &io::Error) -> FsError<'a> {
+        FsError { command, path, kind: error.kind() }
+    }
+}
+
+impl fmt::Display for FsError<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}: {:?}", self.command, self.path, self.kind)
+    }
+}
+
+/// A parsed `watch [-n seconds] <command>`.
+#[derive(Debug, PartialEq)]
+struct Watch<'a> {
+    interval: Duration,
+    args: Vec<&'a str>,
+}
+
+/// Parses the arguments of `watch`. The interval is a whole number of
+/// seconds given as `-n 5` or `-n5`.
+fn parse_watch<'a>(args: &[&'a str]) -> Option<Watch<'a>> {
+    let (interval, rest) = match args.first() {
+        Some(&"-n") if args.len() > 1 => (Some(args[1]), &args[2..]),
+        Some(flag) if flag.starts_with("-n") => (Some(&flag[2..]), &args[1..]),
+        _ => (None, args),
+    };
+    let interval = match interval {
+        Some(secs) => match u64::from_str_radix(secs, 10) {
+            Ok(secs) if secs > 0 => Duration::from_secs(secs),
+            _ => return None,
+        },
+        None => DEFAULT_WATCH_INTERVAL,
+    };
+    if rest.is_empty() {
+        return None;
+    }
+    Some(Watch { interval, args: rest.to_vec() })
+}
+
+/// Writes `args` to `terminal` for `echo`, each followed by a space.
+fn echo<T: Terminal>(terminal: &mut T, args: &[&str]) {
+    for arg in args {
+        terminal.write_bytes(arg.as_bytes());
+        terminal.write_bytes(b" ");
+    }
+    terminal.write_bytes(b"\n");
+}
+
+/// Calls `run`, then writes how long it took by the clock `now` to
+/// `terminal`, for `time`. Returns what `run` returned.
+fn timed<T, N, R, V>(terminal: &mut T, mut now: N, run: R) -> V
+    where
+        T: Terminal,
+        N: FnMut() -> Duration,
+        R: FnOnce(&mut T) -> V,
+{
+    let start = now();
+    let result = run(terminal);
+    let line = format!("{}\n", Elapsed(now() - start));
+    terminal.write_bytes(line.as_bytes());
+    result
+}
+
+/// Calls `run` every `interval` until a key is pressed, clearing the screen
+/// and printing `header` before each call. Waits with `sleep` in steps of
+/// `WATCH_POLL`, checking for a key after each, so a key pressed while `run`
+/// runs stops the loop once it returns. The key is consumed. Returns the
+/// number of calls to `run`.
+fn watch_loop<T, R, S>(terminal: &mut T, interval: Duration, header: &str, mut run: R, mut sleep: S) -> usize
+    where
+        T: Terminal,
+        R: FnMut(&mut T),
+        S: FnMut(Duration),
+{
+    let mut runs = 0;
+    loop {
+        let title = format!("{}Every {}s: {}\n\n", clear_screen(), interval.as_secs(), header);
+        terminal.write_bytes(title.as_bytes());
+        run(terminal);
+        runs += 1;
+
+        let mut waited = Duration::from_secs(0);
+        loop {
+            if terminal.try_read_byte().is_some() {
+                return runs;
+            }
+            if waited >= interval {
+                break;
+            }
+            let step = core::cmp::min(WATCH_POLL, interval - waited);
+            sleep(step);
+            waited += step;
+        }
+    }
+}
+
+/// Parses the boot partition given to `reboot`, in decimal or `0x` hex.
+fn parse_partition(arg: &str) -> Option<u8> {
+    let partition = if arg.starts_with("0x") {
+        u8::from_str_radix(&arg[2..], 16)
+    } else {
+        u8::from_str_radix(arg, 10)
+    };
+    partition.ok().filter(|&p| p <= pi::power::MAX_PARTITION)
+}
+
+/// Prints one row of the `irqstats` table.
+fn print_irq_stat(name: &str, stat: &IrqStat) {
+    match (stat.min(), stat.avg(), stat.max()) {
+        (Some(min), Some(avg), Some(max)) => kprintln!(
+            "{:<14} {:>10} {:>10} {:>10} {:>10}",
+            name, stat.count, min.as_micros(), avg.as_micros(), max.as_micros()
+        ),
+        _ => kprintln!("{:<14} {:>10} {:>10} {:>10} {:>10}", name, 0, "-", "-", "-"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{complete, parse_partition, read_line, which, AliasLoop, Aliases, Command, Completion, Elapsed, FsError, History, LineTooLong, Terminal, Which};
+    use super::{echo, parse_watch, timed, watch_loop, Uptime, Watch};
+    use super::{CMD_LEN, DEFAULT_WATCH_INTERVAL, HISTORY_LEN, MAX_ALIAS_DEPTH};
+    use core::time::Duration;
+    use alloc::collections::VecDeque;
+    use alloc::string::{String, ToString};
+    use alloc::vec::Vec;
+
+    /// A terminal fed from a fixed input that records what is echoed.
+    struct MockTerminal {
+        input: VecDeque<u8>,
+        output: Vec<u8>,
+    }
+
+    impl MockTerminal {
+        fn new(input: &[u8]) -> MockTerminal {
+            MockTerminal { input: input.iter().cloned().collect(), output: Vec::new() }
+        }
+    }
+
+    impl Terminal for MockTerminal {
+        fn read_byte(&mut self) -> u8 {
+            self.input.pop_front().expect("shell read past the end of the input")
+        }
+
+        fn try_read_byte(&mut self) -> Option<u8> {
+            self.input.pop_front()
+        }
+
+        fn write_bytes(&mut self, bytes: &[u8]) {
+            self.output.extend_from_slice(bytes);
+        }
+    }
+
+    /// Stands in for the file system when nothing should be completed.
+    fn no_entries(_: &str) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// Stands in for the file system when completing paths.
+    fn stub_entries(dir: &str) -> Vec<String> {
+        let names: &[&str] = match dir {
+            "/" => &["programs/", "proc.txt", "boot/"],
+            "" => &["readme.txt", "caf\u{e9}.txt", "caf\u{e8}.txt"],
+            "/programs/" => &["sleep.bin"],
+            _ => &[],
+        };
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    /// Reads lines until the input is exhausted and returns the commands
+    /// that would be run.
+    fn commands(input: &[u8]) -> Vec<String> {
+        let mut terminal = MockTerminal::new(input);
+        let mut commands = Vec::new();
+        while !terminal.input.is_empty() {
+            let mut buf = [0u8; CMD_LEN];
+            if let Ok(len) = read_line(&mut terminal, &mut buf, "", &History::new(), no_entries) {
+                let line = core::str::from_utf8(&buf[..len]).unwrap();
+                if let Ok(cmd) = Command::parse(line) {
+                    commands.push(cmd.args.join(" "));
+                }
+            }
+        }
+        commands
+    }
+
+    #[test]
+    fn reads_a_line() {
+        let mut terminal = MockTerminal::new(b"echo hi\r");
+        let mut buf = [0u8; CMD_LEN];
+        assert_eq!(read_line(&mut terminal, &mut buf, "", &History::new(), no_entries), Ok(7));
+        assert_eq!(&buf[..7], b"echo hi");
+        assert_eq!(terminal.output, b"echo hi\n");
+    }
+
+    #[test]
+    fn backspace() {
+        let mut terminal = MockTerminal::new(b"ecx\x7fho\n");
+        let mut buf = [0u8; CMD_LEN];
+        assert_eq!(read_line(&mut terminal, &mut buf, "", &History::new(), no_entries), Ok(4));
+        assert_eq!(&buf[..4], b"echo");
+    }
+
+    #[test]
+    fn over_length_line_is_discarded() {
+        let mut input = Vec::new();
+        input.extend_from_slice(b"echo ");
+        input.extend(core::iter::repeat(b'x').take(2 * CMD_LEN));
+        input.extend_from_slice(b" ls\n");
+        input.extend_from_slice(b"pwd\n");
+
+        let mut terminal = MockTerminal::new(&input);
+        let mut buf = [0u8; CMD_LEN];
+        assert_eq!(read_line(&mut terminal, &mut buf, "", &History::new(), no_entries), Err(LineTooLong));
+
+        assert_eq!(commands(&input), ["pwd"]);
+    }
+
+    #[test]
+    fn line_of_exactly_max_length() {
+        let mut input = vec![b'a'; CMD_LEN];
+        input.push(b'\n');
+        let mut terminal = MockTerminal::new(&input);
+        let mut buf = [0u8; CMD_LEN];
+        assert_eq!(read_line(&mut terminal, &mut buf, "", &History::new(), no_entries), Ok(CMD_LEN));
+    }
+
+    fn history(lines: &[&str]) -> History {
+        let mut history = History::new();
+        for line in lines {
+            history.push(line);
+        }
+        history
+    }
+
+    #[test]
+    fn history_is_bounded() {
+        let mut history = History::new();
+        history.push("  ");
+        assert_eq!(history.get(1), None);
+        for i in 0..HISTORY_LEN + 2 {
+            history.push(&i.to_string());
+        }
+        assert_eq!(history.get(0), None);
+        assert_eq!(history.get(1), Some("17"));
+        assert_eq!(history.get(HISTORY_LEN), Some("2"));
+        assert_eq!(history.get(HISTORY_LEN + 1), None);
+    }
+
+    #[test]
+    fn arrows_recall_history() {
+        let history = history(&["ls", "cat a"]);
+        let mut buf = [0u8; CMD_LEN];
+
+        let mut terminal = MockTerminal::new(b"\x1b[A\n");
+        assert_eq!(read_line(&mut terminal, &mut buf, "", &history, no_entries), Ok(5));
+        assert_eq!(&buf[..5], b"cat a");
+        assert_eq!(terminal.output, b"cat a\n");
+
+        // Up past the oldest line stays on it.
+        let mut terminal = MockTerminal::new(b"\x1b[A\x1b[A\x1b[A\n");
+        assert_eq!(read_line(&mut terminal, &mut buf, "", &history, no_entries), Ok(2));
+        assert_eq!(&buf[..2], b"ls");
+
+        // Down past the newest line brings back what was typed, and again
+        // does nothing.
+        let mut terminal = MockTerminal::new(b"pw\x1b[A\x1b[A\x1b[B\x1b[B\x1b[Bd\n");
+        assert_eq!(read_line(&mut terminal, &mut buf, "", &history, no_entries), Ok(3));
+        assert_eq!(&buf[..3], b"pwd");
+    }
+
+    #[test]
+    fn other_escape_sequences_are_ignored() {
+        let history = history(&["ls"]);
+        let mut buf = [0u8; CMD_LEN];
+
+        // Delete, Ctrl-Up and Right leave nothing behind.
+        let mut terminal = MockTerminal::new(b"pw\x1b[3~\x1b[1;5A\x1b[Cd\n");
+        assert_eq!(read_line(&mut terminal, &mut buf, "", &history, no_entries), Ok(3));
+        assert_eq!(&buf[..3], b"pwd");
+        assert_eq!(terminal.output, b"pwd\n");
+    }
+
+    #[test]
+    fn recalled_line_can_be_edited() {
+        let history = history(&["echo hi"]);
+        let mut buf = [0u8; CMD_LEN];
+        let mut terminal = MockTerminal::new(b"\x1b[A\x7f\x7fyo\x1b[C\n");
+        assert_eq!(read_line(&mut terminal, &mut buf, "", &history, no_entries), Ok(7));
+        assert_eq!(&buf[..7], b"echo yo");
+    }
+
+    #[test]
+    fn completes_paths() {
+        let extend = |rest: &str| Completion::Extend(rest.to_string());
+        assert_eq!(complete("/prog", stub_entries), extend("rams/"));
+        assert_eq!(complete("/programs/", stub_entries), extend("sleep.bin"));
+        assert_eq!(complete("/programs/sleep.bin", stub_entries), extend(""));
+        assert_eq!(complete("/pro", stub_entries), Completion::Ambiguous(vec![
+            "programs/".to_string(), "proc.txt".to_string(),
+        ]));
+        assert_eq!(complete("/x", stub_entries), Completion::NoMatch);
+        assert_eq!(complete("/nowhere/x", stub_entries), Completion::NoMatch);
+        assert_eq!(complete("rea", stub_entries), extend("dme.txt"));
+        assert_eq!(complete("", stub_entries), Completion::Ambiguous(vec![
+            "readme.txt".to_string(), "caf\u{e9}.txt".to_string(), "caf\u{e8}.txt".to_string(),
+        ]));
+        // The names differ within the bytes of their last character, which
+        // must not be split.
+        assert_eq!(complete("c", stub_entries), extend("af"));
+    }
+
+    #[test]
+    fn tab_completes_the_last_argument() {
+        let mut buf = [0u8; CMD_LEN];
+        let mut terminal = MockTerminal::new(b"cat /prog\tsl\t\n");
+        assert_eq!(read_line(&mut terminal, &mut buf, "/ > ", &History::new(), stub_entries), Ok(23));
+        assert_eq!(&buf[..23], b"cat /programs/sleep.bin");
+    }
+
+    #[test]
+    fn tab_lists_ambiguous_names() {
+        let mut buf = [0u8; CMD_LEN];
+        let mut terminal = MockTerminal::new(b"ls /pro\t\n");
+        assert_eq!(read_line(&mut terminal, &mut buf, "/ > ", &History::new(), stub_entries), Ok(7));
+        assert_eq!(terminal.output, b"ls /pro\nprograms/  proc.txt  \n/ > ls /pro\n".as_ref());
+
+        // The command name itself is not completed.
+        let mut terminal = MockTerminal::new(b"ca\t\n");
+        assert_eq!(read_line(&mut terminal, &mut buf, "/ > ", &History::new(), stub_entries), Ok(2));
+    }
+
+    /// Stands in for the file system: fails to open anything with the error
+    /// kind stored for the path.
+    fn stub_open(path: &str) -> shim::io::Result<()> {
+        use shim::io::{Error, ErrorKind};
+        let kind = match path {
+            "/missing" => ErrorKind::NotFound,
+            "/slow" => ErrorKind::TimedOut,
+            _ => ErrorKind::InvalidData,
+        };
+        Err(Error::new(kind, "stub"))
+    }
+
+    fn cat_message(path: &str) -> String {
+        let err = stub_open(path).unwrap_err();
+        FsError::new("cat", path, &err).to_string()
+    }
+
+    #[test]
+    fn fs_errors_name_their_kind() {
+        assert_eq!(cat_message("/missing"), "cat: /missing: NotFound");
+        assert_eq!(cat_message("/slow"), "cat: /slow: TimedOut");
+        assert_eq!(cat_message("/corrupt"), "cat: /corrupt: InvalidData");
+        assert_ne!(cat_message("/missing"), cat_message("/slow"));
+    }
+
+    #[test]
+    fn elapsed_format() {
+        use core::time::Duration;
+
+        assert_eq!(Elapsed(Duration::from_micros(1_234_567)).to_string(), "real 1.234567s");
+        assert_eq!(Elapsed(Duration::from_micros(42)).to_string(), "real 0.000042s");
+        assert_eq!(Elapsed(Duration::from_secs(0)).to_string(), "real 0.000000s");
+    }
+
+    #[test]
+    fn uptime_format() {
+        assert_eq!(Uptime(Duration::from_millis(3_723_456)).to_string(), "1h 02m 03.456s");
+        assert_eq!(Uptime(Duration::from_micros(999)).to_string(), "0h 00m 00.000s");
+        assert_eq!(Uptime(Duration::from_secs(100 * 3600 + 59)).to_string(), "100h 00m 59.000s");
+    }
+
+    #[test]
+    fn time_wraps_a_command() {
+        let mut cmd = Command::parse("time echo hi").unwrap();
+        assert_eq!(cmd.args.remove(0), "time");
+        assert_eq!(cmd.path(), "echo");
+        assert_eq!(cmd.args, ["echo", "hi"]);
+
+        let mut cmd = Command::parse("time").unwrap();
+        cmd.args.remove(0);
+        assert!(cmd.args.is_empty());
+    }
+
+    #[test]
+    fn time_runs_the_command_then_reports() {
+        let mut cmd = Command::parse("time echo hi").unwrap();
+        assert_eq!(cmd.args.remove(0), "time");
+        let mut terminal = MockTerminal::new(b"");
+        // Read back to front: the start, then the end.
+        let mut clock = vec![Duration::from_micros(3_250_000), Duration::from_micros(1_000_000)];
+
+        let result = timed(&mut terminal, || clock.pop().unwrap(), |terminal| {
+            assert_eq!(cmd.args.remove(0), "echo");
+            echo(terminal, &cmd.args);
+            Some(())
+        });
+
+        assert_eq!(result, Some(()));
+        assert!(clock.is_empty());
+        assert_eq!(String::from_utf8(terminal.output).unwrap(), "hi \nreal 2.250000s\n");
+    }
+
+    #[test]
+    fn alias_definitions() {
+        assert_eq!(Aliases::parse_definition("ll='ls -a'"), Some(("ll", "ls -a")));
+        assert_eq!(Aliases::parse_definition("ll=\"ls -a\""), Some(("ll", "ls -a")));
+        assert_eq!(Aliases::parse_definition("l=ls"), Some(("l", "ls")));
+        assert_eq!(Aliases::parse_definition("q='"), Some(("q", "'")));
+        assert_eq!(Aliases::parse_definition("=ls"), None);
+        assert_eq!(Aliases::parse_definition("ls"), None);
+
+        let mut aliases = Aliases::new();
+        aliases.define("ll", "ls");
+        aliases.define("ll", "ls -a");
+        assert_eq!(aliases.get("ll"), Some("ls -a"));
+        assert_eq!(aliases.get("ls"), None);
+    }
+
+    #[test]
+    fn alias_expansion() {
+        let mut aliases = Aliases::new();
+        aliases.define("ll", "ls -a");
+        aliases.define("la", "ll /programs");
+
+        assert_eq!(aliases.expand("pwd").unwrap(), "pwd");
+        assert_eq!(aliases.expand("").unwrap(), "");
+        assert_eq!(aliases.expand("echo ll").unwrap(), "echo ll");
+        assert_eq!(aliases.expand("ll /programs").unwrap(), "ls -a /programs");
+        assert_eq!(aliases.expand("  ll").unwrap(), "ls -a");
+        assert_eq!(aliases.expand("la").unwrap(), "ls -a /programs");
+
+        // Expanding `ls` again would never end.
+        aliases.define("ls", "ls -a");
+        assert_eq!(aliases.expand("ls /").unwrap(), "ls -a /");
+    }
+
+    #[test]
+    fn alias_recursion_is_capped() {
+        let mut aliases = Aliases::new();
+        aliases.define("a", "b x");
+        aliases.define("b", "a y");
+        assert_eq!(aliases.expand("a"), Err(AliasLoop("a")));
+
+        // A chain exactly as long as the cap still expands.
+        let names = ["c0", "c1", "c2", "c3", "c4", "c5", "c6", "c7", "c8"];
+        for i in 0..MAX_ALIAS_DEPTH - 1 {
+            aliases.define(names[i], names[i + 1]);
+        }
+        aliases.define(names[MAX_ALIAS_DEPTH - 1], "pwd");
+        assert_eq!(aliases.expand("c0").unwrap(), "pwd");
+
+        aliases.define(names[MAX_ALIAS_DEPTH - 1], names[MAX_ALIAS_DEPTH]);
+        aliases.define(names[MAX_ALIAS_DEPTH], "pwd");
+        assert_eq!(aliases.expand("c0"), Err(AliasLoop("c0")));
+    }
+
+    #[test]
+    fn which_classification() {
+        let mut aliases = Aliases::new();
+        aliases.define("ll", "ls -a");
+        aliases.define("cat", "echo meow");
+
+        assert_eq!(which(&aliases, "ll"), Which::Alias("ls -a"));
+        assert_eq!(which(&aliases, "cat"), Which::Alias("echo meow"));
+        assert_eq!(which(&aliases, "ls"), Which::Builtin);
+        assert_eq!(which(&aliases, "which"), Which::Builtin);
+        assert_eq!(which(&aliases, "top"), Which::NotFound);
+    }
+
+    #[test]
+    fn reboot_partition() {
+        assert_eq!(parse_partition("0"), Some(0));
+        assert_eq!(parse_partition("63"), Some(63));
+        assert_eq!(parse_partition("0x3f"), Some(63));
+        assert_eq!(parse_partition("64"), None);
+        assert_eq!(parse_partition("0x40"), None);
+        assert_eq!(parse_partition("-1"), None);
+    }
+
+    #[test]
+    fn watch_arguments() {
+        let secs = Duration::from_secs;
+        assert_eq!(parse_watch(&["-n1", "echo", "hi"]), Some(Watch { interval: secs(1), args: vec!["echo", "hi"] }));
+        assert_eq!(parse_watch(&["-n", "5", "ps"]), Some(Watch { interval: secs(5), args: vec!["ps"] }));
+        assert_eq!(
+            parse_watch(&["netstat"]),
+            Some(Watch { interval: DEFAULT_WATCH_INTERVAL, args: vec!["netstat"] })
+        );
+        assert_eq!(parse_watch(&["-n0", "ps"]), None);
+        assert_eq!(parse_watch(&["-nx", "ps"]), None);
+        assert_eq!(parse_watch(&["-n", "3"]), None);
+        assert_eq!(parse_watch(&[]), None);
+    }
+
+    #[test]
+    fn watch_runs_command_until_key() {
+        let watch = parse_watch(&["-n1", "echo", "hi"]).unwrap();
+        let mut terminal = MockTerminal::new(b"");
+        let mut ran = Vec::new();
+        let mut slept = Duration::from_secs(0);
+
+        let runs = watch_loop(
+            &mut terminal,
+            watch.interval,
+            "echo hi",
+            |terminal| {
+                ran.push(watch.args.join(" "));
+                // A key arrives during the third run.
+                if ran.len() == 3 {
+                    terminal.input.push_back(b'q');
+                }
+            },
+            |duration| slept += duration,
+        );
+
+        assert_eq!(runs, 3);
+        assert_eq!(ran, ["echo hi", "echo hi", "echo hi"]);
+        assert_eq!(slept, Duration::from_secs(2));
+        assert!(terminal.input.is_empty());
+        let output = String::from_utf8(terminal.output).unwrap();
+        assert_eq!(output.matches("Every 1s: echo hi").count(), 3);
+    }
+
+    #[test]
+    fn pending_key_stops_after_one_run() {
+        let mut terminal = MockTerminal::new(b"x");
+        let mut sleeps = 0;
+        let runs = watch_loop(&mut terminal, Duration::from_secs(2), "ps", |_| {}, |_| sleeps += 1);
+        assert_eq!(runs, 1);
+        assert_eq!(sleeps, 0);
+    }
+}
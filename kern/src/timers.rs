@@ -0,0 +1,207 @@
+use alloc::boxed::Box;
+use alloc::collections::BinaryHeap;
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+use core::fmt;
+use core::time::Duration;
+
+use pi::timer::current_time;
+
+use crate::mutex::Mutex;
+
+/// A callback invoked when a timer expires. Callbacks run in interrupt
+/// context and must not block.
+pub type TimerCallback = Box<dyn FnMut() + Send>;
+
+/// A registered callback and the time at which it should next fire.
+pub struct Timer {
+    deadline: Duration,
+    period: Option<Duration>,
+    seq: u64,
+    callback: TimerCallback,
+}
+
+impl Timer {
+    /// Invokes the callback. Returns the timer re-armed for its next deadline
+    /// if it is periodic, or `None` if it was a one-shot.
+    fn fire(mut self) -> Option<Timer> {
+        (self.callback)();
+        let period = self.period?;
+        self.deadline += period;
+        Some(self)
+    }
+}
+
+impl PartialEq for Timer {
+    fn eq(&self, other: &Timer) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Timer {}
+
+impl PartialOrd for Timer {
+    fn partial_cmp(&self, other: &Timer) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Timer {
+    /// Timers are ordered so that the earliest deadline is the greatest,
+    /// making `BinaryHeap` a min-heap. Ties are broken by registration order.
+    fn cmp(&self, other: &Timer) -> Ordering {
+        (other.deadline, other.seq).cmp(&(self.deadline, self.seq))
+    }
+}
+
+/// A set of pending timers ordered by deadline.
+pub struct TimerWheel {
+    timers: BinaryHeap<Timer>,
+    next_seq: u64,
+}
+
+impl TimerWheel {
+    /// Returns an empty `TimerWheel`.
+    pub fn new() -> TimerWheel {
+        TimerWheel {
+            timers: BinaryHeap::new(),
+            next_seq: 0,
+        }
+    }
+
+    /// Registers `callback` to fire at `deadline`, and then every `period`
+    /// after that if `period` is `Some`.
+    pub fn add(&mut self, deadline: Duration, period: Option<Duration>, callback: TimerCallback) {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.timers.push(Timer { deadline, period, seq, callback });
+    }
+
+    /// Returns the number of pending timers.
+    pub fn len(&self) -> usize {
+        self.timers.len()
+    }
+
+    /// Removes and returns every timer whose deadline is at or before `now`,
+    /// in deadline order.
+    fn take_expired(&mut self, now: Duration) -> Vec<Timer> {
+        let mut expired = Vec::new();
+        while self.timers.peek().map_or(false, |t| t.deadline <= now) {
+            expired.push(self.timers.pop().unwrap());
+        }
+        expired
+    }
+
+    /// Fires every timer that has expired at `now` and re-arms the periodic
+    /// ones. A periodic timer fires at most once per call.
+    pub fn poll(&mut self, now: Duration) {
+        for timer in self.take_expired(now) {
+            if let Some(timer) = timer.fire() {
+                self.timers.push(timer);
+            }
+        }
+    }
+}
+
+impl fmt::Debug for TimerWheel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TimerWheel")
+            .field("pending", &self.timers.len())
+            .finish()
+    }
+}
+
+static TIMERS: Mutex<Option<TimerWheel>> = Mutex::new(None);
+
+fn with_timers<F, R>(f: F) -> R
+    where
+        F: FnOnce(&mut TimerWheel) -> R,
+{
+    let mut guard = TIMERS.lock();
+    f(guard.get_or_insert_with(TimerWheel::new))
+}
+
+/// Calls `callback` once, `delay` from now.
+pub fn after(delay: Duration, callback: TimerCallback) {
+    let deadline = current_time() + delay;
+    with_timers(|timers| timers.add(deadline, None, callback))
+}
+
+/// Calls `callback` every `period`, starting `period` from now.
+pub fn every(period: Duration, callback: TimerCallback) {
+    let deadline = current_time() + period;
+    with_timers(|timers| timers.add(deadline, Some(period), callback))
+}
+
+/// Fires all expired kernel timers. Called from the timer interrupt handler.
+///
+/// The lock is released while callbacks run so that they may register new
+/// timers.
+pub fn poll() {
+    let now = current_time();
+    let expired = with_timers(|timers| timers.take_expired(now));
+    for timer in expired {
+        if let Some(timer) = timer.fire() {
+            with_timers(|timers| timers.timers.push(timer));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TimerWheel;
+    use alloc::boxed::Box;
+    use alloc::vec::Vec;
+    use core::time::Duration;
+    use std::sync::{Arc, Mutex};
+
+    fn ms(n: u64) -> Duration {
+        Duration::from_millis(n)
+    }
+
+    fn recorder(log: &Arc<Mutex<Vec<u32>>>, id: u32) -> Box<dyn FnMut() + Send> {
+        let log = log.clone();
+        Box::new(move || log.lock().unwrap().push(id))
+    }
+
+    #[test]
+    fn one_shot_fires_once() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut wheel = TimerWheel::new();
+        wheel.add(ms(10), None, recorder(&log, 1));
+
+        wheel.poll(ms(9));
+        assert!(log.lock().unwrap().is_empty());
+        wheel.poll(ms(10));
+        assert_eq!(*log.lock().unwrap(), [1]);
+        wheel.poll(ms(100));
+        assert_eq!(*log.lock().unwrap(), [1]);
+        assert_eq!(wheel.len(), 0);
+    }
+
+    #[test]
+    fn periodic_rearms() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut wheel = TimerWheel::new();
+        wheel.add(ms(10), Some(ms(10)), recorder(&log, 1));
+
+        for now in (0..=40).step_by(5) {
+            wheel.poll(ms(now));
+        }
+        assert_eq!(*log.lock().unwrap(), [1, 1, 1, 1]);
+        assert_eq!(wheel.len(), 1);
+    }
+
+    #[test]
+    fn fires_in_deadline_order() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut wheel = TimerWheel::new();
+        wheel.add(ms(30), None, recorder(&log, 3));
+        wheel.add(ms(10), None, recorder(&log, 1));
+        wheel.add(ms(20), None, recorder(&log, 2));
+        wheel.add(ms(10), None, recorder(&log, 4));
+
+        wheel.poll(ms(50));
+        assert_eq!(*log.lock().unwrap(), [1, 4, 2, 3]);
+    }
+}
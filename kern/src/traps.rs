@@ -3,20 +3,23 @@ mod syndrome;
 mod syscall;
 
 pub mod irq;
+pub mod stats;
 
 pub use self::frame::TrapFrame;
 
+use aarch64::FAR_EL1;
 use pi::interrupt::{Controller, Interrupt};
 use pi::local_interrupt::{LocalController, LocalInterrupt};
 
-use self::syndrome::Syndrome;
+use self::syndrome::{Fault, Syndrome};
 use self::syscall::handle_syscall;
 use crate::percore;
 use crate::traps::irq::IrqHandlerRegistry;
 
-use crate::{GLOBAL_IRQ, shell};
+use crate::{GLOBAL_IRQ, IRQ_STATS, SCHEDULER, shell};
 use crate::console::{kprintln};
 use crate::percore::getcpu;
+use pi::timer::current_time;
 
 #[repr(u16)]
 #[derive(Debug, PartialEq, Eq, Copy, Clone)]
@@ -56,7 +59,9 @@ pub extern "C" fn handle_exception(info: Info, esr: u32, tf: &mut TrapFrame) {
                 let controller = Controller::new();
                 for interrupt in Interrupt::iter() {
                     if controller.is_pending(interrupt) {
+                        let start = current_time();
                         GLOBAL_IRQ.invoke(interrupt, tf);
+                        IRQ_STATS.lock().record_global(interrupt, current_time() - start);
                     }
                 }
             }
@@ -67,7 +72,9 @@ pub extern "C" fn handle_exception(info: Info, esr: u32, tf: &mut TrapFrame) {
                 if controller.is_pending(interrupt) {
                     let cpu = getcpu();
                     trace!("Cpu {} is handling an IRQ", cpu);
+                    let start = current_time();
                     percore::local_irq().invoke(interrupt, tf);
+                    IRQ_STATS.lock().record_local(interrupt, current_time() - start);
                 }
             }
         }
@@ -81,6 +88,17 @@ pub extern "C" fn handle_exception(info: Info, esr: u32, tf: &mut TrapFrame) {
                 Syndrome::Svc(v) => {
                     handle_syscall(v, tf);
                 },
+                Syndrome::DataAbort { kind: Fault::Translation, .. }
+                | Syndrome::InstructionAbort { kind: Fault::Translation, .. }
+                    if info.source == Source::LowerAArch64 =>
+                {
+                    let va = unsafe { FAR_EL1.get() } as usize;
+                    if !SCHEDULER.page_in(va, tf) {
+                        kprintln!("{:?} at 0x{:x}, killing process {}", syndrome, va, tf.TPIDR);
+                        SCHEDULER.kill(tf);
+                        SCHEDULER.switch_to(tf);
+                    }
+                },
                 _ => {
                     kprintln!("{:?}", syndrome);
                     kprintln!("-------------------------------");
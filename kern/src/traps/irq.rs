@@ -33,6 +33,7 @@ impl GlobalIrq {
             Mutex::new(None),
             Mutex::new(None),
             Mutex::new(None),
+            Mutex::new(None),
         ])
     }
 }
@@ -62,22 +63,27 @@ impl Fiq {
     }
 }
 
+/// Returns the position of `int` in tables indexed by global interrupt.
+pub fn global_index(int: Interrupt) -> usize {
+    use Interrupt::*;
+    match int {
+        Timer1 => 0,
+        Timer3 => 1,
+        Usb => 2,
+        Gpio0 => 3,
+        Gpio1 => 4,
+        Gpio2 => 5,
+        Gpio3 => 6,
+        Uart => 7,
+        Aux => 8,
+    }
+}
+
 impl Index<Interrupt> for GlobalIrq {
     type Output = IrqHandlerMutex;
 
     fn index(&self, int: Interrupt) -> &IrqHandlerMutex {
-        use Interrupt::*;
-        let index = match int {
-            Timer1 => 0,
-            Timer3 => 1,
-            Usb => 2,
-            Gpio0 => 3,
-            Gpio1 => 4,
-            Gpio2 => 5,
-            Gpio3 => 6,
-            Uart => 7,
-        };
-        &self.0[index]
+        &self.0[global_index(int)]
     }
 }
 
@@ -93,8 +99,7 @@ impl Index<()> for Fiq {
     type Output = IrqHandlerMutex;
 
     fn index(&self, _: ()) -> &IrqHandlerMutex {
-        // Lab 5 2.B
-        unimplemented!("FIQ Index")
+        &self.0
     }
 }
 
@@ -0,0 +1,161 @@
+use core::time::Duration;
+
+use pi::interrupt::Interrupt;
+use pi::local_interrupt::LocalInterrupt;
+
+use crate::traps::irq::global_index;
+
+/// How often one interrupt source fired and how long its handler took.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IrqStat {
+    pub count: u64,
+    total: Duration,
+    min: Duration,
+    max: Duration,
+}
+
+impl IrqStat {
+    pub const fn new() -> IrqStat {
+        IrqStat {
+            count: 0,
+            total: Duration::from_secs(0),
+            min: Duration::from_secs(0),
+            max: Duration::from_secs(0),
+        }
+    }
+
+    /// Records one invocation of the handler that took `elapsed`.
+    pub fn record(&mut self, elapsed: Duration) {
+        if self.count == 0 || elapsed < self.min {
+            self.min = elapsed;
+        }
+        if elapsed > self.max {
+            self.max = elapsed;
+        }
+        self.total += elapsed;
+        self.count += 1;
+    }
+
+    /// Returns the shortest handler duration, or `None` if the interrupt never
+    /// fired.
+    pub fn min(&self) -> Option<Duration> {
+        if self.count == 0 { None } else { Some(self.min) }
+    }
+
+    /// Returns the longest handler duration, or `None` if the interrupt never
+    /// fired.
+    pub fn max(&self) -> Option<Duration> {
+        if self.count == 0 { None } else { Some(self.max) }
+    }
+
+    /// Returns the mean handler duration, or `None` if the interrupt never
+    /// fired.
+    pub fn avg(&self) -> Option<Duration> {
+        if self.count == 0 {
+            return None;
+        }
+        let nanos = self.total.as_nanos() / self.count as u128;
+        Some(Duration::from_nanos(nanos as u64))
+    }
+}
+
+/// Per-source interrupt statistics. Local interrupts are counted over all
+/// cores.
+#[derive(Debug, Clone, Copy)]
+pub struct IrqStats {
+    global: [IrqStat; Interrupt::MAX],
+    local: [IrqStat; LocalInterrupt::MAX],
+}
+
+impl IrqStats {
+    pub const fn new() -> IrqStats {
+        IrqStats {
+            global: [IrqStat::new(); Interrupt::MAX],
+            local: [IrqStat::new(); LocalInterrupt::MAX],
+        }
+    }
+
+    /// Records a global interrupt whose handler took `elapsed`.
+    pub fn record_global(&mut self, int: Interrupt, elapsed: Duration) {
+        self.global[global_index(int)].record(elapsed);
+    }
+
+    /// Records a local interrupt whose handler took `elapsed`.
+    pub fn record_local(&mut self, int: LocalInterrupt, elapsed: Duration) {
+        self.local[int as usize].record(elapsed);
+    }
+
+    pub fn global(&self, int: Interrupt) -> &IrqStat {
+        &self.global[global_index(int)]
+    }
+
+    pub fn local(&self, int: LocalInterrupt) -> &IrqStat {
+        &self.local[int as usize]
+    }
+
+    /// Forgets everything recorded so far.
+    pub fn reset(&mut self) {
+        *self = IrqStats::new();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{IrqStat, IrqStats};
+    use core::time::Duration;
+    use pi::interrupt::Interrupt;
+    use pi::local_interrupt::LocalInterrupt;
+
+    fn us(n: u64) -> Duration {
+        Duration::from_micros(n)
+    }
+
+    #[test]
+    fn empty_stat() {
+        let stat = IrqStat::new();
+        assert_eq!(stat.count, 0);
+        assert_eq!(stat.min(), None);
+        assert_eq!(stat.max(), None);
+        assert_eq!(stat.avg(), None);
+    }
+
+    #[test]
+    fn min_max_avg() {
+        let mut stat = IrqStat::new();
+        for &n in [30, 10, 50, 20].iter() {
+            stat.record(us(n));
+        }
+        assert_eq!(stat.count, 4);
+        assert_eq!(stat.min(), Some(us(10)));
+        assert_eq!(stat.max(), Some(us(50)));
+        assert_eq!(stat.avg(), Some(Duration::from_nanos(27_500)));
+    }
+
+    #[test]
+    fn zero_duration_is_a_valid_min() {
+        let mut stat = IrqStat::new();
+        stat.record(us(5));
+        stat.record(us(0));
+        assert_eq!(stat.min(), Some(us(0)));
+        assert_eq!(stat.max(), Some(us(5)));
+    }
+
+    #[test]
+    fn sources_are_counted_separately() {
+        let mut stats = IrqStats::new();
+        stats.record_global(Interrupt::Gpio0, us(1));
+        stats.record_global(Interrupt::Gpio0, us(3));
+        stats.record_global(Interrupt::Uart, us(2));
+        stats.record_local(LocalInterrupt::CNT_PNS_IRQ, us(4));
+
+        assert_eq!(stats.global(Interrupt::Gpio0).count, 2);
+        assert_eq!(stats.global(Interrupt::Gpio0).avg(), Some(us(2)));
+        assert_eq!(stats.global(Interrupt::Uart).count, 1);
+        assert_eq!(stats.global(Interrupt::Timer1).count, 0);
+        assert_eq!(stats.local(LocalInterrupt::CNT_PNS_IRQ).count, 1);
+        assert_eq!(stats.local(LocalInterrupt::CNT_PS_IRQ).count, 0);
+
+        stats.reset();
+        assert_eq!(stats.global(Interrupt::Gpio0).count, 0);
+    }
+}
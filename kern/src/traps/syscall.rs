@@ -1,13 +1,17 @@
 use alloc::boxed::Box;
+use fat32::traits::{Entry, FileSystem};
+use shim::io::{Read, Write};
+use shim::path::PathBuf;
 use smoltcp::wire::{IpAddress, IpEndpoint};
 
 use crate::console::{kprint, kprintln, CONSOLE};
 use crate::param::USER_IMG_BASE;
-use crate::process::{Process, State};
+use crate::process::{self, Fd, OpenFile, Process, State};
 use crate::traps::TrapFrame;
-use crate::{ETHERNET, SCHEDULER};
+use crate::{ETHERNET, FILESYSTEM, SCHEDULER};
 
 use kernel_api::*;
+use core::time::Duration;
 use pi::timer::{current_time};
 
 /// Sleep for `ms` milliseconds.
@@ -38,6 +42,49 @@ pub fn sys_sleep(ms: u32, tf: &mut TrapFrame) {
     SCHEDULER.switch(new_state, tf);
 }
 
+/// Sleep for `secs` seconds and `nanos` nanoseconds.
+///
+/// This system call takes two parameters: the whole seconds and the
+/// nanoseconds (less than one second) to sleep. `current_time()` has a
+/// resolution of one microsecond, so that is the shortest effective sleep.
+///
+/// In addition to the usual status value, this system call returns two
+/// parameters: the whole seconds and the nanoseconds of the true elapsed
+/// time. It fails with `InvalidArgument` if `nanos` is a second or more.
+pub fn sys_nanosleep(secs: u64, nanos: u64, tf: &mut TrapFrame) {
+    if nanos >= 1_000_000_000 {
+        set_result(Err(OsError::InvalidArgument), tf);
+        return;
+    }
+    let span = Duration::new(secs, nanos as u32);
+    let start_time = current_time();
+
+    let sleep_fn = Box::new(move |p: &mut Process| {
+        match sleep_elapsed(start_time, span, current_time()) {
+            Some(elapsed) => {
+                p.context.x[0] = elapsed.as_secs();
+                p.context.x[1] = elapsed.subsec_nanos() as u64;
+                p.context.x[7] = OsError::Ok as u64;
+                true
+            }
+            None => false,
+        }
+    });
+
+    SCHEDULER.switch(State::Waiting(sleep_fn), tf);
+}
+
+/// Returns the time slept so far if a sleep of `span` that began at `start`
+/// is over at `now`, or `None` if it is not.
+fn sleep_elapsed(start: Duration, span: Duration, now: Duration) -> Option<Duration> {
+    let elapsed = now.checked_sub(start).unwrap_or(Duration::from_secs(0));
+    if elapsed >= span {
+        Some(elapsed)
+    } else {
+        None
+    }
+}
+
 /// Returns current time.
 ///
 /// This system call does not take parameter.
@@ -56,8 +103,12 @@ pub fn sys_time(tf: &mut TrapFrame) {
 /// Kills the current process.
 ///
 /// This system call does not take paramer and does not return any value.
+/// The process and its page table are dropped, and `tf` is replaced with the
+/// next ready process, waiting for one if there is none.
 pub fn sys_exit(tf: &mut TrapFrame) {
-    SCHEDULER.kill(tf);
+    if let Some(id) = SCHEDULER.kill(tf) {
+        trace!("process {} exited", id);
+    }
     SCHEDULER.switch_to(tf);
 }
 
@@ -261,6 +312,208 @@ pub fn sys_write_str(va: usize, len: usize, tf: &mut TrapFrame) {
     }
 }
 
+/// Checks that the `len` bytes at `va` are mapped in the current process's
+/// page table, paging in the parts of its image that were not touched yet.
+///
+/// # Errors
+/// This functions returns `Err(OsError::BadAddress)` if any page of the buffer
+/// is not mapped.
+fn check_user_buffer(va: usize, len: usize, tf: &TrapFrame) -> OsResult<()> {
+    if SCHEDULER.critical(|scheduler| scheduler.find_process(tf).fault_in(va, len)) {
+        Ok(())
+    } else {
+        Err(OsError::BadAddress)
+    }
+}
+
+/// Stores `result` in the trap frame: the value in `x0` on success and the
+/// status in `x7`.
+fn set_result(result: OsResult<u64>, tf: &mut TrapFrame) {
+    match result {
+        Ok(value) => {
+            tf.x[0] = value;
+            tf.x[7] = OsError::Ok as u64;
+        }
+        Err(e) => {
+            tf.x[7] = e as u64;
+        }
+    }
+}
+
+/// Reads a UTF-8 encoded path of `len` bytes at `va` from user space and
+/// resolves it against the current process's working directory.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::BadAddress`: The address and the length pair does not form a valid userspace slice.
+/// - `OsError::InvalidArgument`: The path is not UTF-8 encoded.
+fn user_path(va: usize, len: usize, tf: &TrapFrame) -> OsResult<PathBuf> {
+    check_user_buffer(va, len, tf)?;
+    let slice = unsafe { to_user_slice(va, len)? };
+    let path = core::str::from_utf8(slice).map_err(|_| OsError::InvalidArgument)?;
+    Ok(SCHEDULER.critical(|scheduler| process::resolve(&scheduler.find_process(tf).cwd, path)))
+}
+
+/// Opens a file.
+///
+/// This system call takes the address of a UTF-8 encoded path as the first
+/// parameter and the length of the path as the second parameter. Relative
+/// paths are resolved against the process's working directory.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the file descriptor of the opened file.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::BadAddress`: The address and the length pair does not form a valid userspace slice.
+/// - `OsError::InvalidArgument`: The path is not UTF-8 encoded.
+/// - `OsError::NoEntry`: There is no file at the path.
+/// - `OsError::IoError*`: The file system failed to open the file.
+pub fn sys_open(va: usize, len: usize, tf: &mut TrapFrame) {
+    let result = user_path(va, len, tf)
+        .and_then(|path| Ok((&FILESYSTEM).open_file(path)?))
+        .map(|file| SCHEDULER.critical(|scheduler| scheduler.find_process(tf).files.insert(file)) as u64);
+
+    set_result(result, tf);
+}
+
+/// Reads from a file descriptor.
+///
+/// This system call takes a file descriptor as the first parameter, the
+/// address of the buffer as the second parameter, and the length of the buffer
+/// as the third parameter. Reading from the console blocks until one byte is
+/// available.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the number of bytes read, which is 0 at the end of a file.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::BadDescriptor`: `fd` is not an open file descriptor.
+/// - `OsError::BadAddress`: The address and the length pair does not form a valid userspace slice.
+/// - `OsError::IoError*`: Reading from the file failed.
+pub fn sys_read(fd: Fd, va: usize, len: usize, tf: &mut TrapFrame) {
+    let result = check_user_buffer(va, len, tf)
+        .and_then(|_| unsafe { to_user_slice_mut(va, len) })
+        .and_then(|buf| {
+            // The file is taken out of the table so that the scheduler is not
+            // held up by the SD card.
+            let mut open = SCHEDULER.critical(|scheduler| scheduler.find_process(tf).files.lend(fd))?;
+            let read = match &mut open {
+                OpenFile::File(file) => file.read(buf).map_err(OsError::from),
+                OpenFile::Console if buf.is_empty() => Ok(0),
+                OpenFile::Console => {
+                    buf[0] = CONSOLE.lock().read_byte();
+                    Ok(1)
+                }
+            };
+            SCHEDULER.critical(|scheduler| scheduler.find_process(tf).files.give_back(fd, open));
+            read.map(|n| n as u64)
+        });
+
+    set_result(result, tf);
+}
+
+/// Writes to a file descriptor.
+///
+/// This system call takes a file descriptor as the first parameter, the
+/// address of the buffer as the second parameter, and the length of the buffer
+/// as the third parameter.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the number of bytes written.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::BadDescriptor`: `fd` is not an open file descriptor.
+/// - `OsError::BadAddress`: The address and the length pair does not form a valid userspace slice.
+/// - `OsError::IoError*`: Writing to the file failed.
+pub fn sys_write_fd(fd: Fd, va: usize, len: usize, tf: &mut TrapFrame) {
+    let result = check_user_buffer(va, len, tf)
+        .and_then(|_| unsafe { to_user_slice(va, len) })
+        .and_then(|buf| {
+            // As in `sys_read`, the write happens outside the critical section.
+            let mut open = SCHEDULER.critical(|scheduler| scheduler.find_process(tf).files.lend(fd))?;
+            let written = match &mut open {
+                OpenFile::File(file) => file.write(buf),
+                OpenFile::Console => CONSOLE.lock().write(buf),
+            };
+            SCHEDULER.critical(|scheduler| scheduler.find_process(tf).files.give_back(fd, open));
+            Ok(written? as u64)
+        });
+
+    set_result(result, tf);
+}
+
+/// Closes a file descriptor.
+///
+/// This system call takes a file descriptor as the first parameter.
+///
+/// It only returns the usual status value.
+///
+/// # Errors
+/// This function returns `OsError::BadDescriptor` if `fd` is not an open file
+/// descriptor.
+pub fn sys_close(fd: Fd, tf: &mut TrapFrame) {
+    let result = SCHEDULER.critical(|scheduler| scheduler.find_process(tf).files.close(fd));
+    set_result(result.map(|_| 0), tf);
+}
+
+/// Returns the current process's working directory.
+///
+/// This system call takes the address of a buffer as the first parameter and
+/// the length of the buffer as the second parameter. The UTF-8 encoded path is
+/// written to the start of the buffer.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the length of the path in bytes.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::BadAddress`: The address and the length pair does not form a valid userspace slice.
+/// - `OsError::InvalidArgument`: The buffer is too small to hold the path.
+pub fn sys_getcwd(va: usize, len: usize, tf: &mut TrapFrame) {
+    let result = check_user_buffer(va, len, tf)
+        .and_then(|_| unsafe { to_user_slice_mut(va, len) })
+        .and_then(|buf| {
+            SCHEDULER.critical(|scheduler| process::copy_cwd(&scheduler.find_process(tf).cwd, buf))
+        })
+        .map(|len| len as u64);
+
+    set_result(result, tf);
+}
+
+/// Changes the current process's working directory.
+///
+/// This system call takes the address of a UTF-8 encoded path as the first
+/// parameter and the length of the path as the second parameter. Relative
+/// paths are resolved against the current working directory.
+///
+/// It only returns the usual status value.
+///
+/// # Errors
+/// This function can return following errors:
+///
+/// - `OsError::BadAddress`: The address and the length pair does not form a valid userspace slice.
+/// - `OsError::InvalidArgument`: The path is not UTF-8 encoded or names a file.
+/// - `OsError::NoEntry`: There is nothing at the path.
+pub fn sys_chdir(va: usize, len: usize, tf: &mut TrapFrame) {
+    let result = check_user_buffer(va, len, tf)
+        .and_then(|_| unsafe { to_user_slice(va, len) })
+        .and_then(|slice| core::str::from_utf8(slice).map_err(|_| OsError::InvalidArgument))
+        .and_then(|path| {
+            let cwd = SCHEDULER.critical(|scheduler| scheduler.find_process(tf).cwd.clone());
+            process::chdir(&cwd, path, |target| Ok((&FILESYSTEM).open(target)?.is_dir()))
+        })
+        .map(|cwd| SCHEDULER.critical(|scheduler| scheduler.find_process(tf).cwd = cwd));
+
+    set_result(result.map(|_| 0), tf);
+}
 
 pub fn handle_syscall(num: u16, tf: &mut TrapFrame) {
     match num as usize {
@@ -277,6 +530,53 @@ pub fn handle_syscall(num: u16, tf: &mut TrapFrame) {
         NR_GETPID => {
             sys_getpid(tf);
         }
+        NR_OPEN => sys_open(tf.x[0] as usize, tf.x[1] as usize, tf),
+        NR_READ => sys_read(tf.x[0] as Fd, tf.x[1] as usize, tf.x[2] as usize, tf),
+        NR_WRITE_FD => sys_write_fd(tf.x[0] as Fd, tf.x[1] as usize, tf.x[2] as usize, tf),
+        NR_CLOSE => sys_close(tf.x[0] as Fd, tf),
+        NR_GETCWD => sys_getcwd(tf.x[0] as usize, tf.x[1] as usize, tf),
+        NR_CHDIR => sys_chdir(tf.x[0] as usize, tf.x[1] as usize, tf),
+        NR_NANOSLEEP => sys_nanosleep(tf.x[0], tf.x[1], tf),
         _ => {}
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::sleep_elapsed;
+    use core::time::Duration;
+
+    fn us(n: u64) -> Duration {
+        Duration::from_micros(n)
+    }
+
+    #[test]
+    fn sub_millisecond_deadline() {
+        let start = us(1_000_000);
+        let span = Duration::from_nanos(250_000);
+        assert_eq!(sleep_elapsed(start, span, start), None);
+        assert_eq!(sleep_elapsed(start, span, start + us(249)), None);
+        assert_eq!(sleep_elapsed(start, span, start + us(250)), Some(us(250)));
+        assert_eq!(sleep_elapsed(start, span, start + us(300)), Some(us(300)));
+    }
+
+    #[test]
+    fn span_below_clock_resolution() {
+        let start = us(5);
+        let span = Duration::from_nanos(1);
+        assert_eq!(sleep_elapsed(start, span, start), None);
+        assert_eq!(sleep_elapsed(start, span, start + us(1)), Some(us(1)));
+    }
+
+    #[test]
+    fn zero_span_is_ready_immediately() {
+        let start = us(5);
+        assert_eq!(sleep_elapsed(start, Duration::from_secs(0), start), Some(us(0)));
+    }
+
+    #[test]
+    fn clock_before_start() {
+        let start = us(100);
+        assert_eq!(sleep_elapsed(start, us(1), us(50)), None);
+    }
+}
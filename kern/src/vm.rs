@@ -1,7 +1,9 @@
 mod address;
+mod maps;
 mod pagetable;
 
 pub use self::address::{PhysicalAddr, VirtualAddr};
+pub use self::maps::write_maps;
 pub use self::pagetable::*;
 
 use aarch64::*;
@@ -0,0 +1,133 @@
+use core::fmt;
+
+use aarch64::vmsa::EntryPerm;
+
+use crate::param::PAGE_SIZE;
+use crate::vm::Mapping;
+
+/// Returns the `/proc/pid/maps`-style permission string for an `EntryPerm`
+/// value. Pages are never marked execute-never, so user pages are always
+/// executable; kernel-only pages are inaccessible from user space.
+fn perm_str(perm: u64) -> &'static str {
+    match perm {
+        EntryPerm::USER_RW => "rwxp",
+        EntryPerm::USER_RO => "r-xp",
+        _ => "---p",
+    }
+}
+
+/// A run of pages with contiguous virtual and physical addresses and the same
+/// permission.
+struct Region {
+    start: u64,
+    len: u64,
+    pa: u64,
+    perm: u64,
+}
+
+impl Region {
+    fn from(mapping: Mapping) -> Region {
+        Region {
+            start: mapping.va.as_u64(),
+            len: PAGE_SIZE as u64,
+            pa: mapping.pa.as_u64(),
+            perm: mapping.perm,
+        }
+    }
+
+    /// Returns the address one past the end of the region. The top page of
+    /// the address space ends at (wraps to) `0`.
+    fn end(&self) -> u64 {
+        self.start.wrapping_add(self.len)
+    }
+
+    /// Extends `self` by `mapping` if it directly follows the region.
+    fn extend(&mut self, mapping: &Mapping) -> bool {
+        let follows = mapping.va.as_u64() == self.end()
+            && mapping.pa.as_u64() == self.pa + self.len
+            && mapping.perm == self.perm;
+        if follows {
+            self.len += PAGE_SIZE as u64;
+        }
+        follows
+    }
+}
+
+impl fmt::Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:016x}-{:016x} {} {:016x}", self.start, self.end(), perm_str(self.perm), self.pa)
+    }
+}
+
+/// Writes one line per region of `mappings` in the format of
+/// `/proc/pid/maps`: virtual start and end, permissions, and the physical
+/// address of the start of the region. Adjacent pages that are also
+/// physically contiguous and have the same permission are merged.
+///
+/// `mappings` must be sorted by virtual address.
+pub fn write_maps<W, I>(w: &mut W, mappings: I) -> fmt::Result
+    where
+        W: fmt::Write,
+        I: IntoIterator<Item = Mapping>,
+{
+    let mut current: Option<Region> = None;
+    for mapping in mappings {
+        if let Some(ref mut region) = current {
+            if region.extend(&mapping) {
+                continue;
+            }
+            writeln!(w, "{}", region)?;
+        }
+        current = Some(Region::from(mapping));
+    }
+    if let Some(region) = current {
+        writeln!(w, "{}", region)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::write_maps;
+    use crate::param::{PAGE_SIZE, USER_IMG_BASE};
+    use crate::vm::{Mapping, PhysicalAddr, VirtualAddr};
+    use aarch64::vmsa::EntryPerm;
+    use alloc::string::String;
+    use alloc::vec::Vec;
+
+    fn page(index: usize, pa: usize, perm: u64) -> Mapping {
+        Mapping {
+            va: VirtualAddr::from(USER_IMG_BASE + index * PAGE_SIZE),
+            pa: PhysicalAddr::from(pa),
+            perm,
+        }
+    }
+
+    #[test]
+    fn formats_regions() {
+        let mappings = vec![
+            page(0, 0x0100_0000, EntryPerm::USER_RW),
+            page(1, 0x0101_0000, EntryPerm::USER_RW),
+            page(2, 0x0200_0000, EntryPerm::USER_RW),
+            page(3, 0x0201_0000, EntryPerm::USER_RO),
+            page(0x3fff, 0x0300_0000, EntryPerm::USER_RW),
+        ];
+
+        let mut out = String::new();
+        write_maps(&mut out, mappings).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines, [
+            "ffffffffc0000000-ffffffffc0020000 rwxp 0000000001000000",
+            "ffffffffc0020000-ffffffffc0030000 rwxp 0000000002000000",
+            "ffffffffc0030000-ffffffffc0040000 r-xp 0000000002010000",
+            "ffffffffffff0000-0000000000000000 rwxp 0000000003000000",
+        ]);
+    }
+
+    #[test]
+    fn empty_table() {
+        let mut out = String::new();
+        write_maps(&mut out, Vec::new()).unwrap();
+        assert!(out.is_empty());
+    }
+}
@@ -205,6 +205,20 @@ impl PageTable {
     }
 }
 
+impl PageTable {
+    /// Returns an iterator over the valid L3 entries together with the
+    /// virtual address, relative to the start of the table, that each
+    /// translates.
+    pub fn valid_entries<'a>(&'a self) -> impl Iterator<Item = (VirtualAddr, &'a L3Entry)> + 'a {
+        self.l3.iter().enumerate().flat_map(|(l2index, table)| {
+            table.entries.iter().enumerate().filter(|(_, entry)| entry.is_valid()).map(move |(l3index, entry)| {
+                let va = ((l2index as u64) << 29) | ((l3index as u64) << 16);
+                (VirtualAddr::from(va), entry)
+            })
+        })
+    }
+}
+
 impl<'a> IntoIterator for &'a PageTable {
     type Item = &'a L3Entry;
     type IntoIter = FlatMap<Iter<'a,L3PageTable>, Iter<'a, L3Entry>, fn(&L3PageTable) -> Iter<L3Entry>>;
@@ -311,11 +325,58 @@ impl UserPageTable {
         unsafe { core::slice::from_raw_parts_mut(addr as *mut u8, PAGE_SIZE) }
     }
 
+    /// Returns `true` if every page overlapping the `len` bytes starting at
+    /// user virtual address `va` has been allocated.
+    pub fn is_mapped(&self, va: usize, len: usize) -> bool {
+        if va < USER_IMG_BASE {
+            return false;
+        }
+        if len == 0 {
+            return true;
+        }
+        let last = match va.checked_add(len - 1) {
+            Some(last) => last & PAGE_MASK,
+            None => return false,
+        };
+
+        let mut page = va & PAGE_MASK;
+        loop {
+            if self.0.is_invalid(VirtualAddr::from(page - USER_IMG_BASE)) {
+                return false;
+            }
+            if page == last {
+                return true;
+            }
+            page += PAGE_SIZE;
+        }
+    }
+
+    /// Returns an iterator over the pages mapped in this address space, in
+    /// increasing virtual address order.
+    pub fn mappings<'a>(&'a self) -> impl Iterator<Item = Mapping> + 'a {
+        self.0.valid_entries().map(|(offset, entry)| Mapping {
+            va: VirtualAddr::from(USER_IMG_BASE) + offset,
+            pa: entry.get_page_addr().unwrap(),
+            perm: entry.0.get_value(RawL3Entry::AP),
+        })
+    }
+
     pub fn debug_addr(&self, addr : VirtualAddr) {
         self.0.debug_addr(addr);
     }
 }
 
+/// A page mapped in a `UserPageTable`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Mapping {
+    /// The virtual address of the page.
+    pub va: VirtualAddr,
+    /// The physical address the page translates to.
+    pub pa: PhysicalAddr,
+    /// The access permission, one of the `EntryPerm` values.
+    pub perm: u64,
+}
+
 impl Deref for KernPageTable {
     type Target = PageTable;
 
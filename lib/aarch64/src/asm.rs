@@ -28,6 +28,46 @@ pub fn isb() {
     unsafe { asm!("isb" :::: "volatile") };
 }
 
+/// Data Synchronization Barrier over the full system
+#[inline(always)]
+pub fn dsb_sy() {
+    unsafe { asm!("dsb sy" ::: "memory" : "volatile") };
+}
+
+/// The size of a data cache line on the Cortex-A72, in bytes.
+pub const CACHE_LINE_SIZE: usize = 64;
+
+/// Cleans the data cache lines covering `len` bytes at `addr` to the point of
+/// coherency, so that devices reading memory directly see what was written.
+///
+/// # Safety
+///
+/// `addr..addr + len` must be mapped.
+pub unsafe fn clean_dcache_range(addr: usize, len: usize) {
+    let mut line = addr & !(CACHE_LINE_SIZE - 1);
+    while line < addr + len {
+        asm!("dc cvac, $0" :: "r"(line) : "memory" : "volatile");
+        line += CACHE_LINE_SIZE;
+    }
+    dsb_sy();
+}
+
+/// Invalidates the data cache lines covering `len` bytes at `addr`, so that
+/// the next reads fetch what a device wrote to memory.
+///
+/// # Safety
+///
+/// `addr..addr + len` must be mapped, and any other data sharing its first or
+/// last cache line must not have unwritten changes, since they are discarded.
+pub unsafe fn invalidate_dcache_range(addr: usize, len: usize) {
+    let mut line = addr & !(CACHE_LINE_SIZE - 1);
+    while line < addr + len {
+        asm!("dc ivac, $0" :: "r"(line) : "memory" : "volatile");
+        line += CACHE_LINE_SIZE;
+    }
+    dsb_sy();
+}
+
 /// Set Event
 #[inline(always)]
 pub fn sev() {
@@ -13,6 +13,19 @@ defreg!(
     ]
 );
 
+// (ref: D13.2.85: Main ID Register)
+defreg!(
+    MIDR_EL1,
+    [
+        Implementer[31 - 24],  // Implementer code
+        Variant[23 - 20],      // Major revision number
+        Architecture[19 - 16], // Architecture code
+        PartNum[15 - 04],      // Primary part number
+        Revision[03 - 00],     // Minor revision number
+        RES0[63 - 32],
+    ]
+);
+
 // (ref: D7.2.87: Secure Configuration Register)
 defreg!(
     SCR_EL3,
@@ -267,3 +280,6 @@ defreg!(CNTP_CVAL_EL0);
 
 // (ref: D7.5.12 Counter-timer Physical Timer TimerValue Register)
 defreg!(CNTP_TVAL_EL0, [TVAL[31 - 00],]);
+
+// (ref: D7.5.13 Counter-timer Physical Count register)
+defreg!(CNTPCT_EL0);
@@ -32,6 +32,14 @@ pub struct PartitionEntry {
     pub total_sectors_in_partition: u32,
 }
 
+impl PartitionEntry {
+    /// Returns `true` if the partition type is one of the FAT32 types (`0xB`
+    /// or `0xC`).
+    pub fn is_fat32(&self) -> bool {
+        self.partition_type == 0xB || self.partition_type == 0xC
+    }
+}
+
 impl fmt::Debug for PartitionEntry {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
         writeln!(f, "Boot: {}", self.boot )?;
@@ -101,4 +109,16 @@ impl MasterBootRecord {
         }
         Ok(mbr)
     }
+
+    /// Returns the index and entry of every FAT32 partition in the partition
+    /// table, in table order.
+    pub fn fat32_partitions(&self) -> impl Iterator<Item = (usize, &PartitionEntry)> + '_ {
+        self.partition_table.iter().enumerate().filter(|(_, entry)| entry.is_fat32())
+    }
+
+    /// Returns the index of the first FAT32 partition in the partition table,
+    /// if there is one.
+    pub fn first_fat32(&self) -> Option<usize> {
+        self.fat32_partitions().next().map(|(index, _)| index)
+    }
 }
@@ -4,7 +4,8 @@ use std::fmt::{self, Debug};
 use std::io;
 use std::io::prelude::*;
 use std::io::Cursor;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
 
 use crate::mbr;
@@ -12,8 +13,8 @@ use crate::traits::*;
 use crate::vfat;
 
 use mbr::{MasterBootRecord, PartitionEntry, CHS};
-use vfat::{BiosParameterBlock, VFat, VFatHandle};
-use crate::vfat::{PartitionedDevice, Partition};
+use vfat::{BackupBootCheck, BiosParameterBlock, BufferedFile, VFat, VFatHandle};
+use crate::vfat::{Cluster, PartitionedDevice, Partition};
 
 #[derive(Clone)]
 struct StdVFatHandle(Arc<Mutex<VFat<Self>>>);
@@ -52,7 +53,7 @@ match $e {
 }
 
 macro resource($name:expr) {{
-    let path = concat!(env!("CARGO_MANIFEST_DIR"), "/../../ext/fat32-imgs/", $name);
+    let path = resource_path!($name);
     match ::std::fs::File::open(path) {
         Ok(file) => file,
         Err(e) => {
@@ -66,6 +67,10 @@ macro resource($name:expr) {{
     }
 }}
 
+macro resource_path($name:expr) {
+    concat!(env!("CARGO_MANIFEST_DIR"), "/../../ext/fat32-imgs/", $name)
+}
+
 macro assert_hash_eq($name:expr, $actual:expr, $expected:expr) {
 let (actual, expected) = ($actual, $expected);
     let (actual, expected) = (actual.trim(), expected.trim());
@@ -92,6 +97,12 @@ macro vfat_from_resource($name:expr) {
 VFat::<StdVFatHandle>::from(resource!($name)).expect("failed to initialize VFAT from image")
 }
 
+macro vfat_from_resource_mut($name:expr) {{
+    let mut data = Vec::new();
+    resource!($name).read_to_end(&mut data).expect("read image");
+    VFat::<StdVFatHandle>::from(Cursor::new(data)).expect("failed to initialize VFAT from image")
+}}
+
 
 #[test]
 fn check_mbr_size() {
@@ -377,6 +388,28 @@ fn hash_files_recursive_from<P: AsRef<Path>>(vfat: StdVFatHandle, path: P) -> St
     hash
 }
 
+#[test]
+fn test_file_block_device() {
+    let device = FileBlockDevice::open(resource_path!("mock1.fat32.img")).expect("open mock 1");
+    let vfat = VFat::<StdVFatHandle>::from(device).expect("mount mock 1 from a host file");
+
+    let hash = hash_dir_from(vfat.clone(), "/");
+    assert_hash_eq!("mock 1 root directory", hash, hash_for!("root-entries-1"));
+
+    let mut hash = String::new();
+    hash_file(&mut hash, vfat.open_file("/CS140E").expect("open /CS140E")).expect("hash");
+    assert_eq!(hash, "15130871412783076140");
+}
+
+#[test]
+fn test_file_block_device_read_only() {
+    let mut device = FileBlockDevice::open(resource_path!("mock1.fat32.img")).expect("open mock 1");
+    let mut sector = [0u8; 512];
+    assert_eq!(device.read_sector(0, &mut sector).expect("read MBR"), 512);
+    assert_eq!(&sector[510..], &[0x55, 0xAA]);
+    assert!(device.write_sector(0, &sector).is_err());
+}
+
 #[test]
 fn test_mock1_files_recursive() {
     let hash = hash_files_recursive_from(vfat_from_resource!("mock1.fat32.img"), "/");
@@ -538,3 +571,1158 @@ fn partitioned_device_test_2() {
     expect.extend([0xCC; 512].iter());
     assert_eq!(expect, sector_data.to_vec());
 }
+
+/// A device over shared memory that counts its sector reads, so that tests
+/// can see which reads get past a `PartitionedDevice`'s cache.
+struct SharedDevice {
+    data: Arc<Mutex<Vec<u8>>>,
+    reads: Arc<AtomicUsize>,
+}
+
+impl BlockDevice for SharedDevice {
+    fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        self.reads.fetch_add(1, Ordering::SeqCst);
+        let start = n as usize * 512;
+        let len = std::cmp::min(buf.len(), 512);
+        buf[..len].copy_from_slice(&self.data.lock().unwrap()[start..start + len]);
+        Ok(len)
+    }
+
+    fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+        let start = n as usize * 512;
+        let len = std::cmp::min(buf.len(), 512);
+        self.data.lock().unwrap()[start..start + len].copy_from_slice(&buf[..len]);
+        Ok(len)
+    }
+}
+
+/// Returns a device of 8 sectors, sector `i` filled with `i`, behind a cache
+/// of `capacity` sectors, along with the device's memory and read count.
+fn cached_device(
+    capacity: usize,
+) -> (PartitionedDevice, Arc<Mutex<Vec<u8>>>, Arc<AtomicUsize>) {
+    let data: Vec<u8> = (0..8 * 512).map(|i| (i / 512) as u8).collect();
+    let data = Arc::new(Mutex::new(data));
+    let reads = Arc::new(AtomicUsize::new(0));
+    let device = SharedDevice { data: data.clone(), reads: reads.clone() };
+    let partition = Partition { start: 0, num_sectors: 8, sector_size: 512 };
+    (PartitionedDevice::with_cache_capacity(device, partition, capacity), data, reads)
+}
+
+#[test]
+fn test_sector_cache_hits() {
+    use crate::vfat::CacheStats;
+
+    let (mut device, _, reads) = cached_device(4);
+    assert_eq!(device.get(3).expect("get"), &[3u8; 512][..]);
+    assert_eq!(device.get(3).expect("get"), &[3u8; 512][..]);
+
+    let mut buf = [0u8; 512];
+    device.read_sector(3, &mut buf).expect("read");
+    assert_eq!(&buf[..], &[3u8; 512][..]);
+    assert_eq!(reads.load(Ordering::SeqCst), 1);
+    assert_eq!(device.cache_stats(), CacheStats { hits: 2, misses: 1 });
+
+    // Out-of-range sectors are errors, not cached.
+    assert!(device.get(8).is_err());
+    assert_eq!(device.cache_stats().misses, 2);
+}
+
+#[test]
+fn test_sector_cache_evicts_least_recently_used() {
+    let (mut device, _, reads) = cached_device(2);
+    device.get(0).expect("get");
+    device.get(1).expect("get");
+    // Sector 0 is used again, so sector 1 is the one evicted for sector 2.
+    device.get(0).expect("get");
+    device.get(2).expect("get");
+    assert_eq!(reads.load(Ordering::SeqCst), 3);
+
+    assert_eq!(device.get(0).expect("get"), &[0u8; 512][..]);
+    assert_eq!(reads.load(Ordering::SeqCst), 3);
+    assert_eq!(device.get(1).expect("get"), &[1u8; 512][..]);
+    assert_eq!(reads.load(Ordering::SeqCst), 4);
+}
+
+#[test]
+fn test_sector_cache_writes_through() {
+    let (mut device, data, reads) = cached_device(4);
+    device.get(5).expect("get");
+
+    device.write_sector(5, &[0xEEu8; 512]).expect("write");
+    assert_eq!(&data.lock().unwrap()[5 * 512..6 * 512], &[0xEEu8; 512][..]);
+    assert_eq!(device.get(5).expect("get"), &[0xEEu8; 512][..]);
+    assert_eq!(reads.load(Ordering::SeqCst), 1);
+
+    // A sector that was not cached is written without being cached.
+    device.write_sector(6, &[0xDDu8; 512]).expect("write");
+    assert_eq!(device.get(6).expect("get"), &[0xDDu8; 512][..]);
+    assert_eq!(reads.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn test_sector_cache_invalidate() {
+    let (mut device, data, reads) = cached_device(4);
+    device.get(2).expect("get");
+
+    // A write behind the cache's back is only seen once the cache is dropped.
+    data.lock().unwrap()[2 * 512..3 * 512].copy_from_slice(&[0xAAu8; 512]);
+    assert_eq!(device.get(2).expect("get"), &[2u8; 512][..]);
+    device.invalidate();
+    assert_eq!(device.get(2).expect("get"), &[0xAAu8; 512][..]);
+    assert_eq!(reads.load(Ordering::SeqCst), 2);
+}
+
+fn dir_entry_cluster(entry: vfat::Entry<StdVFatHandle>) -> u32 {
+    match entry {
+        vfat::Entry::Dir(dir) => dir.first_cluster.raw(),
+        vfat::Entry::File(_) => panic!("expected a directory"),
+    }
+}
+
+#[test]
+fn test_create_dir() {
+    let vfat = vfat_from_resource_mut!("mock1.fat32.img");
+    let root = (&vfat).open_dir("/").expect("open root");
+    let root_cluster = root.first_cluster.raw();
+
+    let created = root.create_dir("newdir").expect("create_dir");
+    let names: Vec<String> = created.entries().expect("entries").map(|e| e.name().to_string()).collect();
+    assert_eq!(names, vec![".", ".."]);
+
+    let mut entries = created.entries().expect("entries");
+    assert_eq!(dir_entry_cluster(entries.next().unwrap()), created.first_cluster.raw());
+    assert_eq!(dir_entry_cluster(entries.next().unwrap()), root_cluster);
+
+    let reopened = (&vfat).open_dir("/NEWDIR").expect("open new directory");
+    assert_eq!(reopened.first_cluster, created.first_cluster);
+    let up = reopened.find("..").expect("find ..");
+    assert_eq!(dir_entry_cluster(up), root_cluster);
+
+    let nested = reopened.create_dir("sub").expect("create nested");
+    let mut entries = nested.entries().expect("entries");
+    entries.next();
+    assert_eq!(dir_entry_cluster(entries.next().unwrap()), created.first_cluster.raw());
+
+    let err = root.create_dir("NEWDIR").expect_err("duplicate name");
+    assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+}
+
+#[test]
+fn test_create_dir_long_name() {
+    use crate::vfat::dir::VFatRegularDirEntry;
+
+    let vfat = vfat_from_resource_mut!("mock1.fat32.img");
+    let root = (&vfat).open_dir("/").expect("open root");
+
+    // Enough entries of three slots each to spill into another cluster.
+    let names: Vec<String> = (0..20).map(|i| format!("A long directory name {:02}", i)).collect();
+    for name in names.iter() {
+        assert_eq!(&root.create_dir(name).expect("create_dir").metadata.name, name);
+    }
+    for name in names.iter() {
+        let dir = root.find(&name.to_uppercase()).expect("find").into_dir().expect("a directory");
+        assert_eq!(&dir.metadata.name, name);
+        let entries: Vec<String> = dir.entries().expect("entries").map(|e| e.name().to_string()).collect();
+        assert_eq!(entries, vec![".", ".."]);
+    }
+
+    let err = root.create_dir("a LONG directory name 07").expect_err("duplicate name");
+    assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+    for &bad in ["bad:name", "trailing ", "a\\b", ""].iter() {
+        let err = root.create_dir(bad).expect_err("invalid name");
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput, "{:?}", bad);
+    }
+
+    assert_eq!(&VFatRegularDirEntry::numbered_short_name("A long name.text", 1), b"ALONGN~1TEX");
+    assert_eq!(&VFatRegularDirEntry::numbered_short_name("x", 12), b"X~12       ");
+}
+
+#[test]
+fn test_remove() {
+    use crate::vfat::Status;
+
+    let vfat = vfat_from_resource_mut!("mock1.fat32.img");
+    let root = (&vfat).open_dir("/").expect("open root");
+    let is_free = |cluster: Cluster| vfat.lock(|vfat| vfat.fat_entry(cluster).expect("fat entry").status() == Status::Free);
+
+    let gone = root.create_dir("A directory with a long name").expect("create_dir");
+    root.remove("a directory with a long name").expect("remove");
+    assert!(root.find("A directory with a long name").is_err());
+    assert!(is_free(gone.first_cluster));
+    // Its LFN entries are deleted along with it.
+    assert!(root.raw_entries().expect("raw entries")
+        .filter(|raw| !raw.deleted)
+        .all(|raw| !raw.entry.name().eq_ignore_ascii_case("A directory with a long name")));
+
+    let full = root.create_dir("full").expect("create_dir");
+    full.create_dir("sub").expect("create nested");
+    let err = root.remove("FULL").expect_err("not empty");
+    assert_eq!(err.kind(), io::ErrorKind::Other);
+    full.remove("sub").expect("remove nested");
+    root.remove("full").expect("remove emptied directory");
+    assert!(is_free(full.first_cluster));
+
+    let cluster_size = vfat.lock(|vfat| vfat.cluster_size());
+    let data = vec![0x5Au8; 2 * cluster_size + 1];
+    let file = new_root_file(&vfat, "GONE.BIN", &data);
+    let clusters = vfat.lock(|vfat| vfat.file_extents(file.first_cluster)).expect("extents");
+    root.remove(&file.metadata.name).expect("remove file");
+    assert!(root.find(&file.metadata.name).is_err());
+    assert_eq!(clusters.len(), 3);
+    assert!(clusters.iter().all(|&(cluster, _)| is_free(cluster)));
+
+    for &(name, kind) in [(".", io::ErrorKind::InvalidInput), ("..", io::ErrorKind::InvalidInput),
+                          ("missing", io::ErrorKind::NotFound)].iter() {
+        assert_eq!(root.remove(name).expect_err("cannot remove").kind(), kind, "{:?}", name);
+    }
+
+    // Free clusters are allocated lowest first, so freed ones are reused.
+    let reused = root.create_dir("again").expect("create_dir");
+    assert!(reused.first_cluster.raw() <= gone.first_cluster.raw());
+}
+
+fn lfn_entry(seq: u8, name: &str) -> vfat::dir::VFatLfnDirEntry {
+    let mut chars = [0xffffu16; 13];
+    for (i, c) in name.encode_utf16().enumerate() {
+        chars[i] = c;
+    }
+    if name.len() < 13 {
+        chars[name.len()] = 0;
+    }
+
+    let mut raw = [0u8; 32];
+    raw[0] = seq;
+    raw[11] = 0x0F;
+    for (i, c) in chars.iter().enumerate() {
+        let offset = match i {
+            0..=4 => 1 + i * 2,
+            5..=10 => 14 + (i - 5) * 2,
+            _ => 28 + (i - 11) * 2,
+        };
+        raw[offset..offset + 2].copy_from_slice(&c.to_le_bytes());
+    }
+    unsafe { ::std::mem::transmute(raw) }
+}
+
+fn regular_entry(short_name: &[u8; 11]) -> vfat::dir::VFatRegularDirEntry {
+    let mut raw = [0u8; 32];
+    raw[..11].copy_from_slice(short_name);
+    raw[11] = 0x20;
+    unsafe { ::std::mem::transmute(raw) }
+}
+
+#[test]
+fn test_lfn_name() {
+    let mut name = vfat::dir::LfnName::new();
+    name.push(&lfn_entry(0x42, "name.txt"));
+    name.push(&lfn_entry(0x01, "a-rather-long"));
+    assert_eq!(name.name_for(&regular_entry(b"A-RATH~1TXT")), "a-rather-longname.txt");
+}
+
+#[test]
+fn test_lfn_checksum() {
+    // Pairs taken from the LFN entries in mock1.
+    let pairs: [(&[u8; 11], u8); 4] = [
+        (b"RPI3-D~1   ", 0x34),
+        (b"BROADCOM   ", 0x8c),
+        (b"RPI3-S~1PDF", 0x20),
+        (b"PHYSIC~1PDF", 0x5f),
+    ];
+    for &(short_name, checksum) in pairs.iter() {
+        assert_eq!(vfat::dir::lfn_checksum(short_name), checksum);
+        assert_eq!(regular_entry(short_name).short_name_checksum(), checksum);
+    }
+
+    let short_name = vfat::dir::VFatRegularDirEntry::short_name("solution.c").unwrap();
+    assert_eq!(vfat::dir::lfn_checksum(&short_name), 0x2a);
+}
+
+#[test]
+fn test_lfn_zero_sequence_number() {
+    let mut name = vfat::dir::LfnName::new();
+    name.push(&lfn_entry(0x40, "broken"));
+    assert_eq!(name.name_for(&regular_entry(b"BROKEN  TXT")), "BROKEN.TXT");
+}
+
+#[test]
+fn test_lfn_out_of_range_sequence_number() {
+    let mut name = vfat::dir::LfnName::new();
+    name.push(&lfn_entry(0x5F, "broken"));
+    name.push(&lfn_entry(0x01, "fine"));
+    assert_eq!(name.name_for(&regular_entry(b"BROKEN  TXT")), "BROKEN.TXT");
+}
+
+/// Returns the bytes of mock 1 with its FAT32 partition entry moved to slot 2
+/// of the partition table and slot 0 holding a non-FAT partition.
+fn mock1_with_fat32_in_slot_2() -> Vec<u8> {
+    let mut data = Vec::new();
+    resource!("mock1.fat32.img").read_to_end(&mut data).expect("read image");
+
+    let entry = |i: usize| 446 + i * 16;
+    let mut fat32_entry = [0u8; 16];
+    fat32_entry.copy_from_slice(&data[entry(0)..entry(1)]);
+    data[entry(2)..entry(3)].copy_from_slice(&fat32_entry);
+    data[entry(0)..entry(1)].iter_mut().for_each(|b| *b = 0);
+    data[entry(0) + 4] = 0x83;
+    data
+}
+
+#[test]
+fn test_mount_partition_by_index() {
+    let data = mock1_with_fat32_in_slot_2();
+
+    let mbr = MasterBootRecord::from(Cursor::new(data[..512].to_vec())).expect("valid MBR");
+    assert_eq!(mbr.first_fat32(), Some(2));
+    let indices: Vec<usize> = mbr.fat32_partitions().map(|(i, _)| i).collect();
+    assert_eq!(indices, vec![2]);
+
+    let vfat = VFat::<StdVFatHandle>::from_partition(Cursor::new(data.clone()), 2)
+        .expect("mount partition 2");
+    let hash = hash_dir_from(vfat, "/");
+    assert_hash_eq!("mock 1 root entries", hash, hash_for!("root-entries-1"));
+
+    let vfat = VFat::<StdVFatHandle>::from(Cursor::new(data)).expect("auto-select partition 2");
+    let hash = hash_dir_from(vfat, "/");
+    assert_hash_eq!("mock 1 root entries", hash, hash_for!("root-entries-1"));
+}
+
+#[test]
+fn test_fat32_partitions() {
+    let mut data = mock1_with_fat32_in_slot_2();
+    // Copy the FAT32 entry into slot 3 too, with the LBA type.
+    let fat32_entry: Vec<u8> = data[478..494].to_vec();
+    data[494..510].copy_from_slice(&fat32_entry);
+    data[494 + 4] = 0x0C;
+
+    let mbr = MasterBootRecord::from(Cursor::new(data[..512].to_vec())).expect("valid MBR");
+    let found: Vec<(usize, u8)> = mbr.fat32_partitions().map(|(i, entry)| (i, entry.partition_type)).collect();
+    assert_eq!(found, vec![(2, 0x0B), (3, 0x0C)]);
+    assert_eq!(mbr.first_fat32(), Some(2));
+
+    let vfat = VFat::<StdVFatHandle>::from_partition(Cursor::new(data), 3).expect("mount partition 3");
+    let hash = hash_dir_from(vfat, "/");
+    assert_hash_eq!("mock 1 root entries", hash, hash_for!("root-entries-1"));
+}
+
+#[test]
+fn test_mount_partition_bad_index() {
+    let data = mock1_with_fat32_in_slot_2();
+
+    let e = VFat::<StdVFatHandle>::from_partition(Cursor::new(data.clone()), 4).unwrap_err();
+    expect_variant!(e, vfat::Error::NotFound);
+
+    let e = VFat::<StdVFatHandle>::from_partition(Cursor::new(data), 0).unwrap_err();
+    expect_variant!(e, vfat::Error::NotFormatted);
+}
+
+/// Returns the bytes of mock 1 with the backup boot sector overwritten by
+/// `fill`, which is passed the primary boot sector.
+fn mock1_with_backup_boot(fill: impl FnOnce(&[u8]) -> Vec<u8>) -> Vec<u8> {
+    let mut data = Vec::new();
+    resource!("mock1.fat32.img").read_to_end(&mut data).expect("read image");
+
+    let le32 = |b: &[u8]| u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize;
+    let start = le32(&data[446 + 8..]) * 512;
+    let backup = u16::from_le_bytes([data[start + 50], data[start + 51]]) as usize;
+    assert_ne!(backup, 0, "mock 1 has no backup boot sector");
+
+    let replacement = fill(&data[start..start + 512]);
+    let backup_start = start + backup * 512;
+    data[backup_start..backup_start + 512].copy_from_slice(&replacement);
+    data
+}
+
+#[test]
+fn test_backup_boot_match() {
+    let data = mock1_with_backup_boot(|primary| primary.to_vec());
+    let vfat = VFat::<StdVFatHandle>::from(Cursor::new(data)).expect("mount");
+    assert_eq!(vfat.lock(|v| v.backup_boot_check()), BackupBootCheck::Match);
+}
+
+#[test]
+fn test_backup_boot_mismatch() {
+    let data = mock1_with_backup_boot(|_| vec![0u8; 512]);
+    let vfat = VFat::<StdVFatHandle>::from(Cursor::new(data)).expect("mount despite bad backup");
+    assert_eq!(vfat.lock(|v| v.backup_boot_check()), BackupBootCheck::Mismatch);
+
+    // Same signature, different geometry: the number of FATs differs.
+    let data = mock1_with_backup_boot(|primary| {
+        let mut backup = primary.to_vec();
+        backup[16] = backup[16].wrapping_add(1);
+        backup
+    });
+    let vfat = VFat::<StdVFatHandle>::from(Cursor::new(data)).expect("mount despite bad backup");
+    assert_eq!(vfat.lock(|v| v.backup_boot_check()), BackupBootCheck::Mismatch);
+}
+
+/// Returns the bytes of mock 1 with one byte of its second FAT flipped if
+/// `diverge` is set.
+fn mock1_with_fats(diverge: bool) -> Vec<u8> {
+    let mut data = Vec::new();
+    resource!("mock1.fat32.img").read_to_end(&mut data).expect("read image");
+
+    let le16 = |b: &[u8]| u16::from_le_bytes([b[0], b[1]]) as usize;
+    let le32 = |b: &[u8]| u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize;
+    let start = le32(&data[446 + 8..]) * 512;
+    let reserved = le16(&data[start + 14..]);
+    let number_fats = data[start + 16];
+    let sectors_per_fat = le32(&data[start + 36..]);
+    assert_eq!(number_fats, 2, "mock 1 should have two FATs");
+
+    if diverge {
+        // The entry of cluster 3, in the first sector of the second FAT.
+        let second_fat = start + (reserved + sectors_per_fat) * 512;
+        data[second_fat + 12] ^= 0x01;
+    }
+    data
+}
+
+#[test]
+fn test_verify_fats_match() {
+    let vfat = VFat::<StdVFatHandle>::from(Cursor::new(mock1_with_fats(false))).expect("mount");
+    assert!(vfat.lock(|v| v.verify_fats()).expect("verify"));
+
+    // Allocating updates every copy, so they still agree.
+    vfat.lock(|v| v.alloc_cluster()).expect("alloc");
+    assert!(vfat.lock(|v| v.verify_fats()).expect("verify"));
+}
+
+#[test]
+fn test_verify_fats_mismatch() {
+    let vfat = VFat::<StdVFatHandle>::from(Cursor::new(mock1_with_fats(true))).expect("mount");
+    assert!(!vfat.lock(|v| v.verify_fats()).expect("verify"));
+}
+
+/// A writer that records every call to `write`.
+#[derive(Default)]
+struct CountingWriter {
+    writes: usize,
+    data: Vec<u8>,
+}
+
+impl Write for CountingWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.writes += 1;
+        self.data.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_buffered_file_batches_writes() {
+    let mut inner = CountingWriter::default();
+    {
+        let mut file = BufferedFile::with_capacity(512, &mut inner);
+        for i in 0..64u8 {
+            file.write_all(&[i; 8]).unwrap();
+        }
+        assert_eq!(file.get_ref().writes, 0);
+        file.flush().unwrap();
+        assert_eq!(file.get_ref().writes, 1);
+        assert!(file.buffer().is_empty());
+    }
+
+    let expected: Vec<u8> = (0..64u8).flat_map(|i| vec![i; 8]).collect();
+    assert_eq!(inner.writes, 1);
+    assert_eq!(inner.data, expected);
+}
+
+#[test]
+fn test_buffered_file_flushes_when_full_and_on_drop() {
+    let mut inner = CountingWriter::default();
+    {
+        let mut file = BufferedFile::with_capacity(16, &mut inner);
+        file.write_all(b"0123456789").unwrap();
+        file.write_all(b"abcdefghij").unwrap();
+        assert_eq!(file.get_ref().writes, 1);
+        assert_eq!(file.buffer(), b"abcdefghij");
+
+        // Writes at least as large as the buffer bypass it.
+        file.write_all(b"ABCDEFGHIJKLMNOPQRSTUVWXYZ").unwrap();
+        assert_eq!(file.get_ref().writes, 3);
+        file.write_all(b"!").unwrap();
+    }
+
+    assert_eq!(inner.writes, 4);
+    assert_eq!(&inner.data[..], &b"0123456789abcdefghijABCDEFGHIJKLMNOPQRSTUVWXYZ!"[..]);
+}
+
+#[test]
+fn test_file_extents_fragmented() {
+    let vfat = vfat_from_resource_mut!("mock1.fat32.img");
+    vfat.lock(|vfat| {
+        // Build a three-cluster chain with gaps between its clusters.
+        let first = vfat.alloc_cluster().expect("alloc");
+        vfat.alloc_cluster().expect("alloc gap");
+        let second = vfat.alloc_cluster().expect("alloc");
+        vfat.alloc_cluster().expect("alloc gap");
+        vfat.alloc_cluster().expect("alloc gap");
+        let third = vfat.alloc_cluster().expect("alloc");
+        vfat.set_fat_entry(first, second.raw()).expect("link");
+        vfat.set_fat_entry(second, third.raw()).expect("link");
+
+        let extents = vfat.file_extents(first).expect("extents");
+        let clusters: Vec<Cluster> = extents.iter().map(|&(c, _)| c).collect();
+        assert_eq!(clusters, vec![first, second, third]);
+
+        let spc = vfat.sectors_per_cluster as u64;
+        let base = extents[0].1;
+        for &(cluster, sector) in &extents {
+            let expected = base + (cluster.raw() - first.raw()) as u64 * spc;
+            assert_eq!(sector, expected, "sector of cluster {}", cluster.raw());
+        }
+        assert!(second.raw() > first.raw() + 1 && third.raw() > second.raw() + 1);
+    });
+}
+
+#[test]
+fn test_file_extents_rejects_cycle() {
+    let vfat = vfat_from_resource_mut!("mock1.fat32.img");
+    vfat.lock(|vfat| {
+        let first = vfat.alloc_cluster().expect("alloc");
+        let second = vfat.alloc_cluster().expect("alloc");
+        vfat.set_fat_entry(first, second.raw()).expect("link");
+        vfat.set_fat_entry(second, first.raw()).expect("link");
+
+        let err = vfat.file_extents(first).expect_err("cycle");
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    });
+}
+
+/// Allocates a cluster and fills it with a pattern that differs between
+/// sectors. Returns the cluster and its contents.
+fn patterned_cluster(vfat: &mut VFat<StdVFatHandle>) -> (Cluster, Vec<u8>) {
+    let cluster = vfat.alloc_cluster().expect("alloc");
+    let data: Vec<u8> = (0..vfat.cluster_size()).map(|i| (i % 251) as u8).collect();
+    vfat.write_cluster(cluster, &data).expect("write cluster");
+    (cluster, data)
+}
+
+/// Counts the allocations, including reallocations, made by each thread, so
+/// that tests can check how many allocations an operation makes.
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: std::cell::Cell<usize> = std::cell::Cell::new(0);
+}
+
+fn count_allocation() {
+    let _ = ALLOCATIONS.try_with(|n| n.set(n.get() + 1));
+}
+
+unsafe impl std::alloc::GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: std::alloc::Layout) -> *mut u8 {
+        count_allocation();
+        std::alloc::System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: std::alloc::Layout) {
+        std::alloc::System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: std::alloc::Layout, new_size: usize) -> *mut u8 {
+        count_allocation();
+        std::alloc::System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static COUNTING_ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// Runs `f` and returns its result and the number of allocations it made.
+fn count_allocations<R>(f: impl FnOnce() -> R) -> (R, usize) {
+    let before = ALLOCATIONS.with(|n| n.get());
+    let result = f();
+    (result, ALLOCATIONS.with(|n| n.get()) - before)
+}
+
+/// Allocates a chain of `len` clusters, each filled with a different
+/// pattern, and returns its first cluster.
+fn patterned_chain(vfat: &mut VFat<StdVFatHandle>, len: usize) -> Cluster {
+    let mut clusters = Vec::new();
+    for i in 0..len {
+        let cluster = vfat.alloc_cluster().expect("alloc");
+        let data: Vec<u8> = (0..vfat.cluster_size()).map(|j| ((i * 7 + j) % 251) as u8).collect();
+        vfat.write_cluster(cluster, &data).expect("write cluster");
+        if let Some(&prev) = clusters.last() {
+            vfat.set_fat_entry(prev, cluster.raw()).expect("link");
+        }
+        clusters.push(cluster);
+    }
+    clusters[0]
+}
+
+/// Reads a chain the way `read_chain` used to: through a scratch cluster
+/// copied onto the end of the output, with a sector allocated for every FAT
+/// lookup.
+fn read_chain_reference(vfat: &mut VFat<StdVFatHandle>, start: Cluster) -> Vec<u8> {
+    use crate::vfat::Status;
+
+    let mut data = Vec::new();
+    let mut cluster_data = vec![0u8; vfat.cluster_size()];
+    let mut next = start;
+    loop {
+        vfat.read_cluster(next, 0, &mut cluster_data).expect("read cluster");
+        data.extend_from_slice(&cluster_data);
+        // `fat_entry` now reads through the sector cache; the old one
+        // allocated a sector buffer for every lookup.
+        let _sector = vec![0u8; vfat.bytes_per_sector as usize];
+        match vfat.fat_entry(next).expect("fat entry").status() {
+            Status::Data(cluster) => next = cluster,
+            Status::Eoc(_) => return data,
+            status => panic!("unexpected FAT entry {:?}", status),
+        }
+    }
+}
+
+#[test]
+fn test_read_chain_matches_reference() {
+    const LEN: usize = 16;
+
+    let vfat = vfat_from_resource_mut!("mock1.fat32.img");
+    vfat.lock(|vfat| {
+        let start = patterned_chain(vfat, LEN);
+        let size = LEN * vfat.cluster_size();
+
+        let (expected, reference) = count_allocations(|| read_chain_reference(vfat, start));
+        assert_eq!(expected.len(), size);
+
+        let mut data = Vec::new();
+        let (read, allocations) = count_allocations(|| vfat.read_chain(start, &mut data).expect("read chain"));
+        assert_eq!(read, size);
+        assert_eq!(data, expected);
+        assert!(allocations < reference, "{} allocations, the reference made {}", allocations, reference);
+
+        // With the size known, only the output and the FAT sector are
+        // allocated.
+        let mut sized = Vec::new();
+        let (read, allocations) =
+            count_allocations(|| vfat.read_chain_with_hint(start, &mut sized, size).expect("read chain"));
+        assert_eq!(read, size);
+        assert_eq!(sized, expected);
+        assert_eq!(allocations, 2);
+    });
+}
+
+#[test]
+fn test_read_chain_appends() {
+    let vfat = vfat_from_resource_mut!("mock1.fat32.img");
+    vfat.lock(|vfat| {
+        let start = patterned_chain(vfat, 3);
+        let expected = read_chain_reference(vfat, start);
+
+        // The chain is appended to what `buf` holds, and a hint that is not
+        // a multiple of the cluster size still reads whole clusters.
+        let mut data = b"prefix".to_vec();
+        vfat.read_chain_with_hint(start, &mut data, 1).expect("read chain");
+        assert_eq!(&data[..6], b"prefix");
+        assert_eq!(&data[6..], &expected[..]);
+    });
+}
+
+#[test]
+fn test_read_cluster_partial() {
+    let vfat = vfat_from_resource_mut!("mock1.fat32.img");
+    vfat.lock(|vfat| {
+        let (cluster, data) = patterned_cluster(vfat);
+
+        let mut buf = [0u8; 100];
+        assert_eq!(vfat.read_cluster(cluster, 0, &mut buf).expect("read"), 100);
+        assert_eq!(&buf[..], &data[..100]);
+    });
+}
+
+#[test]
+fn test_read_cluster_full() {
+    let vfat = vfat_from_resource_mut!("mock1.fat32.img");
+    vfat.lock(|vfat| {
+        let (cluster, data) = patterned_cluster(vfat);
+
+        // A buffer larger than the cluster is only filled up to its size.
+        let mut buf = vec![0xAAu8; data.len() + 10];
+        assert_eq!(vfat.read_cluster(cluster, 0, &mut buf).expect("read"), data.len());
+        assert_eq!(&buf[..data.len()], &data[..]);
+        assert!(buf[data.len()..].iter().all(|&b| b == 0xAA));
+    });
+}
+
+#[test]
+fn test_read_cluster_with_offset() {
+    // mock4 has four sectors per cluster, so reads can cross sectors.
+    let vfat = vfat_from_resource_mut!("mock4.fat32.img");
+    vfat.lock(|vfat| {
+        let (cluster, data) = patterned_cluster(vfat);
+        let sector_size = vfat.bytes_per_sector as usize;
+        assert!(data.len() > sector_size);
+
+        // Spans a sector boundary.
+        let offset = sector_size - 7;
+        let mut buf = [0u8; 20];
+        assert_eq!(vfat.read_cluster(cluster, offset, &mut buf).expect("read"), 20);
+        assert_eq!(&buf[..], &data[offset..offset + 20]);
+
+        // The tail of the cluster.
+        let offset = data.len() - 5;
+        let mut buf = [0u8; 64];
+        assert_eq!(vfat.read_cluster(cluster, offset, &mut buf).expect("read"), 5);
+        assert_eq!(&buf[..5], &data[offset..]);
+
+        assert_eq!(vfat.read_cluster(cluster, data.len(), &mut buf).expect("read"), 0);
+        let err = vfat.read_cluster(cluster, data.len() + 1, &mut buf).expect_err("past end");
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    });
+}
+
+#[test]
+fn test_read_multi_sector_clusters() {
+    // Mock 4 is the only image with more than one sector per cluster.
+    let vfat = vfat_from_resource_mut!("mock4.fat32.img");
+    let (start, cluster_size) = vfat.lock(|vfat| {
+        assert_eq!(vfat.sectors_per_cluster, 4);
+        assert_eq!(vfat.bytes_per_sector, 512);
+
+        let (cluster, data) = patterned_cluster(vfat);
+        let mut buf = vec![0u8; data.len()];
+        assert_eq!(vfat.read_cluster(cluster, 0, &mut buf).expect("read"), 2048);
+        assert_eq!(buf, data);
+
+        // An offset into the third sector.
+        let mut buf = [0u8; 100];
+        assert_eq!(vfat.read_cluster(cluster, 1100, &mut buf).expect("read"), 100);
+        assert_eq!(&buf[..], &data[1100..1200]);
+
+        (patterned_chain(vfat, 2), vfat.cluster_size())
+    });
+
+    // A file ending partway through the second sector of its second cluster.
+    let size = cluster_size + 700;
+    let expected: Vec<u8> = (0..size).map(|k| ((k / cluster_size * 7 + k % cluster_size) % 251) as u8).collect();
+    let metadata = vfat::Metadata { size: size as u32, ..Default::default() };
+    let mut file = vfat::File { vfat, metadata, first_cluster: start, pos: 0, dir_entry: None };
+    let mut data = Vec::new();
+    file.read_to_end(&mut data).expect("read file");
+    assert_eq!(data, expected);
+}
+
+#[test]
+fn test_walk_visits_every_entry_once() {
+    let vfat = vfat_from_resource_mut!("mock1.fat32.img");
+    let root = (&vfat).open_dir("/").expect("open root");
+    let a = root.create_dir("a").expect("create /a");
+    a.create_dir("b").expect("create /a/b").create_dir("c").expect("create /a/b/c");
+
+    let mut paths = Vec::new();
+    root.walk(|path, entry| {
+        assert_eq!(path.file_name().unwrap().to_str().unwrap(), entry.name());
+        paths.push(path.to_path_buf());
+    })
+    .expect("walk");
+
+    for path in ["/A", "/A/B", "/A/B/C"].iter() {
+        assert_eq!(paths.iter().filter(|p| **p == PathBuf::from(*path)).count(), 1, "{}", path);
+    }
+    let mut unique = paths.clone();
+    unique.sort();
+    unique.dedup();
+    assert_eq!(unique.len(), paths.len());
+
+    // Every path names the entry it was reported for.
+    for path in paths.iter() {
+        (&vfat).open(path).expect("walked path exists");
+    }
+    let files = paths.iter().filter(|p| (&vfat).open_file(p).is_ok()).count();
+    assert!(files > 0);
+}
+
+#[test]
+fn test_walk_terminates_on_cycle() {
+    use crate::vfat::dir::VFatRegularDirEntry;
+
+    let vfat = vfat_from_resource_mut!("mock1.fat32.img");
+    let root = (&vfat).open_dir("/").expect("open root");
+    let a = root.create_dir("a").expect("create /a");
+    let b = a.create_dir("b").expect("create /a/b");
+
+    // Link /A/B/LOOP back to /A, as corrupt media might.
+    let attributes = vfat::Attributes::new().with_directory(true);
+    let link = VFatRegularDirEntry::new(*b"LOOP       ", attributes, a.first_cluster, 0);
+    vfat.lock(|vfat| vfat.append_dir_entry(b.first_cluster, &link)).expect("append entry");
+
+    let mut paths = Vec::new();
+    a.walk(|path, _| paths.push(path.to_str().unwrap().to_string())).expect("walk");
+    assert_eq!(paths, ["/B", "/B/LOOP"]);
+}
+
+#[test]
+fn test_raw_entries_surface_deleted() {
+    use crate::vfat::dir::{DirIter, RawDirIter};
+
+    fn bytes<T>(entry: &T) -> &[u8] {
+        unsafe { ::std::slice::from_raw_parts(entry as *const T as *const u8, 32) }
+    }
+
+    let deleted_lfn = lfn_entry(0x41, "gone.txt");
+    let deleted = regular_entry(b"GONE    TXT");
+    let live = regular_entry(b"LIVE    TXT");
+    let stale = regular_entry(b"STALE   TXT");
+
+    let mut data = Vec::new();
+    data.extend_from_slice(bytes(&deleted_lfn));
+    data.extend_from_slice(bytes(&deleted));
+    data.extend_from_slice(bytes(&live));
+    data.extend_from_slice(&[0u8; 32]);
+    data.extend_from_slice(bytes(&stale));
+    data.extend_from_slice(&[0u8; 64]);
+    // Deleting overwrites the first byte of every entry of the file.
+    data[0] = 0xE5;
+    data[32] = 0xE5;
+
+    let vfat = vfat_from_resource!("mock1.fat32.img");
+    let names: Vec<String> = DirIter::new(vfat.clone(), &data)
+        .map(|entry| entry.name().to_string())
+        .collect();
+    assert_eq!(names, ["LIVE.TXT"]);
+
+    let raw: Vec<(String, bool)> = RawDirIter::new(DirIter::new(vfat.clone(), &data))
+        .map(|raw| (raw.entry.name().to_string(), raw.deleted))
+        .collect();
+    assert_eq!(raw, [
+        ("gone.txt".to_string(), true),
+        ("LIVE.TXT".to_string(), false),
+        ("STALE.TXT".to_string(), true),
+    ]);
+
+    // Without its LFN entry only the short name is left.
+    let raw: Vec<String> = RawDirIter::new(DirIter::new(vfat.clone(), &data[32..]))
+        .map(|raw| raw.entry.name().to_string())
+        .collect();
+    assert_eq!(raw, ["?ONE.TXT", "LIVE.TXT", "STALE.TXT"]);
+}
+
+#[test]
+fn test_raw_entries_include_live_entries() {
+    let vfat = vfat_from_resource!("mock1.fat32.img");
+    let root = (&vfat).open_dir("/").expect("open root");
+
+    let live: Vec<String> = root.entries().expect("entries")
+        .map(|entry| entry.name().to_string())
+        .collect();
+    let raw_live: Vec<String> = root.raw_entries().expect("raw entries")
+        .filter(|raw| !raw.deleted)
+        .map(|raw| raw.entry.name().to_string())
+        .collect();
+    assert_eq!(raw_live, live);
+}
+
+#[test]
+fn test_dir_iter_ignores_partial_entry() {
+    use crate::vfat::dir::DirIter;
+
+    let vfat = vfat_from_resource!("mock1.fat32.img");
+    let mut data = Vec::new();
+    vfat.lock(|vfat| {
+        let root = vfat.rootdir_cluster;
+        vfat.read_chain(root, &mut data).expect("read root");
+    });
+
+    // Cut the directory off at its end marker so only the trailing bytes
+    // could stop the iteration, then append a partial entry.
+    let end = data.chunks(32).position(|entry| entry[0] == 0x00).expect("end marker");
+    let expected: Vec<String> = DirIter::new(vfat.clone(), &data)
+        .map(|entry| entry.name().to_string())
+        .collect();
+    data.truncate(end * 32);
+    data.extend_from_slice(b"TRUNC");
+
+    let iter = DirIter::new(vfat.clone(), &data);
+    assert_eq!(iter.raw_entries.len(), end);
+    let names: Vec<String> = iter.map(|entry| entry.name().to_string()).collect();
+    assert_eq!(names, expected);
+}
+
+#[test]
+fn test_attributes_builder() {
+    use crate::vfat::Attributes;
+
+    let attributes = Attributes::new().with_directory(true).with_archive(true);
+    assert_eq!(attributes.raw(), 0x30);
+    assert!(attributes.directory() && attributes.archive());
+    assert!(!attributes.read_only() && !attributes.hidden() && !attributes.lfn());
+
+    let attributes = attributes.with_read_only(true).with_hidden(true).with_directory(false);
+    assert_eq!(attributes.raw(), 0x23);
+    assert_eq!(Attributes::new().raw(), 0);
+}
+
+#[test]
+fn test_attributes_setters_idempotent() {
+    use crate::vfat::Attributes;
+
+    let mut attributes = Attributes::new();
+    attributes.set_read_only(true);
+    attributes.set_read_only(true);
+    assert_eq!(attributes.raw(), 0x01);
+
+    attributes.set_hidden(true);
+    attributes.set_read_only(false);
+    attributes.set_read_only(false);
+    assert_eq!(attributes.raw(), 0x02);
+
+    // Clearing a bit leaves the others alone.
+    let mut attributes = Attributes(0xFF);
+    attributes.set_archive(false);
+    attributes.set_archive(false);
+    assert_eq!(attributes.raw(), 0xDF);
+}
+
+/// Returns a file on a copy of mock 1 holding `contents`, which must fit in
+/// one cluster.
+fn small_file(contents: &[u8]) -> vfat::File<StdVFatHandle> {
+    let vfat = vfat_from_resource_mut!("mock1.fat32.img");
+    let first_cluster = vfat.lock(|vfat| {
+        let cluster = vfat.alloc_cluster().expect("alloc");
+        let mut data = vec![0u8; vfat.cluster_size()];
+        data[..contents.len()].copy_from_slice(contents);
+        vfat.write_cluster(cluster, &data).expect("write cluster");
+        cluster
+    });
+    let metadata = vfat::Metadata { size: contents.len() as u32, ..Default::default() };
+    vfat::File { vfat, metadata, first_cluster, pos: 0, dir_entry: None }
+}
+
+#[test]
+fn test_file_seek() {
+    use std::io::SeekFrom;
+
+    let mut file = small_file(b"0123456789abcdefghij");
+    let mut buf = [0u8; 4];
+
+    assert_eq!(file.seek(SeekFrom::Start(10)).unwrap(), 10);
+    file.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"abcd");
+
+    assert_eq!(file.seek(SeekFrom::Current(-8)).unwrap(), 6);
+    file.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"6789");
+
+    assert_eq!(file.seek(SeekFrom::End(-3)).unwrap(), 17);
+    let mut tail = Vec::new();
+    file.read_to_end(&mut tail).unwrap();
+    assert_eq!(tail, b"hij");
+
+    // Seeking to the end is allowed and reads nothing.
+    assert_eq!(file.seek(SeekFrom::End(0)).unwrap(), 20);
+    assert_eq!(file.read(&mut buf).unwrap(), 0);
+    assert_eq!(file.seek(SeekFrom::Start(0)).unwrap(), 0);
+    file.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"0123");
+}
+
+#[test]
+fn test_file_seek_out_of_bounds() {
+    use std::io::SeekFrom;
+
+    let mut file = small_file(b"0123456789");
+    file.seek(SeekFrom::Start(4)).unwrap();
+
+    let out_of_bounds = [
+        SeekFrom::Start(11),
+        SeekFrom::Start(u64::max_value()),
+        SeekFrom::End(1),
+        SeekFrom::End(-11),
+        SeekFrom::Current(7),
+        SeekFrom::Current(-5),
+        SeekFrom::Current(i64::min_value()),
+    ];
+    for &pos in out_of_bounds.iter() {
+        let err = file.seek(pos).expect_err("out-of-bounds seek");
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput, "{:?}", pos);
+        // A failed seek leaves the position alone.
+        assert_eq!(file.seek(SeekFrom::Current(0)).unwrap(), 4, "{:?}", pos);
+    }
+}
+
+/// Adds a file named `name` holding `data` to the root directory of `vfat`.
+fn new_root_file(vfat: &StdVFatHandle, name: &str, data: &[u8]) -> vfat::File<StdVFatHandle> {
+    use crate::vfat::dir::VFatRegularDirEntry;
+
+    let root = vfat.open_dir("/").expect("root");
+    let short_name = VFatRegularDirEntry::short_name(name).expect("short name");
+    let entry = VFatRegularDirEntry::new(short_name, vfat::Attributes::new(), Cluster::from(0), 0);
+    vfat.lock(|vfat| vfat.append_dir_entry(root.first_cluster, &entry)).expect("add entry");
+
+    let mut file = root.find(name).expect("find").into_file().expect("a file");
+    file.write_all(data).expect("write");
+    file
+}
+
+#[test]
+fn test_file_write_overwrite_and_append() {
+    use std::io::SeekFrom;
+
+    let vfat = vfat_from_resource_mut!("mock1.fat32.img");
+    let cluster_size = vfat.lock(|vfat| vfat.cluster_size());
+    let mut expected = b"the original contents".to_vec();
+    let mut file = new_root_file(&vfat, "DATA.TXT", &expected);
+    let name = file.metadata.name.clone();
+
+    // Overwrite the start of the file.
+    file.seek(SeekFrom::Start(0)).unwrap();
+    file.write_all(b"overwritten").expect("overwrite");
+    expected[..11].copy_from_slice(b"overwritten");
+
+    // Append enough to need at least two more clusters.
+    let appended: Vec<u8> = (0..2 * cluster_size + 100).map(|i| (i % 251) as u8).collect();
+    file.seek(SeekFrom::End(0)).unwrap();
+    file.write_all(&appended).expect("append");
+    expected.extend_from_slice(&appended);
+    assert_eq!(file.size(), expected.len() as u64);
+
+    // The new size and contents are found through the directory again.
+    let mut reopened = vfat.open_dir("/").expect("root").find(&name)
+        .expect("find file").into_file().expect("a file");
+    assert_eq!(reopened.size(), expected.len() as u64);
+    let mut data = Vec::new();
+    reopened.read_to_end(&mut data).expect("read file");
+    assert_eq!(data, expected);
+
+    let extents = vfat.lock(|vfat| vfat.file_extents(reopened.first_cluster)).expect("extents");
+    assert_eq!(extents.len(), (expected.len() + cluster_size - 1) / cluster_size);
+}
+
+#[test]
+fn test_file_write_empty_file() {
+    use crate::vfat::dir::VFatRegularDirEntry;
+
+    let vfat = vfat_from_resource_mut!("mock1.fat32.img");
+    let root = vfat.open_dir("/").expect("root");
+    let short_name = VFatRegularDirEntry::short_name("EMPTY.TXT").unwrap();
+    let entry = VFatRegularDirEntry::new(short_name, vfat::Attributes::new(), Cluster::from(0), 0);
+    vfat.lock(|vfat| vfat.append_dir_entry(root.first_cluster, &entry)).expect("add entry");
+
+    let mut file = root.find("EMPTY.TXT").expect("find").into_file().expect("a file");
+    assert_eq!(file.first_cluster.raw(), 0);
+    file.write_all(b"hello, world").expect("write");
+    assert_ne!(file.first_cluster.raw(), 0);
+
+    let mut reopened = root.find("EMPTY.TXT").expect("find").into_file().expect("a file");
+    assert_eq!(reopened.first_cluster, file.first_cluster);
+    let mut data = Vec::new();
+    reopened.read_to_end(&mut data).expect("read file");
+    assert_eq!(data, b"hello, world");
+}
+
+#[test]
+fn test_file_write_needs_dir_entry() {
+    let mut file = small_file(b"0123456789");
+    assert_eq!(file.write(b"x").expect_err("no entry").kind(), io::ErrorKind::Other);
+    assert_eq!(file.write(b"").expect("empty write"), 0);
+}
+
+fn timestamp(date: u16, time: u16) -> vfat::Timestamp {
+    vfat::Timestamp { date: vfat::Date(date), time: vfat::Time(time) }
+}
+
+#[test]
+fn test_timestamp_fields() {
+    // Date: year 37 (2017) in bits 15-9, month 1, day 1. Time: hour 15 in
+    // bits 15-11, minute 16 in bits 10-5, 0 two-second units.
+    let ts = timestamp(0x4A21, 0x7A00);
+    assert_eq!((ts.year(), ts.month(), ts.day()), (2017, 1, 1));
+    assert_eq!((ts.hour(), ts.minute(), ts.second()), (15, 16, 0));
+    assert_eq!(ts.to_string(), "2017-01-01 15:16:00");
+
+    let ts = timestamp(0x4EE1, 0x645C);
+    assert_eq!((ts.year(), ts.month(), ts.day()), (2019, 7, 1));
+    assert_eq!((ts.hour(), ts.minute(), ts.second()), (12, 34, 56));
+
+    // The earliest representable date, 1980, and midnight.
+    let ts = timestamp(0x0021, 0x0000);
+    assert_eq!((ts.year(), ts.month(), ts.day()), (1980, 1, 1));
+    assert_eq!((ts.hour(), ts.minute(), ts.second()), (0, 0, 0));
+}
+
+#[test]
+fn test_timestamp_fields_independent() {
+    // Every bit of one word set, the other clear: no field reads the wrong
+    // word or spills into its neighbour.
+    let ts = timestamp(0xFFFF, 0x0000);
+    assert_eq!((ts.year(), ts.month(), ts.day()), (1980 + 127, 15, 31));
+    assert_eq!((ts.hour(), ts.minute(), ts.second()), (0, 0, 0));
+
+    let ts = timestamp(0x0000, 0xFFFF);
+    assert_eq!((ts.year(), ts.month(), ts.day()), (1980, 0, 0));
+    assert_eq!((ts.hour(), ts.minute(), ts.second()), (31, 63, 62));
+}
+
+/// Returns the FAT32 partition of mock 1 on its own, as it would be on a card
+/// formatted without a partition table.
+fn mock1_superfloppy() -> Vec<u8> {
+    let mut data = Vec::new();
+    resource!("mock1.fat32.img").read_to_end(&mut data).expect("read image");
+    let mbr = MasterBootRecord::from(Cursor::new(data[..512].to_vec())).expect("valid MBR");
+    let start = mbr.partition_table[0].relative_sector as usize * 512;
+    data.split_off(start)
+}
+
+#[test]
+fn test_superfloppy_detection() {
+    let superfloppy = mock1_superfloppy();
+    let mut sector = [0u8; 512];
+    sector.copy_from_slice(&superfloppy[..512]);
+    assert!(BiosParameterBlock::is_boot_sector(&sector));
+
+    let mut mbr = Vec::new();
+    resource!("mock1.fat32.img").read_to_end(&mut mbr).expect("read image");
+    sector.copy_from_slice(&mbr[..512]);
+    assert!(!BiosParameterBlock::is_boot_sector(&sector));
+}
+
+#[test]
+fn test_mount_superfloppy() {
+    let vfat = VFat::<StdVFatHandle>::from(Cursor::new(mock1_superfloppy())).expect("mount superfloppy");
+    let hash = hash_dir_from(vfat, "/");
+    assert_hash_eq!("mock 1 superfloppy root entries", hash, hash_for!("root-entries-1"));
+
+    let vfat = VFat::<StdVFatHandle>::from_superfloppy(Cursor::new(mock1_superfloppy()))
+        .expect("mount superfloppy explicitly");
+    let hash = hash_dir_from(vfat, "/");
+    assert_hash_eq!("mock 1 superfloppy root entries", hash, hash_for!("root-entries-1"));
+
+    // A partitioned image still mounts through its MBR.
+    let hash = hash_dir_from(vfat_from_resource!("mock1.fat32.img"), "/");
+    assert_hash_eq!("mock 1 root entries", hash, hash_for!("root-entries-1"));
+}
+
+#[test]
+fn test_readonly_mount_rejects_writes() {
+    let mut data = Vec::new();
+    resource!("mock1.fat32.img").read_to_end(&mut data).expect("read image");
+    let vfat = VFat::<StdVFatHandle>::from_readonly(Cursor::new(data)).expect("mount read-only");
+    assert!(!vfat.lock(|vfat| vfat.is_writable()));
+
+    let root = vfat.open_dir("/").expect("root");
+    let err = root.create_dir("NEWDIR").expect_err("read-only");
+    assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+    let err = vfat.lock(|vfat| vfat.alloc_cluster()).expect_err("read-only");
+    assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+
+    let mut file = root
+        .entries()
+        .expect("entries")
+        .find_map(|entry| entry.into_file())
+        .expect("a file in the root directory");
+    assert_eq!(file.write(b"x").expect_err("read-only").kind(), io::ErrorKind::PermissionDenied);
+
+    // Nothing was written, so the directory is unchanged.
+    assert!(root.find("NEWDIR").is_err());
+
+    vfat.lock(|vfat| vfat.set_writable(true));
+    root.create_dir("NEWDIR").expect("writable after remount");
+    assert!(root.find("NEWDIR").is_ok());
+}
@@ -93,5 +93,45 @@ macro impl_for_read_write_seek($(<$($gen:tt),*>)* $T:path) {
 impl_for_read_write_seek!(<'a> shim::io::Cursor<&'a mut [u8]>);
 impl_for_read_write_seek!(shim::io::Cursor<Vec<u8>>);
 impl_for_read_write_seek!(shim::io::Cursor<Box<[u8]>>);
-#[cfg(test)]
+#[cfg(any(test, feature = "std"))]
 impl_for_read_write_seek!(::std::fs::File);
+
+/// A `BlockDevice` backed by a disk image file on the host, for tools and
+/// tests that run the file system against real `.img` files.
+#[cfg(any(test, feature = "std"))]
+#[derive(Debug)]
+pub struct FileBlockDevice {
+    file: ::std::fs::File,
+}
+
+#[cfg(any(test, feature = "std"))]
+impl FileBlockDevice {
+    /// Opens the image at `path` read-only. Writing a sector fails.
+    pub fn open<P: AsRef<::std::path::Path>>(path: P) -> io::Result<FileBlockDevice> {
+        Ok(FileBlockDevice::from(::std::fs::File::open(path)?))
+    }
+
+    /// Opens the image at `path` for reading and writing.
+    pub fn open_writable<P: AsRef<::std::path::Path>>(path: P) -> io::Result<FileBlockDevice> {
+        let file = ::std::fs::OpenOptions::new().read(true).write(true).open(path)?;
+        Ok(FileBlockDevice::from(file))
+    }
+}
+
+#[cfg(any(test, feature = "std"))]
+impl From<::std::fs::File> for FileBlockDevice {
+    fn from(file: ::std::fs::File) -> FileBlockDevice {
+        FileBlockDevice { file }
+    }
+}
+
+#[cfg(any(test, feature = "std"))]
+impl BlockDevice for FileBlockDevice {
+    fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        self.file.read_sector(n, buf)
+    }
+
+    fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+        self.file.write_sector(n, buf)
+    }
+}
@@ -21,6 +21,26 @@ pub trait Dir: Sized {
 
     /// Returns an interator over the entries in this directory.
     fn entries(&self) -> io::Result<Self::Iter>;
+
+    /// Creates a new, empty directory named `name` inside this directory and
+    /// returns it.
+    ///
+    /// # Errors
+    ///
+    /// If an entry named `name` already exists, an error kind of
+    /// `AlreadyExists` is returned. All other error values are implementation
+    /// defined.
+    fn create_dir(&self, name: &str) -> io::Result<Self>;
+
+    /// Removes the file or empty directory named `name` from this directory.
+    ///
+    /// # Errors
+    ///
+    /// If there is no entry named `name`, an error kind of `NotFound` is
+    /// returned. If `name` is `.` or `..`, an error kind of `InvalidInput` is
+    /// returned. All other error values, including the one for a directory
+    /// that is not empty, are implementation defined.
+    fn remove(&self, name: &str) -> io::Result<()>;
 }
 
 /// Trait implemented by directory entries in a file system.
@@ -3,5 +3,7 @@ mod fs;
 mod metadata;
 
 pub use self::block_device::BlockDevice;
+#[cfg(any(test, feature = "std"))]
+pub use self::block_device::FileBlockDevice;
 pub use self::fs::{Dir, Entry, File, FileSystem};
 pub use self::metadata::{Metadata, Timestamp};
@@ -0,0 +1,98 @@
+use shim::io;
+
+use crate::vfat::{File, VFatHandle};
+
+use alloc::vec::Vec;
+
+/// A writer that collects small writes into a buffer and passes them on to
+/// the underlying writer in one piece, like `std::io::BufWriter`.
+///
+/// The buffer is written out when a write would overflow it, on `flush`, and
+/// when the `BufferedFile` is dropped. Errors while writing out on drop are
+/// ignored, so callers that care about them should call `flush` explicitly
+/// before dropping.
+#[derive(Debug)]
+pub struct BufferedFile<W: io::Write> {
+    inner: Option<W>,
+    buf: Vec<u8>,
+    capacity: usize,
+}
+
+impl<W: io::Write> BufferedFile<W> {
+    /// Returns a `BufferedFile` that buffers up to `capacity` bytes before
+    /// writing to `inner`.
+    pub fn with_capacity(capacity: usize, inner: W) -> BufferedFile<W> {
+        BufferedFile {
+            inner: Some(inner),
+            buf: Vec::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// Returns a reference to the underlying writer.
+    pub fn get_ref(&self) -> &W {
+        self.inner.as_ref().unwrap()
+    }
+
+    /// Returns the bytes that have not been written out yet.
+    pub fn buffer(&self) -> &[u8] {
+        &self.buf
+    }
+
+    /// Writes out the buffer and returns the underlying writer.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error from writing out the buffer. The buffered data is
+    /// kept and the underlying writer is not returned in that case.
+    pub fn into_inner(mut self) -> io::Result<W> {
+        self.flush_buf()?;
+        Ok(self.inner.take().unwrap())
+    }
+
+    /// Writes the whole buffer to the underlying writer in one call.
+    fn flush_buf(&mut self) -> io::Result<()> {
+        if !self.buf.is_empty() {
+            self.inner.as_mut().unwrap().write_all(&self.buf)?;
+            self.buf.clear();
+        }
+        Ok(())
+    }
+}
+
+impl<HANDLE: VFatHandle> BufferedFile<File<HANDLE>> {
+    /// Returns a `BufferedFile` for `file` whose buffer holds one cluster of
+    /// the file system.
+    pub fn new(file: File<HANDLE>) -> BufferedFile<File<HANDLE>> {
+        let capacity = file.vfat.lock(|vfat| vfat.cluster_size());
+        BufferedFile::with_capacity(capacity, file)
+    }
+}
+
+impl<W: io::Write> io::Write for BufferedFile<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.buf.len() + buf.len() > self.capacity {
+            self.flush_buf()?;
+        }
+
+        if buf.len() >= self.capacity {
+            self.inner.as_mut().unwrap().write(buf)
+        } else {
+            self.buf.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.flush_buf()?;
+        self.inner.as_mut().unwrap().flush()
+    }
+}
+
+impl<W: io::Write> Drop for BufferedFile<W> {
+    fn drop(&mut self) {
+        if self.inner.is_some() {
+            let _ = self.flush_buf();
+        }
+    }
+}
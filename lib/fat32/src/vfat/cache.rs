@@ -1,6 +1,8 @@
 use alloc::boxed::Box;
+use alloc::vec::Vec;
 use core::{cmp, fmt};
 
+use hashbrown::HashMap;
 use shim::io;
 
 use crate::traits::BlockDevice;
@@ -15,9 +17,32 @@ pub struct Partition {
     pub sector_size: u64,
 }
 
+/// The number of logical sectors `PartitionedDevice::new` caches.
+pub const DEFAULT_CACHE_SECTORS: usize = 256;
+
+/// Counts of sector reads served from the cache and from the device.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+#[derive(Debug)]
+struct CacheEntry {
+    data: Vec<u8>,
+    /// The value of `PartitionedDevice::clock` when the entry was last read.
+    last_used: u64,
+}
+
 pub struct PartitionedDevice {
     device: Box<dyn BlockDevice>,
     partition: Partition,
+    /// Cached logical sectors, keyed by sector number.
+    cache: HashMap<u64, CacheEntry>,
+    capacity: usize,
+    /// Incremented on every cache access, to order entries by recency.
+    clock: u64,
+    stats: CacheStats,
 }
 
 impl PartitionedDevice {
@@ -32,22 +57,93 @@ impl PartitionedDevice {
     /// `partition.sector_size` must be an integer multiple of
     /// `device.sector_size()`.
     ///
+    /// Up to `DEFAULT_CACHE_SECTORS` logical sectors are cached. See
+    /// `with_cache_capacity`.
+    ///
     /// # Panics
     ///
     /// Panics if the partition's sector size is < the device's sector size.
     pub fn new<T>(device: T, partition: Partition) -> PartitionedDevice
         where
             T: BlockDevice + 'static,
+    {
+        PartitionedDevice::with_cache_capacity(device, partition, DEFAULT_CACHE_SECTORS)
+    }
+
+    /// Like `new`, but caches up to `capacity` logical sectors.
+    ///
+    /// Reads go through a write-through cache: a sector read from the device
+    /// is kept, evicting the least recently used sector once `capacity`
+    /// sectors are cached, and writes go straight to the device, updating
+    /// the cached copy if there is one.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the partition's sector size is < the device's sector size,
+    /// or if `capacity` is zero.
+    pub fn with_cache_capacity<T>(device: T, partition: Partition, capacity: usize) -> PartitionedDevice
+        where
+            T: BlockDevice + 'static,
     {
         assert!(partition.sector_size >= device.sector_size());
         assert!(partition.sector_size % device.sector_size() == 0);
+        assert!(capacity > 0);
 
         PartitionedDevice {
             device: Box::new(device),
             partition: partition,
+            cache: HashMap::with_capacity(capacity),
+            capacity,
+            clock: 0,
+            stats: CacheStats::default(),
         }
     }
 
+    /// Returns how many sector reads were served from the cache and from the
+    /// device.
+    pub fn cache_stats(&self) -> CacheStats {
+        self.stats
+    }
+
+    /// Drops every cached sector, so that the next reads go to the device.
+    /// Needed after the device was written to other than through `self`.
+    pub fn invalidate(&mut self) {
+        self.cache.clear();
+    }
+
+    /// Returns the contents of logical sector `sector`, reading it from the
+    /// device unless it is cached.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `sector` is out of range or the device fails.
+    pub fn get(&mut self, sector: u64) -> io::Result<&[u8]> {
+        self.clock += 1;
+        if self.cache.contains_key(&sector) {
+            self.stats.hits += 1;
+        } else {
+            self.stats.misses += 1;
+            let mut data = self.evict().unwrap_or_else(Vec::new);
+            data.resize(self.partition.sector_size as usize, 0);
+            self.read_uncached(sector, &mut data)?;
+            self.cache.insert(sector, CacheEntry { data, last_used: 0 });
+        }
+
+        let entry = self.cache.get_mut(&sector).unwrap();
+        entry.last_used = self.clock;
+        Ok(&entry.data)
+    }
+
+    /// Removes the least recently used sector if the cache is full, and
+    /// returns its buffer for reuse.
+    fn evict(&mut self) -> Option<Vec<u8>> {
+        if self.cache.len() < self.capacity {
+            return None;
+        }
+        let lru = *self.cache.iter().min_by_key(|(_, entry)| entry.last_used)?.0;
+        self.cache.remove(&lru).map(|entry| entry.data)
+    }
+
     /// Returns the number of physical sectors that corresponds to
     /// one logical sector.
     fn factor(&self) -> u64 {
@@ -66,16 +162,10 @@ impl PartitionedDevice {
 
         Some(physical_sector)
     }
-}
-
-
-
-impl BlockDevice for PartitionedDevice {
-    fn sector_size(&self) -> u64 {
-        self.partition.sector_size
-    }
 
-    fn read_sector(&mut self, sector: u64, buf: &mut [u8]) -> io::Result<usize> {
+    /// Reads logical sector `sector` from the device into `buf`, bypassing
+    /// the cache.
+    fn read_uncached(&mut self, sector: u64, buf: &mut [u8]) -> io::Result<usize> {
         let real_sector = self.virtual_to_physical(sector).ok_or(io::Error::new(io::ErrorKind::BrokenPipe, "Not a broken pipe. virtual address is wrong"))?;
         let physical_sector_size = self.device.sector_size() as usize;
         let mut read_bytes = 0;
@@ -98,9 +188,49 @@ impl BlockDevice for PartitionedDevice {
 
         Ok(read_bytes)
     }
+}
+
+impl BlockDevice for PartitionedDevice {
+    fn sector_size(&self) -> u64 {
+        self.partition.sector_size
+    }
 
-    fn write_sector(&mut self, _sector: u64, _buf: &[u8]) -> io::Result<usize> {
-        unimplemented!()
+    fn read_sector(&mut self, sector: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let data = self.get(sector)?;
+        let n = cmp::min(buf.len(), data.len());
+        buf[..n].copy_from_slice(&data[..n]);
+        Ok(n)
+    }
+
+    fn write_sector(&mut self, sector: u64, buf: &[u8]) -> io::Result<usize> {
+        let real_sector = self.virtual_to_physical(sector).ok_or(io::Error::new(io::ErrorKind::InvalidInput, "virtual sector out of range"))?;
+        // Taken out while writing, so that a failed write leaves the sector
+        // uncached rather than cached with contents the device may not have.
+        let cached = self.cache.remove(&sector);
+        let physical_sector_size = self.device.sector_size() as usize;
+        let mut written_bytes = 0;
+        let n = self.factor();
+
+        for i in 0..n as usize {
+            let end = cmp::min((i + 1) * physical_sector_size, buf.len());
+
+            let num = self.device.write_sector(
+                real_sector + i as u64,
+                &buf[i * physical_sector_size..end],
+            )?;
+            written_bytes += num;
+
+            if end == buf.len() {
+                break;
+            }
+        }
+
+        if let Some(mut entry) = cached {
+            let n = cmp::min(written_bytes, entry.data.len());
+            entry.data[..n].copy_from_slice(&buf[..n]);
+            self.cache.insert(sector, entry);
+        }
+        Ok(written_bytes)
     }
 }
 
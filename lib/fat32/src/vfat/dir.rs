@@ -1,15 +1,17 @@
 use core::fmt;
+use alloc::collections::BTreeSet;
 use alloc::string::String;
 use alloc::string::ToString;
 use alloc::vec::Vec;
 
 use shim::const_assert_size;
 use shim::ffi::OsStr;
+use shim::path::{Path, PathBuf};
 use shim::io;
 use shim::newioerr;
 
 use crate::traits;
-use crate::util::VecExt;
+use crate::util::SliceExt;
 use crate::vfat::{Attributes, Date, Metadata, Time, Timestamp};
 use crate::vfat::{Cluster, Entry, File, VFatHandle};
 
@@ -24,6 +26,26 @@ pub struct DirIter<HANDLE: VFatHandle> {
     pub vfat: HANDLE,
     pub raw_entries: Vec<VFatDirEntry>,
     pub pos: usize,
+    /// The first cluster of the directory the entries were read from, if
+    /// known. Only files from a known directory can be written to, since
+    /// writing updates their directory entry.
+    pub dir: Option<Cluster>,
+}
+
+/// An iterator over every entry stored in a directory, including deleted
+/// ones. Returned by `Dir::raw_entries`.
+pub struct RawDirIter<HANDLE: VFatHandle> {
+    inner: DirIter<HANDLE>,
+    past_end: bool,
+}
+
+/// An entry yielded by `RawDirIter`.
+pub struct RawEntry<HANDLE: VFatHandle> {
+    pub entry: Entry<HANDLE>,
+    /// `true` if the entry is marked deleted (`0xE5`) or lies after the
+    /// end-of-directory marker. The clusters of a deleted entry may since have
+    /// been reused.
+    pub deleted: bool,
 }
 
 #[repr(C, packed)]
@@ -48,10 +70,108 @@ const_assert_size!(VFatRegularDirEntry, 32);
 
 
 impl VFatRegularDirEntry {
+    /// Creates an entry with the raw 8.3 name `short_name` and zeroed
+    /// timestamps.
+    pub fn new(short_name: [u8; 11], attributes: Attributes, cluster: Cluster, file_size: u32) -> VFatRegularDirEntry {
+        let mut file_name = [0u8; 8];
+        let mut extension = [0u8; 3];
+        file_name.copy_from_slice(&short_name[..8]);
+        extension.copy_from_slice(&short_name[8..]);
+
+        VFatRegularDirEntry {
+            file_name,
+            extension,
+            attributes,
+            __reserved: 0,
+            creation_time_tenths: 0,
+            creation_time: Time(0),
+            creation_date: Date(0),
+            last_accessed_date: Date(0),
+            high_bits_cluster_number: (cluster.raw() >> 16) as u16,
+            last_modification_time: Time(0),
+            last_modification_date: Date(0),
+            low_bits_cluster_number: cluster.raw() as u16,
+            file_size,
+        }
+    }
+
+    /// Converts `name` into a space-padded, upper-case 8.3 short name.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `InvalidInput` if the base name is empty or
+    /// longer than 8 characters, the extension is longer than 3 characters,
+    /// or `name` contains characters not allowed in short names.
+    pub fn short_name(name: &str) -> io::Result<[u8; 11]> {
+        let (base, ext) = match name.rfind('.') {
+            Some(i) => (&name[..i], &name[i + 1..]),
+            None => (name, ""),
+        };
+
+        if base.is_empty() || base.len() > 8 || ext.len() > 3
+            || !base.bytes().all(is_short_name_char) || !ext.bytes().all(is_short_name_char) {
+            return Err(newioerr!(InvalidInput, "not a valid 8.3 name"));
+        }
+
+        let mut short_name = [b' '; 11];
+        short_name[..base.len()].copy_from_slice(base.as_bytes());
+        short_name[8..8 + ext.len()].copy_from_slice(ext.as_bytes());
+        short_name.make_ascii_uppercase();
+        Ok(short_name)
+    }
+
+    /// Returns the short name `BASIS~n.EXT` stored alongside the long name
+    /// `name`. The basis and extension are the first characters of `name`
+    /// before and after its last dot that are allowed in short names,
+    /// upper-cased. Callers pick `n` so that the short name is unique within
+    /// the directory.
+    pub fn numbered_short_name(name: &str, n: u32) -> [u8; 11] {
+        let (base, ext) = match name.rfind('.') {
+            Some(i) if i > 0 => (&name[..i], &name[i + 1..]),
+            _ => (name, ""),
+        };
+
+        let mut tail = vec![b'~'];
+        let mut n = n;
+        loop {
+            tail.insert(1, b'0' + (n % 10) as u8);
+            n /= 10;
+            if n == 0 {
+                break;
+            }
+        }
+
+        let mut short_name = [b' '; 11];
+        let basis = base.bytes().filter(|&b| is_short_name_char(b)).take(8 - tail.len());
+        for (slot, b) in short_name.iter_mut().zip(basis.chain(tail.into_iter())) {
+            *slot = b;
+        }
+        for (slot, b) in short_name[8..].iter_mut().zip(ext.bytes().filter(|&b| is_short_name_char(b))) {
+            *slot = b;
+        }
+        short_name.make_ascii_uppercase();
+        short_name
+    }
+
+    /// Returns the checksum of this entry's 8.3 name that the LFN entries
+    /// preceding it must carry. See `lfn_checksum`.
+    pub fn short_name_checksum(&self) -> u8 {
+        let mut short_name = [0u8; 11];
+        short_name[..8].copy_from_slice(&self.file_name);
+        short_name[8..].copy_from_slice(&self.extension);
+        lfn_checksum(&short_name)
+    }
+
     pub fn first_cluster(&self) -> Cluster {
         Cluster::from(self.low_bits_cluster_number as u32 | (self.high_bits_cluster_number as u32) << 16)
     }
 
+    /// Points the entry at the cluster chain starting at `cluster`.
+    pub fn set_first_cluster(&mut self, cluster: Cluster) {
+        self.high_bits_cluster_number = (cluster.raw() >> 16) as u16;
+        self.low_bits_cluster_number = cluster.raw() as u16;
+    }
+
     pub fn make_metadata(&self, name: String) -> Metadata {
         Metadata {
             attributes: self.attributes,
@@ -93,6 +213,37 @@ impl fmt::Display for VFatRegularDirEntry {
 }
 
 
+/// Returns `true` if `b` may appear in an 8.3 short name.
+fn is_short_name_char(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b"!#$%&'()-@^_`{}~".contains(&b)
+}
+
+/// The longest long file name, in UTF-16 code units.
+pub const MAX_LONG_NAME_LEN: usize = 255;
+
+/// Checks that `name` can be stored as a long file name.
+///
+/// # Errors
+///
+/// Returns an error of kind `InvalidInput` if `name` is empty, `.` or `..`,
+/// longer than `MAX_LONG_NAME_LEN`, ends in a space or dot, or contains a
+/// control character or one of `"*/:<>?\|`.
+pub fn check_long_name(name: &str) -> io::Result<()> {
+    let invalid = |c: char| c < ' ' || "\"*/:<>?\\|".contains(c);
+    if name.is_empty() || name.ends_with(' ') || name.ends_with('.')
+        || name.encode_utf16().count() > MAX_LONG_NAME_LEN || name.chars().any(invalid) {
+        return Err(newioerr!(InvalidInput, "not a valid file name"));
+    }
+    Ok(())
+}
+
+/// Computes the checksum of a raw, space-padded 8.3 name that ties LFN
+/// entries to their regular entry: each byte is added to the running sum
+/// after rotating it right by one bit.
+pub fn lfn_checksum(short_name: &[u8; 11]) -> u8 {
+    short_name.iter().fold(0u8, |sum, &b| sum.rotate_right(1).wrapping_add(b))
+}
+
 #[repr(C, packed)]
 #[derive(Copy, Clone)]
 pub struct VFatLfnDirEntry {
@@ -108,6 +259,46 @@ pub struct VFatLfnDirEntry {
 
 const_assert_size!(VFatLfnDirEntry, 32);
 
+impl VFatLfnDirEntry {
+    /// Returns the LFN entries that store the long name `name` for the
+    /// regular entry named `short_name`, in the order they are stored in
+    /// front of it: last part first.
+    pub fn for_name(name: &str, short_name: &[u8; 11]) -> Vec<VFatLfnDirEntry> {
+        let checksum = lfn_checksum(short_name);
+        let mut chars: Vec<u16> = name.encode_utf16().collect();
+        let count = (chars.len() + LFN_ENTRY_LEN - 1) / LFN_ENTRY_LEN;
+        // The name is terminated by a NUL unless it fills the last entry,
+        // and the rest is padded with `0xFFFF`.
+        if chars.len() % LFN_ENTRY_LEN != 0 {
+            chars.push(0);
+        }
+        chars.resize(count * LFN_ENTRY_LEN, 0xffff);
+
+        (0..count).rev().map(|i| {
+            let part = &chars[i * LFN_ENTRY_LEN..(i + 1) * LFN_ENTRY_LEN];
+            let mut name_characters_0 = [0u16; 5];
+            let mut name_characters_1 = [0u16; 6];
+            let mut name_characters_2 = [0u16; 2];
+            name_characters_0.copy_from_slice(&part[0..5]);
+            name_characters_1.copy_from_slice(&part[5..11]);
+            name_characters_2.copy_from_slice(&part[11..13]);
+
+            // The last part is flagged with bit 6 of its sequence number.
+            let last = if i == count - 1 { 0x40 } else { 0 };
+            VFatLfnDirEntry {
+                sequence_number: (i as u8 + 1) | last,
+                name_characters_0,
+                attributes: Attributes(0x0F),
+                entry_type: 0,
+                checksum,
+                name_characters_1,
+                empty: 0,
+                name_characters_2,
+            }
+        }).collect()
+    }
+}
+
 impl fmt::Display for VFatLfnDirEntry {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let mut name_u16 = [0xffffu16; LFN_ENTRY_LEN];
@@ -160,29 +351,177 @@ impl<HANDLE: VFatHandle> Dir<HANDLE> {
             .find(|e| e.name().eq_ignore_ascii_case(name))
             .ok_or(newioerr!(NotFound, "file name not found"))
     }
+
+    /// Visits every entry below `self` depth-first, calling `visitor` with the
+    /// entry and its path. Paths start at `/`, which stands for `self`, so
+    /// they are full paths when walking the root directory. `.` and `..` are
+    /// not visited.
+    ///
+    /// Directories are not descended into more than once, and not below
+    /// `MAX_WALK_DEPTH` levels, so the walk ends even if corrupt media links
+    /// a directory into its own subtree.
+    pub fn walk<F>(&self, mut visitor: F) -> io::Result<()>
+        where
+            F: FnMut(&Path, &Entry<HANDLE>),
+    {
+        let mut visited = BTreeSet::new();
+        visited.insert(self.first_cluster.raw());
+        self.walk_from(&mut PathBuf::from("/"), 1, &mut visited, &mut visitor)
+    }
+
+    fn walk_from<F>(
+        &self,
+        path: &mut PathBuf,
+        depth: usize,
+        visited: &mut BTreeSet<u32>,
+        visitor: &mut F,
+    ) -> io::Result<()>
+        where
+            F: FnMut(&Path, &Entry<HANDLE>),
+    {
+        use crate::traits::Dir;
+        use crate::traits::Entry;
+        for entry in self.entries()? {
+            let name = entry.name();
+            if name == "." || name == ".." {
+                continue;
+            }
+
+            path.push(name);
+            visitor(path, &entry);
+            if let Some(dir) = entry.as_dir() {
+                if depth < MAX_WALK_DEPTH && visited.insert(dir.first_cluster.raw()) {
+                    dir.walk_from(path, depth + 1, visited, visitor)?;
+                }
+            }
+            path.pop();
+        }
+        Ok(())
+    }
+
+    /// Returns an iterator over all entries stored in `self`, for recovering
+    /// deleted files. Unlike `entries`, it yields deleted entries and keeps
+    /// going after the end-of-directory marker up to the end of the cluster
+    /// chain, skipping only never-used (all zero) slots.
+    ///
+    /// The first character of a deleted short name is lost and shown as `?`.
+    /// Long names of deleted entries are recovered from the position of their
+    /// LFN entries, since their sequence numbers are overwritten too.
+    pub fn raw_entries(&self) -> io::Result<RawDirIter<HANDLE>> {
+        use crate::traits::Dir;
+        Ok(RawDirIter::new(self.entries()?))
+    }
 }
 
+/// How many directory levels `Dir::walk` descends at most.
+pub const MAX_WALK_DEPTH: usize = 32;
+
 const MAX_LFN_ENTRIES: usize = 0x14;
 const LFN_ENTRY_LEN: usize = 13;
 
-fn handle_lfn_entry(lfn_entry: VFatLfnDirEntry, name_u16: &mut [u16]) {
-    let seq_num = ((lfn_entry.sequence_number & 0x1f) - 1) as usize;
-    assert!(seq_num < MAX_LFN_ENTRIES);
-    let raw_name =
-        &mut name_u16[seq_num * LFN_ENTRY_LEN..seq_num * LFN_ENTRY_LEN + LFN_ENTRY_LEN];
-    raw_name[0..5].copy_from_slice(&{ lfn_entry.name_characters_0 });
-    raw_name[5..11].copy_from_slice(&{ lfn_entry.name_characters_1 });
-    raw_name[11..13].copy_from_slice(&{ lfn_entry.name_characters_2 });
+/// Accumulates the long file name spread over a run of LFN entries preceding a
+/// regular entry.
+pub(crate) struct LfnName {
+    name_u16: [u16; MAX_LFN_ENTRIES * LFN_ENTRY_LEN],
+    seen: bool,
+    corrupt: bool,
+}
+
+impl LfnName {
+    pub(crate) fn new() -> LfnName {
+        LfnName {
+            name_u16: [0xffffu16; MAX_LFN_ENTRIES * LFN_ENTRY_LEN],
+            seen: false,
+            corrupt: false,
+        }
+    }
+
+    /// Copies the characters of `lfn_entry` into place. An entry whose
+    /// sequence number is out of range marks the whole name as corrupt.
+    pub(crate) fn push(&mut self, lfn_entry: &VFatLfnDirEntry) {
+        self.seen = true;
+        let seq_num = (lfn_entry.sequence_number & 0x1f) as usize;
+        if seq_num == 0 || seq_num > MAX_LFN_ENTRIES {
+            self.corrupt = true;
+            return;
+        }
+
+        let start = (seq_num - 1) * LFN_ENTRY_LEN;
+        let raw_name = &mut self.name_u16[start..start + LFN_ENTRY_LEN];
+        raw_name[0..5].copy_from_slice(&{ lfn_entry.name_characters_0 });
+        raw_name[5..11].copy_from_slice(&{ lfn_entry.name_characters_1 });
+        raw_name[11..13].copy_from_slice(&{ lfn_entry.name_characters_2 });
+    }
+
+    /// Returns the long name if a valid one was accumulated, falling back to
+    /// the short name of `regular_entry` otherwise.
+    pub(crate) fn name_for(&self, regular_entry: &VFatRegularDirEntry) -> String {
+        if !self.seen || self.corrupt {
+            return regular_entry.make_regular_filename();
+        }
+
+        let name_len = self.name_u16
+            .iter()
+            .position(|&b| b == 0x0000 || b == 0xffff)
+            .unwrap_or(self.name_u16.len());
+        String::from_utf16_lossy(&self.name_u16[..name_len])
+    }
+}
+
+
+impl<HANDLE: VFatHandle> DirIter<HANDLE> {
+    /// Returns an iterator over the directory entries stored in `data`.
+    ///
+    /// A trailing partial entry, e.g. left by a short read, is ignored
+    /// rather than read as a malformed entry.
+    pub(crate) fn new(vfat: HANDLE, data: &[u8]) -> DirIter<HANDLE> {
+        const ENTRY_SIZE: usize = core::mem::size_of::<VFatDirEntry>();
+        let len = data.len() - data.len() % ENTRY_SIZE;
+        DirIter {
+            vfat,
+            raw_entries: unsafe { data[..len].cast::<VFatDirEntry>() }.to_vec(),
+            pos: 0,
+            dir: None,
+        }
+    }
 }
 
+impl<HANDLE: VFatHandle> DirIter<HANDLE> {
+    /// Returns the file or directory described by `regular_entry`, which must
+    /// be the entry just before `self.pos`.
+    fn make_entry(&self, regular_entry: &VFatRegularDirEntry, name: String) -> Entry<HANDLE> {
+        let mut first_cluster = regular_entry.first_cluster();
+        let metadata = regular_entry.make_metadata(name);
+
+        if regular_entry.attributes.directory() {
+            // A `..` entry pointing at cluster 0 refers to the root directory.
+            if first_cluster.raw() == 0 {
+                first_cluster = self.vfat.lock(|vfat| vfat.rootdir_cluster);
+            }
+
+            Entry::Dir(Dir {
+                vfat: self.vfat.clone(),
+                first_cluster: first_cluster,
+                metadata,
+            })
+        } else {
+            Entry::File(File {
+                vfat: self.vfat.clone(),
+                first_cluster: first_cluster,
+                metadata,
+                pos: 0,
+                dir_entry: self.dir.map(|dir| (dir, self.pos - 1)),
+            })
+        }
+    }
+}
 
 impl<HANDLE: VFatHandle> Iterator for DirIter<HANDLE> {
     type Item = Entry<HANDLE>;
 
     fn next(&mut self) -> Option<Self::Item> {
         let mut value: Option<Self::Item> = None;
-        let mut name_u16 = [0xffffu16; MAX_LFN_ENTRIES * LFN_ENTRY_LEN];
-        let mut encountered_lfn = false;
+        let mut lfn_name = LfnName::new();
 
         for raw in self.raw_entries[self.pos..].into_iter() {
             self.pos += 1;
@@ -194,48 +533,82 @@ impl<HANDLE: VFatHandle> Iterator for DirIter<HANDLE> {
             }
 
             if unknown_entry.attributes.lfn() {
-                encountered_lfn = true;
                 let lfn_entry = unsafe { raw.long_filename };
-                handle_lfn_entry(lfn_entry, &mut name_u16);
+                lfn_name.push(&lfn_entry);
                 continue;
             }
 
             let regular_entry = unsafe { raw.regular };
-            let name = if encountered_lfn {
-                let name_len = name_u16
-                    .iter()
-                    .position(|&b| b == 0x0000 || b == 0xffff)
-                    .unwrap_or(name_u16.len());
-                String::from_utf16_lossy(&name_u16[..name_len])
-            } else {
-                regular_entry.make_regular_filename()
-            };
-
-            let first_cluster = regular_entry.first_cluster();
-            let metadata = regular_entry.make_metadata(name);
-
-            let the_value = if regular_entry.attributes.directory() {
-                Entry::Dir(Dir {
-                    vfat: self.vfat.clone(),
-                    first_cluster: first_cluster,
-                    metadata,
-                })
-            } else {
-                Entry::File(File {
-                    vfat: self.vfat.clone(),
-                    first_cluster: first_cluster,
-                    metadata,
-                    pos: 0,
-                })
-            };
-
-            value = Some(the_value);
+            let name = lfn_name.name_for(&regular_entry);
+            value = Some(self.make_entry(&regular_entry, name));
             break;
         }
         value
     }
 }
 
+impl<HANDLE: VFatHandle> RawDirIter<HANDLE> {
+    pub(crate) fn new(inner: DirIter<HANDLE>) -> RawDirIter<HANDLE> {
+        RawDirIter { inner, past_end: false }
+    }
+}
+
+impl<HANDLE: VFatHandle> Iterator for RawDirIter<HANDLE> {
+    type Item = RawEntry<HANDLE>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // LFN entries seen since the last regular entry, with whether each
+        // one is deleted.
+        let mut lfn_entries: Vec<(VFatLfnDirEntry, bool)> = Vec::new();
+
+        while self.inner.pos < self.inner.raw_entries.len() {
+            let raw = self.inner.raw_entries[self.inner.pos];
+            self.inner.pos += 1;
+
+            let unknown_entry = unsafe { raw.unknown };
+            if unknown_entry.id == 0x00 {
+                self.past_end = true;
+                let bytes: [u8; 32] = unsafe { core::mem::transmute(raw) };
+                if bytes.iter().all(|&b| b == 0) {
+                    continue;
+                }
+            }
+            let deleted = self.past_end || unknown_entry.id == 0xE5;
+
+            if unknown_entry.attributes.lfn() {
+                lfn_entries.push((unsafe { raw.long_filename }, deleted));
+                continue;
+            }
+
+            // Only the LFN entries directly before this one, deleted along
+            // with it, belong to it. They are stored last part first.
+            let mut lfn_name = LfnName::new();
+            let run = lfn_entries.iter().rev().take_while(|&&(_, d)| d == deleted);
+            for (i, &(mut lfn_entry, _)) in run.enumerate() {
+                if deleted {
+                    lfn_entry.sequence_number = i as u8 + 1;
+                }
+                lfn_name.push(&lfn_entry);
+            }
+
+            let mut regular_entry = unsafe { raw.regular };
+            if regular_entry.file_name[0] == 0x00 || regular_entry.file_name[0] == 0xE5 {
+                regular_entry.file_name[0] = b'?';
+            }
+            let name = lfn_name.name_for(&regular_entry);
+            let mut entry = self.inner.make_entry(&regular_entry, name);
+            if let Entry::File(ref mut file) = entry {
+                if deleted {
+                    // Writing would update an entry that is no longer in use.
+                    file.dir_entry = None;
+                }
+            }
+            return Some(RawEntry { entry, deleted });
+        }
+        None
+    }
+}
+
 impl<HANDLE: VFatHandle> traits::Dir for Dir<HANDLE> {
     type Entry = Entry<HANDLE>;
     type Iter = DirIter<HANDLE>;
@@ -247,10 +620,63 @@ impl<HANDLE: VFatHandle> traits::Dir for Dir<HANDLE> {
             Ok(())
         })?;
 
-        Ok(DirIter {
+        let mut iter = DirIter::new(self.vfat.clone(), &data);
+        iter.dir = Some(self.first_cluster);
+        Ok(iter)
+    }
+
+    fn create_dir(&self, name: &str) -> io::Result<Self> {
+        if self.find(name).is_ok() {
+            return Err(newioerr!(AlreadyExists, "entry already exists"));
+        }
+
+        let entry = self.vfat.lock(|vfat| vfat.create_dir(self.first_cluster, name))?;
+        // Names that fit in 8.3 are stored upper-cased, without a long name.
+        let stored_name = match VFatRegularDirEntry::short_name(name) {
+            Ok(_) => entry.make_regular_filename(),
+            Err(_) => name.to_string(),
+        };
+        Ok(Dir {
             vfat: self.vfat.clone(),
-            raw_entries: unsafe { data.cast() },
-            pos: 0,
+            first_cluster: entry.first_cluster(),
+            metadata: entry.make_metadata(stored_name),
         })
     }
+
+    /// Removes the entry named `name`, compared case-insensitively as in
+    /// `find`, together with its LFN entries, and frees its clusters.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `Other` if `name` is a directory that holds
+    /// anything but `.` and `..`, and of kind `PermissionDenied` if the file
+    /// system is read-only.
+    fn remove(&self, name: &str) -> io::Result<()> {
+        use crate::traits::{Dir as _, Entry as _};
+        if name == "." || name == ".." {
+            return Err(newioerr!(InvalidInput, "cannot remove . or .."));
+        }
+
+        let mut iter = self.entries()?;
+        loop {
+            // Deleted and LFN entries in front of the entry returned next
+            // belong to it or are unused, so they are all removed with it.
+            let start = iter.pos;
+            let entry = iter.next().ok_or(newioerr!(NotFound, "file name not found"))?;
+            if !entry.name().eq_ignore_ascii_case(name) {
+                continue;
+            }
+
+            let first_cluster = match entry {
+                Entry::File(file) => file.first_cluster,
+                Entry::Dir(dir) => {
+                    if dir.entries()?.any(|e| e.name() != "." && e.name() != "..") {
+                        return Err(newioerr!(Other, "directory not empty"));
+                    }
+                    dir.first_cluster
+                }
+            };
+            return self.vfat.lock(|vfat| vfat.remove_dir_entries(self.first_cluster, start..iter.pos, first_cluster));
+        }
+    }
 }
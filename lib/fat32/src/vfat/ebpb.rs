@@ -1,6 +1,7 @@
 use core::fmt;
 use shim::const_assert_size;
 use alloc::string::String;
+use alloc::vec;
 
 use crate::traits::BlockDevice;
 use crate::vfat::Error;
@@ -47,7 +48,85 @@ pub struct BiosParameterBlock {
 
 const_assert_size!(BiosParameterBlock, 512);
 
+/// The outcome of comparing a volume's boot sector with its backup copy.
+///
+/// A backup that does not match the boot sector indicates a corrupted or
+/// partially written volume. The volume can still be mounted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackupBootCheck {
+    /// The backup's signature and geometry fields match the boot sector.
+    Match,
+    /// The volume does not record a backup boot sector.
+    NoBackup,
+    /// The backup boot sector could not be read.
+    Unreadable,
+    /// The backup has a bad signature or different geometry fields.
+    Mismatch,
+}
+
 impl BiosParameterBlock {
+    /// Returns `true` if `self` and `other` describe the same volume layout:
+    /// sector and cluster sizes, reserved sectors, FAT count and size, total
+    /// sectors and root directory cluster.
+    pub fn same_geometry(&self, other: &BiosParameterBlock) -> bool {
+        // Copies of the packed fields, since they can't be borrowed.
+        let (bytes, other_bytes) = (self.bytes_per_sector, other.bytes_per_sector);
+        let (reserved, other_reserved) = (self.number_reserved_sectors, other.number_reserved_sectors);
+        let (root, other_root) = (self.cluster_number_of_root, other.cluster_number_of_root);
+        bytes == other_bytes
+            && self.sectors_per_cluster == other.sectors_per_cluster
+            && reserved == other_reserved
+            && self.number_fats == other.number_fats
+            && self.sectors_per_fat() == other.sectors_per_fat()
+            && self.logical_sectors() == other.logical_sectors()
+            && root == other_root
+    }
+
+    /// Compares `self`, the boot sector of a volume, with the backup boot
+    /// sector it points to. `device` must address sectors relative to the
+    /// start of the volume.
+    pub fn check_backup<T: BlockDevice>(&self, mut device: T) -> BackupBootCheck {
+        let sector = self.sector_number_backup_boot;
+        if sector == 0 || sector == 0xFFFF {
+            return BackupBootCheck::NoBackup;
+        }
+
+        let mut buf = vec![0u8; core::cmp::max(device.sector_size() as usize, 512)];
+        if device.read_sector(sector as u64, &mut buf).is_err() {
+            return BackupBootCheck::Unreadable;
+        }
+        let backup = unsafe { *{ buf.as_ptr() as *const BiosParameterBlock } };
+
+        if { backup.bootable_partition_signature } != 0xAA55 || !self.same_geometry(&backup) {
+            return BackupBootCheck::Mismatch;
+        }
+        BackupBootCheck::Match
+    }
+
+    /// Returns `true` if `sector`, the first sector of a device, holds a FAT32
+    /// boot sector rather than an MBR, i.e. the device is a "superfloppy"
+    /// without a partition table.
+    ///
+    /// The check looks for the x86 jump a boot sector starts with and for
+    /// sane FAT32 geometry fields, which an MBR's boot code does not have.
+    pub fn is_boot_sector(sector: &[u8; 512]) -> bool {
+        let ebpb = unsafe { *{ sector.as_ptr() as *const BiosParameterBlock } };
+        let jump = match ebpb.jump_short_noop {
+            [0xEB, _, 0x90] | [0xE9, _, _] => true,
+            _ => false,
+        };
+        let bytes_per_sector = ebpb.bytes_per_sector;
+        jump && bytes_per_sector.is_power_of_two()
+            && bytes_per_sector >= 512
+            && bytes_per_sector <= 4096
+            && ebpb.sectors_per_cluster.is_power_of_two()
+            && { ebpb.number_reserved_sectors } != 0
+            && (ebpb.number_fats == 1 || ebpb.number_fats == 2)
+            && { ebpb.number_sectors_per_fat_16 } == 0
+            && { ebpb.number_sectors_per_fat_32 } != 0
+            && { ebpb.bootable_partition_signature } == 0xAA55
+    }
+
     /// Reads the FAT32 extended BIOS parameter block from sector `sector` of
     /// device `device`.
     ///
@@ -12,6 +12,10 @@ pub struct File<HANDLE: VFatHandle> {
     pub metadata: Metadata,
     pub first_cluster: Cluster,
     pub pos: usize,
+    /// The first cluster of the directory holding this file's entry, and the
+    /// entry's index in it. `None` if unknown, in which case the file
+    /// cannot be written to.
+    pub dir_entry: Option<(Cluster, usize)>,
 }
 
 impl<HANDLE: VFatHandle> traits::File for File<HANDLE> {
@@ -35,7 +39,7 @@ impl<HANDLE: VFatHandle> io::Read for File<HANDLE> {
 
         let mut data = Vec::new();
         self.vfat.lock(|vfat| -> io::Result<()> {
-            vfat.read_chain(self.first_cluster, &mut data)?;
+            vfat.read_chain_with_hint(self.first_cluster, &mut data, self.metadata.size as usize)?;
             Ok(())
         })?;
 
@@ -51,8 +55,43 @@ impl<HANDLE: VFatHandle> io::Read for File<HANDLE> {
 }
 
 impl<HANDLE: VFatHandle> io::Write for File<HANDLE> {
-    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
-        Ok(0)
+    /// Writes `buf` at the current position, overwriting the file's contents
+    /// there and growing the file past its end. New clusters are allocated
+    /// as needed and the directory entry is updated with the new size.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `PermissionDenied` if the file system is
+    /// read-only and of kind `Other` if the file has no known directory
+    /// entry, is at the FAT file size limit of 4 GiB - 1, or the file system
+    /// is full.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.vfat.lock(|vfat| vfat.check_writable())?;
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let (dir, index) = match self.dir_entry {
+            Some(location) => location,
+            None => return ioerr!(Other, "file has no directory entry"),
+        };
+
+        let len = core::cmp::min(buf.len(), core::u32::MAX as usize - self.pos);
+        if len == 0 {
+            return ioerr!(Other, "file size limit reached");
+        }
+        let end = self.pos + len;
+        let size = core::cmp::max(self.metadata.size, end as u32);
+
+        let first_cluster = self.vfat.lock(|vfat| -> io::Result<Cluster> {
+            let first_cluster = vfat.write_chain_at(self.first_cluster, self.pos, &buf[..len])?;
+            vfat.update_dir_entry(dir, index, first_cluster, size)?;
+            Ok(first_cluster)
+        })?;
+
+        self.first_cluster = first_cluster;
+        self.metadata.size = size;
+        self.pos = end;
+        Ok(len)
     }
     fn flush(&mut self) -> io::Result<()> {
         Ok(())
@@ -75,7 +114,23 @@ impl<HANDLE: VFatHandle> io::Seek for File<HANDLE> {
     ///
     /// Seeking before the start of a file or beyond the end of the file results
     /// in an `InvalidInput` error.
-    fn seek(&mut self, _pos: SeekFrom) -> io::Result<u64> {
-        unimplemented!("File::seek()")
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let size = self.metadata.size as i64;
+        let new_pos = match pos {
+            // Offsets past the end are clamped to one past it, so the
+            // conversion to `i64` cannot wrap.
+            SeekFrom::Start(offset) => core::cmp::min(offset, size as u64 + 1) as i64,
+            SeekFrom::End(offset) => size.saturating_add(offset),
+            SeekFrom::Current(offset) => (self.pos as i64).saturating_add(offset),
+        };
+
+        if new_pos < 0 {
+            return ioerr!(InvalidInput, "seek before the start of the file");
+        } else if new_pos > size {
+            return ioerr!(InvalidInput, "seek beyond the end of the file");
+        }
+
+        self.pos = new_pos as usize;
+        Ok(new_pos as u64)
     }
 }
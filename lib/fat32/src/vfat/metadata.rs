@@ -28,6 +28,53 @@ const ATTR_LFN: u8 = 0x0F;
 pub struct Attributes(pub u8);
 
 impl Attributes {
+    /// Returns attributes with no bits set: a writable, visible file.
+    pub fn new() -> Attributes {
+        Attributes(0)
+    }
+
+    fn set(&mut self, bit: u8, value: bool) {
+        if value {
+            self.0 |= bit;
+        } else {
+            self.0 &= !bit;
+        }
+    }
+
+    pub fn set_read_only(&mut self, value: bool) {
+        self.set(ATTR_READ_ONLY, value)
+    }
+    pub fn set_hidden(&mut self, value: bool) {
+        self.set(ATTR_HIDDEN, value)
+    }
+    pub fn set_directory(&mut self, value: bool) {
+        self.set(ATTR_DIRECTORY, value)
+    }
+    pub fn set_archive(&mut self, value: bool) {
+        self.set(ATTR_ARCHIVE, value)
+    }
+
+    /// Like `set_read_only`, but consumes and returns `self` for chaining.
+    pub fn with_read_only(mut self, value: bool) -> Attributes {
+        self.set_read_only(value);
+        self
+    }
+    /// Like `set_hidden`, but consumes and returns `self` for chaining.
+    pub fn with_hidden(mut self, value: bool) -> Attributes {
+        self.set_hidden(value);
+        self
+    }
+    /// Like `set_directory`, but consumes and returns `self` for chaining.
+    pub fn with_directory(mut self, value: bool) -> Attributes {
+        self.set_directory(value);
+        self
+    }
+    /// Like `set_archive`, but consumes and returns `self` for chaining.
+    pub fn with_archive(mut self, value: bool) -> Attributes {
+        self.set_archive(value);
+        self
+    }
+
     pub fn raw(&self) -> u8 {
         self.0
     }
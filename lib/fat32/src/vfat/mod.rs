@@ -1,3 +1,4 @@
+pub(crate) mod buffered;
 pub(crate) mod cache;
 pub(crate) mod cluster;
 pub(crate) mod dir;
@@ -9,8 +10,10 @@ pub(crate) mod file;
 pub(crate) mod metadata;
 pub(crate) mod vfat;
 
-pub use self::dir::Dir;
-pub use self::ebpb::BiosParameterBlock;
+pub use self::buffered::BufferedFile;
+pub use self::cache::CacheStats;
+pub use self::dir::{Dir, RawDirIter, RawEntry};
+pub use self::ebpb::{BackupBootCheck, BiosParameterBlock};
 pub use self::entry::Entry;
 pub use self::error::Error;
 pub use self::file::File;
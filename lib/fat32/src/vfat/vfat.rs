@@ -1,6 +1,7 @@
 use core::fmt::Debug;
 use core::marker::PhantomData;
 use core::mem::size_of;
+use core::ops::Range;
 use alloc::string::String;
 
 use alloc::vec::Vec;
@@ -11,10 +12,15 @@ use shim::path::{Component, Path};
 
 use crate::mbr::MasterBootRecord;
 use crate::traits::{BlockDevice, FileSystem};
-use crate::vfat::{Attributes, BiosParameterBlock, PartitionedDevice, Metadata, Timestamp, Date, Time, Partition};
+use crate::vfat::{Attributes, BackupBootCheck, BiosParameterBlock, CacheStats, PartitionedDevice, Metadata, Timestamp, Date, Time, Partition};
 use crate::vfat::{Cluster, Dir, Entry, Error, FatEntry, File, Status};
+use crate::util::SliceExt;
+use crate::vfat::dir::{check_long_name, VFatLfnDirEntry, VFatRegularDirEntry};
 use crate::vfat::Error::NotFormatted;
 
+/// The size of a directory entry of any kind, in bytes.
+const DIR_ENTRY_SIZE: usize = size_of::<VFatRegularDirEntry>();
+
 /// A generic trait that handles a critical section as a closure
 pub trait VFatHandle: Clone + Debug + Send + Sync {
     fn new(val: VFat<Self>) -> Self;
@@ -28,34 +34,113 @@ pub struct VFat<HANDLE: VFatHandle> {
     pub bytes_per_sector: u16,
     pub sectors_per_cluster: u8,
     sectors_per_fat: u32,
+    number_fats: u8,
     fat_start_sector: u64,
     data_start_sector: u64,
-    rootdir_cluster: Cluster,
+    num_clusters: u32,
+    pub(crate) rootdir_cluster: Cluster,
+    backup_boot: BackupBootCheck,
+    writable: bool,
 }
 
 impl<HANDLE: VFatHandle> VFat<HANDLE> {
+    /// Mounts the first FAT32 partition of `device`, or the whole device if
+    /// it is a "superfloppy" whose first sector is a FAT32 boot sector
+    /// instead of an MBR.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NotFormatted` if no partition in the MBR is FAT32. See
+    /// `from_partition` for the other errors.
     pub fn from<T>(mut device: T) -> Result<HANDLE, Error>
         where
             T: BlockDevice + 'static,
+    {
+        let mut sector = [0u8; 512];
+        device.read_sector(0, &mut sector).map_err(Error::Io)?;
+        if BiosParameterBlock::is_boot_sector(&sector) {
+            return Self::from_superfloppy(device);
+        }
+
+        let mbr = MasterBootRecord::from(&mut device)?;
+        let index = mbr.first_fat32().ok_or(NotFormatted)?;
+        Self::from_partition(device, index)
+    }
+
+    /// Like `from`, but mounts the file system read-only: every write fails
+    /// with an error of kind `PermissionDenied` until `set_writable(true)` is
+    /// called.
+    pub fn from_readonly<T>(device: T) -> Result<HANDLE, Error>
+        where
+            T: BlockDevice + 'static,
+    {
+        let handle = Self::from(device)?;
+        handle.lock(|vfat| vfat.set_writable(false));
+        Ok(handle)
+    }
+
+    /// Mounts `device` as a "superfloppy": a FAT32 volume starting at sector
+    /// 0 with no partition table.
+    ///
+    /// # Errors
+    ///
+    /// Returns `BadSignature` or `NotFormatted` if sector 0 is not a valid
+    /// FAT32 boot sector.
+    pub fn from_superfloppy<T>(mut device: T) -> Result<HANDLE, Error>
+        where
+            T: BlockDevice + 'static,
+    {
+        let ebpb = BiosParameterBlock::from(&mut device, 0)?;
+        let num_sectors = ebpb.logical_sectors() as u64;
+        Self::mount(device, ebpb, 0, num_sectors)
+    }
+
+    /// Mounts partition `index` (0-indexed) of `device`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NotFound` if `index` is not a valid partition table index and
+    /// `NotFormatted` if the partition is not FAT32. Returns `Mbr` or
+    /// `BadSignature` if the MBR or the partition's EBPB is invalid.
+    ///
+    /// A damaged backup boot sector does not cause an error; the result of
+    /// the comparison is available from `backup_boot_check`.
+    pub fn from_partition<T>(mut device: T, index: usize) -> Result<HANDLE, Error>
+        where
+            T: BlockDevice + 'static,
     {
         let mbr = MasterBootRecord::from(&mut device)?;
 
-        let first_partition = mbr.partition_table[0];
+        let partition_entry = *mbr.partition_table.get(index).ok_or(Error::NotFound)?;
 
-        if first_partition.partition_type != 0xB && first_partition.partition_type != 0xC {
+        if !partition_entry.is_fat32() {
             return Err(NotFormatted);
         }
 
-        let ebpb = BiosParameterBlock::from(&mut device, first_partition.relative_sector as u64)?;
+        let start = partition_entry.relative_sector as u64;
+        let ebpb = BiosParameterBlock::from(&mut device, start)?;
+        Self::mount(device, ebpb, start, partition_entry.total_sectors_in_partition as u64)
+    }
 
+    /// Mounts the volume described by `ebpb` that occupies `num_sectors`
+    /// sectors of `device` from sector `start` on.
+    fn mount<T>(device: T, ebpb: BiosParameterBlock, start: u64, num_sectors: u64) -> Result<HANDLE, Error>
+        where
+            T: BlockDevice + 'static,
+    {
         let partition = Partition {
-            start: first_partition.relative_sector as u64,
-            num_sectors: first_partition.total_sectors_in_partition as u64,
+            start,
+            num_sectors,
             sector_size: ebpb.bytes_per_sector as u64,
         };
-        let cached_partition = PartitionedDevice::new(device, partition);
+        let mut cached_partition = PartitionedDevice::new(device, partition);
+        let backup_boot = ebpb.check_backup(&mut cached_partition);
 
         let rootdir_cluster = Cluster::from(ebpb.cluster_number_of_root);
+        let data_start_sector = ebpb.number_reserved_sectors as u64 + (ebpb.number_fats as u64 * ebpb.sectors_per_fat() as u64);
+        let data_sectors = (ebpb.logical_sectors() as u64).saturating_sub(data_start_sector);
+        let fat_entries = ebpb.sectors_per_fat() as u64 * ebpb.bytes_per_sector as u64 / size_of::<FatEntry>() as u64;
+        let num_clusters = core::cmp::min(data_sectors / ebpb.sectors_per_cluster as u64 + 2, fat_entries) as u32;
 
         let vfat = VFat {
             phantom: Default::default(),
@@ -63,30 +148,107 @@ impl<HANDLE: VFatHandle> VFat<HANDLE> {
             bytes_per_sector: ebpb.bytes_per_sector,
             sectors_per_cluster: ebpb.sectors_per_cluster,
             sectors_per_fat: ebpb.sectors_per_fat(),
+            number_fats: ebpb.number_fats,
             fat_start_sector: ebpb.number_reserved_sectors as u64,
-            data_start_sector: ebpb.number_reserved_sectors as u64 + (ebpb.number_fats as u64 * ebpb.sectors_per_fat() as u64),
+            data_start_sector,
+            num_clusters,
             rootdir_cluster: rootdir_cluster,
+            backup_boot,
+            writable: true,
         };
         Ok(HANDLE::new(vfat))
     }
 
+    /// Returns the result of comparing the boot sector with its backup when
+    /// the volume was mounted. Anything other than `Match` or `NoBackup`
+    /// suggests the volume is damaged.
+    pub fn backup_boot_check(&self) -> BackupBootCheck {
+        self.backup_boot
+    }
+
+    /// Returns how many sector reads were served from the sector cache and
+    /// from the device since the volume was mounted.
+    pub fn cache_stats(&self) -> CacheStats {
+        self.device.cache_stats()
+    }
+
+    /// Forgets the cached sectors, for when the device was written to
+    /// without going through this `VFat`.
+    pub fn invalidate_cache(&mut self) {
+        self.device.invalidate();
+    }
+
+    /// Returns `true` if the file system accepts writes.
+    pub fn is_writable(&self) -> bool {
+        self.writable
+    }
+
+    /// Allows or forbids writes to the file system.
+    pub fn set_writable(&mut self, writable: bool) {
+        self.writable = writable;
+    }
+
+    /// Returns an error of kind `PermissionDenied` if the file system is
+    /// mounted read-only.
+    pub(crate) fn check_writable(&self) -> io::Result<()> {
+        if self.writable {
+            Ok(())
+        } else {
+            ioerr!(PermissionDenied, "filesystem is read-only")
+        }
+    }
+
+    /// Writes `buf` to `sector` of the partition. Every write to the device
+    /// goes through here so that read-only mounts are enforced.
+    fn write_sector(&mut self, sector: u64, buf: &[u8]) -> io::Result<usize> {
+        self.check_writable()?;
+        self.device.write_sector(sector, buf)
+    }
+
     fn get_sector_for_cluster(&self, cluster: Cluster) -> u64 {
         self.data_start_sector + (cluster.raw() as u64 - 2) * self.sectors_per_cluster as u64
     }
 
+    /// Reads from `cluster`, starting `offset` bytes into it, into `buf`.
+    ///
+    /// Reads `min(buf.len(), cluster_size - offset)` bytes and returns that
+    /// count, so `buf` may be shorter than the rest of the cluster.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `InvalidInput` if `offset` is past the end of
+    /// the cluster.
     pub fn read_cluster(
         &mut self,
         cluster: Cluster,
-        _offset: usize,
+        offset: usize,
         buf: &mut [u8],
     ) -> io::Result<usize> {
+        let cluster_size = self.cluster_size();
+        if offset > cluster_size {
+            return ioerr!(InvalidInput, "offset past end of cluster");
+        }
+
+        let len = core::cmp::min(buf.len(), cluster_size - offset);
+        let sector_size = self.bytes_per_sector as usize;
         let start_sector = self.get_sector_for_cluster(cluster);
+        let mut sector_data = Vec::new();
         let mut n_read = 0;
-        for i in 0..self.sectors_per_cluster as u64 {
-            let start = (i as u16 * self.bytes_per_sector) as usize;
-            let end = ((i as u16  + 1) * self.bytes_per_sector) as usize;
-            let mut buf_slice = &mut buf[start..end];
-            n_read += self.device.read_sector(start_sector + i, buf_slice)?;
+        while n_read < len {
+            let pos = offset + n_read;
+            let sector = start_sector + (pos / sector_size) as u64;
+            let sector_offset = pos % sector_size;
+            let n = core::cmp::min(len - n_read, sector_size - sector_offset);
+
+            if sector_offset == 0 && n == sector_size {
+                // Whole sectors go straight into `buf`.
+                self.device.read_sector(sector, &mut buf[n_read..n_read + n])?;
+            } else {
+                sector_data.resize(sector_size, 0);
+                self.device.read_sector(sector, &mut sector_data)?;
+                buf[n_read..n_read + n].copy_from_slice(&sector_data[sector_offset..sector_offset + n]);
+            }
+            n_read += n;
         }
 
         Ok(n_read)
@@ -99,14 +261,41 @@ impl<HANDLE: VFatHandle> VFat<HANDLE> {
         start: Cluster,
         buf: &mut Vec<u8>,
     ) -> io::Result<usize> {
-        let n_bytes = (self.bytes_per_sector * (self.sectors_per_cluster as u16)) as usize;
-        let mut cluster_data = vec![0u8; n_bytes];
+        self.read_chain_with_hint(start, buf, 0)
+    }
+
+    /// Like `read_chain`, but first reserves room in `buf` for `size_hint`
+    /// bytes, such as the size of the file the chain holds, so that `buf` is
+    /// allocated once instead of being grown as clusters are appended.
+    ///
+    /// Clusters are read straight into `buf`, and the FAT is looked up
+    /// through a single sector buffer that is only re-read when the chain
+    /// moves on to another FAT sector.
+    pub fn read_chain_with_hint(
+        &mut self,
+        start: Cluster,
+        buf: &mut Vec<u8>,
+        size_hint: usize,
+    ) -> io::Result<usize> {
+        let cluster_size = self.cluster_size();
+        // Whole clusters are read, so round the hint up to one.
+        buf.reserve((size_hint + cluster_size - 1) / cluster_size * cluster_size);
+
+        let mut fat_sector = vec![0u8; self.device.sector_size() as usize];
+        let mut loaded = None;
         let mut next = start;
         let mut read_bytes = 0;
         loop {
-            read_bytes += self.read_cluster(next, 0, &mut cluster_data)?;
-            buf.extend_from_slice(&cluster_data);
-            match self.fat_entry(next)?.status() {
+            let end = buf.len();
+            buf.resize(end + cluster_size, 0);
+            match self.read_cluster(next, 0, &mut buf[end..]) {
+                Ok(n) => read_bytes += n,
+                Err(e) => {
+                    buf.truncate(end);
+                    return Err(e);
+                }
+            }
+            match self.fat_entry_in(next, &mut fat_sector, &mut loaded)?.status() {
                 Status::Data(cluster) => next = cluster,
                 Status::Eoc(_) => break,
                 _ => return ioerr!(InvalidData, "Invalid chain fat entry"),
@@ -116,24 +305,420 @@ impl<HANDLE: VFatHandle> VFat<HANDLE> {
     }
 
     pub fn fat_entry(&mut self, cluster: Cluster) -> io::Result<FatEntry> {
+        let (sector, offset_bytes) = self.fat_entry_position(cluster);
+        let sector_data = self.device.get(sector)?;
+
+        let mut bytes = [0u8; 4];
+        bytes.copy_from_slice(&sector_data[offset_bytes..offset_bytes + 4]);
+
+        Ok(FatEntry(u32::from_le_bytes(bytes)))
+    }
+
+    /// Returns the sector of the first FAT holding the entry of `cluster`,
+    /// and the entry's offset in bytes within that sector.
+    fn fat_entry_position(&self, cluster: Cluster) -> (u64, usize) {
         let fat_entries_per_sector = self.device.sector_size() as usize / size_of::<FatEntry>();
         let sector = self.fat_start_sector + cluster.raw() as u64 / (fat_entries_per_sector as u64);
-        let offset = cluster.raw() as usize % (fat_entries_per_sector as usize);
-        let offset_bytes = offset * size_of::<FatEntry>();
-        let mut sector_data = vec![0u8; self.device.sector_size() as usize];
+        let offset = cluster.raw() as usize % fat_entries_per_sector;
+        (sector, offset * size_of::<FatEntry>())
+    }
 
-        self.device.read_sector(sector, &mut sector_data)?;
+    /// Looks up the FAT entry of `cluster` using `sector_data` to hold the
+    /// FAT sector it is in. `loaded` is the sector `sector_data` currently
+    /// holds, if any; it is only read from the device if it differs.
+    fn fat_entry_in(
+        &mut self,
+        cluster: Cluster,
+        sector_data: &mut [u8],
+        loaded: &mut Option<u64>,
+    ) -> io::Result<FatEntry> {
+        let (sector, offset_bytes) = self.fat_entry_position(cluster);
+
+        if *loaded != Some(sector) {
+            *loaded = None;
+            self.device.read_sector(sector, sector_data)?;
+            *loaded = Some(sector);
+        }
 
         let mut bytes = [0u8; 4];
         bytes.copy_from_slice(&sector_data[offset_bytes..offset_bytes + 4]);
 
         Ok(FatEntry(u32::from_le_bytes(bytes)))
     }
+
+    /// Compares every copy of the FAT with the first, sector by sector, and
+    /// returns `true` if they all match. A mismatch means the volume is
+    /// corrupt or a write to the FATs was interrupted; `fat_entry` only
+    /// reads the first copy.
+    pub fn verify_fats(&mut self) -> io::Result<bool> {
+        let sector_size = self.device.sector_size() as usize;
+        let mut primary = vec![0u8; sector_size];
+        let mut copy = vec![0u8; sector_size];
+
+        for sector in 0..self.sectors_per_fat as u64 {
+            self.device.read_sector(self.fat_start_sector + sector, &mut primary)?;
+            for fat in 1..self.number_fats as u64 {
+                let copy_sector = self.fat_start_sector + fat * self.sectors_per_fat as u64 + sector;
+                self.device.read_sector(copy_sector, &mut copy)?;
+                if primary != copy {
+                    return Ok(false);
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    /// Returns the size of a cluster in bytes.
+    pub fn cluster_size(&self) -> usize {
+        self.bytes_per_sector as usize * self.sectors_per_cluster as usize
+    }
+
+    /// Writes `buf` to `cluster`, one sector at a time. `buf` must hold a full
+    /// cluster.
+    pub fn write_cluster(&mut self, cluster: Cluster, buf: &[u8]) -> io::Result<usize> {
+        if buf.len() < self.cluster_size() {
+            return ioerr!(UnexpectedEof, "buffer smaller than a cluster");
+        }
+
+        let start_sector = self.get_sector_for_cluster(cluster);
+        let sector_size = self.bytes_per_sector as usize;
+        let mut n_written = 0;
+        for i in 0..self.sectors_per_cluster as usize {
+            let buf_slice = &buf[i * sector_size..(i + 1) * sector_size];
+            n_written += self.write_sector(start_sector + i as u64, buf_slice)?;
+        }
+
+        Ok(n_written)
+    }
+
+    /// Sets the FAT entry for `cluster` to `value` in every copy of the FAT.
+    pub(crate) fn set_fat_entry(&mut self, cluster: Cluster, value: u32) -> io::Result<()> {
+        let fat_entries_per_sector = self.device.sector_size() as usize / size_of::<FatEntry>();
+        let sector_in_fat = cluster.raw() as u64 / (fat_entries_per_sector as u64);
+        let offset_bytes = (cluster.raw() as usize % fat_entries_per_sector) * size_of::<FatEntry>();
+        let mut sector_data = vec![0u8; self.device.sector_size() as usize];
+
+        for fat in 0..self.number_fats as u64 {
+            let sector = self.fat_start_sector + fat * self.sectors_per_fat as u64 + sector_in_fat;
+            self.device.read_sector(sector, &mut sector_data)?;
+
+            // The upper four bits of an entry are reserved and must be preserved.
+            let mut bytes = [0u8; 4];
+            bytes.copy_from_slice(&sector_data[offset_bytes..offset_bytes + 4]);
+            let old = u32::from_le_bytes(bytes);
+            let new = (old & 0xF000_0000) | (value & 0x0FFF_FFFF);
+            sector_data[offset_bytes..offset_bytes + 4].copy_from_slice(&new.to_le_bytes());
+
+            self.write_sector(sector, &sector_data)?;
+        }
+        Ok(())
+    }
+
+    /// Returns each cluster in the chain starting at `first_cluster`, in
+    /// chain order, together with the first sector it occupies. Sector
+    /// numbers are relative to the start of the partition.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `InvalidData` if the chain contains a free,
+    /// reserved or bad cluster, or is longer than the number of clusters in
+    /// the file system (i.e. contains a cycle).
+    pub fn file_extents(&mut self, first_cluster: Cluster) -> io::Result<Vec<(Cluster, u64)>> {
+        let mut extents = Vec::new();
+        let mut current = first_cluster;
+        loop {
+            if extents.len() >= self.num_clusters as usize {
+                return ioerr!(InvalidData, "cluster chain contains a cycle");
+            }
+            extents.push((current, self.get_sector_for_cluster(current)));
+
+            match self.fat_entry(current)?.status() {
+                Status::Data(next) => current = next,
+                Status::Eoc(_) => return Ok(extents),
+                _ => return ioerr!(InvalidData, "Invalid chain fat entry"),
+            }
+        }
+    }
+
+    /// Finds a free cluster, marks it as the end of a chain, zeroes its
+    /// contents and returns it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `Other` if there are no free clusters left.
+    pub fn alloc_cluster(&mut self) -> io::Result<Cluster> {
+        for raw in 2..self.num_clusters {
+            let cluster = Cluster::from(raw);
+            if self.fat_entry(cluster)?.status() == Status::Free {
+                self.set_fat_entry(cluster, 0x0FFF_FFFF)?;
+                let zeroes = vec![0u8; self.cluster_size()];
+                self.write_cluster(cluster, &zeroes)?;
+                return Ok(cluster);
+            }
+        }
+        ioerr!(Other, "no free clusters left")
+    }
+
+    /// Stores `entry` in the first unused slot of the directory starting at
+    /// `dir`, growing the directory by a cluster if it is full.
+    pub(crate) fn append_dir_entry(&mut self, dir: Cluster, entry: &VFatRegularDirEntry) -> io::Result<()> {
+        let raw_entry: [u8; DIR_ENTRY_SIZE] = unsafe { core::mem::transmute(*entry) };
+        self.append_dir_entries(dir, &[raw_entry])?;
+        Ok(())
+    }
+
+    /// Stores `entries` in the first run of as many consecutive unused slots
+    /// in the directory starting at `dir`, growing the directory by as many
+    /// clusters as needed if there is no such run. Returns the index of the
+    /// slot holding the last entry.
+    pub(crate) fn append_dir_entries(&mut self, dir: Cluster, entries: &[[u8; DIR_ENTRY_SIZE]]) -> io::Result<usize> {
+        let mut clusters: Vec<Cluster> = self.file_extents(dir)?.into_iter().map(|(cluster, _)| cluster).collect();
+        let mut data = Vec::new();
+        self.read_chain(dir, &mut data)?;
+
+        // Slots past the end of the chain count as unused, so `start` ends up
+        // at the last run of unused slots if none is long enough.
+        let mut start = 0;
+        let mut run = 0;
+        for (i, slot) in data.chunks(DIR_ENTRY_SIZE).enumerate() {
+            if run == entries.len() {
+                break;
+            }
+            if slot[0] == 0x00 || slot[0] == 0xE5 {
+                run += 1;
+            } else {
+                start = i + 1;
+                run = 0;
+            }
+        }
+
+        let end = (start + entries.len()) * DIR_ENTRY_SIZE;
+        while data.len() < end {
+            let last = clusters[clusters.len() - 1];
+            clusters.push(self.next_or_alloc(last)?);
+            data.resize(data.len() + self.cluster_size(), 0);
+        }
+
+        for (i, entry) in entries.iter().enumerate() {
+            let offset = (start + i) * DIR_ENTRY_SIZE;
+            data[offset..offset + DIR_ENTRY_SIZE].copy_from_slice(entry);
+        }
+        self.write_dir_slots(&clusters, &data, start..start + entries.len())?;
+        Ok(start + entries.len() - 1)
+    }
+
+    /// Writes back the clusters of a directory that hold `slots`. `clusters`
+    /// is the directory's chain and `data` its contents.
+    fn write_dir_slots(&mut self, clusters: &[Cluster], data: &[u8], slots: Range<usize>) -> io::Result<()> {
+        let cluster_size = self.cluster_size();
+        let first = slots.start * DIR_ENTRY_SIZE / cluster_size;
+        let last = (slots.end * DIR_ENTRY_SIZE - 1) / cluster_size;
+        for i in first..=last {
+            self.write_cluster(clusters[i], &data[i * cluster_size..(i + 1) * cluster_size])?;
+        }
+        Ok(())
+    }
+
+    /// Marks `slots` of the directory starting at `dir` as deleted, then
+    /// frees the chain starting at `first_cluster` unless it is cluster 0.
+    ///
+    /// The entries are deleted first, so that failing halfway leaks clusters
+    /// rather than leaving an entry that points at free clusters.
+    pub(crate) fn remove_dir_entries(
+        &mut self,
+        dir: Cluster,
+        slots: Range<usize>,
+        first_cluster: Cluster,
+    ) -> io::Result<()> {
+        self.check_writable()?;
+        let clusters: Vec<Cluster> = self.file_extents(dir)?.into_iter().map(|(cluster, _)| cluster).collect();
+        let mut data = Vec::new();
+        self.read_chain(dir, &mut data)?;
+        if slots.end * DIR_ENTRY_SIZE > data.len() {
+            return ioerr!(InvalidInput, "directory entry past the end of the directory");
+        }
+
+        for i in slots.clone() {
+            data[i * DIR_ENTRY_SIZE] = 0xE5;
+        }
+        self.write_dir_slots(&clusters, &data, slots)?;
+
+        if first_cluster.raw() != 0 {
+            self.free_chain(first_cluster)?;
+        }
+        Ok(())
+    }
+
+    /// Marks every cluster in the chain starting at `first_cluster` as free.
+    /// The whole chain is checked before any cluster is freed.
+    pub(crate) fn free_chain(&mut self, first_cluster: Cluster) -> io::Result<()> {
+        for (cluster, _) in self.file_extents(first_cluster)? {
+            self.set_fat_entry(cluster, 0)?;
+        }
+        Ok(())
+    }
+
+    /// Returns a short name `BASIS~n.EXT` for the long name `name` that no
+    /// entry of the directory starting at `dir` uses yet.
+    fn unique_short_name(&mut self, dir: Cluster, name: &str) -> io::Result<[u8; 11]> {
+        let mut data = Vec::new();
+        self.read_chain(dir, &mut data)?;
+        let taken: Vec<&[u8]> = data
+            .chunks(DIR_ENTRY_SIZE)
+            .take_while(|slot| slot[0] != 0x00)
+            .filter(|slot| slot[0] != 0xE5 && slot[11] & 0x0F != 0x0F)
+            .map(|slot| &slot[..11])
+            .collect();
+
+        for n in 1..1_000_000 {
+            let short_name = VFatRegularDirEntry::numbered_short_name(name, n);
+            if !taken.contains(&&short_name[..]) {
+                return Ok(short_name);
+            }
+        }
+        ioerr!(AlreadyExists, "no unused short name left")
+    }
+
+    /// Returns the cluster following `cluster` in its chain. If `cluster`
+    /// ends the chain, a free cluster is allocated and linked in after it.
+    fn next_or_alloc(&mut self, cluster: Cluster) -> io::Result<Cluster> {
+        match self.fat_entry(cluster)?.status() {
+            Status::Data(next) => Ok(next),
+            Status::Eoc(_) => {
+                let next = self.alloc_cluster()?;
+                self.set_fat_entry(cluster, next.raw())?;
+                Ok(next)
+            }
+            _ => ioerr!(InvalidData, "Invalid chain fat entry"),
+        }
+    }
+
+    /// Writes `buf` into the chain starting at `start`, beginning `offset`
+    /// bytes into it. The chain is extended with newly allocated clusters
+    /// as needed, so `offset` may be at most the chain's length in bytes.
+    ///
+    /// Returns the first cluster of the chain. A `start` of cluster 0, as
+    /// stored for an empty file, starts a new chain.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `Other` if the file system runs out of free
+    /// clusters. The clusters allocated and written until then stay in the
+    /// chain.
+    pub(crate) fn write_chain_at(&mut self, start: Cluster, offset: usize, buf: &[u8]) -> io::Result<Cluster> {
+        self.check_writable()?;
+        let first = if start.raw() == 0 { self.alloc_cluster()? } else { start };
+
+        let cluster_size = self.cluster_size();
+        let mut current = first;
+        for _ in 0..offset / cluster_size {
+            current = self.next_or_alloc(current)?;
+        }
+
+        let mut cluster_data = vec![0u8; cluster_size];
+        let mut cluster_offset = offset % cluster_size;
+        let mut written = 0;
+        loop {
+            let n = core::cmp::min(buf.len() - written, cluster_size - cluster_offset);
+            if n < cluster_size {
+                // Keep the parts of the cluster that are not overwritten.
+                self.read_cluster(current, 0, &mut cluster_data)?;
+            }
+            cluster_data[cluster_offset..cluster_offset + n].copy_from_slice(&buf[written..written + n]);
+            self.write_cluster(current, &cluster_data)?;
+
+            written += n;
+            if written == buf.len() {
+                return Ok(first);
+            }
+            current = self.next_or_alloc(current)?;
+            cluster_offset = 0;
+        }
+    }
+
+    /// Records `first_cluster` and `size` in the regular entry at `index` of
+    /// the directory starting at `dir`. `index` counts 32-byte entries from
+    /// the start of the directory, including LFN and deleted entries.
+    pub(crate) fn update_dir_entry(
+        &mut self,
+        dir: Cluster,
+        index: usize,
+        first_cluster: Cluster,
+        size: u32,
+    ) -> io::Result<()> {
+        let cluster_size = self.cluster_size();
+        let mut current = dir;
+        for _ in 0..index * DIR_ENTRY_SIZE / cluster_size {
+            match self.fat_entry(current)?.status() {
+                Status::Data(next) => current = next,
+                _ => return ioerr!(InvalidData, "directory entry past the end of the directory"),
+            }
+        }
+
+        let mut cluster_data = vec![0u8; cluster_size];
+        self.read_cluster(current, 0, &mut cluster_data)?;
+        let start = index * DIR_ENTRY_SIZE % cluster_size;
+        let entry = &mut unsafe { cluster_data[start..start + DIR_ENTRY_SIZE].cast_mut::<VFatRegularDirEntry>() }[0];
+        entry.set_first_cluster(first_cluster);
+        entry.file_size = size;
+        self.write_cluster(current, &cluster_data)?;
+        Ok(())
+    }
+
+    /// Creates a directory named `name` inside the directory starting at
+    /// `parent` and returns its directory entry. The new directory's cluster
+    /// is initialized with the `.` and `..` entries. Following the FAT
+    /// convention, `..` refers to cluster 0 when `parent` is the root
+    /// directory.
+    ///
+    /// A `name` that is a valid 8.3 name is stored upper-cased as the short
+    /// name. Any other name is stored in LFN entries in front of a generated
+    /// `BASIS~n.EXT` short name.
+    ///
+    /// This does not check whether `name` already exists in `parent`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `InvalidInput` if `name` is not a valid long
+    /// file name (see `check_long_name`) and of kind `PermissionDenied` if
+    /// the file system is read-only.
+    pub(crate) fn create_dir(&mut self, parent: Cluster, name: &str) -> io::Result<VFatRegularDirEntry> {
+        self.check_writable()?;
+        let (short_name, lfn_entries) = match VFatRegularDirEntry::short_name(name) {
+            Ok(short_name) => (short_name, Vec::new()),
+            Err(_) => {
+                check_long_name(name)?;
+                let short_name = self.unique_short_name(parent, name)?;
+                (short_name, VFatLfnDirEntry::for_name(name, &short_name))
+            }
+        };
+        let cluster = self.alloc_cluster()?;
+        let attributes = Attributes::new().with_directory(true);
+
+        let parent_target = if parent == self.rootdir_cluster {
+            Cluster::from(0)
+        } else {
+            parent
+        };
+        let dot = VFatRegularDirEntry::new(*b".          ", attributes, cluster, 0);
+        let dot_dot = VFatRegularDirEntry::new(*b"..         ", attributes, parent_target, 0);
+        self.append_dir_entry(cluster, &dot)?;
+        self.append_dir_entry(cluster, &dot_dot)?;
+
+        let entry = VFatRegularDirEntry::new(short_name, attributes, cluster, 0);
+        let mut raw_entries: Vec<[u8; DIR_ENTRY_SIZE]> = lfn_entries
+            .into_iter()
+            .map(|lfn_entry| unsafe { core::mem::transmute(lfn_entry) })
+            .collect();
+        raw_entries.push(unsafe { core::mem::transmute(entry) });
+        self.append_dir_entries(parent, &raw_entries)?;
+        Ok(entry)
+    }
 }
 
 fn make_root_dir_metadata() -> Metadata {
     Metadata {
-        attributes: Attributes(0),
+        attributes: Attributes::new(),
         created_ts: Timestamp { date: Date(0), time: Time(0) },
         accessed_ts: Timestamp { date: Date(0), time: Time(0) },
         modified_ts: Timestamp { date: Date(0), time: Time(0) },
@@ -22,6 +22,7 @@ pub enum OsError {
     BadAddress = 50,
     FileExists = 60,
     InvalidArgument = 70,
+    BadDescriptor = 80,
 
     IoError = 101,
     IoErrorEof = 102,
@@ -45,6 +46,7 @@ impl core::convert::From<u64> for OsError {
             50 => OsError::BadAddress,
             60 => OsError::FileExists,
             70 => OsError::InvalidArgument,
+            80 => OsError::BadDescriptor,
 
             101 => OsError::IoError,
             102 => OsError::IoErrorEof,
@@ -78,6 +80,13 @@ pub const NR_EXIT: usize = 3;
 pub const NR_WRITE: usize = 4;
 pub const NR_GETPID: usize = 5;
 pub const NR_WRITE_STR: usize = 6;
+pub const NR_OPEN: usize = 7;
+pub const NR_READ: usize = 8;
+pub const NR_WRITE_FD: usize = 9;
+pub const NR_CLOSE: usize = 10;
+pub const NR_GETCWD: usize = 11;
+pub const NR_CHDIR: usize = 12;
+pub const NR_NANOSLEEP: usize = 13;
 
 #[derive(Clone, Copy, Debug)]
 pub struct SocketDescriptor(u64);
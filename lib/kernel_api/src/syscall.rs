@@ -38,6 +38,30 @@ pub fn sleep(span: Duration) -> OsResult<Duration> {
     err_or!(ecode, Duration::from_millis(elapsed_ms))
 }
 
+/// Sleeps for `span`, which may be shorter than a millisecond. The kernel
+/// clock counts microseconds, so shorter spans are rounded up to the next
+/// microsecond. Returns the time actually slept.
+pub fn nanosleep(span: Duration) -> OsResult<Duration> {
+    let mut ecode: u64;
+    let mut elapsed_secs: u64;
+    let mut elapsed_ns: u64;
+
+    unsafe {
+        asm!("mov x0, $3
+              mov x1, $4
+              svc $5
+              mov $0, x0
+              mov $1, x1
+              mov $2, x7"
+             : "=r"(elapsed_secs), "=r"(elapsed_ns), "=r"(ecode)
+             : "r"(span.as_secs()), "r"(span.subsec_nanos() as u64), "i"(NR_NANOSLEEP)
+             : "x0", "x1", "x7"
+             : "volatile");
+    }
+
+    err_or!(ecode, Duration::new(elapsed_secs, elapsed_ns as u32))
+}
+
 pub fn time() -> Duration {
     let mut ecode: u64;
     let mut time_secs: u64;
@@ -50,7 +74,7 @@ pub fn time() -> Duration {
               mov $2, x7"
              : "=r"(time_secs), "=r"(time_ns), "=r"(ecode)
              : "i"(NR_TIME)
-             : "x0", "x7"
+             : "x0", "x1", "x7"
              : "volatile");
     }
     match OsError::from(ecode) {
@@ -110,6 +134,117 @@ pub fn getpid() -> u64 {
 
     pid
 }
+pub fn open(path: &str) -> OsResult<usize> {
+    let mut ecode: u64;
+    let mut fd: u64;
+
+    unsafe {
+        asm!("mov x0, $2
+              mov x1, $3
+              svc $4
+              mov $0, x0
+              mov $1, x7"
+             : "=r"(fd), "=r"(ecode)
+             : "r"(path.as_ptr()), "r"(path.len()), "i"(NR_OPEN)
+             : "x0", "x1", "x7"
+             : "volatile");
+    }
+
+    err_or!(ecode, fd as usize)
+}
+
+pub fn read(fd: usize, buf: &mut [u8]) -> OsResult<usize> {
+    let mut ecode: u64;
+    let mut len: u64;
+
+    unsafe {
+        asm!("mov x0, $2
+              mov x1, $3
+              mov x2, $4
+              svc $5
+              mov $0, x0
+              mov $1, x7"
+             : "=r"(len), "=r"(ecode)
+             : "r"(fd), "r"(buf.as_mut_ptr()), "r"(buf.len()), "i"(NR_READ)
+             : "x0", "x1", "x2", "x7"
+             : "volatile");
+    }
+
+    err_or!(ecode, len as usize)
+}
+
+pub fn write_fd(fd: usize, buf: &[u8]) -> OsResult<usize> {
+    let mut ecode: u64;
+    let mut len: u64;
+
+    unsafe {
+        asm!("mov x0, $2
+              mov x1, $3
+              mov x2, $4
+              svc $5
+              mov $0, x0
+              mov $1, x7"
+             : "=r"(len), "=r"(ecode)
+             : "r"(fd), "r"(buf.as_ptr()), "r"(buf.len()), "i"(NR_WRITE_FD)
+             : "x0", "x1", "x2", "x7"
+             : "volatile");
+    }
+
+    err_or!(ecode, len as usize)
+}
+
+pub fn close(fd: usize) -> OsResult<()> {
+    let mut ecode: u64;
+
+    unsafe {
+        asm!("mov x0, $1
+              svc $2
+              mov $0, x7"
+             : "=r"(ecode)
+             : "r"(fd), "i"(NR_CLOSE)
+             : "x0", "x7"
+             : "volatile");
+    }
+
+    err_or!(ecode, ())
+}
+
+pub fn getcwd(buf: &mut [u8]) -> OsResult<usize> {
+    let mut ecode: u64;
+    let mut len: u64;
+
+    unsafe {
+        asm!("mov x0, $2
+              mov x1, $3
+              svc $4
+              mov $0, x0
+              mov $1, x7"
+             : "=r"(len), "=r"(ecode)
+             : "r"(buf.as_mut_ptr()), "r"(buf.len()), "i"(NR_GETCWD)
+             : "x0", "x1", "x7"
+             : "volatile");
+    }
+
+    err_or!(ecode, len as usize)
+}
+
+pub fn chdir(path: &str) -> OsResult<()> {
+    let mut ecode: u64;
+
+    unsafe {
+        asm!("mov x0, $1
+              mov x1, $2
+              svc $3
+              mov $0, x7"
+             : "=r"(ecode)
+             : "r"(path.as_ptr()), "r"(path.len()), "i"(NR_CHDIR)
+             : "x0", "x1", "x7"
+             : "volatile");
+    }
+
+    err_or!(ecode, ())
+}
+
 struct Console;
 
 impl fmt::Write for Console {
@@ -46,20 +46,24 @@ impl From<&'static raw::Atag> for Atag {
             match (atag.tag, &atag.kind) {
                 (raw::Atag::CORE, &raw::Kind { core }) => Atag::Core(core),
                 (raw::Atag::MEM, &raw::Kind { mem }) => Atag::Mem(mem),
-                (raw::Atag::CMDLINE, &raw::Kind { ref cmd }) => {
-                    let start = &cmd.cmd as *const u8;
-                    let mut ptr = &cmd.cmd as *const u8;
-                    while *ptr != 0 {
-                        ptr = ptr.add(1);
-                    }
-                    let c_str_len = ptr.offset_from(start) as usize;
-                    let c_str_slice = core::slice::from_raw_parts(start, c_str_len);
-                    let cmd_str = core::str::from_utf8(c_str_slice).expect("valid utf8");
-                    Atag::Cmd(cmd_str)
-                },
+                (raw::Atag::CMDLINE, &raw::Kind { ref cmd }) => cmdline(atag.dwords, cmd),
                 (raw::Atag::NONE, _) => Atag::None,
                 (id, _) => Atag::Unknown(id),
             }
         }
     }
 }
+
+/// Returns the command line of a `CMDLINE` ATAG that is `dwords` words long,
+/// including its two-word header. The string ends at the first NUL, or at the
+/// end of the ATAG if it has none. A command line that is not valid UTF-8
+/// yields `Atag::Unknown(raw::Atag::CMDLINE)`.
+unsafe fn cmdline(dwords: u32, cmd: &'static raw::Cmd) -> Atag {
+    let max_len = (dwords as usize).saturating_sub(2) * 4;
+    let bytes = core::slice::from_raw_parts(&cmd.cmd as *const u8, max_len);
+    let len = bytes.iter().position(|&b| b == 0).unwrap_or(max_len);
+    match core::str::from_utf8(&bytes[..len]) {
+        Ok(cmd) => Atag::Cmd(cmd),
+        Err(_) => Atag::Unknown(raw::Atag::CMDLINE),
+    }
+}
@@ -13,9 +13,14 @@ pub struct Atags {
 
 impl Atags {
     /// Returns an instance of `Atags`, an iterator over ATAGS on this system.
+    ///
+    /// A valid list starts with a `CORE` ATAG. If the bootloader left none at
+    /// `ATAG_BASE`, e.g. because it passed a device tree instead, the
+    /// iterator is empty.
     pub fn get() -> Atags {
+        let first = unsafe { &*(ATAG_BASE as *const raw::Atag) };
         Atags {
-            ptr: Some(unsafe { &*(ATAG_BASE as *const raw::Atag) }),
+            ptr: if first.tag == raw::Atag::CORE { Some(first) } else { None },
         }
     }
 }
@@ -102,4 +107,45 @@ mod test {
         assert_eq!(atags.next(), None);
         assert_eq!(atags.next(), None);
     }
+
+    #[test]
+    fn test_find_mem() {
+        let atags = Atags {
+            ptr: Some(unsafe { &*(&MEM as *const u32 as *const raw::Atag) }),
+        };
+        // How the allocator finds the usable RAM, with `Atags::get()`.
+        let mem = atags.filter_map(Atag::mem).next();
+        assert_eq!(mem, Some(raw::Mem { size: 1234, start: 5678 }));
+    }
+
+    #[test]
+    fn test_cmdline() {
+        let mem: [u32; 12] = [
+            // CMDLINE that is not UTF-8
+            3,
+            raw::Atag::CMDLINE,
+            0x0000feff,
+            // CMDLINE filling the ATAG, without a NUL: "abcdefgh"
+            4,
+            raw::Atag::CMDLINE,
+            0x64636261,
+            0x68676665,
+            // Empty CMDLINE
+            3,
+            raw::Atag::CMDLINE,
+            0,
+            // NONE
+            2,
+            raw::Atag::NONE,
+        ];
+        let mut atags = Atags {
+            ptr: Some(unsafe { &*(&mem as *const u32 as *const raw::Atag) }),
+        };
+
+        assert_eq!(atags.next(), Some(Atag::Unknown(raw::Atag::CMDLINE)));
+        assert_eq!(atags.next(), Some(Atag::Cmd("abcdefgh")));
+        assert_eq!(atags.next(), Some(Atag::Cmd("")));
+        assert_eq!(atags.next(), Some(Atag::None));
+        assert_eq!(atags.next(), None);
+    }
 }
@@ -20,6 +20,7 @@ use tock_registers::{
 use core::fmt;
 
 use core::time::Duration;
+use shim::io;
 use crate::timer;
 use crate::common::{states, EMMC_START};
 
@@ -1002,6 +1003,50 @@ pub enum SdResult {
     NONE,
 }
 
+impl SdResult {
+    /// Returns the `io::ErrorKind` and message that `self` translates to.
+    ///
+    /// Timeouts map to `TimedOut`, a busy card to `WouldBlock`, a missing or
+    /// unresponsive card to `NotFound`/`NotConnected`, a failed data transfer
+    /// to `InvalidData`, and controller or card setup failures to `Other`.
+    pub fn io_error_kind(self) -> (io::ErrorKind, &'static str) {
+        use io::ErrorKind::*;
+        use SdResult::*;
+        match self {
+            EMMC_OK => (Other, "no error"),
+            EMMC_ERROR => (Other, "SD card error"),
+            EMMC_TIMEOUT => (TimedOut, "SD card timed out"),
+            EMMC_BUSY => (WouldBlock, "SD card is busy"),
+            EMMC_NO_RESP => (NotConnected, "SD card did not respond"),
+            EMMC_ERROR_RESET => (Other, "SD card did not reset"),
+            EMMC_ERROR_CLOCK => (Other, "SD card clock change failed"),
+            EMMC_ERROR_VOLTAGE => (Other, "SD card does not support the requested voltage"),
+            EMMC_ERROR_APP_CMD => (Other, "SD card app command failed"),
+            EMMC_CARD_ABSENT => (NotFound, "SD card not present"),
+            EMMC_READ_ERROR => (InvalidData, "SD card read failed"),
+            EMMC_MOUNT_FAIL => (Other, "SD card mount failed"),
+            EMMC_CARD_STATE(_) => (Other, "SD card in unexpected state"),
+            NONE => (Other, "no SD card result"),
+        }
+    }
+
+    /// Returns `Ok(())` for `EMMC_OK` and the corresponding `io::Error`
+    /// otherwise.
+    pub fn into_result(self) -> io::Result<()> {
+        match self {
+            SdResult::EMMC_OK => Ok(()),
+            err => Err(err.into()),
+        }
+    }
+}
+
+impl From<SdResult> for io::Error {
+    fn from(result: SdResult) -> io::Error {
+        let (kind, msg) = result.io_error_kind();
+        io::Error::new(kind, msg)
+    }
+}
+
 /*--------------------------------------------------------------------------
                     PUBLIC ENUMERATION OF SD CARD TYPE
 --------------------------------------------------------------------------*/
@@ -2927,3 +2972,40 @@ impl Debug for SCR::BUS_WIDTH::Value {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::SdResult::{self, *};
+    use shim::io::ErrorKind;
+
+    // Deliberately has no wildcard arm so that adding a variant to `SdResult`
+    // fails to compile until its mapping is decided here.
+    fn expected_kind(result: SdResult) -> ErrorKind {
+        match result {
+            EMMC_OK | EMMC_ERROR | EMMC_ERROR_RESET | EMMC_ERROR_CLOCK
+            | EMMC_ERROR_VOLTAGE | EMMC_ERROR_APP_CMD | EMMC_MOUNT_FAIL
+            | EMMC_CARD_STATE(_) | NONE => ErrorKind::Other,
+            EMMC_TIMEOUT => ErrorKind::TimedOut,
+            EMMC_BUSY => ErrorKind::WouldBlock,
+            EMMC_NO_RESP => ErrorKind::NotConnected,
+            EMMC_CARD_ABSENT => ErrorKind::NotFound,
+            EMMC_READ_ERROR => ErrorKind::InvalidData,
+        }
+    }
+
+    #[test]
+    fn sd_result_error_kinds() {
+        let all = [
+            EMMC_OK, EMMC_ERROR, EMMC_TIMEOUT, EMMC_BUSY, EMMC_NO_RESP,
+            EMMC_ERROR_RESET, EMMC_ERROR_CLOCK, EMMC_ERROR_VOLTAGE,
+            EMMC_ERROR_APP_CMD, EMMC_CARD_ABSENT, EMMC_READ_ERROR,
+            EMMC_MOUNT_FAIL, EMMC_CARD_STATE(3), NONE,
+        ];
+        for &result in all.iter() {
+            let err: shim::io::Error = result.into();
+            assert_eq!(err.kind(), expected_kind(result), "{:?}", result);
+        }
+        assert!(EMMC_OK.into_result().is_ok());
+        assert!(EMMC_TIMEOUT.into_result().is_err());
+    }
+}
@@ -101,7 +101,9 @@ impl Gpio<Uninitialized> {
     /// and returns a `Gpio` structure in the `Alt` state.
     pub fn into_alt(self, function: Function) -> Gpio<Alt> {
         let register  = &mut self.registers.FSEL[self.pin as usize / 10];
-        register.write(register.read() | ((function as u32) << (3 * (self.pin % 10))));
+        let shift = 3 * (self.pin % 10);
+        let cleared = register.read() & !(0b111 << shift);
+        register.write(cleared | ((function as u32) << shift));
         self.transition()
     }
 
@@ -11,19 +11,22 @@ pub enum Interrupt {
     Timer1 = 1,
     Timer3 = 3,
     Usb = 9,
+    /// The auxiliary peripherals, including the mini UART.
+    Aux = 29,
     Gpio0 = 49,
     Gpio1 = 50,
     Gpio2 = 51,
     Gpio3 = 52,
+    /// The PL011 UART.
     Uart = 57,
 }
 
 impl Interrupt {
-    pub const MAX: usize = 8;
+    pub const MAX: usize = 9;
 
     pub fn iter() -> impl Iterator<Item = Interrupt> {
         use Interrupt::*;
-        [Timer1, Timer3, Usb, Gpio0, Gpio1, Gpio2, Gpio3, Uart]
+        [Timer1, Timer3, Usb, Aux, Gpio0, Gpio1, Gpio2, Gpio3, Uart]
             .iter()
             .map(|int| *int)
     }
@@ -36,6 +39,7 @@ impl From<usize> for Interrupt {
             1 => Timer1,
             3 => Timer3,
             9 => Usb,
+            29 => Aux,
             49 => Gpio0,
             50 => Gpio1,
             51 => Gpio2,
@@ -60,8 +64,28 @@ struct Registers {
     IRQ0_CLR_EN_0: Volatile<u32>,
     IRQ0_CLR_EN_1: Volatile<u32>,
     IRQ0_CLR_EN_2: Volatile<u32>,
+    _res_2: Volatile<u32>,
+    IRQ_STATUS0: Volatile<u32>,
+    IRQ_STATUS1: Volatile<u32>,
+    IRQ_STATUS2: Volatile<u32>,
+    _res_3: Volatile<u32>,
+    // The IRQ registers of cores 1 to 3, which are not used.
+    _res_4: [Volatile<u32>; 48],
+    FIQ0_PENDING0: Volatile<u32>,
+    FIQ0_PENDING1: Volatile<u32>,
+    FIQ0_PENDING2: Volatile<u32>,
+    _res_5: Volatile<u32>,
+    FIQ0_SET_EN_0: Volatile<u32>,
+    FIQ0_SET_EN_1: Volatile<u32>,
+    FIQ0_SET_EN_2: Volatile<u32>,
+    _res_6: Volatile<u32>,
+    FIQ0_CLR_EN_0: Volatile<u32>,
+    FIQ0_CLR_EN_1: Volatile<u32>,
+    FIQ0_CLR_EN_2: Volatile<u32>,
 }
 
+const_assert_size!(Registers, 0x7E00B32C - 0x7E00B200);
+
 /// An interrupt controller. Used to enable and disable interrupts as well as to
 /// check if an interrupt is pending.
 pub struct Controller {
@@ -106,9 +130,15 @@ impl Controller {
         }
     }
 
-    /// Enables the interrupt as FIQ interrupt
+    /// Enables the interrupt `int` as an FIQ for core 0. It is disabled as an
+    /// IRQ first, so that it is not handled twice.
     pub fn enable_fiq(&mut self, int: Interrupt) {
-        // Lab 5 2.B
-        unimplemented!("enable_fiq")
+        self.disable(int);
+        let irq = int as u32;
+        if irq < 32 {
+            self.registers.FIQ0_SET_EN_0.write(1 << irq);
+        } else {
+            self.registers.FIQ0_SET_EN_1.write(1 << (irq - 32));
+        }
     }
 }
@@ -17,6 +17,9 @@ pub mod common;
 pub mod gpio;
 pub mod interrupt;
 pub mod local_interrupt;
+pub mod mailbox;
+pub mod power;
+pub mod pwm;
 pub mod timer;
 pub mod uart;
 pub mod armlocal;
@@ -1,6 +1,7 @@
 
+use core::sync::atomic::{AtomicU64, Ordering};
 use core::time::Duration;
-use aarch64::{CNTFRQ_EL0, CNTP_CTL_EL0, CNTP_TVAL_EL0};
+use aarch64::{CNTFRQ_EL0, CNTPCT_EL0, CNTP_CTL_EL0, CNTP_CVAL_EL0, CNTP_TVAL_EL0};
 use shim::const_assert_size;
 
 use volatile::prelude::*;
@@ -79,6 +80,32 @@ struct Registers {
 }
 const_assert_size!(Registers, 0x80);
 
+/// The period of each core's local timer set by `tick_every`, in counter
+/// ticks, or 0 if the core's timer is not periodic.
+static PERIODS: [AtomicU64; 4] = [
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+];
+
+/// Converts `t` to ticks of a counter running at `freq` Hz.
+fn to_ticks(freq: u64, t: Duration) -> u64 {
+    (freq as u128 * t.as_micros() / 1000000) as u64
+}
+
+/// Returns the deadline one `period` after `previous`. If that has already
+/// passed at `now`, the ticks in between are skipped and the deadline is a
+/// full period from `now` instead.
+fn next_deadline(previous: u64, period: u64, now: u64) -> u64 {
+    let next = previous.wrapping_add(period);
+    if next <= now {
+        now + period
+    } else {
+        next
+    }
+}
+
 pub struct LocalController {
     core: usize,
     registers: &'static mut Registers,
@@ -109,11 +136,70 @@ impl LocalController {
 
     pub fn tick_in(&mut self, t: Duration) {
         let freq = unsafe { CNTFRQ_EL0.get() };
-        let ticks = freq as u128 * t.as_micros() / 1000000;
-        unsafe { CNTP_TVAL_EL0.set(ticks as u64) };
+        unsafe { CNTP_TVAL_EL0.set(to_ticks(freq, t)) };
+    }
+
+    /// Enables the local timer of this core and has it fire every `t`. The
+    /// generic timer has no auto-reload, so the interrupt handler must call
+    /// `rearm` to schedule each following tick.
+    pub fn tick_every(&mut self, t: Duration) {
+        let freq = unsafe { CNTFRQ_EL0.get() };
+        let ticks = to_ticks(freq, t);
+        PERIODS[self.core].store(ticks, Ordering::Relaxed);
+        self.enable_local_timer();
+        unsafe { CNTP_TVAL_EL0.set(ticks) };
+    }
+
+    /// Schedules the next tick of the timer set up by `tick_every`, one
+    /// period after the previous deadline, so that the period does not drift
+    /// with the handler's latency. Does nothing unless `tick_every` was
+    /// called since the timer was last disabled.
+    pub fn rearm(&mut self) {
+        let period = PERIODS[self.core].load(Ordering::Relaxed);
+        if period == 0 {
+            return;
+        }
+        unsafe {
+            let deadline = next_deadline(CNTP_CVAL_EL0.get(), period, CNTPCT_EL0.get());
+            CNTP_CVAL_EL0.set(deadline);
+        }
+    }
+
+    /// Stops the local timer of this core and masks its interrupt, e.g. to
+    /// quiesce the core. Also ends a period set by `tick_every`.
+    /// `enable_local_timer` turns it back on.
+    pub fn disable_local_timer(&mut self) {
+        PERIODS[self.core].store(0, Ordering::Relaxed);
+        self.registers.TIMER_CNTRL[self.core].write(0);
+        unsafe {
+            CNTP_CTL_EL0.set(CNTP_CTL_EL0.get() | CNTP_CTL_EL0::IMASK);
+            CNTP_CTL_EL0.set(CNTP_CTL_EL0.get() & !CNTP_CTL_EL0::ENABLE);
+        }
     }
 }
 
 pub fn local_tick_in(core: usize, t: Duration) {
     LocalController::new(core).tick_in(t);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{next_deadline, to_ticks};
+    use core::time::Duration;
+
+    #[test]
+    fn ticks() {
+        assert_eq!(to_ticks(54_000_000, Duration::from_millis(10)), 540_000);
+        assert_eq!(to_ticks(54_000_000, Duration::from_micros(1)), 54);
+        assert_eq!(to_ticks(54_000_000, Duration::from_nanos(999)), 0);
+    }
+
+    #[test]
+    fn deadlines() {
+        // On time: the next deadline is a period after the previous one.
+        assert_eq!(next_deadline(1000, 100, 1010), 1100);
+        // Late by more than a period: skip ahead from now.
+        assert_eq!(next_deadline(1000, 100, 1100), 1200);
+        assert_eq!(next_deadline(1000, 100, 1350), 1450);
+    }
+}
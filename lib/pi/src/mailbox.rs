@@ -0,0 +1,264 @@
+use core::mem;
+use core::sync::atomic::{compiler_fence, Ordering};
+
+use aarch64::{clean_dcache_range, invalidate_dcache_range};
+
+use crate::common::IO_BASE;
+use volatile::prelude::*;
+use volatile::{ReadVolatile, Reserved, Volatile, WriteVolatile};
+
+/// The base address of the VideoCore mailbox registers.
+const MBOX_BASE: usize = IO_BASE + 0xB880;
+
+/// Status: the mailbox cannot accept another message.
+const MBOX_FULL: u32 = 1 << 31;
+/// Status: there is no message to read.
+const MBOX_EMPTY: u32 = 1 << 30;
+
+/// The channel carrying property tags from the ARM to the VideoCore.
+const PROPERTY_CHANNEL: u32 = 8;
+
+/// Buffer code of a request.
+const CODE_REQUEST: u32 = 0;
+/// Buffer code of a successfully processed request.
+const CODE_RESPONSE_OK: u32 = 0x8000_0000;
+/// Set in a tag's request/response code when the firmware has filled it in.
+const TAG_RESPONSE: u32 = 1 << 31;
+/// The tag ending a property buffer.
+const TAG_END: u32 = 0;
+
+/// Tag: board revision, one word.
+pub const TAG_GET_BOARD_REVISION: u32 = 0x0001_0002;
+/// Tag: MAC address, six bytes.
+pub const TAG_GET_MAC_ADDRESS: u32 = 0x0001_0003;
+/// Tag: base address and size of the ARM's memory, two words.
+pub const TAG_GET_ARM_MEMORY: u32 = 0x0001_0005;
+
+/// The number of words in a `PropertyBuffer`.
+const BUFFER_WORDS: usize = 32;
+/// The number of words before a tag's value: size, code, tag, value size and
+/// request/response code.
+const HEADER_WORDS: usize = 5;
+/// The largest tag value that fits in a `PropertyBuffer`, in words.
+pub const MAX_VALUE_WORDS: usize = BUFFER_WORDS - HEADER_WORDS - 1;
+
+#[repr(C)]
+#[allow(non_snake_case)]
+struct Registers {
+    READ: ReadVolatile<u32>,
+    __r0: [Reserved<u32>; 5],
+    STATUS: ReadVolatile<u32>,
+    __r1: Reserved<u32>,
+    WRITE: WriteVolatile<u32>,
+}
+
+/// A property channel request holding a single tag. The firmware writes its
+/// response over the request.
+///
+/// The mailbox passes the buffer address in the upper 28 bits of a message,
+/// so the buffer must be 16-byte aligned. It is aligned to a whole cache line
+/// instead, so that the cache maintenance in `Mailbox::call` touches nothing
+/// but the buffer.
+#[repr(C, align(64))]
+#[derive(Debug, Clone)]
+pub struct PropertyBuffer([u32; BUFFER_WORDS]);
+
+impl PropertyBuffer {
+    /// Returns a request for `tag` with room for a `value_words`-word value.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `value_words` is greater than `MAX_VALUE_WORDS`.
+    pub fn new(tag: u32, value_words: usize) -> PropertyBuffer {
+        if value_words > MAX_VALUE_WORDS {
+            panic!("PropertyBuffer::new(): {} value words do not fit", value_words);
+        }
+
+        let mut words = [0u32; BUFFER_WORDS];
+        let len = HEADER_WORDS + value_words + 1;
+        words[0] = (len * 4) as u32;
+        words[1] = CODE_REQUEST;
+        words[2] = tag;
+        words[3] = (value_words * 4) as u32;
+        words[4] = 0;
+        words[len - 1] = TAG_END;
+        PropertyBuffer(words)
+    }
+
+    /// Returns the words of the buffer, including the unused ones at the end.
+    pub fn words(&self) -> &[u32] {
+        &self.0
+    }
+
+    /// Returns the words of the buffer for the firmware to fill in.
+    pub fn words_mut(&mut self) -> &mut [u32] {
+        &mut self.0
+    }
+
+    /// Returns the value words of the response, or `None` if the firmware
+    /// did not process the request or the tag.
+    pub fn response(&self) -> Option<&[u32]> {
+        if self.0[1] != CODE_RESPONSE_OK || self.0[4] & TAG_RESPONSE == 0 {
+            return None;
+        }
+        let value_words = self.0[3] as usize / 4;
+        Some(&self.0[HEADER_WORDS..HEADER_WORDS + value_words])
+    }
+}
+
+/// Something that can pass a `PropertyBuffer` to the firmware.
+pub trait PropertyChannel {
+    /// Sends `buffer` and returns once the firmware has written its response
+    /// into it.
+    fn call(&mut self, buffer: &mut PropertyBuffer);
+}
+
+/// The VideoCore mailbox.
+pub struct Mailbox {
+    registers: &'static mut Registers,
+}
+
+impl Mailbox {
+    /// Returns a handle to the mailbox.
+    pub fn new() -> Mailbox {
+        Mailbox {
+            registers: unsafe { &mut *(MBOX_BASE as *mut Registers) },
+        }
+    }
+}
+
+impl PropertyChannel for Mailbox {
+    fn call(&mut self, buffer: &mut PropertyBuffer) {
+        let message = (buffer as *mut PropertyBuffer as usize as u32 & !0xF) | PROPERTY_CHANNEL;
+
+        let (start, len) = (buffer as *mut PropertyBuffer as usize, mem::size_of::<PropertyBuffer>());
+
+        // The firmware reads and writes the buffer in memory, behind the
+        // compiler's back and bypassing the data cache: write the request out
+        // before sending it, and drop stale lines before reading the response.
+        compiler_fence(Ordering::SeqCst);
+        unsafe { clean_dcache_range(start, len) };
+        while self.registers.STATUS.read() & MBOX_FULL != 0 {}
+        self.registers.WRITE.write(message);
+
+        loop {
+            while self.registers.STATUS.read() & MBOX_EMPTY != 0 {}
+            if self.registers.READ.read() == message {
+                break;
+            }
+        }
+        unsafe { invalidate_dcache_range(start, len) };
+        compiler_fence(Ordering::SeqCst);
+    }
+}
+
+/// Sends a request for `tag` with a `value_words`-word value and returns the
+/// answered buffer, or `None` if the firmware did not answer the tag.
+fn query<C: PropertyChannel>(channel: &mut C, tag: u32, value_words: usize) -> Option<PropertyBuffer> {
+    let mut buffer = PropertyBuffer::new(tag, value_words);
+    channel.call(&mut buffer);
+    if buffer.response().is_some() {
+        Some(buffer)
+    } else {
+        None
+    }
+}
+
+/// Returns the board revision code.
+pub fn get_board_revision<C: PropertyChannel>(channel: &mut C) -> Option<u32> {
+    let buffer = query(channel, TAG_GET_BOARD_REVISION, 1)?;
+    buffer.response().map(|value| value[0])
+}
+
+/// Returns the MAC address of the on-board ethernet controller.
+pub fn get_mac_address<C: PropertyChannel>(channel: &mut C) -> Option<[u8; 6]> {
+    let buffer = query(channel, TAG_GET_MAC_ADDRESS, 2)?;
+    let value = buffer.response()?;
+    let (low, high) = (value[0].to_le_bytes(), value[1].to_le_bytes());
+    Some([low[0], low[1], low[2], low[3], high[0], high[1]])
+}
+
+/// Returns the base address and size in bytes of the memory available to the
+/// ARM.
+pub fn get_arm_memory<C: PropertyChannel>(channel: &mut C) -> Option<(u32, u32)> {
+    let buffer = query(channel, TAG_GET_ARM_MEMORY, 2)?;
+    buffer.response().map(|value| (value[0], value[1]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Answers requests for one tag with a fixed value and records what was
+    /// asked.
+    struct MockMailbox {
+        tag: u32,
+        value: [u32; 2],
+        requests: usize,
+    }
+
+    impl PropertyChannel for MockMailbox {
+        fn call(&mut self, buffer: &mut PropertyBuffer) {
+            self.requests += 1;
+            let words = buffer.words_mut();
+            if words[2] != self.tag {
+                // Unknown tags are left untouched, but the buffer is processed.
+                words[1] = CODE_RESPONSE_OK;
+                return;
+            }
+            let value_words = words[3] as usize / 4;
+            words[1] = CODE_RESPONSE_OK;
+            words[4] = TAG_RESPONSE | words[3];
+            words[HEADER_WORDS..HEADER_WORDS + value_words].copy_from_slice(&self.value[..value_words]);
+        }
+    }
+
+    #[test]
+    fn request_layout() {
+        let buffer = PropertyBuffer::new(TAG_GET_ARM_MEMORY, 2);
+        assert_eq!(&buffer.words()[..8], &[32, 0, 0x0001_0005, 8, 0, 0, 0, 0]);
+        assert_eq!(buffer.response(), None);
+        // At least 16-byte aligned for the mailbox, and whole cache lines.
+        assert_eq!(core::mem::align_of::<PropertyBuffer>(), aarch64::CACHE_LINE_SIZE);
+        assert_eq!(core::mem::size_of::<PropertyBuffer>() % aarch64::CACHE_LINE_SIZE, 0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn oversized_request() {
+        PropertyBuffer::new(TAG_GET_ARM_MEMORY, MAX_VALUE_WORDS + 1);
+    }
+
+    #[test]
+    fn board_revision() {
+        let mut mailbox = MockMailbox { tag: TAG_GET_BOARD_REVISION, value: [0x00c0_3111, 0], requests: 0 };
+        assert_eq!(get_board_revision(&mut mailbox), Some(0x00c0_3111));
+        assert_eq!(mailbox.requests, 1);
+    }
+
+    #[test]
+    fn mac_address() {
+        let mut mailbox = MockMailbox { tag: TAG_GET_MAC_ADDRESS, value: [0x27_eb_a6_dc, 0x00_00_3e_4c], requests: 0 };
+        assert_eq!(get_mac_address(&mut mailbox), Some([0xdc, 0xa6, 0xeb, 0x27, 0x4c, 0x3e]));
+    }
+
+    #[test]
+    fn arm_memory() {
+        let mut mailbox = MockMailbox { tag: TAG_GET_ARM_MEMORY, value: [0, 0x3b40_0000], requests: 0 };
+        assert_eq!(get_arm_memory(&mut mailbox), Some((0, 0x3b40_0000)));
+    }
+
+    #[test]
+    fn unanswered_tag() {
+        let mut mailbox = MockMailbox { tag: TAG_GET_ARM_MEMORY, value: [0, 0], requests: 0 };
+        assert_eq!(get_board_revision(&mut mailbox), None);
+    }
+
+    #[test]
+    fn failed_request() {
+        let mut buffer = PropertyBuffer::new(TAG_GET_BOARD_REVISION, 1);
+        buffer.words_mut()[1] = 0x8000_0001;
+        buffer.words_mut()[4] = TAG_RESPONSE | 4;
+        assert_eq!(buffer.response(), None);
+    }
+}
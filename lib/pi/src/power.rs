@@ -0,0 +1,94 @@
+//! Resetting the board through the power management watchdog.
+//!
+//! Before resetting, the number of the partition to boot from next can be
+//! stored in the `PM_RSTS` register. The firmware reads it back on the next
+//! boot; this is what `reboot N` does on Linux for the Pi. Partition 0 is a
+//! normal boot and partition 63 makes the firmware halt instead of booting.
+//! What other partitions mean is up to the firmware and its configuration.
+//!
+//! `PM_RSTS` holds the six partition bits at its even bit positions 0, 2, 4,
+//! 6, 8 and 10: bit `i` of the partition number is stored in bit `2 * i` of
+//! the register. Halting (partition 63) therefore sets `0x555`.
+
+use volatile::prelude::*;
+use volatile::Volatile;
+
+use crate::common::IO_BASE;
+
+const PM_BASE: usize = IO_BASE + 0x10_0000;
+
+const PM_RSTC: *mut Volatile<u32> = (PM_BASE + 0x1c) as *mut Volatile<u32>;
+const PM_RSTS: *mut Volatile<u32> = (PM_BASE + 0x20) as *mut Volatile<u32>;
+const PM_WDOG: *mut Volatile<u32> = (PM_BASE + 0x24) as *mut Volatile<u32>;
+
+/// Writes to the power management registers are ignored without this in the
+/// top byte.
+const PM_PASSWORD: u32 = 0x5a00_0000;
+
+const PM_RSTC_WRCFG_CLR: u32 = 0xffff_ffcf;
+const PM_RSTC_WRCFG_FULL_RESET: u32 = 0x0000_0020;
+
+/// Clears the partition bits of `PM_RSTS`.
+const PM_RSTS_PARTITION_CLR: u32 = 0xffff_faaa;
+
+/// Watchdog ticks until the reset, about 150us.
+const RESET_TICKS: u32 = 10;
+
+/// The highest partition number `PM_RSTS` can hold.
+pub const MAX_PARTITION: u8 = 63;
+
+/// The partition that makes the firmware halt instead of booting.
+pub const HALT_PARTITION: u8 = 63;
+
+/// Spreads the bits of `partition` over the even bit positions of `PM_RSTS`.
+/// Bits above `MAX_PARTITION` are ignored.
+pub fn partition_bits(partition: u8) -> u32 {
+    (0..6)
+        .filter(|i| partition & (1 << i) != 0)
+        .fold(0, |bits, i| bits | 1 << (2 * i))
+}
+
+/// Returns the value to write to `PM_RSTS` to boot `partition` next, keeping
+/// the other bits of the current value `rsts`.
+pub fn rsts_value(rsts: u32, partition: u8) -> u32 {
+    PM_PASSWORD | (rsts & PM_RSTS_PARTITION_CLR) | partition_bits(partition)
+}
+
+/// Resets the board through the watchdog. The firmware boots `partition`
+/// next; pass 0 for a normal restart.
+pub fn reset(partition: u8) -> ! {
+    unsafe {
+        let rsts = (*PM_RSTS).read();
+        (*PM_RSTS).write(rsts_value(rsts, partition));
+
+        (*PM_WDOG).write(PM_PASSWORD | RESET_TICKS);
+        let rstc = (*PM_RSTC).read();
+        (*PM_RSTC).write(PM_PASSWORD | (rstc & PM_RSTC_WRCFG_CLR) | PM_RSTC_WRCFG_FULL_RESET);
+    }
+    loop {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{partition_bits, rsts_value, HALT_PARTITION, MAX_PARTITION};
+
+    #[test]
+    fn partition_encoding() {
+        assert_eq!(partition_bits(0), 0);
+        assert_eq!(partition_bits(1), 0b1);
+        assert_eq!(partition_bits(2), 0b100);
+        assert_eq!(partition_bits(3), 0b101);
+        assert_eq!(partition_bits(0b10_0000), 1 << 10);
+        assert_eq!(partition_bits(HALT_PARTITION), 0x555);
+        assert_eq!(partition_bits(MAX_PARTITION + 1), 0);
+    }
+
+    #[test]
+    fn rsts_keeps_other_bits() {
+        assert_eq!(rsts_value(0, 0), 0x5a00_0000);
+        assert_eq!(rsts_value(0, 5), 0x5a00_0011);
+        // Old partition bits are cleared, odd bits are kept.
+        assert_eq!(rsts_value(0x0000_1555, 2), 0x5a00_1004);
+        assert_eq!(rsts_value(0x0000_0aaa, HALT_PARTITION), 0x5a00_0fff);
+    }
+}
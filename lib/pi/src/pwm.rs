@@ -0,0 +1,243 @@
+use crate::common::IO_BASE;
+use crate::gpio::{Alt, Function, Gpio};
+use volatile::prelude::*;
+use volatile::{Reserved, Volatile};
+
+/// The base address of the `PWM0` registers.
+const PWM_BASE: usize = IO_BASE + 0x20C000;
+
+/// The address of the PWM clock control and divisor registers in the clock
+/// manager.
+const CM_PWM_BASE: usize = IO_BASE + 0x101000 + 0xA0;
+
+/// Every write to a clock manager register must carry this password.
+const CM_PASSWORD: u32 = 0x5A << 24;
+
+/// Clock manager control: clock source is the 54 MHz oscillator.
+const CM_SRC_OSC: u32 = 1;
+/// Clock manager control: enable the clock generator.
+const CM_ENAB: u32 = 1 << 4;
+/// Clock manager control: the clock generator is running.
+const CM_BUSY: u32 = 1 << 7;
+
+/// The largest integer divisor the clock manager accepts.
+pub const MAX_DIVISOR: u32 = 0xFFF;
+/// The smallest integer divisor the clock manager accepts.
+pub const MIN_DIVISOR: u32 = 2;
+/// The divisor used by `Pwm::new`: 54 MHz / 54 = 1 MHz.
+pub const DEFAULT_DIVISOR: u32 = 54;
+
+/// Channel control: enable the channel.
+const CTL_PWEN: u32 = 1 << 0;
+/// Channel control: use mark-space mode so that the output is high for `data`
+/// cycles out of every `range`.
+const CTL_MSEN: u32 = 1 << 7;
+
+#[repr(C)]
+#[allow(non_snake_case)]
+struct Registers {
+    CTL: Volatile<u32>,
+    STA: Volatile<u32>,
+    DMAC: Volatile<u32>,
+    __r0: Reserved<u32>,
+    RNG1: Volatile<u32>,
+    DAT1: Volatile<u32>,
+    FIF1: Volatile<u32>,
+    __r1: Reserved<u32>,
+    RNG2: Volatile<u32>,
+    DAT2: Volatile<u32>,
+}
+
+#[repr(C)]
+#[allow(non_snake_case)]
+struct ClockRegisters {
+    CTL: Volatile<u32>,
+    DIV: Volatile<u32>,
+}
+
+/// A channel of the `PWM0` peripheral.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    /// Channel 1, output on GPIO 18.
+    One,
+    /// Channel 2, output on GPIO 19.
+    Two,
+}
+
+impl Channel {
+    /// Returns the GPIO pin and alternative function that route this channel
+    /// to the header.
+    fn pin(self) -> (u8, Function) {
+        match self {
+            Channel::One => (18, Function::Alt5),
+            Channel::Two => (19, Function::Alt5),
+        }
+    }
+
+    /// Returns the offset of this channel's bits in the `CTL` register.
+    fn ctl_shift(self) -> u32 {
+        match self {
+            Channel::One => 0,
+            Channel::Two => 8,
+        }
+    }
+
+    /// Returns the `CTL` bits that enable this channel in mark-space mode.
+    fn enable_bits(self) -> u32 {
+        (CTL_PWEN | CTL_MSEN) << self.ctl_shift()
+    }
+}
+
+/// Returns the value of the clock manager's divisor register for an integer
+/// divisor of `divisor`, or `None` if `divisor` is out of range.
+fn divisor_value(divisor: u32) -> Option<u32> {
+    if divisor < MIN_DIVISOR || divisor > MAX_DIVISOR {
+        return None;
+    }
+    Some(CM_PASSWORD | (divisor << 12))
+}
+
+/// Returns the value of a channel's range register for a period of `range`
+/// clock cycles, or `None` if `range` is `0`, which the hardware cannot count.
+fn range_value(range: u32) -> Option<u32> {
+    if range == 0 {
+        return None;
+    }
+    Some(range)
+}
+
+/// A PWM output on one channel of the `PWM0` peripheral.
+///
+/// The output is high for `data` out of every `range` cycles of the PWM
+/// clock, so the duty cycle is `data / range`.
+pub struct Pwm {
+    channel: Channel,
+    registers: &'static mut Registers,
+    clock: &'static mut ClockRegisters,
+    _pin: Gpio<Alt>,
+}
+
+impl Pwm {
+    /// Routes `channel` to its GPIO pin and starts the PWM clock with
+    /// `DEFAULT_DIVISOR`. The channel is left disabled with a range of `100`
+    /// and data of `0`.
+    pub fn new(channel: Channel) -> Pwm {
+        let (pin, function) = channel.pin();
+        let mut pwm = Pwm {
+            channel,
+            registers: unsafe { &mut *(PWM_BASE as *mut Registers) },
+            clock: unsafe { &mut *(CM_PWM_BASE as *mut ClockRegisters) },
+            _pin: Gpio::new(pin).into_alt(function),
+        };
+
+        pwm.disable();
+        pwm.set_clock_divisor(DEFAULT_DIVISOR);
+        pwm.set_range(100);
+        pwm.set_data(0);
+        pwm
+    }
+
+    /// Sets the integer divisor of the PWM clock. The clock is shared by both
+    /// channels.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `divisor` is not in `MIN_DIVISOR..=MAX_DIVISOR`.
+    pub fn set_clock_divisor(&mut self, divisor: u32) {
+        let value = match divisor_value(divisor) {
+            Some(value) => value,
+            None => panic!("Pwm::set_clock_divisor(): divisor {} out of range", divisor),
+        };
+
+        // The divisor may only be changed while the clock is stopped.
+        self.clock.CTL.write(CM_PASSWORD | CM_SRC_OSC);
+        while self.clock.CTL.read() & CM_BUSY != 0 {}
+
+        self.clock.DIV.write(value);
+        self.clock.CTL.write(CM_PASSWORD | CM_SRC_OSC | CM_ENAB);
+    }
+
+    /// Sets the number of clock cycles in one PWM period.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `range` is `0`.
+    pub fn set_range(&mut self, range: u32) {
+        let value = match range_value(range) {
+            Some(value) => value,
+            None => panic!("Pwm::set_range(): range must be non-zero"),
+        };
+
+        match self.channel {
+            Channel::One => self.registers.RNG1.write(value),
+            Channel::Two => self.registers.RNG2.write(value),
+        }
+    }
+
+    /// Sets the number of clock cycles per period for which the output is
+    /// high. Values greater than the range keep the output high.
+    pub fn set_data(&mut self, data: u32) {
+        match self.channel {
+            Channel::One => self.registers.DAT1.write(data),
+            Channel::Two => self.registers.DAT2.write(data),
+        }
+    }
+
+    /// Starts driving the output.
+    pub fn enable(&mut self) {
+        let ctl = self.registers.CTL.read();
+        self.registers.CTL.write(ctl | self.channel.enable_bits());
+    }
+
+    /// Stops driving the output.
+    pub fn disable(&mut self) {
+        let ctl = self.registers.CTL.read();
+        self.registers.CTL.write(ctl & !(0xFF << self.channel.ctl_shift()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn divisor_encoding() {
+        assert_eq!(divisor_value(DEFAULT_DIVISOR), Some(0x5A03_6000));
+        assert_eq!(divisor_value(MIN_DIVISOR), Some(0x5A00_2000));
+        assert_eq!(divisor_value(MAX_DIVISOR), Some(0x5AFF_F000));
+        assert_eq!(divisor_value(1), None);
+        assert_eq!(divisor_value(MAX_DIVISOR + 1), None);
+    }
+
+    #[test]
+    fn range_encoding() {
+        assert_eq!(range_value(100), Some(100));
+        assert_eq!(range_value(1), Some(1));
+        assert_eq!(range_value(u32::max_value()), Some(u32::max_value()));
+        assert_eq!(range_value(0), None);
+    }
+
+    #[test]
+    fn range_and_data_registers() {
+        // Offsets from the BCM2711 peripherals documentation.
+        let registers: Registers = unsafe { core::mem::zeroed() };
+        let base = &registers as *const Registers as usize;
+        let offset = |register: &Volatile<u32>| register as *const Volatile<u32> as usize - base;
+        assert_eq!(offset(&registers.RNG1), 0x10);
+        assert_eq!(offset(&registers.DAT1), 0x14);
+        assert_eq!(offset(&registers.RNG2), 0x20);
+        assert_eq!(offset(&registers.DAT2), 0x24);
+    }
+
+    #[test]
+    fn enable_bits() {
+        assert_eq!(Channel::One.enable_bits(), 0x0081);
+        assert_eq!(Channel::Two.enable_bits(), 0x8100);
+    }
+
+    #[test]
+    fn pins() {
+        assert_eq!(Channel::One.pin().0, 18);
+        assert_eq!(Channel::Two.pin().0, 19);
+    }
+}
@@ -7,6 +7,42 @@ use volatile::{ReadVolatile, Volatile};
 /// The base address for the ARM system timer registers.
 const TIMER_REG_BASE: usize = IO_BASE + 0x3000;
 
+/// The longest duration `tick_in` can schedule, about 71.6 minutes. The
+/// compare registers match against the low 32 bits of the microsecond
+/// counter, so a match can be at most `u32::MAX` microseconds away.
+pub const MAX_TICK: Duration = Duration::from_micros(core::u32::MAX as u64);
+
+/// Returns the compare register value that matches `t` after the counter
+/// reads `clo`. `t` is clamped to `MAX_TICK`; the sum wraps like the counter.
+fn compare_value(clo: u32, t: Duration) -> u32 {
+    let micros = core::cmp::min(t.as_micros(), MAX_TICK.as_micros()) as u32;
+    clo.wrapping_add(micros)
+}
+
+/// The compare channels the VideoCore firmware uses for itself. Programming
+/// them disturbs the GPU, so `Timer::tick_in_channel` refuses to.
+pub const VIDEOCORE_CHANNELS: [usize; 2] = [0, 2];
+
+/// An error returned by `Timer::tick_in_channel` and `Timer::ack_channel`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ChannelError {
+    /// The channel is used by the VideoCore. See `VIDEOCORE_CHANNELS`.
+    Reserved(usize),
+    /// There is no such channel. The timer has channels 0 to 3.
+    NoSuchChannel(usize),
+}
+
+/// Checks that `channel` is a compare channel the ARM may use.
+fn check_channel(channel: usize) -> Result<(), ChannelError> {
+    if channel > 3 {
+        Err(ChannelError::NoSuchChannel(channel))
+    } else if VIDEOCORE_CHANNELS.contains(&channel) {
+        Err(ChannelError::Reserved(channel))
+    } else {
+        Ok(())
+    }
+}
+
 #[repr(C)]
 #[allow(non_snake_case)]
 struct Registers {
@@ -32,28 +68,48 @@ impl Timer {
     /// Reads the system timer's counter and returns Duration.
     /// `CLO` and `CHI` together can represent the number of elapsed microseconds.
     pub fn read(&self) -> Duration {
-        let clo = self.registers.CLO.read();
-        let chi = self.registers.CHI.read();
-        let sec: u64 = ((chi as u64) << 32) + clo as u64;
-        Duration::from_micros(sec)
+        Duration::from_micros(self.micros())
+    }
+
+    /// Returns the number of microseconds the counter has counted.
+    fn micros(&self) -> u64 {
+        read_counter(|| self.registers.CHI.read(), || self.registers.CLO.read())
     }
 
     /// Sets up a match in timer 1 to occur `t` duration from now. If
     /// interrupts for timer 1 are enabled and IRQs are unmasked, then a timer
-    /// interrupt will be issued in `t` duration.
+    /// interrupt will be issued in `t` duration. Durations longer than
+    /// `MAX_TICK` are clamped to `MAX_TICK`.
     pub fn tick_in(&mut self, t: Duration) {
-        let cs = &mut self.registers.CS;
-        let register = &mut self.registers.COMPARE[1];
+        self.tick_in_channel(1, t).expect("channel 1 is free");
+    }
 
+    /// Like `tick_in`, but sets up the match in compare channel `channel`,
+    /// which raises the `Timer<channel>` interrupt.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Reserved` for the channels the VideoCore uses (0 and 2) and
+    /// `NoSuchChannel` for channels above 3. The timer is left untouched.
+    pub fn tick_in_channel(&mut self, channel: usize, t: Duration) -> Result<(), ChannelError> {
+        check_channel(channel)?;
         let clo = self.registers.CLO.read();
-        let micros = t.as_micros() as u32;
-        cs.write(0b10);
-        register.write(clo + micros);
+        self.registers.CS.write(1 << channel);
+        self.registers.COMPARE[channel].write(compare_value(clo, t));
+        Ok(())
     }
 
+    /// Acknowledges a match in timer 1, clearing its interrupt.
     pub fn ack(&mut self) {
-        let cs = &mut self.registers.CS;
-        cs.write(0b10);
+        self.ack_channel(1).expect("channel 1 is free");
+    }
+
+    /// Acknowledges a match in compare channel `channel`, clearing its
+    /// interrupt. Fails like `tick_in_channel`.
+    pub fn ack_channel(&mut self, channel: usize) -> Result<(), ChannelError> {
+        check_channel(channel)?;
+        self.registers.CS.write(1 << channel);
+        Ok(())
     }
 }
 
@@ -62,17 +118,45 @@ pub fn current_time() -> Duration {
     Timer::new().read()
 }
 
-/// Spins until `t` duration have passed.
+/// Combines the halves of the 64-bit counter read with `read_chi` and
+/// `read_clo`. If `CLO` rolls over between the reads, `CHI` changes, and the
+/// halves are read again so that they belong together.
+fn read_counter<H: FnMut() -> u32, L: FnMut() -> u32>(mut read_chi: H, mut read_clo: L) -> u64 {
+    loop {
+        let chi = read_chi();
+        let clo = read_clo();
+        if read_chi() == chi {
+            return ((chi as u64) << 32) | clo as u64;
+        }
+    }
+}
+
+/// Spins until at least `t` duration have passed. Returns at once if `t` is
+/// shorter than the counter's resolution of one microsecond.
 pub fn spin_sleep(t: Duration) {
     let timer = Timer::new();
-    let current_time = timer.read();
-    let target_time = current_time.checked_add(t).expect("Duration addition failed");
-    while timer.read() <= target_time {}
+    spin_micros(t, || timer.micros());
+}
+
+/// Spins until the microsecond counter read with `now` shows that at least
+/// `t` has passed.
+fn spin_micros<F: FnMut() -> u64>(t: Duration, mut now: F) {
+    let micros = core::cmp::min(t.as_micros(), core::u64::MAX as u128 - 1) as u64;
+    if micros == 0 {
+        return;
+    }
+
+    // The first reading may come just before the counter ticks, so one more
+    // tick than `micros` must be seen. Comparing elapsed ticks rather than
+    // absolute times keeps this right if the counter wraps.
+    let start = now();
+    while now().wrapping_sub(start) <= micros {}
 }
 
 /// Sets up a match in timer 1 to occur `t` duration from now. If
 /// interrupts for timer 1 are enabled and IRQs are unmasked, then a timer
-/// interrupt will be issued in `t` duration.
+/// interrupt will be issued in `t` duration. Durations longer than
+/// `MAX_TICK` are clamped to `MAX_TICK`.
 pub fn tick_in(t: Duration) {
     let mut timer = Timer::new();
     timer.tick_in(t);
@@ -81,4 +165,76 @@ pub fn tick_in(t: Duration) {
 pub fn ack() {
     let mut timer = Timer::new();
     timer.ack();
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_channel, compare_value, read_counter, spin_micros, ChannelError, MAX_TICK};
+    use core::cell::Cell;
+    use core::time::Duration;
+
+    #[test]
+    fn normal_tick() {
+        assert_eq!(compare_value(1_000, Duration::from_millis(10)), 11_000);
+    }
+
+    #[test]
+    fn near_max_tick() {
+        let t = MAX_TICK - Duration::from_micros(1);
+        assert_eq!(compare_value(0, t), core::u32::MAX - 1);
+        // The sum wraps around like the counter does.
+        assert_eq!(compare_value(10, t), 8);
+    }
+
+    #[test]
+    fn over_max_tick_is_clamped() {
+        assert_eq!(compare_value(0, MAX_TICK), core::u32::MAX);
+        assert_eq!(compare_value(0, Duration::from_secs(2 * 60 * 60)), core::u32::MAX);
+        assert_eq!(compare_value(5, Duration::from_secs(core::u64::MAX)), 4);
+    }
+
+    #[test]
+    fn channels() {
+        assert_eq!(check_channel(1), Ok(()));
+        assert_eq!(check_channel(3), Ok(()));
+        assert_eq!(check_channel(0), Err(ChannelError::Reserved(0)));
+        assert_eq!(check_channel(2), Err(ChannelError::Reserved(2)));
+        assert_eq!(check_channel(4), Err(ChannelError::NoSuchChannel(4)));
+    }
+
+    #[test]
+    fn spin_zero() {
+        spin_micros(Duration::from_nanos(0), || panic!("read the counter"));
+        spin_micros(Duration::from_nanos(999), || panic!("read the counter"));
+    }
+
+    #[test]
+    fn spin_waits_long_enough() {
+        for &start in [0, 1000, core::u64::MAX - 50].iter() {
+            // A counter that ticks once per reading.
+            let next = Cell::new(start);
+            spin_micros(Duration::from_micros(100), || {
+                let t = next.get();
+                next.set(t.wrapping_add(1));
+                t
+            });
+            // More than 100 ticks between the first and last reading mean at
+            // least 100 full microseconds passed.
+            let last = next.get().wrapping_sub(1);
+            assert!(last.wrapping_sub(start) > 100, "start {}", start);
+        }
+    }
+
+    #[test]
+    fn counter_rollover() {
+        // `CLO` rolls over between the first reads of `CHI` and `CLO`.
+        let chi = [7, 8, 8, 8];
+        let clo = [0, 5];
+        let (i, j) = (Cell::new(0), Cell::new(0));
+        let micros = read_counter(
+            || { let v = chi[i.get()]; i.set(i.get() + 1); v },
+            || { let v = clo[j.get()]; j.set(j.get() + 1); v },
+        );
+        assert_eq!(micros, (8 << 32) | 5);
+    }
+}
@@ -23,6 +23,31 @@ const AUX_ENABLES: *mut Volatile<u8> = (IO_BASE + 0x215004) as *mut Volatile<u8>
 enum LsrStatus {
     DataReady = 1,
     TxAvailable = 1 << 5,
+    TxIdle = 1 << 6,
+}
+
+/// The loopback bit of `AUX_MU_MCR_REG`, at the same position as in the
+/// 16550 modem control register the mini UART is modelled on. While set,
+/// transmitted bytes are fed straight back into the receiver.
+const MCR_LOOPBACK: u8 = 1 << 4;
+
+/// The receive interrupt bit of `AUX_MU_IER_REG`. The BCM2835 documentation
+/// swaps bits 0 and 1; bit 0 is the one that enables receive interrupts.
+const IER_RX: u8 = 1 << 0;
+
+/// The number of received bytes buffered between reads.
+pub const RX_BUFFER_SIZE: usize = 256;
+
+/// How long `loopback_test` waits for the looped-back byte.
+const LOOPBACK_TIMEOUT: Duration = Duration::from_millis(10);
+
+/// Returns `mcr` with the loopback bit set or cleared.
+fn loopback_mcr(mcr: u8, enabled: bool) -> u8 {
+    if enabled {
+        mcr | MCR_LOOPBACK
+    } else {
+        mcr & !MCR_LOOPBACK
+    }
 }
 
 #[repr(C)]
@@ -30,7 +55,7 @@ enum LsrStatus {
 struct Registers {
     AUX_MU_IO_REG: Volatile<u8>,
     __r1: [Reserved<u8>; 3],
-    AUX_MU_IER_REG: Reserved<u8>,
+    AUX_MU_IER_REG: Volatile<u8>,
     __r2: [Reserved<u8>; 3],
     AUX_MU_IIR_REG: Reserved<u8>,
     __r3: [Reserved<u8>; 3],
@@ -52,25 +77,111 @@ struct Registers {
 
 const_assert_size!(Registers, 0x7E21506C - 0x7E215040);
 
+/// A fixed-size FIFO of received bytes. When full, newly received bytes are
+/// dropped and counted.
+pub struct RxBuffer {
+    bytes: [u8; RX_BUFFER_SIZE],
+    head: usize,
+    len: usize,
+    dropped: usize,
+}
+
+impl RxBuffer {
+    pub const fn new() -> RxBuffer {
+        RxBuffer { bytes: [0; RX_BUFFER_SIZE], head: 0, len: 0, dropped: 0 }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns the number of bytes dropped because the buffer was full.
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+
+    /// Appends `byte`. Returns `false` and drops it if the buffer is full.
+    pub fn push(&mut self, byte: u8) -> bool {
+        if self.len == RX_BUFFER_SIZE {
+            self.dropped += 1;
+            return false;
+        }
+        self.bytes[(self.head + self.len) % RX_BUFFER_SIZE] = byte;
+        self.len += 1;
+        true
+    }
+
+    /// Removes and returns the oldest byte.
+    pub fn pop(&mut self) -> Option<u8> {
+        if self.len == 0 {
+            return None;
+        }
+        let byte = self.bytes[self.head];
+        self.head = (self.head + 1) % RX_BUFFER_SIZE;
+        self.len -= 1;
+        Some(byte)
+    }
+}
+
+/// Returns `true` if less than `timeout` has passed since `start_time`, or if
+/// there is no timeout.
+fn within(start_time: Duration, timeout: Option<Duration>) -> bool {
+    match timeout {
+        Some(x) => timer::current_time() - start_time < x,
+        None => true,
+    }
+}
+
 /// The Raspberry Pi's "mini UART".
 pub struct MiniUart {
     registers: &'static mut Registers,
     timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+    rx: RxBuffer,
 }
 
+/// The baud rate set by `MiniUart::new`.
+pub const DEFAULT_BAUD: u64 = 115200;
+
+/// Returns the `AUX_MU_BAUD_REG` divider closest to `baud`. The mini UART runs
+/// at `CLOCK_HZ / (8 * (divider + 1))` baud.
 fn calculate_baud_multiplier(baud: u64) -> u16 {
-    ((CLOCK_HZ / (baud * 8)) - 1).try_into().unwrap()
+    ((CLOCK_HZ + baud * 4) / (baud * 8) - 1).try_into().unwrap()
+}
+
+/// Disables the mini UART's receive interrupt without going through the
+/// `MiniUart` that owns it, for an interrupt handler that cannot reach that
+/// instance. The interrupt is level-triggered, so a handler that cannot drain
+/// the FIFO must mask it to return at all. The owner turns it back on with
+/// `enable_rx_interrupt` once it has drained the FIFO.
+pub fn mask_rx_interrupt() {
+    let registers = unsafe { &mut *(MU_REG_BASE as *mut Registers) };
+    registers.AUX_MU_IER_REG.and_mask(!IER_RX);
 }
 
 impl MiniUart {
+    /// Initializes the mini UART at `DEFAULT_BAUD` (115200). See `with_baud`.
+    pub fn new() -> MiniUart {
+        MiniUart::with_baud(DEFAULT_BAUD)
+    }
+
     /// Initializes the mini UART by enabling it as an auxiliary peripheral,
-    /// setting the data size to 8 bits, setting the BAUD rate to ~115200 (baud
-    /// divider of 270), setting GPIO pins 14 and 15 to alternative function 5
+    /// setting the data size to 8 bits, setting the BAUD rate to the rate
+    /// closest to `baud` (a divider of 542 for 115200 with the 500MHz core
+    /// clock), setting GPIO pins 14 and 15 to alternative function 5
     /// (TXD1/RDXD1), and finally enabling the UART transmitter and receiver.
     ///
-    /// By default, reads will never time out. To set a read timeout, use
-    /// `set_read_timeout()`.
-    pub fn new() -> MiniUart {
+    /// By default, reads and writes will never time out. To set a timeout,
+    /// use `set_read_timeout()` or `set_write_timeout()`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `baud` is too low for the divider to fit in 16 bits.
+    pub fn with_baud(baud: u64) -> MiniUart {
         let registers = unsafe {
             // Enable the mini UART as an auxiliary device.
             (*AUX_ENABLES).or_mask(1);
@@ -79,7 +190,7 @@ impl MiniUart {
 
         // set data length to 8
         registers.AUX_MU_LCR_REG.or_mask(0b11);
-        registers.AUX_MU_BAUD_REG.write(calculate_baud_multiplier(921600));
+        registers.AUX_MU_BAUD_REG.write(calculate_baud_multiplier(baud));
 
         // setting up GPIO pins
         Gpio::new(14).into_alt(Function::Alt5);
@@ -91,6 +202,8 @@ impl MiniUart {
         MiniUart {
             registers,
             timeout: None,
+            write_timeout: None,
+            rx: RxBuffer::new(),
         }
     }
 
@@ -99,22 +212,74 @@ impl MiniUart {
         self.timeout = Some(t);
     }
 
+    /// Set the write timeout to `t` duration.
+    pub fn set_write_timeout(&mut self, t: Duration) {
+        self.write_timeout = Some(t);
+    }
+
+    /// Enables the receive interrupt, raised as `Interrupt::Aux` while the
+    /// receive FIFO holds a byte. The interrupt handler should call
+    /// `drain_rx` to move received bytes into the internal buffer, so that
+    /// none are lost while nobody is reading.
+    pub fn enable_rx_interrupt(&mut self) {
+        self.registers.AUX_MU_IER_REG.or_mask(IER_RX);
+    }
+
+    /// Moves every byte in the receive FIFO into the internal buffer, which
+    /// clears a pending receive interrupt. Bytes that do not fit are dropped.
+    pub fn drain_rx(&mut self) {
+        while self.fifo_has_byte() {
+            let byte = self.registers.AUX_MU_IO_REG.read();
+            self.rx.push(byte);
+        }
+    }
+
+    /// Returns the number of received bytes dropped because the internal
+    /// buffer was full.
+    pub fn rx_dropped(&self) -> usize {
+        self.rx.dropped()
+    }
+
+    /// Enables or disables internal loopback. While enabled, written bytes are
+    /// received by this UART instead of being sent out on the TX pin.
+    pub fn set_loopback(&mut self, enabled: bool) {
+        let mcr = self.registers.AUX_MU_MCR_REG.read();
+        self.registers.AUX_MU_MCR_REG.write(loopback_mcr(mcr, enabled));
+    }
+
     /// Write the byte `byte`. This method blocks until there is space available
-    /// in the output FIFO.
-    pub fn write_byte(&mut self, byte: u8) {
-        loop {
+    /// in the output FIFO. If a write timeout is set, this method blocks for at
+    /// most that amount of time.
+    ///
+    /// Returns `Err(())` without writing the byte if the timeout expired,
+    /// e.g. because flow control keeps the transmitter from draining.
+    pub fn write_byte(&mut self, byte: u8) -> Result<(), ()> {
+        let start_time = timer::current_time();
+        while within(start_time, self.write_timeout) {
             let can_write = self.registers.AUX_MU_LSR_REG.has_mask(LsrStatus::TxAvailable as u8);
             if can_write {
                 self.registers.AUX_MU_IO_REG.write(byte.into());
-                break;
+                return Ok(());
             }
         }
+        Err(())
+    }
+
+    /// Blocks until every byte written so far has been shifted out of the
+    /// transmitter.
+    pub fn drain(&mut self) {
+        while !self.registers.AUX_MU_LSR_REG.has_mask(LsrStatus::TxIdle as u8) {}
     }
 
     /// Returns `true` if there is at least one byte ready to be read. If this
     /// method returns `true`, a subsequent call to `read_byte` is guaranteed to
     /// return immediately. This method does not block.
     pub fn has_byte(&self) -> bool {
+        !self.rx.is_empty() || self.fifo_has_byte()
+    }
+
+    /// Returns `true` if the receive FIFO holds a byte.
+    fn fifo_has_byte(&self) -> bool {
         self.registers.AUX_MU_LSR_REG.has_mask(LsrStatus::DataReady as u8)
     }
 
@@ -128,7 +293,7 @@ impl MiniUart {
     /// return immediately.
     pub fn wait_for_byte(&self) -> Result<(), ()> {
         let start_time = timer::current_time();
-        while self.eval_cond(start_time) {
+        while within(start_time, self.timeout) {
             if self.has_byte() {
                 return Ok(());
             }
@@ -136,30 +301,85 @@ impl MiniUart {
         return Err(());
     }
 
-    fn eval_cond(&self, start_time: Duration) -> bool {
-        match self.timeout {
-            Some(x) => timer::current_time() - start_time < x,
-            None => true,
+    /// Reads a byte. Bytes buffered by `drain_rx` come first, since they
+    /// arrived before anything still in the FIFO. Blocks indefinitely until a
+    /// byte is ready to be read.
+    pub fn read_byte(&mut self) -> u8 {
+        if let Some(byte) = self.rx.pop() {
+            return byte;
+        }
+        loop {
+            if self.fifo_has_byte() {
+                return self.registers.AUX_MU_IO_REG.read() as u8;
+            }
         }
     }
+}
 
-    /// Reads a byte. Blocks indefinitely until a byte is ready to be read.
-    pub fn read_byte(&mut self) -> u8 {
-        loop {
+/// The operations `loopback_test` needs from a UART.
+pub trait LoopbackUart {
+    fn set_loopback(&mut self, enabled: bool);
+
+    fn has_byte(&self) -> bool;
+
+    fn read_byte(&mut self) -> u8;
+
+    fn write_byte(&mut self, byte: u8);
+
+    /// Waits at most `timeout` for a byte and returns it.
+    fn read_byte_timeout(&mut self, timeout: Duration) -> Option<u8>;
+}
+
+impl LoopbackUart for MiniUart {
+    fn set_loopback(&mut self, enabled: bool) {
+        MiniUart::set_loopback(self, enabled)
+    }
+
+    fn has_byte(&self) -> bool {
+        MiniUart::has_byte(self)
+    }
+
+    fn read_byte(&mut self) -> u8 {
+        MiniUart::read_byte(self)
+    }
+
+    fn write_byte(&mut self, byte: u8) {
+        // A byte that times out is not looped back, which fails the test.
+        let _ = MiniUart::write_byte(self, byte);
+    }
+
+    fn read_byte_timeout(&mut self, timeout: Duration) -> Option<u8> {
+        let start = timer::current_time();
+        while timer::current_time() - start < timeout {
             if self.has_byte() {
-                return self.registers.AUX_MU_IO_REG.read() as u8;
+                return Some(MiniUart::read_byte(self));
             }
         }
+        None
+    }
+}
+
+/// Checks that `uart` receives what it sends: enables loopback, discards any
+/// pending input, writes `byte` and reads it back. Loopback is disabled again
+/// before returning. Returns `true` if the same byte came back in time.
+pub fn loopback_test<U: LoopbackUart>(uart: &mut U, byte: u8) -> bool {
+    uart.set_loopback(true);
+    while uart.has_byte() {
+        uart.read_byte();
     }
+    uart.write_byte(byte);
+    let received = uart.read_byte_timeout(LOOPBACK_TIMEOUT);
+    uart.set_loopback(false);
+    received == Some(byte)
 }
 
 impl fmt::Write for MiniUart {
     fn write_str(&mut self, s: &str) -> fmt::Result {
         for byte in s.as_bytes().iter() {
             if *byte == b'\n' {
-                self.write_byte(b'\r');
+                self.write_byte(b'\r').map_err(|_| fmt::Error)?;
             }
-            self.write_byte(*byte);
+            self.write_byte(*byte).map_err(|_| fmt::Error)?;
         }
         Ok(())
     }
@@ -175,31 +395,196 @@ pub mod uart_io {
     // read times out, an error of kind `TimedOut` should be returned.
     //
     // The `io::Write::write()` method must write all of the requested bytes
-    // before returning.
+    // before returning, unless the write timeout expires. It then returns the
+    // number of bytes written so far, or an error of kind `TimedOut` if none
+    // were.
 
 
     impl io::Write for MiniUart {
         fn write(&mut self, buf: &[u8]) -> Result<usize, io::Error> {
-            for b in buf {
-                self.write_byte(*b);
+            for (written, b) in buf.iter().enumerate() {
+                if self.write_byte(*b).is_err() {
+                    if written == 0 {
+                        return Err(io::Error::new(io::ErrorKind::TimedOut, "timed out"));
+                    }
+                    return Ok(written);
+                }
             }
             Ok(buf.len())
         }
 
         fn flush(&mut self) -> Result<(), io::Error> {
+            self.drain();
             Ok(())
         }
     }
 
     impl io::Read for MiniUart {
         fn read(&mut self, buf: &mut [u8]) -> Result<usize, io::Error> {
-            let mut count = 0;
-            for i in 0..buf.len() {
-                self.wait_for_byte().map_err(|_| { io::Error::new(io::ErrorKind::TimedOut, "timed out") })?;
-                buf[i] = self.read_byte();
-                count += 1
+            if buf.is_empty() {
+                return Ok(0);
+            }
+            self.wait_for_byte().map_err(|_| { io::Error::new(io::ErrorKind::TimedOut, "timed out") })?;
+            Ok(fill_ready(buf, || if self.has_byte() { Some(self.read_byte()) } else { None }))
+        }
+    }
+
+    /// Fills `buf` with the bytes returned by `next` until `buf` is full or
+    /// `next` returns `None` because no byte is ready. Returns the number of
+    /// bytes read.
+    pub(super) fn fill_ready<F: FnMut() -> Option<u8>>(buf: &mut [u8], mut next: F) -> usize {
+        let mut count = 0;
+        while count < buf.len() {
+            match next() {
+                Some(byte) => buf[count] = byte,
+                None => break,
+            }
+            count += 1;
+        }
+        count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{calculate_baud_multiplier, loopback_mcr, loopback_test, LoopbackUart, MCR_LOOPBACK};
+    use super::uart_io::fill_ready;
+    use super::{RxBuffer, RX_BUFFER_SIZE};
+    use crate::common::CLOCK_HZ;
+    use core::time::Duration;
+
+    #[test]
+    fn baud_divider() {
+        assert_eq!(CLOCK_HZ, 500_000_000);
+        assert_eq!(calculate_baud_multiplier(115200), 542);
+        assert_eq!(calculate_baud_multiplier(921600), 67);
+
+        // The achieved rate is within 0.5% of the requested one.
+        for &baud in [9600, 19200, 38400, 57600, 115200, 230400, 460800, 921600].iter() {
+            let divider = calculate_baud_multiplier(baud) as u64;
+            let actual = CLOCK_HZ / (8 * (divider + 1));
+            let error = if actual > baud { actual - baud } else { baud - actual };
+            assert!(error * 200 < baud, "{} baud runs at {}", baud, actual);
+        }
+    }
+
+    #[test]
+    fn loopback_bit() {
+        assert_eq!(MCR_LOOPBACK, 0x10);
+        assert_eq!(loopback_mcr(0, true), 0x10);
+        // RTS (bit 1) is left alone.
+        assert_eq!(loopback_mcr(0b10, true), 0b1_0010);
+        assert_eq!(loopback_mcr(0b1_0010, false), 0b10);
+        assert_eq!(loopback_mcr(0b10, false), 0b10);
+    }
+
+    /// A UART with a one-byte receive buffer that is fed from the transmitter
+    /// while loopback is on, and optionally corrupts what it loops back.
+    struct MockUart {
+        mcr: u8,
+        rx: Option<u8>,
+        corrupt: bool,
+    }
+
+    impl MockUart {
+        fn new(rx: Option<u8>, corrupt: bool) -> MockUart {
+            MockUart { mcr: 0, rx, corrupt }
+        }
+    }
+
+    impl LoopbackUart for MockUart {
+        fn set_loopback(&mut self, enabled: bool) {
+            self.mcr = loopback_mcr(self.mcr, enabled);
+        }
+
+        fn has_byte(&self) -> bool {
+            self.rx.is_some()
+        }
+
+        fn read_byte(&mut self) -> u8 {
+            self.rx.take().expect("read from an empty receiver")
+        }
+
+        fn write_byte(&mut self, byte: u8) {
+            if self.mcr & MCR_LOOPBACK != 0 {
+                self.rx = Some(if self.corrupt { !byte } else { byte });
+            }
+        }
+
+        fn read_byte_timeout(&mut self, _timeout: Duration) -> Option<u8> {
+            self.rx.take()
+        }
+    }
+
+    #[test]
+    fn loopback_round_trip() {
+        let mut uart = MockUart::new(None, false);
+        assert!(loopback_test(&mut uart, 0x5A));
+        assert_eq!(uart.mcr & MCR_LOOPBACK, 0);
+    }
+
+    #[test]
+    fn stale_input_is_discarded() {
+        let mut uart = MockUart::new(Some(b'x'), false);
+        assert!(loopback_test(&mut uart, b'y'));
+    }
+
+    #[test]
+    fn mismatch_fails() {
+        let mut uart = MockUart::new(None, true);
+        assert!(!loopback_test(&mut uart, 0x5A));
+        assert_eq!(uart.mcr & MCR_LOOPBACK, 0);
+    }
+
+    #[test]
+    fn read_returns_burst_without_waiting() {
+        // A 3-byte burst fills 3 bytes of a larger buffer: once no byte is
+        // ready the read returns, so a 10ms timeout is not waited out for
+        // each of the remaining slots.
+        let mut rx = b"abc".iter().cloned();
+        let mut buf = [0; 8];
+        assert_eq!(fill_ready(&mut buf, || rx.next()), 3);
+        assert_eq!(&buf[..3], b"abc");
+    }
+
+    #[test]
+    fn read_stops_when_buffer_full() {
+        let mut rx = b"0123456789".iter().cloned();
+        let mut buf = [0; 4];
+        assert_eq!(fill_ready(&mut buf, || rx.next()), 4);
+        assert_eq!(&buf, b"0123");
+        assert_eq!(rx.len(), 6);
+    }
+
+    #[test]
+    fn rx_buffer_is_fifo() {
+        let mut rx = RxBuffer::new();
+        assert_eq!(rx.pop(), None);
+        // Enough rounds to wrap around the end of the array.
+        for round in 0..3 {
+            for i in 0..200 {
+                assert!(rx.push((round + i) as u8));
             }
-            Ok(count)
+            assert_eq!(rx.len(), 200);
+            for i in 0..200 {
+                assert_eq!(rx.pop(), Some((round + i) as u8));
+            }
+            assert!(rx.is_empty());
+        }
+    }
+
+    #[test]
+    fn rx_buffer_drops_when_full() {
+        let mut rx = RxBuffer::new();
+        for i in 0..RX_BUFFER_SIZE {
+            assert!(rx.push(i as u8));
         }
+        assert!(!rx.push(0xFF));
+        assert!(!rx.push(0xFF));
+        assert_eq!(rx.dropped(), 2);
+        assert_eq!(rx.len(), RX_BUFFER_SIZE);
+        assert_eq!(rx.pop(), Some(0));
+        assert!(rx.push(0xAB));
+        assert_eq!(rx.dropped(), 2);
     }
 }
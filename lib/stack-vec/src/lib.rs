@@ -3,10 +3,14 @@
 #[cfg(test)]
 mod tests;
 
+use core::fmt;
+use core::mem;
 use core::slice;
 use core::iter::IntoIterator;
 use core::ops::{Deref, DerefMut, Index, IndexMut};
 
+use shim::io;
+
 /// A contiguous array type backed by a slice.
 ///
 /// `StackVec`'s functionality is similar to that of `std::Vec`. You can `push`
@@ -115,16 +119,123 @@ impl<'a, T: 'a> StackVec<'a, T> {
         }
     }
 
+    /// Appends elements of `iter` until the vector is full or `iter` is
+    /// exhausted, and returns the number of elements appended.
+    ///
+    /// The iterator is not advanced once the vector is full, so no element is
+    /// pulled out and dropped. Pass `iter.by_ref()` to keep the elements that
+    /// did not fit.
+    pub fn extend_from_iter<I: IntoIterator<Item = T>>(&mut self, iter: I) -> usize {
+        let mut iter = iter.into_iter();
+        let start = self.len;
+        while !self.is_full() {
+            match iter.next() {
+                Some(value) => {
+                    self.storage[self.len] = value;
+                    self.len += 1;
+                }
+                None => break,
+            }
+        }
+        self.len - start
+    }
+
+    /// Inserts `value` at position `index`, shifting the elements after it
+    /// one slot to the right.
+    ///
+    /// # Error
+    ///
+    /// If this vector is full, `value` is returned in an `Err`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index > len`.
+    pub fn insert(&mut self, index: usize, value: T) -> Result<(), T> {
+        if index > self.len {
+            panic!("insertion index {} out of bounds for length {}", index, self.len);
+        }
+        if self.is_full() {
+            return Err(value);
+        }
+
+        // Put the value in the first free slot and rotate it into place.
+        self.storage[self.len] = value;
+        self.storage[index..=self.len].rotate_right(1);
+        self.len += 1;
+        Ok(())
+    }
+
     pub fn iter(&'a self) -> core::slice::Iter<'a, T> {
         self.into_iter()
     }
 
+    /// Returns an iterator over mutable references to the elements of the
+    /// vector.
+    pub fn iter_mut(&mut self) -> core::slice::IterMut<'_, T> {
+        self.storage[0..self.len].iter_mut()
+    }
+
 }
 
-impl<'a, T: Clone + 'a> StackVec<'a, T> {
+impl<'a, T: Default + 'a> StackVec<'a, T> {
     /// If this vector is not empty, removes the last element from this vector
-    /// by cloning it and returns it. Otherwise returns `None`.
+    /// and returns it. Otherwise returns `None`.
+    ///
+    /// The element is moved out and its slot in the backing storage is left
+    /// holding `T::default()`. The storage stays fully initialized because
+    /// its owner drops every slot, and `push` drops the slot it overwrites.
+    ///
+    /// Leaving a valid value behind is what `T: Default` is for; the storage
+    /// is borrowed, so the slot cannot be marked uninitialized instead. For
+    /// an element type without a default, back the vector with `Option<T>`
+    /// slots, which are left holding `None`.
     pub fn pop(&mut self) -> Option<T> {
+        if self.len == 0 {
+            None
+        } else {
+            self.len -= 1;
+            Some(mem::replace(&mut self.storage[self.len], T::default()))
+        }
+    }
+
+    /// Removes and returns the element at position `index`, shifting the
+    /// elements after it one slot to the left. The freed slot at the end is
+    /// left holding `T::default()`, so `T: Default` is needed as with `pop`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index >= len`.
+    pub fn remove(&mut self, index: usize) -> T {
+        if index >= self.len {
+            panic!("removal index {} out of bounds for length {}", index, self.len);
+        }
+
+        self.storage[index..self.len].rotate_left(1);
+        self.len -= 1;
+        mem::replace(&mut self.storage[self.len], T::default())
+    }
+}
+
+impl<'a, T: Clone + 'a> StackVec<'a, T> {
+    /// Appends clones of all elements of `other` if they fit.
+    ///
+    /// # Error
+    ///
+    /// If `other` does not fit in the remaining capacity, an `Err` is
+    /// returned and nothing is appended.
+    pub fn extend_from_slice(&mut self, other: &[T]) -> Result<(), ()> {
+        if other.len() > self.capacity() - self.len {
+            return Err(());
+        }
+        self.storage[self.len..self.len + other.len()].clone_from_slice(other);
+        self.len += other.len();
+        Ok(())
+    }
+
+    /// If this vector is not empty, removes the last element from this vector
+    /// by cloning it and returns it. Otherwise returns `None`. The slot in the
+    /// backing storage keeps its value.
+    pub fn pop_cloned(&mut self) -> Option<T> {
         if self.len == 0 {
             None
         } else {
@@ -139,13 +250,13 @@ impl<'a, T> Deref for StackVec<'a, T> {
     type Target = [T];
 
     fn deref(&self) -> &Self::Target {
-        &self.storage
+        &self.storage[..self.len]
     }
 }
 
 impl<'a, T> DerefMut for StackVec<'a, T> {
     fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.storage
+        &mut self.storage[..self.len]
     }
 }
 
@@ -188,3 +299,44 @@ impl <'a, T:'a> IntoIterator for &'a StackVec<'a, T> {
         self.storage[0..self.len].into_iter()
     }
 }
+
+impl <'a, T:'a> IntoIterator for &'a mut StackVec<'_, T> {
+    type Item = &'a mut T;
+    type IntoIter = core::slice::IterMut<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_mut()
+    }
+}
+
+impl<'a> StackVec<'a, u8> {
+    /// Pushes as many bytes of `buf` as fit, returning the number pushed.
+    fn push_bytes(&mut self, buf: &[u8]) -> usize {
+        let n = core::cmp::min(buf.len(), self.capacity() - self.len);
+        self.storage[self.len..self.len + n].copy_from_slice(&buf[..n]);
+        self.len += n;
+        n
+    }
+}
+
+impl fmt::Write for StackVec<'_, u8> {
+    /// Appends the bytes of `s`. If the vector fills up, the bytes that fit
+    /// are kept and `fmt::Error` is returned.
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if self.push_bytes(s.as_bytes()) == s.len() {
+            Ok(())
+        } else {
+            Err(fmt::Error)
+        }
+    }
+}
+
+impl io::Write for StackVec<'_, u8> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Ok(self.push_bytes(buf))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -205,6 +205,174 @@ fn as_slice() {
     assert_eq!(stack_vec.as_mut_slice(), &mut [102]);
 }
 
+/// A value that can be moved but not cloned, like a handle.
+#[derive(Debug, Default, PartialEq)]
+struct Handle(usize);
+
+#[test]
+fn pop_without_clone() {
+    let mut storage = [Handle(0), Handle(0), Handle(0)];
+    {
+        let mut stack_vec = StackVec::new(&mut storage);
+        stack_vec.push(Handle(7)).expect("cap = 3");
+        stack_vec.push(Handle(8)).expect("cap = 3");
+
+        assert_eq!(stack_vec.pop(), Some(Handle(8)));
+        assert_eq!(stack_vec.len(), 1);
+        assert_eq!(stack_vec.as_slice(), &[Handle(7)]);
+
+        stack_vec.push(Handle(9)).expect("cap = 3");
+        assert_eq!(stack_vec.as_slice(), &[Handle(7), Handle(9)]);
+        assert_eq!(stack_vec.pop(), Some(Handle(9)));
+        assert_eq!(stack_vec.pop(), Some(Handle(7)));
+        assert_eq!(stack_vec.pop(), None);
+    }
+    // Popped slots are left holding the default value.
+    assert_eq!(storage, [Handle(0), Handle(0), Handle(0)]);
+}
+
+/// A value with neither `Clone` nor `Default`.
+#[derive(Debug, PartialEq)]
+struct Socket(usize);
+
+#[test]
+fn pop_and_remove_through_option_slots() {
+    let mut storage = [None, None, None];
+    {
+        let mut stack_vec = StackVec::new(&mut storage);
+        for i in 1..4 {
+            stack_vec.push(Some(Socket(i))).expect("cap = 3");
+        }
+
+        assert_eq!(stack_vec.remove(0), Some(Socket(1)));
+        assert_eq!(stack_vec.pop(), Some(Some(Socket(3))));
+        assert_eq!(stack_vec.as_slice(), &[Some(Socket(2))]);
+    }
+    assert_eq!(storage, [Some(Socket(2)), None, None]);
+}
+
+#[test]
+fn pop_cloned_keeps_slot() {
+    let mut storage = [0u8; 2];
+    {
+        let mut stack_vec = StackVec::new(&mut storage);
+        stack_vec.push(5).expect("cap = 2");
+        assert_eq!(stack_vec.pop_cloned(), Some(5));
+        assert!(stack_vec.is_empty());
+        assert_eq!(stack_vec.pop_cloned(), None);
+    }
+    assert_eq!(storage, [5, 0]);
+}
+
+#[test]
+fn insert_at_front_and_back() {
+    let mut storage = [0usize; 4];
+    let mut vec = StackVec::new(&mut storage);
+
+    vec.insert(0, 2).expect("cap = 4");
+    vec.insert(0, 1).expect("cap = 4");
+    assert_eq!(vec.as_slice(), &[1, 2]);
+
+    vec.insert(vec.len(), 4).expect("cap = 4");
+    vec.insert(2, 3).expect("cap = 4");
+    assert_eq!(vec.as_slice(), &[1, 2, 3, 4]);
+    assert_eq!(vec.len(), 4);
+
+    assert_eq!(vec.insert(0, 9), Err(9));
+    assert_eq!(vec.as_slice(), &[1, 2, 3, 4]);
+}
+
+#[test]
+#[should_panic]
+fn insert_oob() {
+    let mut storage = [0usize; 4];
+    let mut vec = StackVec::with_len(&mut storage, 1);
+    let _ = vec.insert(2, 1);
+}
+
+#[test]
+fn remove_from_middle() {
+    let mut storage = [Handle(0), Handle(0), Handle(0), Handle(0)];
+    {
+        let mut vec = StackVec::new(&mut storage);
+        for i in 1..4 {
+            vec.push(Handle(i)).expect("cap = 4");
+        }
+
+        assert_eq!(vec.remove(1), Handle(2));
+        assert_eq!(vec.len(), 2);
+        assert_eq!(vec.as_slice(), &[Handle(1), Handle(3)]);
+
+        assert_eq!(vec.remove(1), Handle(3));
+        assert_eq!(vec.remove(0), Handle(1));
+        assert!(vec.is_empty());
+    }
+    assert_eq!(storage, [Handle(0), Handle(0), Handle(0), Handle(0)]);
+}
+
+#[test]
+#[should_panic]
+fn remove_oob() {
+    let mut storage = [0usize; 4];
+    let mut vec = StackVec::with_len(&mut storage, 2);
+    vec.remove(2);
+}
+
+#[test]
+fn deref_respects_len() {
+    let mut storage = [0usize; 10];
+    let mut vec = StackVec::new(&mut storage);
+    vec.push(1).expect("cap = 10");
+    vec.push(2).expect("cap = 10");
+
+    let slice: &[usize] = &vec;
+    assert_eq!(slice.len(), 2);
+    assert_eq!(slice.iter().count(), 2);
+    assert_eq!(vec.last(), Some(&2));
+
+    let slice: &mut [usize] = &mut vec;
+    assert_eq!(slice, &mut [1, 2]);
+    vec.reverse();
+    assert_eq!(vec.as_slice(), &[2, 1]);
+}
+
+#[test]
+fn extend_from_slice() {
+    let mut storage = [0u8; 6];
+    let mut vec = StackVec::with_len(&mut storage, 1);
+
+    vec.extend_from_slice(b"abc").expect("fits");
+    assert_eq!(vec.as_slice(), b"\0abc");
+    vec.extend_from_slice(&[]).expect("empty slice fits");
+
+    // Too long: nothing is appended.
+    assert_eq!(vec.extend_from_slice(b"xyz"), Err(()));
+    assert_eq!(vec.as_slice(), b"\0abc");
+
+    vec.extend_from_slice(b"de").expect("fits exactly");
+    assert!(vec.is_full());
+    assert_eq!(vec.as_slice(), b"\0abcde");
+}
+
+#[test]
+fn iter_mut() {
+    let mut storage = [1usize; 5];
+    let mut vec = StackVec::with_len(&mut storage, 3);
+
+    for x in vec.iter_mut() {
+        *x += 10;
+    }
+    assert_eq!(vec.iter_mut().count(), 3);
+    for x in &mut vec {
+        *x *= 2;
+    }
+    assert_eq!(vec.as_slice(), &[22, 22, 22]);
+    drop(vec);
+
+    // The slots past the length are untouched.
+    assert_eq!(storage, [22, 22, 22, 1, 1]);
+}
+
 #[test]
 fn errors() {
     let mut storage = [0usize; 1024];
@@ -222,3 +390,58 @@ fn errors() {
         assert_eq!(vec.pop(), None);
     }
 }
+
+#[test]
+fn fmt_write() {
+    use core::fmt::Write;
+
+    let mut storage = [0u8; 16];
+    let mut vec = StackVec::new(&mut storage);
+    assert!(write!(vec, "{}-{}", 12, "ab").is_ok());
+    assert_eq!(vec.as_slice(), b"12-ab");
+
+    assert!(write!(vec, "{}", "this does not fit").is_err());
+    assert!(vec.is_full());
+    assert_eq!(vec.as_slice(), b"12-abthis does n");
+}
+
+#[test]
+fn io_write() {
+    use shim::io::Write;
+
+    let mut storage = [0u8; 4];
+    let mut vec = StackVec::new(&mut storage);
+    assert_eq!(vec.write(b"ab").unwrap(), 2);
+    assert_eq!(vec.write(b"cdef").unwrap(), 2);
+    assert_eq!(vec.write(b"g").unwrap(), 0);
+    assert_eq!(vec.as_slice(), b"abcd");
+    assert!(vec.write_all(b"h").is_err());
+}
+
+#[test]
+fn extend_from_longer_iter() {
+    let mut storage = [0usize; 4];
+    let mut vec = StackVec::with_len(&mut storage, 1);
+
+    let mut iter = 10..20;
+    assert_eq!(vec.extend_from_iter(iter.by_ref()), 3);
+    assert!(vec.is_full());
+    assert_eq!(vec.as_slice(), &[0, 10, 11, 12]);
+
+    // The first element that did not fit is still in the iterator.
+    assert_eq!(iter.next(), Some(13));
+    assert_eq!(vec.extend_from_iter(iter), 0);
+}
+
+#[test]
+fn extend_from_shorter_iter() {
+    let mut storage = [0usize; 8];
+    let mut vec = StackVec::new(&mut storage);
+
+    let mut iter = 1..4;
+    assert_eq!(vec.extend_from_iter(iter.by_ref()), 3);
+    assert_eq!(iter.next(), None);
+    assert_eq!(vec.as_slice(), &[1, 2, 3]);
+    assert_eq!(vec.extend_from_iter([4, 5].iter().cloned()), 2);
+    assert_eq!(vec.as_slice(), &[1, 2, 3, 4, 5]);
+}
@@ -3,13 +3,15 @@ mod parsers;
 use serial;
 use structopt;
 use structopt_derive::StructOpt;
-use xmodem::Xmodem;
+use xmodem::{Progress, Xmodem};
 
+use std::error::Error;
 use std::path::PathBuf;
-use std::time::Duration;
+use std::process;
+use std::time::{Duration, Instant};
 use std::fs;
 use std::io;
-use std::io::Write;
+use std::io::{Read, Write};
 
 
 use structopt::StructOpt;
@@ -18,11 +20,18 @@ use serial::core::{CharSize, BaudRate, StopBits, FlowControl, SerialDevice, Seri
 use parsers::{parse_width, parse_stop_bits, parse_flow_control, parse_baud_rate};
 
 #[derive(StructOpt, Debug)]
-#[structopt(about = "Write to TTY using the XMODEM protocol by default.")]
+#[structopt(about = "Write to or read from TTY using the XMODEM protocol by default.")]
 struct Opt {
     #[structopt(short = "i", help = "Input file (defaults to stdin if not set)", parse(from_os_str))]
     input: Option<PathBuf>,
 
+    #[structopt(short = "R", long = "receive", help = "Receive from the TTY instead of writing to it")]
+    receive: bool,
+
+    #[structopt(short = "o", long = "output", parse(from_os_str),
+    help = "Output file for --receive (defaults to stdout if not set)")]
+    output: Option<PathBuf>,
+
     #[structopt(short = "b", long = "baud", parse(try_from_str = "parse_baud_rate"),
     help = "Set baud rate", default_value = "115200")]
     baud_rate: BaudRate,
@@ -48,37 +57,135 @@ struct Opt {
 
     #[structopt(short = "r", long = "raw", help = "Disable XMODEM")]
     raw: bool,
-}
 
-fn main() {
-    use std::fs::File;
-    use std::io::{self, BufReader};
+    #[structopt(short = "v", long = "verbose", help = "Print transfer progress and statistics")]
+    verbose: bool,
+}
 
-    let opt = Opt::from_args();
-    let mut port = serial::open(&opt.tty_path).expect("path points to invalid TTY");
+/// Prints XMODEM progress to stderr. Used when `--verbose` is set.
+fn print_progress(progress: Progress) {
+    match progress {
+        Progress::Waiting => eprintln!("waiting for receiver..."),
+        Progress::Started => eprintln!("transfer started"),
+        Progress::Packet(n) => eprintln!("sent packet {}", n),
+        Progress::NAK => eprintln!("packet NAKed, resending"),
+        Progress::Unknown => eprintln!("unexpected byte from receiver"),
+        Progress::Done => eprintln!("transfer complete"),
+    }
+}
 
-    let mut tty_settings = port.read_settings().unwrap();
-    tty_settings.set_baud_rate(opt.baud_rate).expect("failed to set baud rate");
-    tty_settings.set_char_size(opt.char_width);
-    tty_settings.set_flow_control(opt.flow_control);
-    tty_settings.set_stop_bits(opt.stop_bits);
-    port.write_settings(&tty_settings).expect("failed to write tty settings");
+/// Prints XMODEM progress of a receive to stderr. Used when `--verbose` is
+/// set.
+fn print_receive_progress(progress: Progress) {
+    match progress {
+        Progress::Packet(n) => eprintln!("received packet {}", n),
+        _ => print_progress(progress),
+    }
+}
 
+/// Copies bytes from `from` to `into` until `from` ends or a read times out.
+/// Returns the number of bytes copied.
+fn read_raw<R: Read, W: Write>(mut from: R, mut into: W) -> io::Result<usize> {
+    let mut buf = [0u8; 1024];
+    let mut received = 0;
+    loop {
+        match from.read(&mut buf) {
+            Ok(0) => return Ok(received),
+            Ok(n) => {
+                into.write_all(&buf[..n])?;
+                received += n;
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::TimedOut => return Ok(received),
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
 
-    let mut buffer = String::new();
-    let the_string = match opt.input {
-        Some(x) => fs::read_to_string(x).expect("File should actually exist"),
+/// Sends the input to `port`. Returns the number of bytes sent, excluding
+/// XMODEM padding.
+fn send<P: Read + Write>(opt: &Opt, mut port: P) -> Result<usize, Box<dyn Error>> {
+    // Read as bytes: images sent to the bootloader are not UTF-8.
+    let bytes = match opt.input {
+        Some(ref path) => fs::read(path)?,
         None => {
-            io::stdin().read_line(&mut buffer);
+            let mut buffer = Vec::new();
+            io::stdin().read_to_end(&mut buffer)?;
             buffer
         }
     };
-    let bytes = the_string.as_bytes();
 
     if opt.raw {
-        port.write(bytes);
+        port.write_all(&bytes)?;
+        Ok(bytes.len())
+    } else if opt.verbose {
+        Ok(Xmodem::new_with_progress(&mut port, print_progress).transmit_from(&bytes[..])?)
     } else {
-        Xmodem::transmit(bytes, port);
+        Ok(Xmodem::new_channel(&mut port).transmit_from(&bytes[..])?)
     }
 }
 
+/// Receives from `port` into the output. Returns the number of bytes
+/// received, including XMODEM padding.
+fn receive<P: Read + Write>(opt: &Opt, mut port: P) -> Result<usize, Box<dyn Error>> {
+    let mut output: Box<dyn Write> = match opt.output {
+        Some(ref path) => Box::new(fs::File::create(path)?),
+        None => Box::new(io::stdout()),
+    };
+
+    let received = if opt.raw {
+        read_raw(&mut port, &mut output)?
+    } else if opt.verbose {
+        Xmodem::new_with_progress(&mut port, print_receive_progress).receive_into(&mut output)?
+    } else {
+        Xmodem::new_channel(&mut port).receive_into(&mut output)?
+    };
+    output.flush()?;
+    Ok(received)
+}
+
+/// Opens and configures the TTY, then sends the input or, with `--receive`,
+/// receives into the output. Returns the number of bytes transferred.
+fn run(opt: &Opt) -> Result<usize, Box<dyn Error>> {
+    if opt.receive && opt.input.is_some() {
+        return Err("-R/--receive cannot be combined with an input file (-i)".into());
+    }
+    if !opt.receive && opt.output.is_some() {
+        return Err("-o/--output is only used with -R/--receive".into());
+    }
+
+    let mut port = serial::open(&opt.tty_path)?;
+
+    let mut tty_settings = port.read_settings()?;
+    tty_settings.set_baud_rate(opt.baud_rate)?;
+    tty_settings.set_char_size(opt.char_width);
+    tty_settings.set_flow_control(opt.flow_control);
+    tty_settings.set_stop_bits(opt.stop_bits);
+    port.write_settings(&tty_settings)?;
+    port.set_timeout(Duration::from_secs(opt.timeout))?;
+
+    if opt.receive {
+        receive(opt, &mut port)
+    } else {
+        send(opt, &mut port)
+    }
+}
+
+fn main() {
+    let opt = Opt::from_args();
+
+    let start = Instant::now();
+    match run(&opt) {
+        Ok(transferred) => {
+            if opt.verbose {
+                let elapsed = start.elapsed();
+                let verb = if opt.receive { "received" } else { "sent" };
+                eprintln!("{} {} bytes in {}.{:03}s", verb, transferred, elapsed.as_secs(), elapsed.subsec_millis());
+            }
+        }
+        Err(e) => {
+            eprintln!("ttywrite: error: {}", e);
+            process::exit(1);
+        }
+    }
+}
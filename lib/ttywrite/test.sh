@@ -55,5 +55,67 @@ for i in {1..10}; do
   fi
 done
 
+echo -e "${KBLU}Checking that all of stdin is sent.${KNRM}"
+input=$(printf "first line\nsecond line\nthird line")
+printf "%s" "${input}" | ./target/debug/ttywrite -r input
+output=$(cat output)
+if [[ "${output}" != "${input}" ]]; then
+  echo -e "${KRED}ERROR: multi-line stdin was not sent in full${KNRM}" >&2
+  echo "${input} != ${output}" >&2
+  cleanup_and_exit 1
+fi
+
+echo -e "${KBLU}Checking that binary input is sent byte for byte.${KNRM}"
+binary=$(mktemp)
+received=$(mktemp)
+printf '\x00\x7f\x80\xc3\x28\xff\xfe\x00\xe2\x82' > "${binary}"
+head -c 256 /dev/urandom >> "${binary}"
+./target/debug/ttywrite -i "${binary}" -r input
+cat output > "${received}"
+if ! cmp -s "${binary}" "${received}"; then
+  echo -e "${KRED}ERROR: binary input was not sent byte for byte${KNRM}" >&2
+  rm -f "${binary}" "${received}"
+  cleanup_and_exit 1
+fi
+rm -f "${binary}" "${received}"
+
+echo -e "${KBLU}Checking raw receive into an output file.${KNRM}"
+received=$(mktemp)
+input=$(rand_string)
+printf "%s" "${input}" > input
+./target/debug/ttywrite -R -r -t 1 -o "${received}" output
+if [[ "$(cat "${received}")" != "${input}" ]]; then
+  echo -e "${KRED}ERROR: received output differs from input${KNRM}" >&2
+  echo "${input} != $(cat "${received}")" >&2
+  rm -f "${received}"
+  cleanup_and_exit 1
+fi
+rm -f "${received}"
+
+echo -e "${KBLU}Checking that --receive rejects an input file.${KNRM}"
+if ./target/debug/ttywrite -R -i <(echo "hello") output 2>/dev/null; then
+  echo -e "${KRED}ERROR: --receive with -i exited 0${KNRM}" >&2
+  cleanup_and_exit 1
+fi
+
+echo -e "${KBLU}Checking exit status of a successful transfer.${KNRM}"
+if ! ./target/debug/ttywrite -i <(echo "hello") -r input; then
+  echo -e "${KRED}ERROR: successful transfer exited non-zero${KNRM}" >&2
+  cleanup_and_exit 1
+fi
+cat output > /dev/null
+
+echo -e "${KBLU}Checking exit status and message of a failed transfer.${KNRM}"
+stderr=$(./target/debug/ttywrite -i <(echo "hello") -r does-not-exist 2>&1 >/dev/null)
+status=$?
+if [[ ${status} -eq 0 ]]; then
+  echo -e "${KRED}ERROR: failed transfer exited 0${KNRM}" >&2
+  cleanup_and_exit 1
+fi
+if [[ "${stderr}" != "ttywrite: error: "* ]]; then
+  echo -e "${KRED}ERROR: unexpected stderr for failed transfer: ${stderr}${KNRM}" >&2
+  cleanup_and_exit 1
+fi
+
 echo -e "${KGRN}SUCCESS${KNRM}"
 cleanup_and_exit 0
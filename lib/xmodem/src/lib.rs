@@ -14,20 +14,40 @@ pub use progress::{Progress, ProgressFn};
 use read_ext::ReadExt;
 
 const SOH: u8 = 0x01;
+const STX: u8 = 0x02;
 const EOT: u8 = 0x04;
 const ACK: u8 = 0x06;
 const NAK: u8 = 0x15;
 const CAN: u8 = 0x18;
+/// Sent by a receiver instead of `NAK` to request CRC-16 mode.
+const CRC: u8 = b'C';
+
+/// How many times a receiver in CRC-16 mode sends `C` and times out before
+/// falling back to the checksum and `NAK`.
+const CRC_ATTEMPTS: usize = 3;
+
+/// How many times a packet is sent again after a `NAK`, as suggested by the
+/// XMODEM specification, unless set with `with_max_retries`.
+pub const DEFAULT_MAX_RETRIES: usize = 10;
 
 /// Implementation of the XMODEM protocol.
-pub struct Xmodem<R> {
+pub struct Xmodem<R, F = ProgressFn> {
     packet: u8,
+    /// The number of packets transferred so far, which unlike `packet` does
+    /// not wrap around.
+    packets: u32,
     started: bool,
     inner: R,
-    progress: ProgressFn
+    progress: F,
+    use_crc: bool,
+    use_1k: bool,
+    max_retries: usize,
+    handshake_timeouts: usize,
 }
 
 const PACKET_SIZE: usize = 128;
+/// The payload size of an XMODEM-1K packet, which starts with `STX`.
+const PACKET_SIZE_1K: usize = 1024;
 
 impl Xmodem<()> {
     /// Transmits `data` to the receiver `to` using the XMODEM protocol. If the
@@ -50,34 +70,10 @@ impl Xmodem<()> {
     /// the transmission. See the [`Progress`] enum for more information.
     ///
     /// Returns the number of bytes written to `to`, excluding padding zeroes.
-    pub fn transmit_with_progress<R, W>(mut data: R, to: W, f: ProgressFn) -> io::Result<usize>
-        where W: io::Read + io::Write, R: io::Read
+    pub fn transmit_with_progress<R, W, F>(data: R, to: W, f: F) -> io::Result<usize>
+        where W: io::Read + io::Write, R: io::Read, F: FnMut(Progress)
     {
-        let mut transmitter = Xmodem::new_with_progress(to, f);
-        let mut packet = [0u8; 128];
-        let mut written = 0;
-        'next_packet: loop {
-            let n = data.read_max(&mut packet)?;
-            packet[n..].iter_mut().for_each(|b| *b = 0);
-
-            if n == 0 {
-                transmitter.write_packet(&[])?;
-                return Ok(written);
-            }
-
-            for _ in 0..10 {
-                match transmitter.write_packet(&packet) {
-                    Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
-                    Err(e) => return Err(e),
-                    Ok(_) => {
-                        written += n;
-                        continue 'next_packet;
-                    }
-                }
-            }
-
-            return ioerr!(BrokenPipe, "bad transmit");
-        }
+        Xmodem::new_with_progress(to, f).transmit_from(data)
     }
 
     /// Receives `data` from `from` using the XMODEM protocol and writes it into
@@ -94,21 +90,100 @@ impl Xmodem<()> {
     ///
     /// The function `f` is used as a callback to indicate progress throughout
     /// the reception. See the [`Progress`] enum for more information.
-    pub fn receive_with_progress<R, W>(from: R, mut into: W, f: ProgressFn) -> io::Result<usize>
-        where R: io::Read + io::Write, W: io::Write
+    pub fn receive_with_progress<R, W, F>(from: R, into: W, f: F) -> io::Result<usize>
+        where R: io::Read + io::Write, W: io::Write, F: FnMut(Progress)
     {
-        let mut receiver = Xmodem::new_with_progress(from, f);
-        let mut packet = [0u8; 128];
+        Xmodem::new_with_progress(from, f).receive_into(into)
+    }
+}
+
+/// A bidirectional transport, such as a serial port, over which XMODEM
+/// packets are exchanged.
+pub trait Channel: io::Read + io::Write {}
+
+impl<T: io::Read + io::Write> Channel for T {}
+
+/// Returns `true` if `e` is a read timing out rather than failing.
+fn timed_out(e: &io::Error) -> bool {
+    e.kind() == io::ErrorKind::TimedOut || e.kind() == io::ErrorKind::WouldBlock
+}
+
+fn get_checksum(buf: &[u8]) -> u8 {
+    return buf.iter().fold(0, |a, b| a.wrapping_add(*b));
+}
+
+/// Returns the CRC-16/CCITT of `buf` as used by XMODEM: polynomial `0x1021`,
+/// initial value 0, no reflection.
+fn get_crc(buf: &[u8]) -> u16 {
+    buf.iter().fold(0u16, |crc, &byte| {
+        let mut crc = crc ^ ((byte as u16) << 8);
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 };
+        }
+        crc
+    })
+}
+
+impl<T: Channel> Xmodem<T> {
+    /// Returns a new `Xmodem` instance with the internal reader/writer set to
+    /// `inner`. The returned instance can be used for both receiving
+    /// (downloading) and sending (uploading).
+    pub fn new(inner: T) -> Self {
+        Xmodem::new_with_progress(inner, progress::noop as ProgressFn)
+    }
+
+    /// Returns a new `Xmodem` instance exchanging packets over `channel`.
+    /// Equivalent to `Xmodem::new(channel)`.
+    pub fn new_channel(channel: T) -> Self {
+        Xmodem::new(channel)
+    }
+}
+
+impl<T: Channel, F: FnMut(Progress)> Xmodem<T, F> {
+
+    /// Transmits `data` over the channel. If the length of the total data
+    /// yielded by `data` is not a multiple of 128 bytes, the data is padded
+    /// with zeroes.
+    ///
+    /// Returns the number of bytes transmitted, excluding padding zeroes.
+    pub fn transmit_from<R: io::Read>(&mut self, mut data: R) -> io::Result<usize> {
+        let mut buf = [0u8; PACKET_SIZE_1K];
+        let mut written = 0;
+        loop {
+            let chunk = if self.use_1k { PACKET_SIZE_1K } else { PACKET_SIZE };
+            let n = data.read_max(&mut buf[..chunk])?;
+            if n == 0 {
+                self.write_packet(&[])?;
+                return Ok(written);
+            }
+
+            if n == PACKET_SIZE_1K {
+                self.write_packet(&buf)?;
+            } else {
+                let padded = (n + PACKET_SIZE - 1) / PACKET_SIZE * PACKET_SIZE;
+                buf[n..padded].iter_mut().for_each(|b| *b = 0);
+                for i in (0..padded).step_by(PACKET_SIZE) {
+                    self.write_packet(&buf[i..i + PACKET_SIZE])?;
+                }
+            }
+            written += n;
+        }
+    }
+
+    /// Receives data over the channel and writes it into `into`. Returns the
+    /// number of bytes received, a multiple of 128.
+    pub fn receive_into<W: io::Write>(&mut self, mut into: W) -> io::Result<usize> {
+        let mut packet = [0u8; PACKET_SIZE_1K];
         let mut received = 0;
         'next_packet: loop {
-            for _ in 0..10 {
-                match receiver.read_packet(&mut packet) {
+            for _ in 0..=self.max_retries {
+                match self.read_packet(&mut packet) {
                     Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
                     Err(e) => return Err(e),
                     Ok(0) => break 'next_packet,
                     Ok(n) => {
                         received += n;
-                        into.write_all(&packet)?;
+                        into.write_all(&packet[..n])?;
                         continue 'next_packet;
                     }
                 }
@@ -119,27 +194,116 @@ impl Xmodem<()> {
 
         Ok(received)
     }
-}
-
-fn get_checksum(buf: &[u8]) -> u8 {
-    return buf.iter().fold(0, |a, b| a.wrapping_add(*b));
-}
-
-impl<T: io::Read + io::Write> Xmodem<T> {
-    /// Returns a new `Xmodem` instance with the internal reader/writer set to
-    /// `inner`. The returned instance can be used for both receiving
-    /// (downloading) and sending (uploading).
-    pub fn new(inner: T) -> Self {
-        Xmodem { packet: 1, started: false, inner, progress: progress::noop}
-    }
 
     /// Returns a new `Xmodem` instance with the internal reader/writer set to
     /// `inner`. The returned instance can be used for both receiving
     /// (downloading) and sending (uploading). The function `f` is used as a
     /// callback to indicate progress throughout the transfer. See the
     /// [`Progress`] enum for more information.
-    pub fn new_with_progress(inner: T, f: ProgressFn) -> Self {
-        Xmodem { packet: 1, started: false, inner, progress: f }
+    pub fn new_with_progress(inner: T, f: F) -> Self {
+        Xmodem {
+            packet: 1,
+            packets: 0,
+            started: false,
+            inner,
+            progress: f,
+            use_crc: false,
+            use_1k: false,
+            max_retries: DEFAULT_MAX_RETRIES,
+            handshake_timeouts: 0,
+        }
+    }
+
+    /// Sets whether a receiver requests CRC-16 mode, in which packets end in
+    /// a two-byte CRC-CCITT instead of the one-byte checksum. The receiver
+    /// starts by sending `C` instead of `NAK`, and falls back to the checksum
+    /// if the sender does not answer a few `C`s. The default is the checksum.
+    ///
+    /// A transmitter uses whichever mode the receiver requests, regardless of
+    /// this setting.
+    pub fn with_crc(mut self, use_crc: bool) -> Self {
+        self.use_crc = use_crc;
+        self
+    }
+
+    /// Sets whether a transmitter sends 1024-byte `STX` packets while at
+    /// least 1024 bytes of data remain. The rest is sent in 128-byte packets,
+    /// so no more padding is sent than without 1K packets. The default is
+    /// 128-byte packets only.
+    ///
+    /// A receiver always accepts both packet sizes.
+    pub fn with_1k(mut self, use_1k: bool) -> Self {
+        self.use_1k = use_1k;
+        self
+    }
+
+    /// Sets how many times a transmitter sends a packet again when the
+    /// receiver answers it with `NAK`, and how many times a receiver retries
+    /// a packet whose read was interrupted. The default is
+    /// `DEFAULT_MAX_RETRIES`.
+    pub fn with_max_retries(mut self, max_retries: usize) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// Sets how many reads that time out are tolerated while waiting for the
+    /// other side to start the transfer: the receiver's `NAK` or `C` for a
+    /// transmitter, the first packet for a receiver, which sends `NAK` again
+    /// after each timeout. Together with the timeout of the underlying
+    /// reader, this bounds how long a handshake with an unresponsive peer
+    /// blocks. The default is 0: the first timeout fails the transfer.
+    ///
+    /// A receiver in CRC-16 mode spends `CRC_ATTEMPTS` timeouts on `C`s
+    /// before these.
+    pub fn with_handshake_timeouts(mut self, timeouts: usize) -> Self {
+        self.handshake_timeouts = timeouts;
+        self
+    }
+
+    /// Returns `true` if packets carry a CRC-16 rather than a checksum. For a
+    /// receiver this can change once the transfer starts if the sender does
+    /// not support CRC-16; for a transmitter it follows the receiver.
+    pub fn uses_crc(&self) -> bool {
+        self.use_crc
+    }
+
+    /// Sends the receiver's start byte and returns the first byte of the
+    /// sender's reply. In CRC-16 mode, `C` is sent up to `CRC_ATTEMPTS` times
+    /// while reads time out, then the receiver falls back to the checksum and
+    /// sends `NAK`.
+    fn start_receive(&mut self) -> io::Result<u8> {
+        if self.use_crc {
+            for _ in 0..CRC_ATTEMPTS {
+                self.write_byte(CRC)?;
+                match self.read_byte(true) {
+                    Err(ref e) if timed_out(e) => continue,
+                    result => return result,
+                }
+            }
+            self.use_crc = false;
+        }
+
+        for _ in 0..self.handshake_timeouts {
+            self.write_byte(NAK)?;
+            match self.read_byte(true) {
+                Err(ref e) if timed_out(e) => continue,
+                result => return result,
+            }
+        }
+        self.write_byte(NAK)?;
+        self.read_byte(true)
+    }
+
+    /// Reads the receiver's start byte, tolerating `handshake_timeouts` reads
+    /// that time out.
+    fn wait_for_receiver(&mut self) -> io::Result<u8> {
+        for _ in 0..self.handshake_timeouts {
+            match self.read_byte(true) {
+                Err(ref e) if timed_out(e) => continue,
+                result => return result,
+            }
+        }
+        self.read_byte(true)
     }
 
     /// Reads a single byte from the inner I/O stream. If `abort_on_can` is
@@ -225,11 +389,14 @@ impl<T: io::Read + io::Write> Xmodem<T> {
     }
 
     /// Reads (downloads) a single packet from the inner stream using the XMODEM
-    /// protocol. On success, returns the number of bytes read (always 128).
+    /// protocol. On success, returns the number of bytes read into `buf`: 128
+    /// for a packet starting with `SOH`, 1024 for one starting with `STX`, or
+    /// 0 at the end of the transmission.
     ///
     /// The progress callback is called with `Progress::Started` when reception
-    /// for the first packet has started and subsequently with
-    /// `Progress::Packet` when a packet is received successfully.
+    /// for the first packet has started, subsequently with `Progress::Packet`
+    /// when a packet is received successfully, and with `Progress::Done` once
+    /// the end of the transmission is acknowledged.
     ///
     /// # Errors
     ///
@@ -237,7 +404,7 @@ impl<T: io::Read + io::Write> Xmodem<T> {
     /// point. Also returns an error if the XMODEM protocol indicates an error.
     /// In particular, an `InvalidData` error is returned when:
     ///
-    ///   * The sender's first byte for a packet isn't `EOT` or `SOH`.
+    ///   * The sender's first byte for a packet isn't `EOT`, `SOH` or `STX`.
     ///   * The sender doesn't send a second `EOT` after the first.
     ///   * The received packet numbers don't match the expected values.
     ///
@@ -246,26 +413,40 @@ impl<T: io::Read + io::Write> Xmodem<T> {
     /// An error of kind `ConnectionAborted` is returned if a `CAN` byte is
     /// received when not expected.
     ///
-    /// An error of kind `UnexpectedEof` is returned if `buf.len() < 128`.
+    /// An error of kind `UnexpectedEof` is returned if `buf.len() < 128`, or
+    /// if `buf.len() < 1024` and the packet starts with `STX`. In the latter
+    /// case `CAN` is sent, since the rest of the packet cannot be read.
+    ///
+    /// See `with_crc` for how the first call starts the transfer.
     pub fn read_packet(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         if buf.len() < PACKET_SIZE {
             return ioerr!(UnexpectedEof, "buffer size is too small");
         }
-        if !self.started {
-            self.write_byte(NAK)?;
+        let first = if self.started {
+            self.read_byte(true)?
+        } else {
+            let first = self.start_receive()?;
             self.started = true;
             (self.progress)(Progress::Started);
-        }
-
-        let first = self.read_byte(true)?;
+            first
+        };
         if first == EOT {
             self.write_byte(NAK)?;
             self.expect_byte(EOT, "expected second EOT")?;
             self.write_byte(ACK)?;
+            (self.progress)(Progress::Done);
             return Ok(0)
-        } else if first != SOH {
-            return ioerr!(InvalidData, "first bit neither EOT or SOH")
         }
+        let size = match first {
+            SOH => PACKET_SIZE,
+            STX => PACKET_SIZE_1K,
+            _ => return ioerr!(InvalidData, "first byte neither EOT, SOH or STX"),
+        };
+        if buf.len() < size {
+            self.write_byte(CAN)?;
+            return ioerr!(UnexpectedEof, "buffer too small for 1K packet");
+        }
+        let buf = &mut buf[..size];
 
         self.expect_byte_or_cancel(self.packet, "expected packet numbers to match")?;
         let complement = 255 - self.packet;
@@ -274,28 +455,39 @@ impl<T: io::Read + io::Write> Xmodem<T> {
 
         self.inner.read_exact(buf)?;
 
-        let my_checksum = get_checksum(&buf);
-        self.expect_byte(my_checksum, "checksum")?;
+        if self.use_crc {
+            let mut crc = [0u8; 2];
+            self.inner.read_exact(&mut crc)?;
+            if u16::from_be_bytes(crc) != get_crc(&buf) {
+                return ioerr!(InvalidData, "CRC");
+            }
+        } else {
+            let my_checksum = get_checksum(&buf);
+            self.expect_byte(my_checksum, "checksum")?;
+        }
 
         self.write_byte(ACK);
-        self.packet += 1;
+        self.packet = self.packet.wrapping_add(1);
+        self.packets += 1;
 
-        (self.progress)(Progress::Packet(self.packet));
+        (self.progress)(Progress::Packet(self.packets));
 
 
-        return Ok(128)
+        return Ok(size)
     }
 
     /// Sends (uploads) a single packet to the inner stream using the XMODEM
     /// protocol. If `buf` is empty, end of transmissions is sent. Users of this
     /// interface should ensure that `write_packet(&[])` is called when data
-    /// transmission is complete. On success, returns the number of bytes
-    /// written.
+    /// transmission is complete. If `buf` holds at least 1024 bytes, its first
+    /// 1024 are sent in an `STX` packet; otherwise its first 128 are sent in
+    /// an `SOH` packet. On success, returns the number of bytes written.
     ///
     /// The progress callback is called with `Progress::Waiting` before waiting
     /// for the receiver's `NAK`, `Progress::Started` when transmission of the
-    /// first packet has started and subsequently with `Progress::Packet` when a
-    /// packet is sent successfully.
+    /// first packet has started, subsequently with `Progress::Packet` when a
+    /// packet is acknowledged by the receiver, and with `Progress::Done` once
+    /// the end of the transmission is acknowledged.
     ///
     /// # Errors
     ///
@@ -303,7 +495,8 @@ impl<T: io::Read + io::Write> Xmodem<T> {
     /// point. Also returns an error if the XMODEM protocol indicates an error.
     /// In particular, an `InvalidData` error is returned when:
     ///
-    ///   * The receiver's first byte isn't a `NAK`.
+    ///   * The receiver's first byte isn't a `NAK` or `C`. After a `C`,
+    ///     packets carry a CRC-16 instead of the checksum.
     ///   * The receiver doesn't respond with a `NAK` to the first `EOT`.
     ///   * The receiver doesn't respond with an `ACK` to the second `EOT`.
     ///   * The receiver responds to a complete packet with something besides
@@ -315,11 +508,20 @@ impl<T: io::Read + io::Write> Xmodem<T> {
     /// An error of kind `ConnectionAborted` is returned if a `CAN` byte is
     /// received when not expected.
     ///
-    /// An error of kind `Interrupted` is returned if a packet checksum fails.
+    /// A packet the receiver answers with `NAK` is sent again, up to
+    /// `max_retries` times (see `with_max_retries`). An error of kind
+    /// `BrokenPipe` is returned once the retries are exhausted.
+    ///
+    /// The wait for the receiver's first byte tolerates `handshake_timeouts`
+    /// read timeouts (see `with_handshake_timeouts`).
     pub fn write_packet(&mut self, buf: &[u8]) -> io::Result<usize> {
         if !self.started {
             (self.progress)(Progress::Waiting);
-            self.expect_byte(NAK, "Expected NAK")?;
+            match self.wait_for_receiver()? {
+                NAK => self.use_crc = false,
+                CRC => self.use_crc = true,
+                _ => return ioerr!(InvalidData, "Expected NAK or C"),
+            }
             self.started = true;
             (self.progress)(Progress::Started);
         }
@@ -332,26 +534,41 @@ impl<T: io::Read + io::Write> Xmodem<T> {
             self.expect_byte(NAK, "Expected NAK")?;
             self.write_byte(EOT)?;
             self.expect_byte(ACK, "Expected ACK")?;
+            (self.progress)(Progress::Done);
             return Ok(0)
         }
 
-        self.write_byte(SOH)?;
-        self.inner.write(&[self.packet])?;
-        self.inner.write(&[255 - self.packet])?;
-        self.inner.write(buf)?;
-        self.inner.write(&[get_checksum(&buf)])?;
-
-        let response = self.read_byte(true)?;
-        if response == NAK {
-            return ioerr!(Interrupted, "Packet didn't send sucessfully")
-        } if response == ACK {
-            self.packet += 1;
-            (self.progress)(Progress::Packet(self.packet));
-            return Ok(128)
-        }
+        let (start, size) = if buf.len() >= PACKET_SIZE_1K {
+            (STX, PACKET_SIZE_1K)
+        } else {
+            (SOH, PACKET_SIZE)
+        };
+        let buf = &buf[..size];
+
+        for _ in 0..=self.max_retries {
+            self.write_byte(start)?;
+            self.inner.write(&[self.packet])?;
+            self.inner.write(&[255 - self.packet])?;
+            self.inner.write(buf)?;
+            if self.use_crc {
+                self.inner.write(&get_crc(&buf).to_be_bytes())?;
+            } else {
+                self.inner.write(&[get_checksum(&buf)])?;
+            }
 
-        return ioerr!(InvalidData, "Receiver didn't respont with ACK or NAK")
+            match self.read_byte(true)? {
+                ACK => {
+                    self.packet = self.packet.wrapping_add(1);
+                    self.packets += 1;
+                    (self.progress)(Progress::Packet(self.packets));
+                    return Ok(size)
+                }
+                NAK => (self.progress)(Progress::NAK),
+                _ => return ioerr!(InvalidData, "Receiver didn't respont with ACK or NAK"),
+            }
+        }
 
+        ioerr!(BrokenPipe, "packet NAKed too many times")
     }
 
     /// Flush this output stream, ensuring that all intermediately buffered
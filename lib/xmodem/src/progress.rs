@@ -6,17 +6,22 @@
 /// is intended to be used by progress indicators or for debugging purposes.
 #[derive(Debug, Copy, Clone)]
 pub enum Progress {
-    /// Waiting for receiver to send NAK.
+    /// Waiting for receiver to send NAK, or C to request CRC-16 mode.
     Waiting,
     /// Download/upload has started.
     Started,
-    /// Packet `.0` was transmitted/received.
-    Packet(u8),
+    /// A packet was transmitted/received. `.0` counts the packets transferred
+    /// so far, starting at 1; unlike the packet number on the wire, it does
+    /// not wrap around.
+    Packet(u32),
     NAK,
     Unknown,
+    /// The end of the transmission was acknowledged.
+    Done,
 }
 
-/// Type for progress callbacks.
+/// Type of the default progress callback. Any `FnMut(Progress)` can be used
+/// as a callback.
 pub type ProgressFn = fn(Progress);
 
 /// Noop progress callback.
@@ -190,6 +190,322 @@ fn test_raw_transmission() {
     assert_eq!(&tx_buf, &[NAK, ACK, ACK, NAK, ACK]);
 }
 
+#[test]
+fn test_crc() {
+    assert_eq!(get_crc(b"123456789"), 0x31C3);
+    assert_eq!(get_crc(&[]), 0);
+    assert_eq!(get_crc(&[0u8; 128]), 0);
+}
+
+#[test]
+fn test_crc_transmission() {
+    let mut input = [0u8; 256];
+    (0..256usize).for_each(|i| input[i] = i as u8);
+    let mut output = [0u8; 256];
+
+    let (mut tx, mut rx) = pipe();
+    let tx_thread = std::thread::spawn(move || {
+        let mut xmodem = Xmodem::new(&mut rx);
+        xmodem.transmit_from(&input[..]).expect("transmit okay");
+        assert!(xmodem.uses_crc());
+        rx.2
+    });
+    let rx_thread = std::thread::spawn(move || {
+        let mut xmodem = Xmodem::new(&mut tx).with_crc(true);
+        xmodem.receive_into(&mut output[..]).expect("receive okay");
+        assert!(xmodem.uses_crc());
+        (tx.2, output)
+    });
+
+    let rx_buf = tx_thread.join().expect("tx join okay");
+    let (tx_buf, output) = rx_thread.join().expect("rx join okay");
+    assert_eq!(&output[..], &input[..]);
+
+    // Each packet ends in a big-endian CRC instead of the checksum.
+    assert_eq!(&rx_buf[0..3], &[SOH, 1, 255 - 1]);
+    assert_eq!(&rx_buf[131..133], &get_crc(&input[..128]).to_be_bytes());
+    assert_eq!(&rx_buf[133..136], &[SOH, 2, 255 - 2]);
+    assert_eq!(&rx_buf[264..266], &get_crc(&input[128..]).to_be_bytes());
+    assert_eq!(&rx_buf[266..], &[EOT, EOT]);
+
+    assert_eq!(&tx_buf, &[CRC, ACK, ACK, NAK, ACK]);
+}
+
+/// A channel whose first `timeouts` reads time out, as a sender that does
+/// not understand `C` would leave them, and that then yields `input`.
+struct SlowChannel {
+    timeouts: usize,
+    input: Cursor<Vec<u8>>,
+    output: Vec<u8>,
+}
+
+impl io::Read for SlowChannel {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.timeouts > 0 {
+            self.timeouts -= 1;
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "no reply"));
+        }
+        self.input.read(buf)
+    }
+}
+
+impl io::Write for SlowChannel {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.output.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Returns what a checksum-mode sender puts on the wire for one packet of
+/// `data` followed by the end of transmission.
+fn checksum_stream(data: &[u8; 128]) -> Vec<u8> {
+    let mut stream = vec![SOH, 1, 254];
+    stream.extend_from_slice(data);
+    stream.push(get_checksum(data));
+    stream.extend_from_slice(&[EOT, EOT]);
+    stream
+}
+
+#[test]
+fn test_crc_falls_back_to_checksum() {
+    let data = [7u8; 128];
+    let mut channel = SlowChannel {
+        timeouts: CRC_ATTEMPTS,
+        input: Cursor::new(checksum_stream(&data)),
+        output: Vec::new(),
+    };
+
+    let mut output = Vec::new();
+    let mut xmodem = Xmodem::new(&mut channel).with_crc(true);
+    assert_eq!(xmodem.receive_into(&mut output).expect("receive okay"), 128);
+    assert!(!xmodem.uses_crc());
+    assert_eq!(&output[..], &data[..]);
+
+    let mut replies = vec![CRC; CRC_ATTEMPTS];
+    replies.extend_from_slice(&[NAK, ACK, NAK, ACK]);
+    assert_eq!(channel.output, replies);
+}
+
+#[test]
+fn test_crc_answered_after_timeout() {
+    // The sender misses the first `C` but answers the second.
+    let data = [9u8; 128];
+    let mut stream = vec![SOH, 1, 254];
+    stream.extend_from_slice(&data);
+    stream.extend_from_slice(&get_crc(&data).to_be_bytes());
+    stream.extend_from_slice(&[EOT, EOT]);
+    let mut channel = SlowChannel { timeouts: 1, input: Cursor::new(stream), output: Vec::new() };
+
+    let mut output = Vec::new();
+    let mut xmodem = Xmodem::new(&mut channel).with_crc(true);
+    assert_eq!(xmodem.receive_into(&mut output).expect("receive okay"), 128);
+    assert!(xmodem.uses_crc());
+    assert_eq!(&output[..], &data[..]);
+    assert_eq!(channel.output, [CRC, CRC, ACK, NAK, ACK]);
+}
+
+#[test]
+fn test_crc_mismatch() {
+    let data = [9u8; 128];
+    let mut stream = vec![SOH, 1, 254];
+    stream.extend_from_slice(&data);
+    stream.extend_from_slice(&(get_crc(&data) ^ 1).to_be_bytes());
+    let channel = SlowChannel { timeouts: 0, input: Cursor::new(stream), output: Vec::new() };
+
+    let mut buffer = [0u8; 128];
+    let e = Xmodem::new(channel).with_crc(true).read_packet(&mut buffer).expect_err("bad CRC");
+    assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+}
+
+/// Returns a `SlowChannel` whose reads time out `timeouts` times and then
+/// yield `bytes`.
+fn replies(timeouts: usize, bytes: &[u8]) -> SlowChannel {
+    SlowChannel { timeouts, input: Cursor::new(bytes.to_vec()), output: Vec::new() }
+}
+
+#[test]
+fn test_nak_resends_packet() {
+    let data = [3u8; 128];
+    let mut channel = replies(0, &[NAK, NAK, NAK, ACK]);
+    assert_eq!(Xmodem::new(&mut channel).write_packet(&data).expect("sent after two NAKs"), 128);
+
+    // The same packet, number 1, three times.
+    assert_eq!(channel.output.len(), 3 * 132);
+    for frame in channel.output.chunks(132) {
+        assert_eq!(&frame[..3], &[SOH, 1, 254]);
+        assert_eq!(&frame[3..131], &data[..]);
+        assert_eq!(frame[131], get_checksum(&data));
+    }
+}
+
+#[test]
+fn test_nak_retries_exhausted() {
+    let data = [3u8; 128];
+    let mut channel = replies(0, &[NAK; 5]);
+    let e = Xmodem::new(&mut channel).with_max_retries(2).write_packet(&data).expect_err("too many NAKs");
+    assert_eq!(e.kind(), io::ErrorKind::BrokenPipe);
+    // The first attempt and two retries.
+    assert_eq!(channel.output.len(), 3 * 132);
+}
+
+#[test]
+fn test_transmitter_handshake_timeouts() {
+    let data = [5u8; 128];
+    let e = Xmodem::new(replies(2, &[NAK, ACK])).write_packet(&data).expect_err("timed out");
+    assert_eq!(e.kind(), io::ErrorKind::TimedOut);
+
+    let e = Xmodem::new(replies(2, &[NAK, ACK]))
+        .with_handshake_timeouts(1)
+        .write_packet(&data)
+        .expect_err("timed out twice");
+    assert_eq!(e.kind(), io::ErrorKind::TimedOut);
+
+    let sent = Xmodem::new(replies(2, &[NAK, ACK]))
+        .with_handshake_timeouts(2)
+        .write_packet(&data)
+        .expect("sent after two timeouts");
+    assert_eq!(sent, 128);
+}
+
+#[test]
+fn test_receiver_handshake_timeouts() {
+    let data = [7u8; 128];
+    let mut buffer = [0u8; 128];
+    let e = Xmodem::new(replies(1, &checksum_stream(&data))).read_packet(&mut buffer).expect_err("timed out");
+    assert_eq!(e.kind(), io::ErrorKind::TimedOut);
+
+    // The receiver sends NAK again after each timeout.
+    let mut channel = replies(2, &checksum_stream(&data));
+    let mut output = Vec::new();
+    let received = Xmodem::new(&mut channel)
+        .with_handshake_timeouts(2)
+        .receive_into(&mut output)
+        .expect("received after two timeouts");
+    assert_eq!(received, 128);
+    assert_eq!(&output[..], &data[..]);
+    assert_eq!(channel.output, [NAK, NAK, NAK, ACK, NAK, ACK]);
+}
+
+/// Transmits `input` from an `Xmodem` set up by `tx_setup` to one set up by
+/// `rx_setup` and returns the bytes received and the bytes the transmitter
+/// put on the wire.
+fn transfer_with<F, G>(input: Vec<u8>, tx_setup: F, rx_setup: G) -> (Vec<u8>, Vec<u8>)
+    where
+        F: FnOnce(Xmodem<&mut Pipe>) -> Xmodem<&mut Pipe> + Send + 'static,
+        G: FnOnce(Xmodem<&mut Pipe>) -> Xmodem<&mut Pipe> + Send + 'static,
+{
+    let (mut tx, mut rx) = pipe();
+    let len = input.len();
+    let tx_thread = std::thread::spawn(move || {
+        let sent = tx_setup(Xmodem::new(&mut rx)).transmit_from(&input[..]).expect("transmit okay");
+        assert_eq!(sent, len);
+        rx.2
+    });
+    let rx_thread = std::thread::spawn(move || {
+        let mut output = Vec::new();
+        rx_setup(Xmodem::new(&mut tx)).receive_into(&mut output).expect("receive okay");
+        output
+    });
+
+    let wire = tx_thread.join().expect("tx join okay");
+    let output = rx_thread.join().expect("rx join okay");
+    (output, wire)
+}
+
+#[test]
+fn test_1k_round_trip() {
+    let input: Vec<u8> = (0..3 * 1024).map(|i| (i * 7 % 251) as u8).collect();
+    let (output, wire) = transfer_with(input.clone(), |x| x.with_1k(true), |x| x);
+    assert_eq!(output, input);
+
+    // Three STX packets of 1024 bytes, then the EOT handshake.
+    assert_eq!(wire.len(), 3 * (3 + 1024 + 1) + 2);
+    for i in 0..3 {
+        let start = i * (3 + 1024 + 1);
+        assert_eq!(&wire[start..start + 3], &[STX, i as u8 + 1, 254 - i as u8]);
+        assert_eq!(&wire[start + 3..start + 3 + 1024], &input[i * 1024..(i + 1) * 1024]);
+    }
+    assert_eq!(&wire[wire.len() - 2..], &[EOT, EOT]);
+}
+
+#[test]
+fn test_1k_round_trip_with_crc() {
+    let input: Vec<u8> = (0..3 * 1024).map(|i| (i % 256) as u8).collect();
+    let (output, wire) = transfer_with(input.clone(), |x| x.with_1k(true), |x| x.with_crc(true));
+    assert_eq!(output, input);
+    assert_eq!(wire.len(), 3 * (3 + 1024 + 2) + 2);
+    assert_eq!(&wire[3 + 1024..3 + 1026], &get_crc(&input[..1024]).to_be_bytes());
+}
+
+#[test]
+fn test_1k_tail_uses_small_packets() {
+    // 1024 + 200 bytes: one STX packet, then two SOH packets for the rest.
+    let input: Vec<u8> = (0..1224).map(|i| i as u8).collect();
+    let (output, wire) = transfer_with(input.clone(), |x| x.with_1k(true), |x| x);
+    assert_eq!(&output[..1224], &input[..]);
+    assert_eq!(output.len(), 1024 + 256);
+    assert!(output[1224..].iter().all(|&b| b == 0));
+
+    assert_eq!(wire[0], STX);
+    assert_eq!(wire[1028], SOH);
+    assert_eq!(wire[1028 + 132], SOH);
+    assert_eq!(wire.len(), 1028 + 2 * 132 + 2);
+}
+
+#[test]
+fn test_1k_packet_needs_large_buffer() {
+    let mut stream = vec![0, STX, 1, 254];
+    stream.extend_from_slice(&[0u8; 1025]);
+    let mut xmodem = Xmodem::new(Cursor::new(stream));
+
+    let mut small = [0u8; 128];
+    let e = xmodem.read_packet(&mut small).expect_err("1K packet in a 128-byte buffer");
+    assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+}
+
+/// Collects the `Progress` events of one side of a transfer in order.
+fn progress_log(events: &[Progress]) -> Vec<String> {
+    events.iter().map(|p| format!("{:?}", p)).collect()
+}
+
+#[test]
+fn test_progress_callbacks() {
+    // More than 255 packets, so the running count outgrows the wire number.
+    let input: Vec<u8> = (0..300 * 128).map(|i| (i % 253) as u8).collect();
+    let (tx, rx) = pipe();
+    let data = input.clone();
+    let tx_thread = std::thread::spawn(move || {
+        let mut events = Vec::new();
+        let sent = Xmodem::transmit_with_progress(&data[..], rx, |p| events.push(p)).expect("tx okay");
+        (sent, events)
+    });
+    let rx_thread = std::thread::spawn(move || {
+        let mut events = Vec::new();
+        let mut output = Vec::new();
+        Xmodem::receive_with_progress(tx, &mut output, |p| events.push(p)).expect("rx okay");
+        (output, events)
+    });
+
+    let (sent, tx_events) = tx_thread.join().expect("tx join okay");
+    let (output, rx_events) = rx_thread.join().expect("rx join okay");
+    assert_eq!(sent, input.len());
+    assert_eq!(output, input);
+
+    let packets: Vec<String> = (1..=300).map(|n| format!("Packet({})", n)).collect();
+    let mut expected_tx = vec!["Waiting".to_string(), "Started".to_string()];
+    expected_tx.extend(packets.iter().cloned());
+    expected_tx.push("Done".to_string());
+    assert_eq!(progress_log(&tx_events), expected_tx);
+
+    let mut expected_rx = vec!["Started".to_string()];
+    expected_rx.extend(packets.iter().cloned());
+    expected_rx.push("Done".to_string());
+    assert_eq!(progress_log(&rx_events), expected_rx);
+}
+
 #[test]
 fn test_small_packet_eof_error() {
     let mut xmodem = Xmodem::new(Cursor::new(vec![NAK, NAK, NAK]));
@@ -341,3 +657,185 @@ fn test_raw_transmission_sync() {
     assert_eq!(&result[266..], &[ACK, EOT,NAK, EOT, ACK]);
 }
 
+
+#[test]
+fn test_channel_loop() {
+    let mut input = [0u8; 300];
+    for (i, b) in input.iter_mut().enumerate() {
+        *b = i as u8;
+    }
+
+    let (tx, rx) = pipe();
+    let tx_thread = std::thread::spawn(move || Xmodem::new_channel(rx).transmit_from(&input[..]));
+    let rx_thread = std::thread::spawn(move || {
+        let mut output = [0u8; 384];
+        Xmodem::new_channel(tx).receive_into(&mut output[..]).map(|n| (n, output))
+    });
+
+    assert_eq!(tx_thread.join().expect("tx join okay").expect("tx okay"), 300);
+    let (received, output) = rx_thread.join().expect("rx join okay").expect("rx okay");
+    assert_eq!(received, 384);
+    assert_eq!(&input[..], &output[..300]);
+    assert!(output[300..].iter().all(|&b| b == 0));
+}
+
+/// A xorshift64* generator, so that the property tests below are random but
+/// reproducible from their seed.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Rng {
+        // Zero is a fixed point of xorshift.
+        Rng(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// Returns a number in `0..n`.
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    fn bytes(&mut self, len: usize) -> Vec<u8> {
+        (0..len).map(|_| self.next() as u8).collect()
+    }
+}
+
+const SEEDS: [u64; 8] = [1, 2, 3, 42, 1337, 0xdead_beef, 0x5eed, 987_654_321];
+
+/// How long a property test run may take before it counts as hung.
+const HANG_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// A `Pipe` that flips bit `bit` of the `at`th byte written through it.
+struct NoisyPipe {
+    pipe: Pipe,
+    flip: Option<(usize, u8)>,
+    written: usize,
+}
+
+impl io::Read for NoisyPipe {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.pipe.read(buf)
+    }
+}
+
+impl io::Write for NoisyPipe {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for &byte in buf {
+            let byte = match self.flip {
+                Some((at, bit)) if at == self.written => byte ^ (1 << bit),
+                _ => byte,
+            };
+            self.written += 1;
+            self.pipe.write_all(&[byte])?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.pipe.flush()
+    }
+}
+
+/// Transfers `input` from a transmitter to a receiver thread and returns
+/// both results. Panics if the transfer does not finish within
+/// `HANG_TIMEOUT`. Either side dropping its end of the pipe after an error
+/// makes the other side's reads fail, so a transfer can only hang if both
+/// sides wait on each other.
+fn transfer(
+    input: Vec<u8>,
+    tx_flip: Option<(usize, u8)>,
+    rx_flip: Option<(usize, u8)>,
+) -> (io::Result<usize>, io::Result<(usize, Vec<u8>)>) {
+    let (a, b) = pipe();
+    let tx_end = NoisyPipe { pipe: a, flip: tx_flip, written: 0 };
+    let rx_end = NoisyPipe { pipe: b, flip: rx_flip, written: 0 };
+
+    let (done_tx, done_rx) = channel();
+    let tx_done = done_tx.clone();
+    std::thread::spawn(move || {
+        let _ = tx_done.send(Ok(Xmodem::transmit(&input[..], tx_end)));
+    });
+    std::thread::spawn(move || {
+        let mut output = Vec::new();
+        let result = Xmodem::receive(rx_end, &mut output).map(|n| (n, output));
+        let _ = done_tx.send(Err(result));
+    });
+
+    let (mut tx_result, mut rx_result) = (None, None);
+    for _ in 0..2 {
+        match done_rx.recv_timeout(HANG_TIMEOUT).expect("transfer hung") {
+            Ok(result) => tx_result = Some(result),
+            Err(result) => rx_result = Some(result),
+        }
+    }
+    (tx_result.unwrap(), rx_result.unwrap())
+}
+
+/// Returns the bytes an XMODEM transfer of `len` data bytes puts on the wire
+/// from transmitter to receiver: 132 per packet plus two `EOT`s.
+fn wire_len(len: usize) -> usize {
+    (len + PACKET_SIZE - 1) / PACKET_SIZE * (PACKET_SIZE + 4) + 2
+}
+
+#[test]
+fn property_round_trip() {
+    for &seed in SEEDS.iter() {
+        let mut rng = Rng::new(seed);
+        for _ in 0..4 {
+            let len = rng.below(2048);
+            let input = rng.bytes(len);
+
+            let (tx, rx) = transfer(input.clone(), None, None);
+            assert_eq!(tx.expect("tx okay"), len, "seed {}", seed);
+            let (received, output) = rx.expect("rx okay");
+
+            let padded = (len + PACKET_SIZE - 1) / PACKET_SIZE * PACKET_SIZE;
+            assert_eq!(received, padded, "seed {}", seed);
+            assert_eq!(output.len(), padded, "seed {}", seed);
+            assert_eq!(&output[..len], &input[..], "seed {}", seed);
+            assert!(output[len..].iter().all(|&b| b == 0), "seed {}", seed);
+        }
+    }
+}
+
+#[test]
+fn property_bit_flips_never_corrupt() {
+    for &seed in SEEDS.iter() {
+        let mut rng = Rng::new(seed);
+        for round in 0..8 {
+            let len = 1 + rng.below(1024);
+            let input = rng.bytes(len);
+
+            // Flip one bit somewhere in the data stream, or in one of the
+            // receiver's replies: its first NAK, the ACKs and the final NAK
+            // and ACK.
+            let flip = (rng.below(8)) as u8;
+            let (tx_flip, rx_flip) = if round % 2 == 0 {
+                (Some((rng.below(wire_len(len)), flip)), None)
+            } else {
+                let packets = (len + PACKET_SIZE - 1) / PACKET_SIZE;
+                (None, Some((rng.below(packets + 3), flip)))
+            };
+
+            let (tx, rx) = transfer(input.clone(), tx_flip, rx_flip);
+            // Either the receiver recovered and holds exactly the input, or
+            // at least one side reported an error; the receiver never
+            // returns corrupted data as a success.
+            if let Ok((received, output)) = rx {
+                assert_eq!(&output[..len], &input[..], "seed {} round {}", seed, round);
+                assert_eq!(received, output.len(), "seed {} round {}", seed, round);
+                if let Ok(sent) = tx {
+                    assert_eq!(sent, len, "seed {} round {}", seed, round);
+                }
+            } else {
+                assert!(tx.is_err() || rx.is_err());
+            }
+        }
+    }
+}